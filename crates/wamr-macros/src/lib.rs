@@ -0,0 +1,279 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! Procedural macros of wamr-rust-sdk. Use them via the `macros` feature of the SDK.
+
+use proc_macro::{Delimiter, Group, Spacing, TokenStream, TokenTree};
+
+/// turn a Rust fn into a host function, which is able to be registered by
+/// `RuntimeBuilder::register()`. The fn is replaced by one of the same name which returns
+/// the host function
+///
+/// Parameters may be
+/// - `i32`, `i64`, `f32` and `f64`, passed as they are
+/// - `&str` and `&[u8]`, which wasm passes as an `i32` pointer and an `i32` length into the
+///   default memory. They are copied out of the memory before the call
+/// - `&Caller`, the calling instance. It should be the first one if any
+///
+/// It returns `()`, a scalar, or a `Result` of them. An `Err`, or a pointer beyond the
+/// memory, traps the wasm caller.
+///
+/// ```ignore
+/// #[host_function]
+/// fn log(level: i32, message: &str) -> Result<(), String> {
+///     println!("[{}] {}", level, message);
+///     Ok(())
+/// }
+///
+/// // (import "env" "log" (func (param i32 i32 i32)))
+/// let runtime = Runtime::builder()
+///     .use_system_allocator()
+///     .register("env", "log", log())
+///     .build()?;
+/// ```
+///
+/// Generics, `async`, `unsafe` and `self` are not supported.
+#[proc_macro_attribute]
+pub fn host_function(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return compile_error("#[host_function] takes no arguments");
+    }
+
+    match HostFunction::parse(item) {
+        Ok(function) => function.expand(),
+        Err(message) => compile_error(message),
+    }
+}
+
+// how a parameter of the fn is passed by wasm
+enum Param {
+    Caller,
+    Str,
+    Bytes,
+    Scalar(String),
+}
+
+struct HostFunction {
+    // attributes and the visibility
+    prefix: TokenStream,
+    name: String,
+    // the original ones are kept for the inner fn
+    params_group: Group,
+    params: Vec<Param>,
+    result: TokenStream,
+    body: Group,
+}
+
+impl HostFunction {
+    fn parse(item: TokenStream) -> Result<Self, &'static str> {
+        let mut tokens = item.into_iter().peekable();
+
+        let mut prefix = TokenStream::new();
+        loop {
+            match tokens.next() {
+                Some(TokenTree::Ident(ident)) if ident.to_string() == "fn" => break,
+                Some(TokenTree::Ident(ident))
+                    if ["async", "unsafe", "const", "extern"].contains(&&*ident.to_string()) =>
+                {
+                    return Err("#[host_function] expects a plain fn");
+                }
+                Some(token) => prefix.extend([token]),
+                None => return Err("#[host_function] expects a fn"),
+            }
+        }
+
+        let name = match tokens.next() {
+            Some(TokenTree::Ident(ident)) => ident.to_string(),
+            _ => return Err("#[host_function] expects a fn name"),
+        };
+
+        let params_group = match tokens.next() {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => group,
+            _ => return Err("#[host_function] doesn't support generics"),
+        };
+        let params = parse_params(params_group.stream())?;
+
+        let mut result = Vec::new();
+        let body = loop {
+            match tokens.next() {
+                Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+                    break group;
+                }
+                Some(TokenTree::Ident(ident)) if ident.to_string() == "where" => {
+                    return Err("#[host_function] doesn't support where clauses");
+                }
+                Some(token) => result.push(token),
+                None => return Err("#[host_function] expects a fn body"),
+            }
+        };
+        let result = match result.as_slice() {
+            [] => "()".parse().unwrap(),
+            [TokenTree::Punct(dash), TokenTree::Punct(arrow), ..]
+                if dash.as_char() == '-' && arrow.as_char() == '>' =>
+            {
+                result.into_iter().skip(2).collect()
+            }
+            _ => return Err("#[host_function] expects `-> Type` as the result"),
+        };
+
+        Ok(HostFunction {
+            prefix,
+            name,
+            params_group,
+            params,
+            result,
+            body,
+        })
+    }
+
+    fn expand(self) -> TokenStream {
+        let mut lowered = Vec::new();
+        let mut lowered_params = Vec::new();
+        let mut conversions = String::new();
+        let mut args = Vec::new();
+        for (i, param) in self.params.iter().enumerate() {
+            match param {
+                Param::Caller => args.push(String::from("__caller")),
+                Param::Str => {
+                    lowered.push("i32, i32");
+                    lowered_params.push(format!("__ptr{}: i32, __len{}: i32", i, i));
+                    conversions.push_str(&format!(
+                        "let __arg{0} = __caller.read_str(__ptr{0} as u32 as u64, \
+                         __len{0} as u32 as u64)?;",
+                        i
+                    ));
+                    args.push(format!("&__arg{}", i));
+                }
+                Param::Bytes => {
+                    lowered.push("i32, i32");
+                    lowered_params.push(format!("__ptr{}: i32, __len{}: i32", i, i));
+                    conversions.push_str(&format!(
+                        "let mut __arg{0} = ::std::vec![0u8; __len{0} as u32 as usize];\
+                         __caller.read(__ptr{0} as u32 as u64, &mut __arg{0})?;",
+                        i
+                    ));
+                    args.push(format!("&__arg{}", i));
+                }
+                Param::Scalar(scalar) => {
+                    lowered.push(scalar);
+                    lowered_params.push(format!("__arg{}: {}", i, scalar));
+                    args.push(format!("__arg{}", i));
+                }
+            }
+        }
+        let lowered = match lowered.is_empty() {
+            true => String::from("()"),
+            false => format!("({},)", lowered.join(", ")),
+        };
+
+        // the fn is kept as an inner one, untouched
+        let mut inner: TokenStream = "fn __host_function".parse().unwrap();
+        inner.extend([TokenTree::Group(self.params_group)]);
+        inner.extend("->".parse::<TokenStream>().unwrap());
+        inner.extend(self.result.clone());
+        inner.extend([TokenTree::Group(self.body)]);
+        let tail = format!(
+            "move |__caller: &::wamr_rust_sdk::host_function::Caller, {lowered_params}| \
+             -> ::std::result::Result<{result}, ::wamr_rust_sdk::RuntimeError> {{\
+                 let _ = __caller;\
+                 {conversions}\
+                 ::std::result::Result::Ok(__host_function({args}))\
+             }}",
+            lowered_params = lowered_params.join(", "),
+            result = self.result,
+            conversions = conversions,
+            args = args.join(", ")
+        );
+        inner.extend(tail.parse::<TokenStream>().unwrap());
+
+        let signature = format!(
+            "fn {name}() -> impl ::wamr_rust_sdk::host_function::IntoHostFunction<\
+             {lowered}, ::std::result::Result<{result}, ::wamr_rust_sdk::RuntimeError>>",
+            name = self.name,
+            lowered = lowered,
+            result = self.result
+        );
+        let mut expanded = self.prefix;
+        expanded.extend(signature.parse::<TokenStream>().unwrap());
+        expanded.extend([TokenTree::Group(Group::new(Delimiter::Brace, inner))]);
+        expanded
+    }
+}
+
+// `name: Type, ...` split by top level commas. Commas between `<` and `>` belong to a type
+fn parse_params(stream: TokenStream) -> Result<Vec<Param>, &'static str> {
+    let mut params = Vec::new();
+    let mut current: Vec<TokenTree> = Vec::new();
+    let mut depth = 0;
+    for token in stream {
+        if let TokenTree::Punct(punct) = &token {
+            let arrow = matches!(current.last(), Some(TokenTree::Punct(p)) if p.as_char() == '-');
+            match punct.as_char() {
+                '<' => depth += 1,
+                '>' if !arrow => depth -= 1,
+                ',' if depth == 0 => {
+                    params.push(parse_param(std::mem::take(&mut current))?);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        current.push(token);
+    }
+    if !current.is_empty() {
+        params.push(parse_param(current)?);
+    }
+
+    if params
+        .iter()
+        .skip(1)
+        .any(|param| matches!(param, Param::Caller))
+    {
+        return Err("#[host_function] expects `&Caller` as the first parameter");
+    }
+    Ok(params)
+}
+
+fn parse_param(tokens: Vec<TokenTree>) -> Result<Param, &'static str> {
+    // the first `:` which isn't a part of `::`
+    let colon = tokens
+        .iter()
+        .enumerate()
+        .position(|(i, token)| match token {
+            TokenTree::Punct(punct) if punct.as_char() == ':' => {
+                punct.spacing() == Spacing::Alone
+                    && !matches!(
+                        i.checked_sub(1).map(|i| &tokens[i]),
+                        Some(TokenTree::Punct(p)) if p.as_char() == ':'
+                    )
+            }
+            _ => false,
+        });
+    let colon = match colon {
+        Some(colon) => colon,
+        None => return Err("#[host_function] doesn't support `self`"),
+    };
+
+    let ty = tokens[colon + 1..]
+        .iter()
+        .map(|token| token.to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+    let compact = ty.replace(' ', "");
+    let param = match compact.as_str() {
+        "&str" => Param::Str,
+        "&[u8]" => Param::Bytes,
+        _ if compact.starts_with('&') && compact.ends_with("Caller") => Param::Caller,
+        _ if compact.starts_with('&') => {
+            return Err("#[host_function] supports only `&str`, `&[u8]` and `&Caller` references")
+        }
+        _ => Param::Scalar(ty),
+    };
+    Ok(param)
+}
+
+fn compile_error(message: &str) -> TokenStream {
+    format!("compile_error!({:?});", message).parse().unwrap()
+}
@@ -15,27 +15,74 @@ fn main() {
     assert!(wamr_root.exists());
 
     let enable_llvm_jit = if cfg!(feature = "llvmjit") { "1" } else { "0" };
+    // the classic interpreter instead of the fast one, which lacks exception handling
+    let (enable_fast_interp, enable_exce_handling) = if cfg!(feature = "exception-handling") {
+        ("0", "1")
+    } else {
+        ("1", "0")
+    };
+    let enable_instruction_metering = if cfg!(feature = "instruction-metering") {
+        "1"
+    } else {
+        "0"
+    };
+    let enable_shared_heap = if cfg!(feature = "shared-heap") {
+        "1"
+    } else {
+        "0"
+    };
+    let enable_memory_profiling = if cfg!(feature = "memory-profiling") {
+        "1"
+    } else {
+        "0"
+    };
     // TODO: define LLVM_DIR
-    let dst = Config::new(&wamr_root)
+    let mut config = Config::new(&wamr_root);
+    // what `wasm_runtime_dump_mem_consumption()` prints goes through src/print.c
+    if cfg!(feature = "memory-profiling") {
+        config.define("WAMR_BH_VPRINTF", "wamr_sys_vprintf");
+    }
+    // WAMR_BUILD_* of vmlib, which are WASM_ENABLE_* in its sources
+    let options = [
         // running mode
-        .define("WAMR_BUILD_AOT", "1")
-        .define("WAMR_BUILD_INTERP", "1")
-        .define("WAMR_BUILD_FAST_INTERP", "1")
-        .define("WAMR_BUILD_JIT", enable_llvm_jit)
+        ("AOT", "1"),
+        ("INTERP", "1"),
+        ("FAST_INTERP", enable_fast_interp),
+        ("JIT", enable_llvm_jit),
         // mvp
-        .define("WAMR_BUILD_BULK_MEMORY", "1")
-        .define("WAMR_BUILD_REF_TYPES", "1")
-        .define("WAMR_BUILD_SIMD", "1")
+        ("BULK_MEMORY", "1"),
+        ("REF_TYPES", "1"),
+        ("MULTI_MEMORY", "1"),
+        ("SIMD", "1"),
+        ("EXCE_HANDLING", enable_exce_handling),
+        ("INSTRUCTION_METERING", enable_instruction_metering),
+        ("SHARED_HEAP", enable_shared_heap),
+        ("MEMORY_PROFILING", enable_memory_profiling),
         // wasi
-        .define("WAMR_BUILD_LIBC_WASI", "1")
+        ("LIBC_WASI", "1"),
         // `nostdlib`
-        .define("WAMR_BUILD_LIBC_BUILTIN", "1")
-        .build_target("iwasm_static")
-        .build();
+        ("LIBC_BUILTIN", "1"),
+        // import functions from other wasm modules
+        ("MULTI_MODULE", "1"),
+        // call stack of a trap
+        ("DUMP_CALL_STACK", "1"),
+        // shared memories between spawned instances
+        ("SHARED_MEMORY", "1"),
+        ("THREAD_MGR", "1"),
+    ];
+    for (option, value) in options {
+        config.define(format!("WAMR_BUILD_{option}"), value);
+    }
+    let dst = config.build_target("iwasm_static").build();
 
     println!("cargo:rustc-link-search=native={}/build", dst.display());
     println!("cargo:rustc-link-lib=static=vmlib");
 
+    // after vmlib, which refers to it
+    if cfg!(feature = "memory-profiling") {
+        cc::Build::new().file("src/print.c").compile("wamr_sys_print");
+        println!("cargo:rerun-if-changed=src/print.c");
+    }
     //TODO: support macos?
     if cfg!(feature = "llvmjit") {
         println!("cargo:rustc-link-lib=dylib=dl");
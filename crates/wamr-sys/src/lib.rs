@@ -11,3 +11,20 @@
 
 // This matches bindgen::Builder output
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+extern "C" {
+    /// the counterpart of `wasm_runtime_register_module()`. It isn't in wasm_export.h, but
+    /// declared by core/iwasm/common/wasm_runtime_common.h
+    pub fn wasm_runtime_unregister_module(module: *const WASMModuleCommon);
+}
+
+/// takes what WAMR prints, instead of stdout. See src/print.c
+#[cfg(feature = "memory-profiling")]
+pub type wamr_sys_print_hook_t =
+    ::core::option::Option<unsafe extern "C" fn(message: *const ::core::ffi::c_char)>;
+
+#[cfg(feature = "memory-profiling")]
+extern "C" {
+    /// `None` to print to stdout again
+    pub fn wamr_sys_set_print_hook(hook: wamr_sys_print_hook_t);
+}
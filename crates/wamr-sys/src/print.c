@@ -0,0 +1,37 @@
+/*
+ * Copyright (C) 2023 Liquid Reply GmbH. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+/*
+ * `os_printf()` of WAMR, as `BH_VPRINTF`. It goes to stdout, unless the host sets a hook
+ * to take the output, like what `wasm_runtime_dump_mem_consumption()` prints
+ */
+
+#include <stdarg.h>
+#include <stdio.h>
+
+typedef void (*wamr_sys_print_hook_t)(const char *message);
+
+static wamr_sys_print_hook_t print_hook;
+
+void
+wamr_sys_set_print_hook(wamr_sys_print_hook_t hook)
+{
+    __atomic_store_n(&print_hook, hook, __ATOMIC_RELEASE);
+}
+
+int
+wamr_sys_vprintf(const char *format, va_list ap)
+{
+    wamr_sys_print_hook_t hook = __atomic_load_n(&print_hook, __ATOMIC_ACQUIRE);
+    char message[512];
+    int len;
+
+    if (!hook)
+        return vprintf(format, ap);
+
+    len = vsnprintf(message, sizeof(message), format, ap);
+    hook(message);
+    return len;
+}
@@ -20,7 +20,7 @@ fn main() -> Result<(), RuntimeError> {
         .set_pre_open_path(vec!["."], vec![])
         .build();
 
-    module.set_wasi_context(wasi_ctx);
+    module.set_wasi_context(wasi_ctx)?;
 
     let instance = Instance::new(&runtime, &module, 1024 * 64)?;
 
@@ -28,7 +28,7 @@ fn main() -> Result<(), RuntimeError> {
 
     let params: Vec<WasmValue> = vec![WasmValue::I32(9), WasmValue::I32(27)];
     let result = function.call(&instance, &params)?;
-    assert_eq!(result, WasmValue::I32(9));
+    assert_eq!(result, vec![WasmValue::I32(9)]);
 
     Ok(())
 }
@@ -0,0 +1,401 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! async host functions, which suspend the calling guest instead of blocking the thread.
+//! register them via `Async`, and call into the guest via `Function::call_async()`
+//!
+//! It relies on the guest being transformed by Binaryen's Asyncify, like
+//! `wasm-opt --asyncify`, which exports `asyncify_start_unwind`, `asyncify_stop_unwind`,
+//! `asyncify_start_rewind` and `asyncify_stop_rewind`. See
+//! <https://github.com/WebAssembly/binaryen/blob/main/src/passes/Asyncify.cpp>
+//!
+//! When an async host function is called, it creates its future and unwinds the guest
+//! stack into a buffer in the linear memory. `Function::call_async()` awaits the future,
+//! then calls the same function again to rewind the stack, and the host function returns
+//! the output of the future to the guest.
+
+use std::{cell::RefCell, future::Future, pin::Pin, ptr};
+
+use wamr_sys::wasm_exec_env_t;
+
+use crate::{
+    function::Function,
+    host_function::{raise, Caller, HostResult, HostType, IntoHostFunction, RawHostFunction},
+    instance::Instance,
+    value::WasmValue,
+    RuntimeError,
+};
+
+/// bytes for an unwound guest stack. Locals and the operand stack of all frames from the
+/// export to the host function are saved
+pub const ASYNCIFY_STACK_SIZE: u64 = 16 * 1024;
+
+// writes the result of an async host function into its slots
+type StoreResult = Box<dyn FnOnce(wasm_exec_env_t, *mut u64)>;
+type PendingHostCall = Pin<Box<dyn Future<Output = StoreResult>>>;
+
+// the `Function::call_async()` running on this thread
+struct AsyncCall {
+    // the address of the asyncify data in the linear memory
+    data: u64,
+    // set by the host function which unwinds the guest
+    pending: Option<PendingHostCall>,
+    // set while rewinding into the host function
+    resumed: Option<StoreResult>,
+}
+
+thread_local! {
+    static ASYNC_CALL: RefCell<Option<AsyncCall>> = const { RefCell::new(None) };
+}
+
+/// an async closure as a host function, which suspends the guest until its future is
+/// ready. The closure takes a `&Caller` and `HostType`s like those of
+/// `RuntimeBuilder::register()`, and returns a future of a `HostResult`
+///
+/// The caller is only lent before the future is created, so read arguments out of the
+/// memory in the closure, not in the future. Guests should be called via
+/// `Function::call_async()`, otherwise the host function traps
+///
+/// ```ignore
+/// let runtime = Runtime::builder()
+///     .use_system_allocator()
+///     .register("env", "fetch", Async(|caller: &Caller, ptr: i32, len: i32| {
+///         let url = caller.read_str(ptr as u64, len as u64).map(String::from);
+///         async move { Ok::<i32, Trap>(client.get(url?).await?.status()) }
+///     }))
+///     .build()?;
+/// ```
+pub struct Async<F>(pub F);
+
+macro_rules! impl_into_async_host_function {
+    ($($name:ident)*) => {
+        impl<Func, Fut, R, $($name: HostType),*> IntoHostFunction<($($name,)*), Async<R>>
+            for Async<Func>
+        where
+            Func: Fn(&Caller, $($name),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = R> + 'static,
+            R: HostResult + 'static,
+        {
+            fn signature() -> String {
+                let mut signature = String::from("(");
+                $(signature.push_str($name::SIGNATURE);)*
+                signature.push(')');
+                signature.push_str(R::SIGNATURE);
+                signature
+            }
+
+            #[allow(non_snake_case, unused_mut, unused_variables, unused_assignments)]
+            fn into_raw(self) -> RawHostFunction {
+                Box::new(move |exec_env, args| {
+                    let caller = Caller::new(exec_env);
+                    if resume(&caller, args) {
+                        return;
+                    }
+
+                    let mut slots = args;
+                    $(
+                        let $name = $name::from_slot(unsafe { *slots });
+                        slots = slots.wrapping_add(1);
+                    )*
+                    let future = (self.0)(&caller, $($name),*);
+                    let pending: PendingHostCall = Box::pin(async move {
+                        let result = future.await;
+                        Box::new(move |exec_env: wasm_exec_env_t, args: *mut u64| unsafe {
+                            result.store(exec_env, args)
+                        }) as StoreResult
+                    });
+                    if let Err(e) = suspend(&caller, pending) {
+                        unsafe { raise(exec_env, &e.to_string()) };
+                    }
+                })
+            }
+        }
+    };
+}
+
+impl_into_async_host_function!();
+impl_into_async_host_function!(A);
+impl_into_async_host_function!(A B);
+impl_into_async_host_function!(A B C);
+impl_into_async_host_function!(A B C D);
+impl_into_async_host_function!(A B C D E);
+impl_into_async_host_function!(A B C D E F);
+impl_into_async_host_function!(A B C D E F G);
+impl_into_async_host_function!(A B C D E F G H);
+
+// return `true` if the guest is rewinding into the host function, whose result is written
+fn resume(caller: &Caller, args: *mut u64) -> bool {
+    let resumed = ASYNC_CALL.with(|call| {
+        call.borrow_mut()
+            .as_mut()
+            .and_then(|call| call.resumed.take())
+    });
+    let Some(store) = resumed else {
+        return false;
+    };
+
+    match caller.call("asyncify_stop_rewind", &[]) {
+        Ok(_) => store(caller.get_inner_exec_env(), args),
+        Err(e) => unsafe { raise(caller.get_inner_exec_env(), &e.to_string()) },
+    }
+    true
+}
+
+// keep the future for `Function::call_async()`, and unwind the guest
+fn suspend(caller: &Caller, pending: PendingHostCall) -> Result<(), RuntimeError> {
+    let data = ASYNC_CALL.with(|call| {
+        call.borrow_mut().as_mut().map(|call| {
+            call.pending = Some(pending);
+            call.data
+        })
+    });
+    let Some(data) = data else {
+        return Err(RuntimeError::ExecutionError(String::from(
+            "async host function called out of Function::call_async()",
+        )));
+    };
+
+    caller
+        .call("asyncify_start_unwind", &[WasmValue::I32(data as i32)])
+        .map(|_| ())
+        .inspect_err(|_| {
+            ASYNC_CALL.with(|call| call.borrow_mut().as_mut().map(|call| call.pending.take()));
+        })
+}
+
+// the exports of an asyncified module, and the data they work on
+pub(crate) struct Asyncify<'instance> {
+    instance: &'instance Instance<'instance>,
+    data: u64,
+    stop_unwind: Function,
+    start_rewind: Function,
+}
+
+impl<'instance> Asyncify<'instance> {
+    pub(crate) fn new(instance: &'instance Instance<'instance>) -> Result<Self, RuntimeError> {
+        let export = |name: &str| {
+            Function::find_export_func(instance, name).map_err(|_| {
+                RuntimeError::ExecutionError(format!(
+                    "{} not found, the module is not asyncified",
+                    name
+                ))
+            })
+        };
+        let stop_unwind = export("asyncify_stop_unwind")?;
+        let start_rewind = export("asyncify_start_rewind")?;
+        export("asyncify_start_unwind")?;
+        export("asyncify_stop_rewind")?;
+
+        // `struct { start: u32, end: u32 }` followed by the stack
+        let (data, native) = instance.module_malloc(8 + ASYNCIFY_STACK_SIZE)?;
+        let start = (data + 8) as u32;
+        let end = (data + 8 + ASYNCIFY_STACK_SIZE) as u32;
+        unsafe {
+            ptr::write_unaligned(native as *mut u32, start.to_le());
+            ptr::write_unaligned((native as *mut u32).wrapping_add(1), end.to_le());
+        }
+
+        Ok(Asyncify {
+            instance,
+            data,
+            stop_unwind,
+            start_rewind,
+        })
+    }
+
+    // run `call` with the host functions it reaches able to suspend. return the future
+    // of the host function if the guest is unwound
+    pub(crate) fn enter<T>(
+        &self,
+        resumed: Option<StoreResult>,
+        call: impl FnOnce() -> T,
+    ) -> (T, Option<PendingHostCall>) {
+        let outer = ASYNC_CALL.with(|current| {
+            current.replace(Some(AsyncCall {
+                data: self.data,
+                pending: None,
+                resumed,
+            }))
+        });
+        let result = call();
+        let inner = ASYNC_CALL.with(|current| current.replace(outer));
+        (result, inner.and_then(|inner| inner.pending))
+    }
+
+    // after the guest is unwound, and before the future is awaited
+    pub(crate) fn stop_unwind(&self) -> Result<(), RuntimeError> {
+        self.stop_unwind.call_slice(self.instance, &[], &mut [])
+    }
+
+    // before calling the same function again
+    pub(crate) fn start_rewind(&self) -> Result<(), RuntimeError> {
+        self.start_rewind
+            .call_slice(self.instance, &[WasmValue::I32(self.data as i32)], &mut [])
+    }
+}
+
+impl Drop for Asyncify<'_> {
+    fn drop(&mut self) {
+        self.instance.free(self.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{module::Module, runtime::Runtime, trap::TrapKind};
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        task::{Context, Poll, Waker},
+    };
+
+    // ready on the second poll, like a socket
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        let mut future = Box::pin(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    // what `wasm-opt --asyncify` generates, by hand and for one local
+    const ASYNCIFIED: &str = r#"
+        (module
+          (import "test_async" "fetch" (func $fetch (param i32) (result i32)))
+          (import "test_async" "fail" (func $fail (result i32)))
+          (memory (export "memory") 1)
+          (global $state (mut i32) (i32.const 0))
+          (global $data (mut i32) (i32.const 0))
+          (func (export "asyncify_start_unwind") (param i32)
+            (global.set $state (i32.const 1))
+            (global.set $data (local.get 0))
+          )
+          (func (export "asyncify_stop_unwind")
+            (global.set $state (i32.const 0))
+          )
+          (func (export "asyncify_start_rewind") (param i32)
+            (global.set $state (i32.const 2))
+            (global.set $data (local.get 0))
+          )
+          (func (export "asyncify_stop_rewind")
+            (global.set $state (i32.const 0))
+          )
+          (func (export "run") (param $x i32) (result i32)
+            (local $r i32)
+            (local $p i32)
+            (if (i32.eq (global.get $state) (i32.const 2))
+              (then
+                (local.set $p (i32.sub (i32.load (global.get $data)) (i32.const 4)))
+                (i32.store (global.get $data) (local.get $p))
+                (local.set $x (i32.load (local.get $p)))
+              )
+            )
+            (local.set $r (call $fetch (local.get $x)))
+            (if (i32.eq (global.get $state) (i32.const 1))
+              (then
+                (local.set $p (i32.load (global.get $data)))
+                (i32.store (local.get $p) (local.get $x))
+                (i32.store (global.get $data) (i32.add (local.get $p) (i32.const 4)))
+                (return (i32.const 0))
+              )
+            )
+            (i32.add (local.get $r) (local.get $x))
+          )
+          (func (export "fail") (result i32)
+            (call $fail)
+          )
+        )
+    "#;
+
+    #[test]
+    fn test_async_host_function() {
+        let polls = Arc::new(AtomicU32::new(0));
+        let fetches = Arc::clone(&polls);
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register(
+                "test_async",
+                "fetch",
+                Async(move |_: &Caller, x: i32| {
+                    let fetches = Arc::clone(&fetches);
+                    async move {
+                        YieldOnce(false).await;
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        x * 10
+                    }
+                }),
+            )
+            .register(
+                "test_async",
+                "fail",
+                Async(|_: &Caller| async { Err::<i32, _>("unavailable") }),
+            )
+            .build()
+            .unwrap();
+
+        let module = Module::from_wat(&runtime, ASYNCIFIED).unwrap();
+        let instance = Instance::new_with_args(&runtime, &module, 1024 * 64, 1024 * 64).unwrap();
+
+        let run = Function::find_export_func(&instance, "run").unwrap();
+        let results = block_on(run.call_async(&instance, &[WasmValue::I32(4)])).unwrap();
+        assert_eq!(results, vec![WasmValue::I32(44)]);
+        let results = block_on(run.call_async(&instance, &[WasmValue::I32(5)])).unwrap();
+        assert_eq!(results, vec![WasmValue::I32(55)]);
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+
+        let fail = Function::find_export_func(&instance, "fail").unwrap();
+        match block_on(fail.call_async(&instance, &[])) {
+            Err(RuntimeError::Trap { kind, message, .. }) => {
+                assert_eq!(kind, TrapKind::Host);
+                assert!(message.contains("unavailable"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // not suspendable out of `call_async()`
+        let result = run.call(&instance, &vec![WasmValue::I32(4)]);
+        assert!(matches!(
+            result,
+            Err(RuntimeError::Trap {
+                kind: TrapKind::Host,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_not_asyncified() {
+        let runtime = Runtime::new().unwrap();
+        let wat = r#"(module (func (export "run")))"#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new_with_args(&runtime, &module, 1024 * 64, 1024 * 64).unwrap();
+
+        let run = Function::find_export_func(&instance, "run").unwrap();
+        assert!(matches!(
+            block_on(run.call_async(&instance, &[])),
+            Err(RuntimeError::ExecutionError(_))
+        ));
+    }
+}
@@ -0,0 +1,78 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! cancel calls in flight from other threads. get a handle via `Instance::cancel_handle()`
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, MutexGuard, PoisonError,
+};
+use wamr_sys::{wasm_module_inst_t, wasm_runtime_terminate};
+
+// shared by an instance and its handles
+#[derive(Debug)]
+pub(crate) struct CancelState {
+    // the underlying instance, or 0 once it is gone. locked while it is replaced or dropped
+    instance: Mutex<usize>,
+    cancelled: AtomicBool,
+}
+
+impl CancelState {
+    pub(crate) fn new(instance: wasm_module_inst_t) -> Arc<Self> {
+        Arc::new(CancelState {
+            instance: Mutex::new(instance as usize),
+            cancelled: AtomicBool::new(false),
+        })
+    }
+
+    // the underlying instance. set it when the instance is replaced or dropped
+    pub(crate) fn instance(&self) -> MutexGuard<'_, usize> {
+        self.instance.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // whether the instance is cancelled since the last time
+    pub(crate) fn take(&self) -> bool {
+        self.cancelled.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// It cancels calls into an instance, from any thread. Cloning it is cheap
+///
+/// ```ignore
+/// let handle = instance.cancel_handle();
+/// thread::spawn(move || handle.cancel());
+/// assert!(matches!(
+///     function.call(&instance, &params),
+///     Err(RuntimeError::Cancelled)
+/// ));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    state: Arc<CancelState>,
+}
+
+impl CancelHandle {
+    pub(crate) fn new(state: &Arc<CancelState>) -> Self {
+        CancelHandle {
+            state: Arc::clone(state),
+        }
+    }
+
+    /// interrupt the call running in the instance, which returns `RuntimeError::Cancelled`.
+    /// If none is running, the next call returns it without running.
+    /// Nothing happens once the instance is dropped
+    ///
+    /// The guest is interrupted wherever it is, so its state may be inconsistent after
+    /// that. Consider `Instance::reset()` then.
+    pub fn cancel(&self) {
+        let instance = self.state.instance();
+        if *instance == 0 {
+            return;
+        }
+
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        unsafe { wasm_runtime_terminate(*instance as wasm_module_inst_t) };
+    }
+}
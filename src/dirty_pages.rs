@@ -0,0 +1,295 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! incremental snapshots, which capture only pages of linear memories written since the
+//! previous snapshot. get one via `DirtyPageTracker::new()`
+//!
+//! Written pages are reported by the soft-dirty bits of the Linux kernel. See
+//! <https://docs.kernel.org/admin-guide/mm/soft-dirty.html>
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::FileExt,
+    ptr,
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+use crate::{
+    instance::Instance,
+    snapshot::{MemoryDiff, Snapshot, SnapshotDiff},
+    RuntimeError,
+};
+
+const PAGEMAP_SOFT_DIRTY: u64 = 1 << 55;
+
+// soft-dirty bits are cleared for the whole process. counts clears, so a tracker knows
+// whether bits are cleared by someone else since its previous snapshot
+static CLEARS: Mutex<u64> = Mutex::new(0);
+
+/// It tracks written pages of linear memories of an instance, between snapshots
+///
+/// ```ignore
+/// let mut tracker = DirtyPageTracker::new()?;
+/// let mut checkpoint = tracker.track(&instance)?;
+/// // run the instance
+/// checkpoint.apply(&tracker.snapshot(&instance)?)?;
+/// ```
+///
+/// Soft-dirty bits are shared by the whole process. A tracker is accurate as long as it is
+/// the only one clearing them. Otherwise, like after another tracker takes a snapshot, or
+/// the instance is reset, the next snapshot falls back to all pages. Shared memories are
+/// always captured fully, since other threads write them meanwhile.
+#[derive(Debug, Default)]
+pub struct DirtyPageTracker {
+    // the count of clears after the previous snapshot
+    clears: Option<u64>,
+    // base addresses of memories at the previous snapshot
+    memories: Vec<usize>,
+}
+
+impl DirtyPageTracker {
+    /// create a tracker, which has not seen any instance yet
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::NotImplemented` if the kernel doesn't provide soft-dirty bits.
+    pub fn new() -> Result<Self, RuntimeError> {
+        static SUPPORTED: OnceLock<bool> = OnceLock::new();
+        match *SUPPORTED.get_or_init(|| probe().unwrap_or(false)) {
+            true => Ok(DirtyPageTracker::default()),
+            false => Err(RuntimeError::NotImplemented),
+        }
+    }
+
+    /// start tracking the instance, and take a full snapshot of it as the base of
+    /// incremental ones
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::WasmFileFSError` if failed to access soft-dirty bits,
+    /// or the error of `Instance::snapshot()`.
+    pub fn track(&mut self, instance: &Instance) -> Result<Snapshot, RuntimeError> {
+        let mut clears = CLEARS.lock().unwrap_or_else(PoisonError::into_inner);
+        clear(&mut clears)?;
+        self.clears = Some(*clears);
+        self.memories = instance
+            .memories()
+            .map(|memory| memory.base_address() as usize)
+            .collect();
+        drop(clears);
+
+        instance.snapshot()
+    }
+
+    /// capture changes of the instance since the previous `track()` or `snapshot()`.
+    /// All pages are captured if it is the first time the tracker sees the instance
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::WasmFileFSError` if failed to access soft-dirty bits,
+    /// or the error of `Instance::snapshot()`.
+    pub fn snapshot(&mut self, instance: &Instance) -> Result<SnapshotDiff, RuntimeError> {
+        let memories = instance.memories().collect::<Vec<_>>();
+        let bases = memories
+            .iter()
+            .map(|memory| memory.base_address() as usize)
+            .collect::<Vec<usize>>();
+
+        let mut clears = CLEARS.lock().unwrap_or_else(PoisonError::into_inner);
+        let tracked = self.clears == Some(*clears) && self.memories == bases;
+
+        // ranges of each memory to capture, in (offset, end)
+        let ranges = memories
+            .iter()
+            .zip(&bases)
+            .map(|(memory, base)| {
+                let size = memory.data_size();
+                match tracked && !memory.is_shared() {
+                    true => dirty_ranges(*base, size),
+                    false => Ok(vec![(0, size)]),
+                }
+            })
+            .collect::<io::Result<Vec<Vec<(u64, u64)>>>>()?;
+
+        clear(&mut clears)?;
+        self.clears = Some(*clears);
+        self.memories = bases;
+        drop(clears);
+
+        let memories = memories
+            .iter()
+            .zip(ranges)
+            .map(|(memory, ranges)| {
+                let pages = ranges
+                    .into_iter()
+                    .map(|(offset, end)| {
+                        let mut content = vec![0u8; (end - offset) as usize];
+                        memory.read(offset, &mut content).map(|_| (offset, content))
+                    })
+                    .collect::<Result<Vec<(u64, Vec<u8>)>, RuntimeError>>()?;
+
+                Ok(MemoryDiff {
+                    size: memory.data_size(),
+                    pages,
+                })
+            })
+            .collect::<Result<Vec<MemoryDiff>, RuntimeError>>()?;
+
+        SnapshotDiff::capture(instance, memories)
+    }
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+fn clear(clears: &mut u64) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open("/proc/self/clear_refs")?
+        .write_all(b"4")?;
+    *clears += 1;
+    Ok(())
+}
+
+// soft-dirty bits of pages in [address, address + len)
+fn soft_dirty_bits(address: usize, len: usize) -> io::Result<Vec<bool>> {
+    let page_size = page_size();
+    let first = address / page_size;
+    let count = (address + len).div_ceil(page_size) - first;
+
+    let mut entries = vec![0u8; count * 8];
+    File::open("/proc/self/pagemap")?.read_exact_at(&mut entries, (first * 8) as u64)?;
+    Ok(entries
+        .chunks_exact(8)
+        .map(|entry| {
+            u64::from_ne_bytes(entry.try_into().expect("8 bytes")) & PAGEMAP_SOFT_DIRTY != 0
+        })
+        .collect())
+}
+
+// written ranges of a memory, in (offset, end). adjacent pages are merged
+fn dirty_ranges(base: usize, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+
+    let page_size = page_size() as u64;
+    // the memory may start in the middle of a page
+    let skew = base as u64 % page_size;
+
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for (index, dirty) in soft_dirty_bits(base, size as usize)?
+        .into_iter()
+        .enumerate()
+    {
+        if !dirty {
+            continue;
+        }
+
+        let offset = (index as u64 * page_size).saturating_sub(skew);
+        let end = ((index as u64 + 1) * page_size - skew).min(size);
+        match ranges.last_mut() {
+            Some(last) if last.1 == offset => last.1 = end,
+            _ => ranges.push((offset, end)),
+        }
+    }
+    Ok(ranges)
+}
+
+// a kernel without CONFIG_MEM_SOFT_DIRTY accepts clears, but never sets the bits
+fn probe() -> io::Result<bool> {
+    let page_size = page_size();
+    let mut buffer = vec![0u8; page_size * 2];
+    let offset = buffer.as_ptr().align_offset(page_size);
+
+    let mut clears = CLEARS.lock().unwrap_or_else(PoisonError::into_inner);
+    clear(&mut clears)?;
+    unsafe { ptr::write_volatile(buffer.as_mut_ptr().add(offset), 1) };
+    let bits = soft_dirty_bits(buffer.as_ptr() as usize + offset, 1)?;
+    Ok(bits[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{module::Module, runtime::Runtime, value::WasmValue};
+
+    // trackers in parallel tests would clear bits of each other
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_incremental_snapshot() {
+        let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+        // the kernel may be built without soft-dirty bits
+        let Ok(mut tracker) = DirtyPageTracker::new() else {
+            return;
+        };
+
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory (export "memory") 4)
+              (global (export "count") (mut i32) (i32.const 0))
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let mut checkpoint = tracker.track(&instance).unwrap();
+        assert_eq!(checkpoint.memory_size(), 4 * 65536);
+
+        let memory = instance.memory().unwrap();
+        memory.write(0, b"used").unwrap();
+        memory.write_u32_le(3 * 65536, 7).unwrap();
+        instance
+            .global("count")
+            .unwrap()
+            .set(WasmValue::I32(2))
+            .unwrap();
+
+        let diff = tracker.snapshot(&instance).unwrap();
+        assert!(diff.memory_size() >= 8);
+        assert!(diff.memory_size() < 4 * 65536);
+        checkpoint.apply(&diff).unwrap();
+
+        // nothing is written since then
+        assert_eq!(tracker.snapshot(&instance).unwrap().memory_size(), 0);
+
+        let another = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        another.restore(&checkpoint).unwrap();
+        let memory = another.memory().unwrap();
+        let mut buf = [0u8; 4];
+        memory.read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"used");
+        assert_eq!(memory.read_u32_le(3 * 65536).unwrap(), 7);
+        assert_eq!(
+            another.global("count").unwrap().get().unwrap(),
+            WasmValue::I32(2)
+        );
+    }
+
+    #[test]
+    fn test_full_snapshot_after_another_tracker() {
+        let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+        let Ok(mut tracker) = DirtyPageTracker::new() else {
+            return;
+        };
+
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, "(module (memory 1))").unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        // never seen
+        assert_eq!(tracker.snapshot(&instance).unwrap().memory_size(), 65536);
+
+        DirtyPageTracker::new().unwrap().track(&instance).unwrap();
+        assert_eq!(tracker.snapshot(&instance).unwrap().memory_size(), 65536);
+    }
+}
@@ -0,0 +1,220 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! explicit execution environments of instances. create one via `ExecEnv::new()`
+
+use std::{any::Any, ffi::c_void, ptr};
+
+use wamr_sys::{
+    wasm_exec_env_t, wasm_runtime_create_exec_env, wasm_runtime_destroy_exec_env,
+    wasm_runtime_get_user_data, wasm_runtime_set_user_data,
+};
+
+use crate::{instance::Instance, RuntimeError};
+
+type UserData = Box<dyn Any + Send>;
+
+/// an execution environment of an instance, with its own wasm stack. Calls via
+/// `Function::call_with_env()` run on it instead of the one every instance has
+///
+/// It is bound to the thread of the instance, like the instance itself. To run on other
+/// threads, `Instance::spawn()` an instance there and create execution environments of it.
+#[derive(Debug)]
+pub struct ExecEnv<'instance> {
+    exec_env: wasm_exec_env_t,
+    instance: &'instance Instance<'instance>,
+    // boxed to have a stable address as the user data of the execution environment
+    user_data: Option<Box<UserData>>,
+}
+
+impl<'instance> ExecEnv<'instance> {
+    /// create an execution environment of `instance`, with a wasm stack of `stack_size`
+    /// bytes
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::ExecutionError` if failed.
+    pub fn new(
+        instance: &'instance Instance<'instance>,
+        stack_size: u32,
+    ) -> Result<Self, RuntimeError> {
+        let exec_env =
+            unsafe { wasm_runtime_create_exec_env(instance.get_inner_instance(), stack_size) };
+        if exec_env.is_null() {
+            return Err(RuntimeError::ExecutionError(String::from(
+                "create exec env failed",
+            )));
+        }
+
+        Ok(ExecEnv {
+            exec_env,
+            instance,
+            user_data: None,
+        })
+    }
+
+    /// the instance it runs
+    pub fn instance(&self) -> &'instance Instance<'instance> {
+        self.instance
+    }
+
+    pub fn get_inner_exec_env(&self) -> wasm_exec_env_t {
+        self.exec_env
+    }
+
+    /// attach data to calls on the execution environment, like a request id or a deadline.
+    /// It replaces the previous one
+    ///
+    /// It is the user data of the underlying execution environment. Host functions get it
+    /// back via `Caller::user_data()`. Data of the whole instance belongs to
+    /// `InstanceBuilder::host_data()`.
+    pub fn set_user_data<T: Any + Send>(&mut self, data: T) {
+        let user_data: Box<UserData> = Box::new(Box::new(data));
+        let user_data_ptr = &*user_data as *const UserData as *mut c_void;
+        unsafe { wasm_runtime_set_user_data(self.exec_env, user_data_ptr) };
+        self.user_data = Some(user_data);
+    }
+
+    /// the data attached by `set_user_data()`, if it is a `T`
+    pub fn user_data<T: Any>(&self) -> Option<&T> {
+        self.user_data.as_ref()?.downcast_ref::<T>()
+    }
+
+    /// detach the data attached by `set_user_data()`
+    pub fn clear_user_data(&mut self) {
+        unsafe { wasm_runtime_set_user_data(self.exec_env, ptr::null_mut()) };
+        self.user_data = None;
+    }
+
+    // the data attached to `exec_env` by `set_user_data()`, if it is a `T`. The user data
+    // of execution environments is only set by this crate
+    pub(crate) unsafe fn user_data_of<'a, T: Any>(exec_env: wasm_exec_env_t) -> Option<&'a T> {
+        (wasm_runtime_get_user_data(exec_env) as *const UserData)
+            .as_ref()?
+            .downcast_ref::<T>()
+    }
+}
+
+impl Drop for ExecEnv<'_> {
+    fn drop(&mut self) {
+        unsafe { wasm_runtime_destroy_exec_env(self.exec_env) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        function::Function, host_function::Caller, module::Module, runtime::Runtime,
+        trap::TrapKind, value::WasmValue,
+    };
+
+    #[test]
+    fn test_exec_env() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func $depth (export "depth") (param i32) (result i32)
+                (if (result i32) (i32.eqz (local.get 0))
+                  (then (i32.const 0))
+                  (else
+                    (i32.add
+                      (call $depth (i32.sub (local.get 0) (i32.const 1)))
+                      (i32.const 1)
+                    )
+                  )
+                )
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+        let depth = Function::find_export_func(&instance, "depth").unwrap();
+        let params = [WasmValue::I32(1000)];
+
+        // too deep for the stack of the instance
+        assert!(matches!(
+            depth.call_slice(&instance, &params, &mut [WasmValue::Void]),
+            Err(RuntimeError::Trap {
+                kind: TrapKind::StackOverflow,
+                ..
+            })
+        ));
+
+        let exec_env = ExecEnv::new(&instance, 256 * 1024).unwrap();
+        assert_eq!(
+            exec_env.instance().get_inner_instance(),
+            instance.get_inner_instance()
+        );
+        assert_eq!(
+            depth.call_with_env(&exec_env, &params).unwrap(),
+            vec![WasmValue::I32(1000)]
+        );
+
+        // bound to the instance
+        let another = Instance::new(&runtime, &module, 1024).unwrap();
+        let another_env = ExecEnv::new(&another, 64 * 1024).unwrap();
+        assert!(matches!(
+            depth.call_with_env(&another_env, &params),
+            Err(RuntimeError::ExecutionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_exec_env_user_data() {
+        struct Request {
+            id: i32,
+        }
+
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register("test_user_data", "request_id", |caller: &Caller| {
+                caller
+                    .user_data::<Request>()
+                    .map_or(-1, |request| request.id)
+            })
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_user_data" "request_id" (func $request_id (result i32)))
+              (func (export "request_id") (result i32)
+                (call $request_id)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let request_id = Function::find_export_func(&instance, "request_id").unwrap();
+
+        let mut exec_env = ExecEnv::new(&instance, 64 * 1024).unwrap();
+        assert_eq!(
+            request_id.call_with_env(&exec_env, &[]).unwrap(),
+            vec![WasmValue::I32(-1)]
+        );
+
+        exec_env.set_user_data(Request { id: 42 });
+        assert_eq!(exec_env.user_data::<Request>().map(|r| r.id), Some(42));
+        assert!(exec_env.user_data::<String>().is_none());
+        assert_eq!(
+            request_id.call_with_env(&exec_env, &[]).unwrap(),
+            vec![WasmValue::I32(42)]
+        );
+
+        // only on the execution environment it is attached to
+        assert_eq!(
+            request_id.call(&instance, &vec![]).unwrap(),
+            vec![WasmValue::I32(-1)]
+        );
+
+        exec_env.clear_user_data();
+        assert_eq!(
+            request_id.call_with_env(&exec_env, &[]).unwrap(),
+            vec![WasmValue::I32(-1)]
+        );
+    }
+}
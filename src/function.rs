@@ -6,20 +6,68 @@
 //! an exported wasm function.
 //! get one via `Function::find_export_func()`
 
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use wamr_sys::{
+    wasm_exec_env_t, wasm_function_inst_t, wasm_module_inst_t, wasm_runtime_call_wasm,
+    wasm_runtime_clear_exception, wasm_runtime_get_exception, wasm_runtime_get_exec_env_singleton,
+    wasm_runtime_lookup_function, wasm_runtime_terminate,
+};
+#[cfg(feature = "instruction-metering")]
 use wamr_sys::{
-    wasm_exec_env_t, wasm_func_get_result_count, wasm_func_get_result_types, wasm_function_inst_t,
-    wasm_runtime_call_wasm, wasm_runtime_get_exception, wasm_runtime_get_exec_env_singleton,
-    wasm_runtime_lookup_function, wasm_valkind_enum_WASM_F32, wasm_valkind_enum_WASM_F64,
-    wasm_valkind_enum_WASM_I32, wasm_valkind_enum_WASM_I64, wasm_valkind_t,
+    wasm_runtime_get_instruction_count_limit, wasm_runtime_set_instruction_count_limit,
 };
 
-use crate::{helper::exception_to_string, instance::Instance, value::WasmValue, RuntimeError};
+use crate::{
+    asyncify::Asyncify,
+    exec_env::ExecEnv,
+    helper::exception_to_string,
+    host_function::running_exec_env,
+    instance::Instance,
+    trap::TrapKind,
+    types::{FuncType, ValType},
+    value::{WasmType, WasmTypes, WasmValue},
+    RuntimeError,
+};
 
+/// a resolved function, bound to the instance it's found in.
+/// It's cheap to clone, so keep one instead of looking it up by name on every call
+#[derive(Debug, Clone)]
 pub struct Function {
     function: wasm_function_inst_t,
+    ty: FuncType,
+    // the id of the instance, not its address which is reused after it's destroyed
+    instance: u64,
+    // shared by clones
+    name: Option<Arc<str>>,
+}
+
+/// a call of a wasm function from the host, or of a host function from wasm.
+/// See `InstanceBuilder::on_call_start()` and `InstanceBuilder::on_host_call()`
+#[derive(Debug)]
+pub struct CallEvent<'a> {
+    /// the export name, or the name in the `name` section. `None` if unknown. The import
+    /// name of a host function
+    pub name: Option<&'a str>,
+    pub params: &'a [WasmValue],
+}
+
+/// how a call ends. See `InstanceBuilder::on_call_end()`
+#[derive(Debug)]
+pub struct CallOutcome<'a> {
+    pub duration: Duration,
+    /// results, or the error
+    pub result: Result<&'a [WasmValue], &'a RuntimeError>,
 }
 
+// the function instance is immutable, and calling it requires the `Instance` anyway
+unsafe impl Send for Function {}
+unsafe impl Sync for Function {}
+
 impl Function {
     /// find a function by name
     ///
@@ -32,76 +80,658 @@ impl Function {
             unsafe { wasm_runtime_lookup_function(instance.get_inner_instance(), name.as_ptr()) };
         match function.is_null() {
             true => Err(RuntimeError::FunctionNotFound),
-            false => Ok(Function { function }),
+            false => {
+                Ok(Function::from_raw(function, instance)
+                    .with_name(name.to_str().unwrap_or_default()))
+            }
+        }
+    }
+
+    pub(crate) fn from_raw(function: wasm_function_inst_t, instance: &Instance) -> Function {
+        Function {
+            function,
+            ty: FuncType::from_function(function, instance.get_inner_instance()),
+            instance: instance.id(),
+            name: None,
+        }
+    }
+
+    pub(crate) fn with_name(mut self, name: &str) -> Function {
+        self.name = Some(Arc::from(name));
+        self
+    }
+
+    /// the export name it is found by, or the name in the `name` section. `None` if unknown
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// types of parameters and results of the function
+    pub fn signature(&self) -> &FuncType {
+        &self.ty
+    }
+
+    /// execute an export function.
+    /// all parameters need to be wrapped in `WasmValue`.
+    /// It returns all results of the function in order, or nothing if the function returns
+    /// nothing
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::ArgumentMismatch` if the count or types of `params` don't
+    /// match the signature, before running anything.
+    /// Return `RuntimeError::Trap` if it traps, or `RuntimeError::Cancelled` if it is
+    /// cancelled by a `CancelHandle`. Return `RuntimeError::ExecutionError` if failed
+    /// otherwise, or `instance` isn't the one the function is found in. Return
+    /// `RuntimeError::InvalidFuncRef` if a `funcref` parameter comes from another instance.
+    // `&Vec` is kept for compatibility. `call_slice()` takes a slice
+    #[allow(clippy::ptr_arg)]
+    pub fn call(
+        &self,
+        instance: &Instance,
+        params: &Vec<WasmValue>,
+    ) -> Result<Vec<WasmValue>, RuntimeError> {
+        let mut results = vec![WasmValue::Void; self.ty.results().len()];
+        self.call_slice(instance, params, &mut results)?;
+        Ok(results)
+    }
+
+    /// call a function following the errno convention: it returns an `i32`, which is a
+    /// value if non-negative, or an error code if negative. return the value
+    ///
+    /// On an error code, if the guest exports `last_error: () -> i64`, it is called for
+    /// a UTF-8 message in the default memory, as `ptr << 32 | len`.
+    ///
+    /// ```ignore
+    /// match read.call_checked(&instance, (fd, buf, len)) {
+    ///     Ok(read) => println!("{} bytes", read),
+    ///     Err(RuntimeError::GuestError { code, message }) => eprintln!("{}: {:?}", code, message),
+    ///     Err(e) => return Err(e),
+    /// }
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::GuestError` for an error code. Others are the same
+    /// as `call_typed()`.
+    pub fn call_checked<Params>(
+        &self,
+        instance: &Instance,
+        params: Params,
+    ) -> Result<i32, RuntimeError>
+    where
+        Params: WasmTypes,
+    {
+        let value: i32 = self.call_typed(instance, params)?;
+        if value >= 0 {
+            return Ok(value);
         }
+
+        Err(RuntimeError::GuestError {
+            code: value,
+            message: last_error(instance),
+        })
     }
 
-    #[allow(non_upper_case_globals)]
-    fn parse_result(
+    /// the same as `call()`, but the call is aborted once it runs longer than `timeout`
+    ///
+    /// A watchdog thread terminates the running instance via `wasm_runtime_terminate()`.
+    /// The guest is interrupted wherever it is, so its state may be inconsistent after a
+    /// timeout. Consider `Instance::reset()` then.
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::Timeout` if it runs out of time. Others are the same as `call()`.
+    pub fn call_with_timeout(
         &self,
         instance: &Instance,
-        result: Vec<u32>,
-    ) -> Result<WasmValue, RuntimeError> {
-        let result_count =
-            unsafe { wasm_func_get_result_count(self.function, instance.get_inner_instance()) };
-        if result_count == 0 {
-            return Ok(WasmValue::Void);
+        params: &[WasmValue],
+        timeout: Duration,
+    ) -> Result<Vec<WasmValue>, RuntimeError> {
+        let watchdog = Watchdog::start(instance.get_inner_instance(), timeout);
+        let mut results = vec![WasmValue::Void; self.ty.results().len()];
+        let result = self.call_slice(instance, params, &mut results);
+        if !watchdog.stop() {
+            return result.map(|_| results);
         }
 
-        let mut result_type: wasm_valkind_t = 0;
-        unsafe {
-            wasm_func_get_result_types(
-                self.function,
-                instance.get_inner_instance(),
-                &mut result_type,
-            );
+        // terminating leaves an exception, even if the call has just finished in time
+        unsafe { wasm_runtime_clear_exception(instance.get_inner_instance()) };
+        match result {
+            Ok(()) => Ok(results),
+            Err(_) => Err(RuntimeError::Timeout(timeout)),
         }
+    }
 
-        match result_type as u32 {
-            wasm_valkind_enum_WASM_I32 => Ok(WasmValue::decode_to_i32(result)),
-            wasm_valkind_enum_WASM_I64 => Ok(WasmValue::decode_to_i64(result)),
-            wasm_valkind_enum_WASM_F32 => Ok(WasmValue::decode_to_f32(result)),
-            wasm_valkind_enum_WASM_F64 => Ok(WasmValue::decode_to_f64(result)),
-            _ => Err(RuntimeError::NotImplemented),
+    /// the same as `call()`, but the call is aborted once it has executed `fuel` wasm
+    /// instructions. Return the results with the fuel consumed. It needs the
+    /// `instruction-metering` feature, and WAMR meters in the interpreters only. `fuel` is
+    /// capped at `i32::MAX`
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::OutOfFuel` if it runs out of fuel. Others are the same as `call()`.
+    #[cfg(feature = "instruction-metering")]
+    pub fn call_metered(
+        &self,
+        instance: &Instance,
+        params: &[WasmValue],
+        fuel: u32,
+    ) -> Result<(Vec<WasmValue>, u32), RuntimeError> {
+        let exec_env =
+            unsafe { wasm_runtime_get_exec_env_singleton(instance.get_inner_instance()) };
+        let limit = fuel.min(i32::MAX as u32);
+        unsafe { wasm_runtime_set_instruction_count_limit(exec_env, limit as i32) };
+        let mut results = vec![WasmValue::Void; self.ty.results().len()];
+        let result = self.call_slice(instance, params, &mut results);
+        // what is left of the limit, counted down by WAMR
+        let left = unsafe { wasm_runtime_get_instruction_count_limit(exec_env) };
+        // -1 for no limit, as other calls expect
+        unsafe { wasm_runtime_set_instruction_count_limit(exec_env, -1) };
+
+        match result {
+            Ok(()) => Ok((results, limit - left.clamp(0, limit as i32) as u32)),
+            Err(RuntimeError::ExecutionError(message))
+                if message.contains("instruction limit exceeded") =>
+            {
+                Err(RuntimeError::OutOfFuel(fuel))
+            }
+            Err(e) => Err(e),
         }
     }
 
-    /// execute an export function.
-    /// all parameters need to be wrapped in `WasmValue`
+    /// execute an export function without allocating on the heap.
+    /// results are written into `results`, which should be as long as `signature().results()`.
+    /// suits hot paths calling the same function repeatedly
     ///
     /// # Error
     ///
-    /// Return `RuntimeError::ExecutionError` if failed.
-    pub fn call(
+    /// Return `RuntimeError::SignatureMismatch` if `results` is of another length.
+    /// Otherwise the same as `call()`.
+    pub fn call_slice(
         &self,
         instance: &Instance,
-        params: &Vec<WasmValue>,
-    ) -> Result<WasmValue, RuntimeError> {
-        // params -> Vec<u32>
-        let mut argv = Vec::new();
+        params: &[WasmValue],
+        results: &mut [WasmValue],
+    ) -> Result<(), RuntimeError> {
+        self.call_in(instance, None, params, results)
+    }
+
+    /// the same as `call()`, but async host functions it reaches suspend the guest while
+    /// their futures are awaited, instead of blocking the thread. See `asyncify`
+    ///
+    /// The module should be asyncified. A buffer of `ASYNCIFY_STACK_SIZE` bytes is
+    /// allocated from the instance for the call. The future borrows the instance, so it is
+    /// polled on the thread of the instance, like by a local executor. Call hooks see one
+    /// call per suspension.
+    ///
+    /// ```ignore
+    /// let status = fetch.call_async(&instance, &[WasmValue::I32(url)]).await?;
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::ExecutionError` if the module is not asyncified, or the buffer
+    /// fails to allocate. Otherwise the same as `call()`.
+    pub async fn call_async(
+        &self,
+        instance: &Instance<'_>,
+        params: &[WasmValue],
+    ) -> Result<Vec<WasmValue>, RuntimeError> {
+        // the same lifetime for `Asyncify`
+        let instance: &Instance = instance;
+        let asyncify = Asyncify::new(instance)?;
+        let mut results = vec![WasmValue::Void; self.ty.results().len()];
+        let mut resumed = None;
+
+        loop {
+            let (result, pending) = asyncify.enter(resumed.take(), || {
+                self.call_slice(instance, params, &mut results)
+            });
+            let Some(pending) = pending else {
+                return result.map(|_| results);
+            };
+            result?;
+
+            asyncify.stop_unwind()?;
+            resumed = Some(pending.await);
+            asyncify.start_rewind()?;
+        }
+    }
+
+    /// the same as `call()`, but runs on `exec_env` instead of the execution environment
+    /// of the instance. See `ExecEnv`
+    ///
+    /// # Error
+    ///
+    /// The same as `call()`, with the instance of `exec_env`.
+    pub fn call_with_env(
+        &self,
+        exec_env: &ExecEnv,
+        params: &[WasmValue],
+    ) -> Result<Vec<WasmValue>, RuntimeError> {
+        let mut results = vec![WasmValue::Void; self.ty.results().len()];
+        self.call_in(
+            exec_env.instance(),
+            Some(exec_env.get_inner_exec_env()),
+            params,
+            &mut results,
+        )?;
+        Ok(results)
+    }
+
+    /// call the function with each item of `args` in turn, and yield results in the
+    /// same order. Calls run lazily, as the iterator advances
+    ///
+    /// The execution environment is looked up once, and arguments are encoded on the stack,
+    /// so only the results of each call are allocated. It suits calling the same function
+    /// a great many times
+    ///
+    /// ```ignore
+    /// let inputs = (0..1_000_000).map(|i| [WasmValue::I32(i)]);
+    /// for results in function.call_batch(&instance, inputs) {
+    ///     let results = results?;
+    /// }
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// Each item is the same as `call()`. A failed call doesn't stop later ones
+    pub fn call_batch<'a, I>(
+        &'a self,
+        instance: &'a Instance<'a>,
+        args: I,
+    ) -> CallBatch<'a, I::IntoIter>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[WasmValue]>,
+    {
+        CallBatch {
+            function: self,
+            instance,
+            exec_env: unsafe { wasm_runtime_get_exec_env_singleton(instance.get_inner_instance()) },
+            args: args.into_iter(),
+        }
+    }
+
+    // on the singleton execution environment of `instance` if `exec_env` is `None`
+    fn call_in(
+        &self,
+        instance: &Instance,
+        exec_env: Option<wasm_exec_env_t>,
+        params: &[WasmValue],
+        results: &mut [WasmValue],
+    ) -> Result<(), RuntimeError> {
+        if !instance.has_call_hooks() {
+            return self.call_unhooked(instance, exec_env, params, results);
+        }
+
+        let event = CallEvent {
+            name: self.name(),
+            params,
+        };
+        instance.report_call_start(&event);
+        let started = Instant::now();
+        let result = self.call_unhooked(instance, exec_env, params, results);
+        let outcome = CallOutcome {
+            duration: started.elapsed(),
+            result: result.as_ref().map(|_| &*results),
+        };
+        instance.report_call_end(&event, &outcome);
+        result
+    }
+
+    fn call_unhooked(
+        &self,
+        instance: &Instance,
+        exec_env: Option<wasm_exec_env_t>,
+        params: &[WasmValue],
+        results: &mut [WasmValue],
+    ) -> Result<(), RuntimeError> {
+        if instance.id() != self.instance {
+            return Err(RuntimeError::ExecutionError(String::from(
+                "function of another instance, or found before a reset",
+            )));
+        }
+
+        if instance.take_cancelled() {
+            return Err(RuntimeError::Cancelled);
+        }
+
+        let ty = &self.ty;
+        let matched = params.len() == ty.params().len()
+            && params
+                .iter()
+                .zip(ty.params())
+                .all(|(p, ty)| p.ty() == Some(*ty));
+        if !matched {
+            return Err(RuntimeError::ArgumentMismatch {
+                expected: ty.params().to_vec(),
+                got: params.to_vec(),
+            });
+        }
+        if results.len() != ty.results().len() {
+            return Err(RuntimeError::SignatureMismatch(format!(
+                "expect {} results, but got a buffer of {}",
+                ty.results().len(),
+                results.len()
+            )));
+        }
+        ty.check_results_known()?;
+
+        // in cells, each of which is 32 bits
+        let argc: usize = params.iter().map(value_cell_count).sum();
+        // results are written back to argv
+        let result_cells = ty.results().iter().map(|result| cell_count(*result)).sum();
+        let cells = argc.max(result_cells);
+
+        // only an unusually long signature spills to the heap
+        let mut inline = [0u32; INLINE_CELLS];
+        let mut spilled = Vec::new();
+        let argv = if cells <= INLINE_CELLS {
+            &mut inline[..cells]
+        } else {
+            spilled.resize(cells, 0);
+            &mut spilled[..]
+        };
+
+        // params -> cells
+        let mut offset = 0;
         for p in params {
-            argv.append(&mut p.encode());
+            if let WasmValue::FuncRef(Some(funcref)) = p {
+                funcref.check_instance(instance.get_inner_instance())?;
+            }
+            offset += p.encode_into(&mut argv[offset..]);
         }
 
-        let argc = params.len();
-        let call_result: bool;
-        unsafe {
-            let exec_env: wasm_exec_env_t =
-                wasm_runtime_get_exec_env_singleton(instance.get_inner_instance());
-            call_result =
-                wasm_runtime_call_wasm(exec_env, self.function, argc as u32, argv.as_mut_ptr());
+        let exec_env = exec_env.unwrap_or_else(|| unsafe {
+            wasm_runtime_get_exec_env_singleton(instance.get_inner_instance())
+        });
+        match running_exec_env(instance.get_inner_instance()) {
+            Some(running) if running != exec_env => {
+                return Err(RuntimeError::IllegalReentrancy(String::from(
+                    "the instance is running a host function on another execution environment, \
+                     call it back via Caller::call()",
+                )));
+            }
+            Some(_) => {}
+            None => instance.reset_host_quotas(),
+        }
+        let call_result = unsafe {
+            wasm_runtime_call_wasm(exec_env, self.function, argc as u32, argv.as_mut_ptr())
         };
 
+        // a cancellation after the call has finished only leaves an exception
+        let cancelled = instance.take_cancelled();
         if !call_result {
-            unsafe {
-                let exception_c = wasm_runtime_get_exception(instance.get_inner_instance());
-                return Err(RuntimeError::ExecutionError(exception_to_string(
-                    exception_c,
-                )));
+            if cancelled {
+                return Err(RuntimeError::Cancelled);
             }
+            if let Some(error) = instance.take_memory_limit_error() {
+                return Err(error);
+            }
+
+            let exception_c = unsafe { wasm_runtime_get_exception(instance.get_inner_instance()) };
+            let mut message = exception_to_string(exception_c);
+            let kind = TrapKind::from_exception(&message);
+            let mut frames = Vec::new();
+            if let Some((call_stack, call_stack_frames)) = instance.dump_call_stack(exec_env) {
+                message = format!("{}\n{}", message, call_stack);
+                frames = call_stack_frames;
+            }
+            return Err(match kind {
+                Some(kind) => RuntimeError::Trap {
+                    kind,
+                    message,
+                    frames,
+                },
+                None => RuntimeError::ExecutionError(message),
+            });
         }
 
-        self.parse_result(instance, argv)
+        parse_results(ty.results(), argv, instance, results);
+        if instance.canonicalizes_result_nans() {
+            results.iter_mut().for_each(WasmValue::canonicalize_nan);
+        }
+        Ok(())
+    }
+
+    /// execute an export function with parameters and results in Rust types.
+    /// both are `()`, a scalar, or a tuple of scalars
+    ///
+    /// ```ignore
+    /// let sum: i32 = add.call_typed(&instance, (3, 6))?;
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::ArgumentMismatch` if `Params` don't match the parameters,
+    /// `RuntimeError::SignatureMismatch` if `Results` don't match the results. Others are
+    /// the same as `call()`.
+    pub fn call_typed<Params, Results>(
+        &self,
+        instance: &Instance,
+        params: Params,
+    ) -> Result<Results, RuntimeError>
+    where
+        Params: WasmTypes,
+        Results: WasmTypes,
+    {
+        let ty = &self.ty;
+        let params = params.into_values();
+        if ty.params() != Params::types() {
+            return Err(RuntimeError::ArgumentMismatch {
+                expected: ty.params().to_vec(),
+                got: params,
+            });
+        }
+        if ty.results() != Results::types() {
+            return Err(RuntimeError::SignatureMismatch(format!(
+                "expect results {:?}, but got {:?}",
+                ty.results(),
+                Results::types()
+            )));
+        }
+
+        let results = self.call(instance, &params)?;
+        Results::try_from_values(results)
+    }
+}
+
+/// results of `Function::call_batch()`, one item per call
+#[derive(Debug)]
+pub struct CallBatch<'a, I> {
+    function: &'a Function,
+    instance: &'a Instance<'a>,
+    exec_env: wasm_exec_env_t,
+    args: I,
+}
+
+impl<I> Iterator for CallBatch<'_, I>
+where
+    I: Iterator,
+    I::Item: AsRef<[WasmValue]>,
+{
+    type Item = Result<Vec<WasmValue>, RuntimeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let params = self.args.next()?;
+        let mut results = vec![WasmValue::Void; self.function.ty.results().len()];
+        let result = self.function.call_in(
+            self.instance,
+            Some(self.exec_env),
+            params.as_ref(),
+            &mut results,
+        );
+        Some(result.map(|()| results))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.args.size_hint()
+    }
+}
+
+/// The builder of a call to an export function. Get one via `Instance::call()`
+///
+/// ```ignore
+/// let results = instance.call("mix").arg(1i32).arg(2.5f64).invoke()?;
+/// ```
+pub struct CallBuilder<'instance> {
+    instance: &'instance Instance<'instance>,
+    name: String,
+    params: Vec<WasmValue>,
+    types: Vec<ValType>,
+}
+
+impl<'instance> CallBuilder<'instance> {
+    pub(crate) fn new(instance: &'instance Instance<'instance>, name: &str) -> Self {
+        CallBuilder {
+            instance,
+            name: String::from(name),
+            params: Vec::new(),
+            types: Vec::new(),
+        }
+    }
+
+    /// append a parameter
+    pub fn arg<T: WasmType>(mut self, arg: T) -> Self {
+        self.params.push(arg.into_value());
+        self.types.push(T::TYPE);
+        self
+    }
+
+    /// append parameters. `()`, a scalar, or a tuple of scalars
+    pub fn args<T: WasmTypes>(mut self, args: T) -> Self {
+        self.params.append(&mut args.into_values());
+        self.types.append(&mut T::types());
+        self
+    }
+
+    /// look up the function, check parameters against its signature, and call it.
+    /// It returns all results
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::FunctionNotFound` if there is no such export function,
+    /// `RuntimeError::ArgumentMismatch` if the count or types of parameters don't match.
+    /// Others are the same as `Function::call()`.
+    pub fn invoke(self) -> Result<Vec<WasmValue>, RuntimeError> {
+        let function = self.function()?;
+        function.call(self.instance, &self.params)
+    }
+
+    /// the same as `invoke()`, with results in Rust types. See `Function::call_typed()`
+    ///
+    /// # Error
+    ///
+    /// The same as `invoke()`, and `RuntimeError::SignatureMismatch` if `Results` doesn't
+    /// match.
+    pub fn invoke_typed<Results: WasmTypes>(self) -> Result<Results, RuntimeError> {
+        let results = self.invoke()?;
+        Results::try_from_values(results)
+    }
+
+    fn function(&self) -> Result<Function, RuntimeError> {
+        let function = Function::find_export_func(self.instance, &self.name)?;
+        if function.signature().params() != self.types {
+            return Err(RuntimeError::ArgumentMismatch {
+                expected: function.signature().params().to_vec(),
+                got: self.params.clone(),
+            });
+        }
+        Ok(function)
+    }
+}
+
+// the message of `last_error()` of the guest, if any. See `Function::call_checked()`
+fn last_error(instance: &Instance) -> Option<String> {
+    let last_error = Function::find_export_func(instance, "last_error").ok()?;
+    let packed: i64 = last_error.call_typed(instance, ()).ok()?;
+
+    let packed = packed as u64;
+    let memory = instance.memory()?;
+    memory.read_str(packed >> 32, packed & 0xFFFF_FFFF).ok()
+}
+
+// terminates an instance once `timeout` passes, unless stopped before
+struct Watchdog {
+    // (stopped, fired)
+    state: Arc<(Mutex<(bool, bool)>, Condvar)>,
+    thread: JoinHandle<()>,
+}
+
+impl Watchdog {
+    fn start(instance: wasm_module_inst_t, timeout: Duration) -> Self {
+        let state = Arc::new((Mutex::new((false, false)), Condvar::new()));
+
+        // the instance is only passed to `wasm_runtime_terminate()`, which is thread-safe
+        let instance = instance as usize;
+        let thread = thread::spawn({
+            let state = Arc::clone(&state);
+            move || {
+                let (lock, stopped) = &*state;
+                let guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+                let (mut guard, _) = stopped
+                    .wait_timeout_while(guard, timeout, |(stopped, _)| !*stopped)
+                    .unwrap_or_else(PoisonError::into_inner);
+                if !guard.0 {
+                    unsafe { wasm_runtime_terminate(instance as wasm_module_inst_t) };
+                    guard.1 = true;
+                }
+            }
+        });
+
+        Watchdog { state, thread }
+    }
+
+    // whether it has fired
+    fn stop(self) -> bool {
+        let (lock, stopped) = &*self.state;
+        lock.lock().unwrap_or_else(PoisonError::into_inner).0 = true;
+        stopped.notify_one();
+        let _ = self.thread.join();
+
+        let fired = lock.lock().unwrap_or_else(PoisonError::into_inner).1;
+        fired
+    }
+}
+
+// argv of most signatures fits on the stack
+const INLINE_CELLS: usize = 32;
+
+// how many 32-bit cells a value takes in arguments of `wasm_runtime_call_wasm()`
+// references are indexes without GC
+pub(crate) fn cell_count(ty: ValType) -> usize {
+    match ty {
+        ValType::I32 | ValType::F32 | ValType::ExternRef | ValType::FuncRef => 1,
+        ValType::I64 | ValType::F64 => 2,
+        ValType::V128 => 4,
+        // rejected before calls
+        ValType::Unknown(_) => 0,
+    }
+}
+
+pub(crate) fn value_cell_count(value: &WasmValue) -> usize {
+    match value {
+        WasmValue::Void => 0,
+        WasmValue::I32(_) | WasmValue::F32(_) | WasmValue::ExternRef(_) | WasmValue::FuncRef(_) => {
+            1
+        }
+        WasmValue::I64(_) | WasmValue::F64(_) => 2,
+        WasmValue::V128(_) => 4,
+    }
+}
+
+// cells -> results
+fn parse_results(
+    types: &[ValType],
+    mut cells: &[u32],
+    instance: &Instance,
+    results: &mut [WasmValue],
+) {
+    for (ty, result) in types.iter().zip(results.iter_mut()) {
+        *result = WasmValue::decode_from(*ty, cells, instance.get_inner_instance());
+        cells = &cells[cell_count(*ty)..];
     }
 }
 
@@ -144,12 +774,603 @@ mod tests {
         let params: Vec<WasmValue> = vec![WasmValue::I32(3), WasmValue::I32(6)];
         let call_result = function.call(instance, &params);
         assert!(call_result.is_ok());
-        assert_eq!(call_result.unwrap(), WasmValue::I32(9));
+        assert_eq!(call_result.unwrap(), vec![WasmValue::I32(9)]);
 
         let params: Vec<WasmValue> = vec![WasmValue::I32(128), WasmValue::I32(256)];
         let call_result = function.call(instance, &params);
         assert!(call_result.is_ok());
-        assert_eq!(call_result.unwrap(), WasmValue::I32(384));
+        assert_eq!(call_result.unwrap(), vec![WasmValue::I32(384)]);
+    }
+
+    #[test]
+    fn test_func_call_typed() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+              (func (export "widen") (param i32 f32) (result i64)
+                (i64.extend_i32_s (i32.add (local.get 0) (i32.trunc_f32_s (local.get 1))))
+              )
+              (func (export "nop"))
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        let add = Function::find_export_func(&instance, "add").unwrap();
+        let sum: i32 = add.call_typed(&instance, (3, 6)).unwrap();
+        assert_eq!(sum, 9);
+        let (sum,): (i32,) = add.call_typed(&instance, (128, 256)).unwrap();
+        assert_eq!(sum, 384);
+
+        let widen = Function::find_export_func(&instance, "widen").unwrap();
+        let widened: i64 = widen.call_typed(&instance, (-5, 2.5f32)).unwrap();
+        assert_eq!(widened, -3);
+
+        let nop = Function::find_export_func(&instance, "nop").unwrap();
+        nop.call_typed::<(), ()>(&instance, ()).unwrap();
+
+        // arity or types don't match
+        assert!(matches!(
+            add.call_typed::<(i32,), i32>(&instance, (3,)),
+            Err(RuntimeError::ArgumentMismatch { got, .. }) if got == vec![WasmValue::I32(3)]
+        ));
+        assert!(matches!(
+            add.call_typed::<(i32, i32), i64>(&instance, (3, 6)),
+            Err(RuntimeError::SignatureMismatch(_))
+        ));
+        assert!(matches!(
+            widen.call_typed::<(i32, f64), i64>(&instance, (1, 2.0)),
+            Err(RuntimeError::ArgumentMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_func_multi_value() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "divmod") (param i32 i32) (result i32 i32)
+                (i32.div_u (local.get 0) (local.get 1))
+                (i32.rem_u (local.get 0) (local.get 1))
+              )
+              (func (export "spread") (param i32) (result i64 f64 i32 f32)
+                (i64.extend_i32_s (local.get 0))
+                (f64.convert_i32_s (local.get 0))
+                (local.get 0)
+                (f32.convert_i32_s (local.get 0))
+              )
+              (func (export "nop"))
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        let divmod = Function::find_export_func(&instance, "divmod").unwrap();
+        let params = vec![WasmValue::I32(17), WasmValue::I32(5)];
+        assert_eq!(
+            divmod.call(&instance, &params).unwrap(),
+            vec![WasmValue::I32(3), WasmValue::I32(2)]
+        );
+        let (quotient, remainder): (i32, i32) = divmod.call_typed(&instance, (17, 5)).unwrap();
+        assert_eq!((quotient, remainder), (3, 2));
+
+        // results take more cells than params
+        let spread = Function::find_export_func(&instance, "spread").unwrap();
+        assert_eq!(
+            spread.call(&instance, &vec![WasmValue::I32(-2)]).unwrap(),
+            vec![
+                WasmValue::I64(-2),
+                WasmValue::F64(-2.0),
+                WasmValue::I32(-2),
+                WasmValue::F32(-2.0)
+            ]
+        );
+        let spread: (i64, f64, i32, f32) = spread.call_typed(&instance, 7).unwrap();
+        assert_eq!(spread, (7, 7.0, 7, 7.0));
+
+        let nop = Function::find_export_func(&instance, "nop").unwrap();
+        assert_eq!(nop.call(&instance, &vec![]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_func_signature() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "mix") (param i32 f64 i64) (result f32 i32)
+                (f32.const 1)
+                (local.get 0)
+              )
+              (func (export "nop"))
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        let mix = Function::find_export_func(&instance, "mix").unwrap();
+        assert_eq!(
+            mix.signature().params(),
+            &[ValType::I32, ValType::F64, ValType::I64]
+        );
+        assert_eq!(mix.signature().results(), &[ValType::F32, ValType::I32]);
+
+        let nop = Function::find_export_func(&instance, "nop").unwrap();
+        assert_eq!(nop.signature(), &FuncType::new(vec![], vec![]));
+    }
+
+    #[test]
+    fn test_func_v128() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "add") (param v128 v128) (result v128)
+                (i32x4.add (local.get 0) (local.get 1))
+              )
+              (func (export "splat") (param i32) (result v128 i32)
+                (i8x16.splat (local.get 0))
+                (local.get 0)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        let mut a = [0u8; 16];
+        let mut b = [0u8; 16];
+        let mut sum = [0u8; 16];
+        for lane in 0..4 {
+            let x = lane as u32 * 1000;
+            let y = lane as u32 + 1;
+            a[lane * 4..lane * 4 + 4].copy_from_slice(&x.to_le_bytes());
+            b[lane * 4..lane * 4 + 4].copy_from_slice(&y.to_le_bytes());
+            sum[lane * 4..lane * 4 + 4].copy_from_slice(&(x + y).to_le_bytes());
+        }
+
+        let add = Function::find_export_func(&instance, "add").unwrap();
+        let params = vec![WasmValue::V128(a), WasmValue::V128(b)];
+        assert_eq!(
+            add.call(&instance, &params).unwrap(),
+            vec![WasmValue::V128(sum)]
+        );
+
+        let splat = Function::find_export_func(&instance, "splat").unwrap();
+        let result: ([u8; 16], i32) = splat.call_typed(&instance, 7).unwrap();
+        assert_eq!(result, ([7; 16], 7));
+    }
+
+    #[test]
+    fn test_call_builder() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "mix") (param i32 f64) (result f64 i32)
+                (f64.add (f64.convert_i32_s (local.get 0)) (local.get 1))
+                (local.get 0)
+              )
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        assert_eq!(
+            instance.call("mix").arg(1i32).arg(2.5f64).invoke().unwrap(),
+            vec![WasmValue::F64(3.5), WasmValue::I32(1)]
+        );
+        let sum: i32 = instance.call("add").args((3, 6)).invoke_typed().unwrap();
+        assert_eq!(sum, 9);
+
+        // count or types don't match
+        assert!(matches!(
+            instance.call("add").arg(1i32).invoke(),
+            Err(RuntimeError::ArgumentMismatch { expected, .. })
+                if expected == vec![ValType::I32, ValType::I32]
+        ));
+        assert!(matches!(
+            instance.call("mix").arg(1i32).arg(2.5f32).invoke(),
+            Err(RuntimeError::ArgumentMismatch { .. })
+        ));
+        assert!(matches!(
+            instance.call("add").args((1, 2)).invoke_typed::<i64>(),
+            Err(RuntimeError::SignatureMismatch(_))
+        ));
+        assert!(matches!(
+            instance.call("unknown").invoke(),
+            Err(RuntimeError::FunctionNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_call_slice() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "swap") (param i64 f32) (result f32 i64)
+                (local.get 1)
+                (local.get 0)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+        let function = Function::find_export_func(&instance, "swap").unwrap();
+
+        let mut results = [WasmValue::Void, WasmValue::Void];
+        for i in 0..3 {
+            function
+                .call_slice(
+                    &instance,
+                    &[WasmValue::I64(i << 40), WasmValue::F32(i as f32)],
+                    &mut results,
+                )
+                .unwrap();
+            assert_eq!(results, [WasmValue::F32(i as f32), WasmValue::I64(i << 40)]);
+        }
+
+        // arguments don't match
+        match function.call_slice(&instance, &[WasmValue::I64(0)], &mut results) {
+            Err(RuntimeError::ArgumentMismatch { expected, got }) => {
+                assert_eq!(expected, vec![ValType::I64, ValType::F32]);
+                assert_eq!(got, vec![WasmValue::I64(0)]);
+            }
+            _ => panic!("expect an argument mismatch"),
+        }
+        assert!(matches!(
+            function.call_slice(
+                &instance,
+                &[WasmValue::I64(0), WasmValue::F64(0.0)],
+                &mut results
+            ),
+            Err(RuntimeError::ArgumentMismatch { .. })
+        ));
+
+        // the buffer doesn't fit the results
+        assert!(matches!(
+            function.call_slice(
+                &instance,
+                &[WasmValue::I64(0), WasmValue::F32(0.0)],
+                &mut results[..1]
+            ),
+            Err(RuntimeError::SignatureMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_call_batch() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "div") (param i32 i32) (result i32)
+                (i32.div_s (local.get 0) (local.get 1))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+        let div = Function::find_export_func(&instance, "div").unwrap();
+
+        let inputs = (1..=1000).map(|i| [WasmValue::I32(i * 6), WasmValue::I32(i)]);
+        let batch = div.call_batch(&instance, inputs);
+        assert_eq!(batch.size_hint(), (1000, Some(1000)));
+        for results in batch {
+            assert_eq!(results.unwrap(), vec![WasmValue::I32(6)]);
+        }
+
+        // a failed call doesn't stop later ones
+        let inputs = vec![
+            vec![WasmValue::I32(9), WasmValue::I32(3)],
+            vec![WasmValue::I32(9), WasmValue::I32(0)],
+            vec![WasmValue::I32(9)],
+            vec![WasmValue::I32(8), WasmValue::I32(2)],
+        ];
+        let results: Vec<_> = div.call_batch(&instance, &inputs).collect();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap(), &vec![WasmValue::I32(3)]);
+        assert!(matches!(
+            results[1],
+            Err(RuntimeError::Trap {
+                kind: TrapKind::IntegerDivideByZero,
+                ..
+            })
+        ));
+        assert!(matches!(
+            results[2],
+            Err(RuntimeError::ArgumentMismatch { .. })
+        ));
+        assert_eq!(results[3].as_ref().unwrap(), &vec![WasmValue::I32(4)]);
+    }
+
+    #[test]
+    fn test_function_handle() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "inc") (param i32) (result i32)
+                (i32.add (local.get 0) (i32.const 1))
+              )
+              (func (export "dec") (param i32) (result i32)
+                (i32.sub (local.get 0) (i32.const 1))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let mut instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        // a dispatch table of cloned handles
+        let inc = Function::find_export_func(&instance, "inc").unwrap();
+        let dec = Function::find_export_func(&instance, "dec").unwrap();
+        let table = [inc.clone(), dec, inc];
+        assert_send_sync(&table);
+        let mut value = 0;
+        for function in table.iter() {
+            value = function.call_typed(&instance, value).unwrap();
+        }
+        assert_eq!(value, 1);
+
+        // bound to the instance
+        let another = Instance::new(&runtime, &module, 1024).unwrap();
+        assert!(matches!(
+            table[0].call(&another, &vec![WasmValue::I32(0)]),
+            Err(RuntimeError::ExecutionError(_))
+        ));
+
+        instance.reset().unwrap();
+        assert!(matches!(
+            table[0].call(&instance, &vec![WasmValue::I32(0)]),
+            Err(RuntimeError::ExecutionError(_))
+        ));
+
+        // even if another instance takes the address of the destroyed one
+        instance.reset().unwrap();
+        drop(instance);
+        let replaced = Instance::new(&runtime, &module, 1024).unwrap();
+        assert!(matches!(
+            table[0].call(&replaced, &vec![WasmValue::I32(0)]),
+            Err(RuntimeError::ExecutionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_func_trap_with_function_names() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func $boom
+                (unreachable)
+              )
+              (func (export "run")
+                (call $boom)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+        let function = Function::find_export_func(&instance, "run").unwrap();
+
+        let result = function.call(&instance, &vec![]);
+        match result {
+            Err(RuntimeError::Trap {
+                kind,
+                message,
+                frames,
+            }) => {
+                assert_eq!(kind, TrapKind::Unreachable);
+                assert!(message.contains("unreachable"));
+                assert!(message.contains("boom"));
+
+                // boom, run
+                assert_eq!(frames.len(), 2);
+                assert_eq!(frames[0].func_index, Some(0));
+                assert_eq!(frames[0].func_name.as_deref(), Some("boom"));
+                assert_eq!(frames[1].func_name.as_deref(), Some("run"));
+            }
+            _ => panic!("expect a trap"),
+        }
+    }
+
+    #[test]
+    fn test_call_checked() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 16) "division by zero")
+              (func (export "div") (param i32 i32) (result i32)
+                (if (result i32) (i32.eqz (local.get 1))
+                  (then (i32.const -22))
+                  (else (i32.div_s (local.get 0) (local.get 1)))
+                )
+              )
+              (func (export "last_error") (result i64)
+                (i64.or (i64.shl (i64.const 16) (i64.const 32)) (i64.const 16))
+              )
+              (func (export "half") (param f32) (result f32)
+                (f32.div (local.get 0) (f32.const 2))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        let div = Function::find_export_func(&instance, "div").unwrap();
+        assert_eq!(div.call_checked(&instance, (9, 3)).unwrap(), 3);
+        match div.call_checked(&instance, (9, 0)) {
+            Err(RuntimeError::GuestError { code, message }) => {
+                assert_eq!(code, -22);
+                assert_eq!(message.as_deref(), Some("division by zero"));
+            }
+            _ => panic!("expect a guest error"),
+        }
+
+        let half = Function::find_export_func(&instance, "half").unwrap();
+        assert!(matches!(
+            half.call_checked(&instance, 1.0f32),
+            Err(RuntimeError::SignatureMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_call_with_timeout() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "spin")
+                (loop (br 0))
+              )
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let mut instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        let add = Function::find_export_func(&instance, "add").unwrap();
+        let params = [WasmValue::I32(3), WasmValue::I32(6)];
+        assert_eq!(
+            add.call_with_timeout(&instance, &params, Duration::from_secs(10))
+                .unwrap(),
+            vec![WasmValue::I32(9)]
+        );
+
+        let spin = Function::find_export_func(&instance, "spin").unwrap();
+        let timeout = Duration::from_millis(100);
+        assert!(matches!(
+            spin.call_with_timeout(&instance, &[], timeout),
+            Err(RuntimeError::Timeout(t)) if t == timeout
+        ));
+
+        instance.reset().unwrap();
+        let sum: i32 = instance.call("add").args((1, 2)).invoke_typed().unwrap();
+        assert_eq!(sum, 3);
+    }
+
+    #[test]
+    #[cfg(feature = "instruction-metering")]
+    fn test_call_metered() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "spin")
+                (loop (br 0))
+              )
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+              (func (export "count") (param $n i32) (result i32)
+                (local $i i32)
+                (loop $next
+                  (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                  (br_if $next (i32.lt_s (local.get $i) (local.get $n)))
+                )
+                (local.get $i)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        let add = Function::find_export_func(&instance, "add").unwrap();
+        let params = [WasmValue::I32(3), WasmValue::I32(6)];
+        let (results, consumed) = add.call_metered(&instance, &params, 100).unwrap();
+        assert_eq!(results, vec![WasmValue::I32(9)]);
+        assert!(consumed > 0 && consumed < 100);
+
+        // every iteration of the loop consumes the same
+        let count = Function::find_export_func(&instance, "count").unwrap();
+        let consumed: Vec<u32> = [10, 20, 30]
+            .into_iter()
+            .map(|n| {
+                let (results, consumed) = count
+                    .call_metered(&instance, &[WasmValue::I32(n)], 10_000)
+                    .unwrap();
+                assert_eq!(results, vec![WasmValue::I32(n)]);
+                consumed
+            })
+            .collect();
+        let per_ten = consumed[1] - consumed[0];
+        assert!(per_ten >= 10);
+        assert_eq!(consumed[2] - consumed[1], per_ten);
+
+        let spin = Function::find_export_func(&instance, "spin").unwrap();
+        assert!(matches!(
+            spin.call_metered(&instance, &[], 1000),
+            Err(RuntimeError::OutOfFuel(1000))
+        ));
+
+        // the limit doesn't outlive the metered call
+        let sum: i32 = add.call_typed(&instance, (1, 2)).unwrap();
+        assert_eq!(sum, 3);
+    }
+
+    #[test]
+    fn test_func_trap_kinds() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (memory 1)
+              (func (export "div") (param i32 i32) (result i32)
+                (i32.div_s (local.get 0) (local.get 1))
+              )
+              (func (export "load") (param i32) (result i32)
+                (i32.load (local.get 0))
+              )
+              (func $recurse (export "recurse")
+                (call $recurse)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+
+        let trap_of = |result: Result<Vec<WasmValue>, RuntimeError>| match result {
+            Err(RuntimeError::Trap { kind, .. }) => Some(kind),
+            _ => None,
+        };
+        assert_eq!(
+            trap_of(instance.call("div").args((1, 0)).invoke()),
+            Some(TrapKind::IntegerDivideByZero)
+        );
+        assert_eq!(
+            trap_of(instance.call("div").args((i32::MIN, -1)).invoke()),
+            Some(TrapKind::IntegerOverflow)
+        );
+        assert_eq!(
+            trap_of(instance.call("load").arg(65536).invoke()),
+            Some(TrapKind::OutOfBoundsMemory)
+        );
+        assert_eq!(
+            trap_of(instance.call("recurse").invoke()),
+            Some(TrapKind::StackOverflow)
+        );
+
+        // not a trap
+        let div = Function::find_export_func(&instance, "div").unwrap();
+        assert!(matches!(
+            div.call(&instance, &vec![WasmValue::I32(1)]),
+            Err(RuntimeError::ArgumentMismatch { .. })
+        ));
     }
 
     #[test]
@@ -166,7 +1387,7 @@ mod tests {
         let wasi_ctx = WasiCtxBuilder::new()
             .set_pre_open_path(vec!["."], vec![])
             .build();
-        module.set_wasi_context(wasi_ctx);
+        module.set_wasi_context(wasi_ctx).unwrap();
 
         let instance = Instance::new(&runtime, &module, 1024 * 64);
         assert!(instance.is_ok());
@@ -178,10 +1399,10 @@ mod tests {
 
         let params: Vec<WasmValue> = vec![WasmValue::I32(9), WasmValue::I32(27)];
         let result = function.call(instance, &params);
-        assert_eq!(result.unwrap(), WasmValue::I32(9));
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(9)]);
 
         let params: Vec<WasmValue> = vec![WasmValue::I32(0), WasmValue::I32(27)];
         let result = function.call(instance, &params);
-        assert_eq!(result.unwrap(), WasmValue::I32(27));
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(27)]);
     }
 }
@@ -0,0 +1,167 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! an exported global of an instance. read and write it from the host.
+//! get one via `Instance::global()`
+
+use std::{marker::PhantomData, ptr};
+
+use wamr_sys::wasm_global_inst_t;
+
+use crate::{
+    instance::Instance,
+    types::{GlobalType, ValType},
+    value::WasmValue,
+    RuntimeError,
+};
+
+/// A view of an exported global. It is only valid as long as the instance is alive.
+#[derive(Debug)]
+pub struct Global<'instance> {
+    global: wasm_global_inst_t,
+    _instance: PhantomData<&'instance Instance<'instance>>,
+}
+
+impl<'instance> Global<'instance> {
+    pub(crate) fn from_raw(global: wasm_global_inst_t) -> Self {
+        Global {
+            global,
+            _instance: PhantomData,
+        }
+    }
+
+    pub fn ty(&self) -> GlobalType {
+        GlobalType {
+            content: ValType::from_valkind(self.global.kind),
+            mutable: self.global.is_mutable,
+        }
+    }
+
+    /// the current value of the global
+    ///
+    /// # Error
+    ///
+    /// If the global is a reference, or of an unknown type, an `RuntimeError::NotImplemented`
+    /// will be returned.
+    pub fn get(&self) -> Result<WasmValue, RuntimeError> {
+        let data = self.global.global_data;
+        let value = unsafe {
+            match self.ty().content {
+                ValType::I32 => WasmValue::I32(ptr::read_unaligned(data as *const i32)),
+                ValType::I64 => WasmValue::I64(ptr::read_unaligned(data as *const i64)),
+                ValType::F32 => WasmValue::F32(ptr::read_unaligned(data as *const f32)),
+                ValType::F64 => WasmValue::F64(ptr::read_unaligned(data as *const f64)),
+                ValType::V128 => WasmValue::V128(ptr::read_unaligned(data as *const [u8; 16])),
+                ValType::ExternRef | ValType::FuncRef | ValType::Unknown(_) => {
+                    return Err(RuntimeError::NotImplemented)
+                }
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// set a new value of the global
+    ///
+    /// # Error
+    ///
+    /// If the global is immutable, or the type of `value` doesn't match, an
+    /// `RuntimeError::InvalidGlobalAccess` will be returned.
+    pub fn set(&self, value: WasmValue) -> Result<(), RuntimeError> {
+        let ty = self.ty();
+        if !ty.mutable {
+            return Err(RuntimeError::InvalidGlobalAccess(String::from(
+                "global is immutable",
+            )));
+        }
+
+        let data = self.global.global_data;
+        unsafe {
+            match (ty.content, value) {
+                (ValType::I32, WasmValue::I32(v)) => ptr::write_unaligned(data as *mut i32, v),
+                (ValType::I64, WasmValue::I64(v)) => ptr::write_unaligned(data as *mut i64, v),
+                (ValType::F32, WasmValue::F32(v)) => ptr::write_unaligned(data as *mut f32, v),
+                (ValType::F64, WasmValue::F64(v)) => ptr::write_unaligned(data as *mut f64, v),
+                (ValType::V128, WasmValue::V128(v)) => {
+                    ptr::write_unaligned(data as *mut [u8; 16], v)
+                }
+                (content, value) => {
+                    return Err(RuntimeError::InvalidGlobalAccess(format!(
+                        "expect a {:?} value, but got {:?}",
+                        content, value
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{module::Module, runtime::Runtime};
+
+    #[test]
+    fn test_global_get_set() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (global (export "limit") (mut i32) (i32.const 10))
+              (global (export "ratio") f64 (f64.const 0.5))
+              (global $counter (export "counter") (mut i64) (i64.const 0))
+              (func (export "tick") (result i64)
+                (global.set $counter (i64.add (global.get $counter) (i64.const 1)))
+                (global.get $counter)
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let limit = instance.global("limit").unwrap();
+        assert_eq!(
+            limit.ty(),
+            GlobalType {
+                content: ValType::I32,
+                mutable: true
+            }
+        );
+        assert_eq!(limit.get().unwrap(), WasmValue::I32(10));
+        assert!(limit.set(WasmValue::I32(20)).is_ok());
+        assert_eq!(limit.get().unwrap(), WasmValue::I32(20));
+        assert!(matches!(
+            limit.set(WasmValue::I64(20)),
+            Err(RuntimeError::InvalidGlobalAccess(_))
+        ));
+
+        let ratio = instance.global("ratio").unwrap();
+        assert_eq!(ratio.get().unwrap(), WasmValue::F64(0.5));
+        assert!(matches!(
+            ratio.set(WasmValue::F64(1.0)),
+            Err(RuntimeError::InvalidGlobalAccess(_))
+        ));
+
+        // shared with wasm
+        let counter = instance.global("counter").unwrap();
+        assert!(counter.set(WasmValue::I64(41)).is_ok());
+        let tick = crate::function::Function::find_export_func(&instance, "tick").unwrap();
+        assert_eq!(
+            tick.call(&instance, &vec![]).unwrap(),
+            vec![WasmValue::I64(42)]
+        );
+        assert_eq!(counter.get().unwrap(), WasmValue::I64(42));
+
+        assert!(matches!(
+            instance.global("unknown"),
+            Err(RuntimeError::GlobalNotFound)
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! opaque `u32` handles to host objects, like files, sockets or sessions, which host
+//! functions hand out to guests. get the table of an instance via `Caller::handles()` or
+//! `Instance::handles()`
+
+use std::{any::Any, collections::HashMap, fmt};
+
+type DropCallback = Box<dyn FnOnce(Box<dyn Any + Send>) + Send>;
+
+struct Entry {
+    object: Box<dyn Any + Send>,
+    on_drop: Option<DropCallback>,
+}
+
+impl Entry {
+    fn release(self) {
+        match self.on_drop {
+            Some(on_drop) => on_drop(self.object),
+            None => drop(self.object),
+        }
+    }
+}
+
+/// host objects of an instance by handles. A handle is never 0, so guests may use 0 as
+/// none, and is not reused until the others are exhausted
+///
+/// An object is released when the guest releases it via a host function calling
+/// `release()`, when the instance is reset, or when the instance is dropped. Its drop
+/// callback runs then, like flushing a file or closing a session
+///
+/// ```ignore
+/// .register("env", "open", |caller: &Caller, ptr: i32, len: i32| {
+///     let file = File::open(caller.read_str(ptr as u64, len as u64)?)?;
+///     Ok::<i32, Trap>(caller.handles().unwrap().insert(file) as i32)
+/// })
+/// .register("env", "close", |caller: &Caller, handle: i32| {
+///     caller.handles().unwrap().release(handle as u32) as i32
+/// })
+/// ```
+#[derive(Default)]
+pub struct HandleTable {
+    entries: HashMap<u32, Entry>,
+    // the last handle given out
+    last: u32,
+}
+
+impl fmt::Debug for HandleTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandleTable")
+            .field("len", &self.entries.len())
+            .field("last", &self.last)
+            .finish()
+    }
+}
+
+impl HandleTable {
+    /// keep an object, and return its handle. It is dropped when released
+    pub fn insert<T: Any + Send>(&mut self, object: T) -> u32 {
+        self.insert_entry(Entry {
+            object: Box::new(object),
+            on_drop: None,
+        })
+    }
+
+    /// keep an object, and return its handle. `on_drop` takes the object when it is
+    /// released. It runs with the table locked, so it should not touch the table
+    pub fn insert_with<T, F>(&mut self, object: T, on_drop: F) -> u32
+    where
+        T: Any + Send,
+        F: FnOnce(T) + Send + 'static,
+    {
+        let on_drop: DropCallback = Box::new(move |object: Box<dyn Any + Send>| {
+            if let Ok(object) = object.downcast::<T>() {
+                on_drop(*object);
+            }
+        });
+        self.insert_entry(Entry {
+            object: Box::new(object),
+            on_drop: Some(on_drop),
+        })
+    }
+
+    fn insert_entry(&mut self, entry: Entry) -> u32 {
+        let mut handle = self.last;
+        loop {
+            handle = handle.wrapping_add(1);
+            if handle != 0 && !self.entries.contains_key(&handle) {
+                break;
+            }
+        }
+        self.last = handle;
+        self.entries.insert(handle, entry);
+        handle
+    }
+
+    /// the object of a handle, if it is a `T`
+    pub fn get<T: Any>(&self, handle: u32) -> Option<&T> {
+        self.entries.get(&handle)?.object.downcast_ref::<T>()
+    }
+
+    /// the object of a handle, if it is a `T`
+    pub fn get_mut<T: Any>(&mut self, handle: u32) -> Option<&mut T> {
+        self.entries.get_mut(&handle)?.object.downcast_mut::<T>()
+    }
+
+    /// release a handle, and run the drop callback of its object.
+    /// return `false` if there is no such handle, like a released one
+    pub fn release(&mut self, handle: u32) -> bool {
+        match self.entries.remove(&handle) {
+            Some(entry) => {
+                entry.release();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// remove a handle and return its object, if it is a `T`, without the drop callback
+    pub fn take<T: Any>(&mut self, handle: u32) -> Option<T> {
+        if !self.entries.get(&handle)?.object.is::<T>() {
+            return None;
+        }
+        let entry = self.entries.remove(&handle)?;
+        entry.object.downcast::<T>().ok().map(|object| *object)
+    }
+
+    pub fn contains(&self, handle: u32) -> bool {
+        self.entries.contains_key(&handle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// release all handles, and run drop callbacks of their objects
+    pub fn clear(&mut self) {
+        self.entries.drain().for_each(|(_, entry)| entry.release());
+    }
+}
+
+impl Drop for HandleTable {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_handle_table() {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let mut table = HandleTable::default();
+
+        let log = Arc::clone(&closed);
+        let file = table.insert_with(String::from("a.txt"), move |name| {
+            log.lock().unwrap().push(name)
+        });
+        let counter = table.insert(7u64);
+        assert_ne!(file, 0);
+        assert_ne!(file, counter);
+        assert_eq!(table.len(), 2);
+
+        assert_eq!(table.get::<String>(file).map(String::as_str), Some("a.txt"));
+        assert!(table.get::<u32>(file).is_none());
+        *table.get_mut::<u64>(counter).unwrap() += 1;
+        assert_eq!(table.get::<u64>(counter), Some(&8));
+
+        // released once
+        assert!(table.release(file));
+        assert!(!table.release(file));
+        assert!(!table.contains(file));
+        assert_eq!(*closed.lock().unwrap(), vec![String::from("a.txt")]);
+
+        // not reused right away
+        let next = table.insert(());
+        assert!(next != file && next != counter);
+
+        assert_eq!(table.take::<u32>(counter), None);
+        assert_eq!(table.take::<u64>(counter), Some(8));
+
+        let log = Arc::clone(&closed);
+        table.insert_with(String::from("b.txt"), move |name| {
+            log.lock().unwrap().push(name)
+        });
+        drop(table);
+        assert_eq!(
+            *closed.lock().unwrap(),
+            vec![String::from("a.txt"), String::from("b.txt")]
+        );
+    }
+
+    #[test]
+    fn test_handle_wrapping() {
+        let mut table = HandleTable::default();
+        table.last = u32::MAX - 1;
+        assert_eq!(table.insert(()), u32::MAX);
+        // 0 is skipped
+        assert_eq!(table.insert(()), 1);
+    }
+}
@@ -23,6 +23,39 @@ pub fn exception_to_string(raw_exception: *const c_char) -> String {
     String::from_utf8_lossy(exception.to_bytes()).to_string()
 }
 
+// what WAMR prints while `f` runs, instead of printing it to stdout. It takes what other
+// threads print meanwhile as well
+#[cfg(feature = "memory-profiling")]
+pub fn capture_output(f: impl FnOnce()) -> String {
+    use std::sync::{Mutex, PoisonError};
+    use wamr_sys::wamr_sys_set_print_hook;
+
+    static OUTPUT: Mutex<Option<String>> = Mutex::new(None);
+    static CAPTURING: Mutex<()> = Mutex::new(());
+
+    unsafe extern "C" fn append(message: *const c_char) {
+        let message = CStr::from_ptr(message).to_string_lossy();
+        if let Some(output) = OUTPUT
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+        {
+            output.push_str(&message);
+        }
+    }
+
+    let _capturing = CAPTURING.lock().unwrap_or_else(PoisonError::into_inner);
+    *OUTPUT.lock().unwrap_or_else(PoisonError::into_inner) = Some(String::new());
+    unsafe { wamr_sys_set_print_hook(Some(append)) };
+    f();
+    unsafe { wamr_sys_set_print_hook(None) };
+    OUTPUT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -4,16 +4,44 @@
  */
 
 /// This is a wrapper of a host defined(Rust) function.
+use std::any::Any;
+use std::cell::RefCell;
 use std::ffi::{c_void, CString};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
+use std::sync::MutexGuard;
+use std::time::Instant;
 
-use wamr_sys::NativeSymbol;
+use wamr_sys::{
+    wasm_exec_env_t, wasm_memory_get_bytes_per_page, wasm_memory_get_cur_page_count,
+    wasm_module_inst_t, wasm_module_t, wasm_runtime_addr_app_to_native, wasm_runtime_call_wasm,
+    wasm_runtime_clear_exception, wasm_runtime_get_default_memory, wasm_runtime_get_exception,
+    wasm_runtime_get_function_attachment, wasm_runtime_get_module_inst,
+    wasm_runtime_lookup_function, wasm_runtime_register_natives_raw, wasm_runtime_resolve_symbols,
+    wasm_runtime_set_exception, wasm_runtime_unregister_natives, wasm_runtime_validate_app_addr,
+    NativeSymbol,
+};
+
+use crate::{
+    exec_env::ExecEnv,
+    function::{cell_count, value_cell_count, CallEvent, CallOutcome},
+    handle::HandleTable,
+    helper::exception_to_string,
+    instance::Instance,
+    trap::{TrapKind, HOST_QUOTA_PREFIX, HOST_TRAP_PREFIX, UNLINKED_IMPORT_PREFIX},
+    types::FuncType,
+    value::WasmValue,
+    RuntimeError,
+};
 
 #[allow(dead_code)]
 #[derive(Debug)]
 struct HostFunction {
     function_name: CString,
     function_ptr: *mut c_void,
+    // derived by `register_native_function()`
+    signature: Option<CString>,
 }
 
 #[derive(Debug)]
@@ -37,6 +65,7 @@ impl HostFunctionList {
         self.host_functions.push(HostFunction {
             function_name: CString::new(function_name).unwrap(),
             function_ptr,
+            signature: None,
         });
 
         let last = self.host_functions.last().unwrap();
@@ -44,6 +73,25 @@ impl HostFunctionList {
             .push(pack_host_function(&(last.function_name), function_ptr));
     }
 
+    /// the same as `register_host_function()`, with the signature derived from the type
+    pub fn register_native_function<F: NativeFunction>(
+        &mut self,
+        function_name: &str,
+        function: F,
+    ) {
+        let function_ptr = function.into_ptr();
+        self.host_functions.push(HostFunction {
+            function_name: CString::new(function_name).unwrap(),
+            function_ptr,
+            signature: Some(CString::new(F::signature()).unwrap()),
+        });
+
+        let last = self.host_functions.last().unwrap();
+        let mut native_symbol = pack_host_function(&(last.function_name), function_ptr);
+        native_symbol.signature = last.signature.as_ref().unwrap().as_ptr();
+        self.native_symbols.push(native_symbol);
+    }
+
     pub fn get_native_symbols(&mut self) -> &mut Vec<NativeSymbol> {
         &mut self.native_symbols
     }
@@ -62,19 +110,1478 @@ pub fn pack_host_function(function_name: &CString, function_ptr: *mut c_void) ->
     }
 }
 
+/// what an `extern "C"` host function returns. `()` or a `HostType`
+pub trait NativeResult {
+    /// the result part of a WAMR signature string
+    const SIGNATURE: &'static str;
+}
+
+impl NativeResult for () {
+    const SIGNATURE: &'static str = "";
+}
+
+impl<T: HostType> NativeResult for T {
+    const SIGNATURE: &'static str = T::SIGNATURE;
+}
+
+/// `extern "C"` functions which are able to be registered by
+/// `RuntimeBuilder::register_native()`. Parameters are `HostType`s, after a
+/// `wasm_exec_env_t` or not, and the result is a `NativeResult`. Cast a function item
+/// into its pointer type, like `extra as extern "C" fn() -> i32`
+pub trait NativeFunction {
+    /// the WAMR signature string, like `(ii)i`. The execution environment isn't in it
+    fn signature() -> String;
+
+    fn into_ptr(self) -> *mut c_void;
+}
+
+macro_rules! impl_native_function {
+    ($($name:ident)*) => {
+        impl<R: NativeResult, $($name: HostType),*> NativeFunction for extern "C" fn($($name),*) -> R {
+            fn signature() -> String {
+                native_signature(&[$($name::SIGNATURE),*], R::SIGNATURE)
+            }
+
+            fn into_ptr(self) -> *mut c_void {
+                self as *mut c_void
+            }
+        }
+
+        impl<R: NativeResult, $($name: HostType),*> NativeFunction
+            for extern "C" fn(wasm_exec_env_t, $($name),*) -> R
+        {
+            fn signature() -> String {
+                native_signature(&[$($name::SIGNATURE),*], R::SIGNATURE)
+            }
+
+            fn into_ptr(self) -> *mut c_void {
+                self as *mut c_void
+            }
+        }
+    };
+}
+
+fn native_signature(params: &[&str], result: &str) -> String {
+    format!("({}){}", params.concat(), result)
+}
+
+impl_native_function!();
+impl_native_function!(A);
+impl_native_function!(A B);
+impl_native_function!(A B C);
+impl_native_function!(A B C D);
+impl_native_function!(A B C D E);
+impl_native_function!(A B C D E F);
+impl_native_function!(A B C D E F G);
+impl_native_function!(A B C D E F G H);
+
+// a closure behind the raw calling convention of WAMR. Each parameter takes a 64-bit
+// slot of `args`, and the result is written back to the first one
+pub(crate) type RawHostFunction = Box<dyn Fn(wasm_exec_env_t, *mut u64) + Send + Sync>;
+
+/// the instance calling a host function registered by `RuntimeBuilder::register()`.
+/// It is only lent for the call
+pub struct Caller {
+    exec_env: wasm_exec_env_t,
+}
+
+impl Caller {
+    pub(crate) fn new(exec_env: wasm_exec_env_t) -> Self {
+        Caller { exec_env }
+    }
+
+    pub fn get_inner_exec_env(&self) -> wasm_exec_env_t {
+        self.exec_env
+    }
+
+    pub fn get_inner_instance(&self) -> wasm_module_inst_t {
+        unsafe { wasm_runtime_get_module_inst(self.exec_env) }
+    }
+
+    /// the data attached to the calling instance, if it is a `T`.
+    /// See `InstanceBuilder::host_data()`
+    pub fn host_data<T: Any>(&self) -> Option<&T> {
+        unsafe { Instance::host_data_of_exec_env(self.exec_env) }
+    }
+
+    /// the table of host objects the calling instance holds handles to. See `HandleTable`
+    pub fn handles(&self) -> Option<MutexGuard<'_, HandleTable>> {
+        unsafe { Instance::handles_of(self.get_inner_instance()) }
+    }
+
+    /// the current size of the default memory in bytes. 0 if there is no memory
+    pub fn data_size(&self) -> u64 {
+        let memory = unsafe { wasm_runtime_get_default_memory(self.get_inner_instance()) };
+        match memory.is_null() {
+            true => 0,
+            false => unsafe {
+                wasm_memory_get_cur_page_count(memory) * wasm_memory_get_bytes_per_page(memory)
+            },
+        }
+    }
+
+    /// the data attached to the execution environment running the call, if it is a `T`.
+    /// See `ExecEnv::set_user_data()`
+    pub fn user_data<T: Any>(&self) -> Option<&T> {
+        unsafe { ExecEnv::user_data_of(self.exec_env) }
+    }
+
+    /// copy `buf.len()` bytes starting at `offset` of the default memory into `buf`
+    ///
+    /// # Error
+    ///
+    /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), RuntimeError> {
+        let src = self.address_of(offset, buf.len())?;
+        unsafe { ptr::copy_nonoverlapping(src, buf.as_mut_ptr(), buf.len()) };
+        Ok(())
+    }
+
+    /// copy `buf` into the default memory starting at `offset`
+    ///
+    /// # Error
+    ///
+    /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+    pub fn write(&self, offset: u64, buf: &[u8]) -> Result<(), RuntimeError> {
+        let dst = self.address_of(offset, buf.len())?;
+        unsafe { ptr::copy_nonoverlapping(buf.as_ptr(), dst, buf.len()) };
+        Ok(())
+    }
+
+    /// read `len` bytes at `offset` of the default memory as a string, like a pointer and
+    /// a length passed by wasm. Invalid UTF-8 sequences are replaced with `U+FFFD`
+    ///
+    /// # Error
+    ///
+    /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+    pub fn read_str(&self, offset: u64, len: u64) -> Result<String, RuntimeError> {
+        let mut bytes = vec![0u8; len as usize];
+        self.read(offset, &mut bytes)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// read a NUL-terminated string at `offset` of the default memory, like a `char *`
+    /// passed by wasm. Invalid UTF-8 sequences are replaced with `U+FFFD`
+    ///
+    /// # Error
+    ///
+    /// If there is no NUL before the end of the memory, an `RuntimeError::MemoryAccessOutOfBounds`
+    /// will be returned.
+    pub fn read_cstr(&self, offset: u64) -> Result<String, RuntimeError> {
+        let start = self.address_of(offset, 0)?;
+        let remaining = (self.data_size() - offset) as usize;
+
+        let bytes = unsafe { std::slice::from_raw_parts(start, remaining) };
+        match bytes.iter().position(|b| *b == 0) {
+            Some(len) => Ok(String::from_utf8_lossy(&bytes[..len]).into_owned()),
+            None => Err(RuntimeError::MemoryAccessOutOfBounds {
+                offset,
+                len: remaining as u64 + 1,
+            }),
+        }
+    }
+
+    /// call an export function of the calling instance on the same execution environment,
+    /// like a callback into the guest. The guest may call host functions again, up to
+    /// `MAX_REENTRANT_DEPTH` host functions in a row
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::FunctionNotFound` if there is no such export function, or
+    /// `RuntimeError::ArgumentMismatch` if `params` don't match its signature. Return
+    /// `RuntimeError::IllegalReentrancy` if it goes beyond `MAX_REENTRANT_DEPTH`.
+    /// Return `RuntimeError::ExecutionError` if it fails. The exception is cleared then,
+    /// so it is up to the host function to trap or not.
+    pub fn call(&self, name: &str, params: &[WasmValue]) -> Result<Vec<WasmValue>, RuntimeError> {
+        let depth = HOST_CALLS.with(|calls| calls.borrow().len());
+        if depth >= MAX_REENTRANT_DEPTH {
+            return Err(RuntimeError::IllegalReentrancy(format!(
+                "more than {} host functions nested",
+                MAX_REENTRANT_DEPTH
+            )));
+        }
+
+        let instance = self.get_inner_instance();
+        let name = CString::new(name).expect("CString::new failed");
+        let function = unsafe { wasm_runtime_lookup_function(instance, name.as_ptr()) };
+        if function.is_null() {
+            return Err(RuntimeError::FunctionNotFound);
+        }
+
+        let ty = FuncType::from_function(function, instance);
+        let matched = params.len() == ty.params().len()
+            && params
+                .iter()
+                .zip(ty.params())
+                .all(|(p, ty)| p.ty() == Some(*ty));
+        if !matched {
+            return Err(RuntimeError::ArgumentMismatch {
+                expected: ty.params().to_vec(),
+                got: params.to_vec(),
+            });
+        }
+        ty.check_results_known()?;
+
+        let argc: usize = params.iter().map(value_cell_count).sum();
+        let result_cells = ty.results().iter().map(|result| cell_count(*result)).sum();
+        let mut argv = vec![0u32; argc.max(result_cells)];
+        let mut offset = 0;
+        for p in params {
+            if let WasmValue::FuncRef(Some(funcref)) = p {
+                funcref.check_instance(instance)?;
+            }
+            offset += p.encode_into(&mut argv[offset..]);
+        }
+
+        let call_result = unsafe {
+            wasm_runtime_call_wasm(self.exec_env, function, argc as u32, argv.as_mut_ptr())
+        };
+        if !call_result {
+            let message = exception_to_string(unsafe { wasm_runtime_get_exception(instance) });
+            unsafe { wasm_runtime_clear_exception(instance) };
+            return Err(RuntimeError::ExecutionError(message));
+        }
+
+        let mut cells = argv.as_slice();
+        let results = ty
+            .results()
+            .iter()
+            .map(|ty| {
+                let result = WasmValue::decode_from(*ty, cells, instance);
+                cells = &cells[cell_count(*ty)..];
+                result
+            })
+            .collect();
+        Ok(results)
+    }
+
+    // the native address of [offset, offset + len), which is valid until the memory grows
+    fn address_of(&self, offset: u64, len: usize) -> Result<*mut u8, RuntimeError> {
+        let instance = self.get_inner_instance();
+        let valid = unsafe { wasm_runtime_validate_app_addr(instance, offset, len as u64) };
+        if !valid {
+            // WAMR raises an "out of bounds memory access", which is up to the host function
+            unsafe { wasm_runtime_clear_exception(instance) };
+            return Err(RuntimeError::MemoryAccessOutOfBounds {
+                offset,
+                len: len as u64,
+            });
+        }
+        Ok(unsafe { wasm_runtime_addr_app_to_native(instance, offset) } as *mut u8)
+    }
+}
+
+/// a type of parameters and results of host functions registered by
+/// `RuntimeBuilder::register()`
+pub trait HostType: Sized {
+    /// the type in a WAMR signature string
+    const SIGNATURE: &'static str;
+
+    fn from_slot(slot: u64) -> Self;
+
+    fn into_slot(self) -> u64;
+}
+
+// a 32-bit value takes the leading bytes of a slot
+macro_rules! impl_host_type {
+    ($ty:ty, $signature:literal) => {
+        impl HostType for $ty {
+            const SIGNATURE: &'static str = $signature;
+
+            fn from_slot(slot: u64) -> Self {
+                let size = std::mem::size_of::<$ty>();
+                let mut bytes = [0u8; std::mem::size_of::<$ty>()];
+                bytes.copy_from_slice(&slot.to_ne_bytes()[..size]);
+                <$ty>::from_ne_bytes(bytes)
+            }
+
+            fn into_slot(self) -> u64 {
+                let mut slot = [0u8; 8];
+                slot[..std::mem::size_of::<$ty>()].copy_from_slice(&self.to_ne_bytes());
+                u64::from_ne_bytes(slot)
+            }
+        }
+    };
+}
+
+impl_host_type!(i32, "i");
+impl_host_type!(i64, "I");
+impl_host_type!(f32, "f");
+impl_host_type!(f64, "F");
+
+/// what a host function registered by `RuntimeBuilder::register()` returns. `()`, a
+/// `HostType`, or a `Result` of them. An `Err`, like a `Trap`, traps the caller with
+/// its message
+pub trait HostResult {
+    /// the result part of a WAMR signature string
+    const SIGNATURE: &'static str;
+
+    /// write the result into the first slot, or raise an exception
+    ///
+    /// # Safety
+    ///
+    /// `exec_env` should be running the host function, and `args` should be its slots
+    unsafe fn store(self, exec_env: wasm_exec_env_t, args: *mut u64);
+}
+
+impl HostResult for () {
+    const SIGNATURE: &'static str = "";
+
+    unsafe fn store(self, _exec_env: wasm_exec_env_t, _args: *mut u64) {}
+}
+
+impl<T: HostType> HostResult for T {
+    const SIGNATURE: &'static str = T::SIGNATURE;
+
+    unsafe fn store(self, _exec_env: wasm_exec_env_t, args: *mut u64) {
+        *args = self.into_slot();
+    }
+}
+
+impl<T: HostResult, E: fmt::Display> HostResult for Result<T, E> {
+    const SIGNATURE: &'static str = T::SIGNATURE;
+
+    unsafe fn store(self, exec_env: wasm_exec_env_t, args: *mut u64) {
+        match self {
+            Ok(result) => result.store(exec_env, args),
+            Err(e) => raise(exec_env, &e.to_string()),
+        }
+    }
+}
+
+// trap the calling instance with the message. See `Trap`
+pub(crate) unsafe fn raise(exec_env: wasm_exec_env_t, message: &str) {
+    set_exception(exec_env, &format!("{}{}", HOST_TRAP_PREFIX, message));
+}
+
+unsafe fn set_exception(exec_env: wasm_exec_env_t, message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    wasm_runtime_set_exception(wasm_runtime_get_module_inst(exec_env), message.as_ptr());
+}
+
+/// Rust closures which are able to be registered as host functions. Parameters are
+/// a `&Caller` followed by `HostType`s, and the result is a `HostResult`
+pub trait IntoHostFunction<Params, Results> {
+    /// the WAMR signature string, like `(ii)i`
+    fn signature() -> String;
+
+    #[doc(hidden)]
+    fn into_raw(self) -> RawHostFunction;
+}
+
+macro_rules! impl_into_host_function {
+    ($($name:ident)*) => {
+        impl<Func, R, $($name: HostType),*> IntoHostFunction<($($name,)*), R> for Func
+        where
+            Func: Fn(&Caller, $($name),*) -> R + Send + Sync + 'static,
+            R: HostResult,
+        {
+            fn signature() -> String {
+                let mut signature = String::from("(");
+                $(signature.push_str($name::SIGNATURE);)*
+                signature.push(')');
+                signature.push_str(R::SIGNATURE);
+                signature
+            }
+
+            #[allow(non_snake_case, unused_mut, unused_variables, unused_assignments)]
+            fn into_raw(self) -> RawHostFunction {
+                Box::new(move |exec_env, args| {
+                    let mut slots = args;
+                    $(
+                        let $name = $name::from_slot(unsafe { *slots });
+                        slots = slots.wrapping_add(1);
+                    )*
+                    let caller = Caller::new(exec_env);
+                    unsafe { self(&caller, $($name),*).store(exec_env, args) };
+                })
+            }
+        }
+    };
+}
+
+impl_into_host_function!();
+impl_into_host_function!(A);
+impl_into_host_function!(A B);
+impl_into_host_function!(A B C);
+impl_into_host_function!(A B C D);
+impl_into_host_function!(A B C D E);
+impl_into_host_function!(A B C D E F);
+impl_into_host_function!(A B C D E F G);
+impl_into_host_function!(A B C D E F G H);
+
+/// how many host closures may run nested on a thread, each calling back into the guest
+/// via `Caller::call()`. It stops a recursion between the host and the guest before the
+/// native stack overflows
+pub const MAX_REENTRANT_DEPTH: usize = 64;
+
+thread_local! {
+    // execution environments running host closures on this thread, innermost last
+    static HOST_CALLS: RefCell<Vec<wasm_exec_env_t>> = const { RefCell::new(Vec::new()) };
+}
+
+// the execution environment running a host closure of the instance on this thread, if any.
+// a call into the instance on another one would run over its stack
+pub(crate) fn running_exec_env(instance: wasm_module_inst_t) -> Option<wasm_exec_env_t> {
+    HOST_CALLS.with(|calls| {
+        calls
+            .borrow()
+            .iter()
+            .rev()
+            .find(|exec_env| unsafe { wasm_runtime_get_module_inst(**exec_env) } == instance)
+            .copied()
+    })
+}
+
+// a closure registered by `HostModule`, as the attachment of its symbol
+struct HostClosure {
+    // like `env.log`
+    import: String,
+    // like `(ii)i`, of `i`, `I`, `f` and `F` only
+    signature: String,
+    function: RawHostFunction,
+}
+
+// the only function WAMR calls for closures. The closure is the attachment of the symbol
+unsafe extern "C" fn trampoline(exec_env: wasm_exec_env_t, args: *mut u64) {
+    let closure = wasm_runtime_get_function_attachment(exec_env) as *const HostClosure;
+    let Some(closure) = closure.as_ref() else {
+        return;
+    };
+
+    let instance = wasm_runtime_get_module_inst(exec_env);
+    if !Instance::has_host_call_hook(instance) {
+        return call_closure(exec_env, closure, args);
+    }
+
+    let (params, results) = closure.signature[1..].split_once(')').unwrap_or_default();
+    let event = CallEvent {
+        name: Some(&closure.import),
+        params: &decode_slots(params, args),
+    };
+    let started = Instant::now();
+    call_closure(exec_env, closure, args);
+    let duration = started.elapsed();
+
+    let exception = wasm_runtime_get_exception(instance);
+    let (values, error);
+    let outcome = CallOutcome {
+        duration,
+        result: match exception.is_null() {
+            true => {
+                values = decode_slots(results, args);
+                Ok(&values)
+            }
+            false => {
+                let message = exception_to_string(exception);
+                error = RuntimeError::Trap {
+                    kind: TrapKind::from_exception(&message).unwrap_or(TrapKind::Host),
+                    message,
+                    frames: Vec::new(),
+                };
+                Err(&error)
+            }
+        },
+    };
+    Instance::report_host_call(instance, &event, &outcome);
+}
+
+unsafe fn call_closure(exec_env: wasm_exec_env_t, closure: &HostClosure, args: *mut u64) {
+    let instance = wasm_runtime_get_module_inst(exec_env);
+    match Instance::charge_host_call(instance, &closure.import) {
+        Ok(()) => {
+            HOST_CALLS.with(|calls| calls.borrow_mut().push(exec_env));
+            // unwinding into WAMR is undefined behavior
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| (closure.function)(exec_env, args)));
+            HOST_CALLS.with(|calls| calls.borrow_mut().pop());
+
+            if let Err(payload) = result {
+                raise(exec_env, &panic_message(payload.as_ref()));
+            }
+        }
+        Err(message) => set_exception(
+            exec_env,
+            &format!("{}{} {}", HOST_QUOTA_PREFIX, closure.import, message),
+        ),
+    }
+}
+
+// values in `slots` of the types in a part of a raw signature, like `iI` of `(iI)f`
+unsafe fn decode_slots(types: &str, slots: *const u64) -> Vec<WasmValue> {
+    types
+        .chars()
+        .enumerate()
+        .map(|(i, ty)| {
+            let slot = *slots.add(i);
+            match ty {
+                'i' => WasmValue::I32(i32::from_slot(slot)),
+                'I' => WasmValue::I64(i64::from_slot(slot)),
+                'f' => WasmValue::F32(f32::from_slot(slot)),
+                _ => WasmValue::F64(f64::from_slot(slot)),
+            }
+        })
+        .collect()
+}
+
+// slots of a raw host function, for its parameters or its result. Each one takes a slot
+fn raw_slot_count(signature: &str) -> usize {
+    let (params, results) = signature
+        .strip_prefix('(')
+        .and_then(|signature| signature.split_once(')'))
+        .unwrap_or_else(|| panic!("malformed signature {:?}", signature));
+    assert!(
+        results.len() <= 1
+            && params
+                .chars()
+                .chain(results.chars())
+                .all(|c| "iIfF".contains(c)),
+        "unsupported signature {:?}",
+        signature
+    );
+    params.len().max(results.len())
+}
+
+// `panic!()` carries a `&str` or a `String`, `panic_any()` anything else
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str));
+    match message {
+        Some(message) => format!("host function panicked: {}", message),
+        None => String::from("host function panicked"),
+    }
+}
+
+/// a group of Rust closures as host functions of a module name. Register it via
+/// `Runtime::register_host_module()` while the runtime is alive.
+/// `RuntimeBuilder::register()` is for those known before building the runtime
+///
+/// ```ignore
+/// let v2 = HostModule::new("env")
+///     .function("log", |caller: &Caller, ptr: i32, len: i32| { ... })
+///     .function("now", |_: &Caller| -> i64 { ... });
+/// runtime.register_host_module(v2)?;
+/// ```
+pub struct HostModule {
+    module_name: CString,
+    // keep ownership of the content of `native_symbols`
+    function_names: Vec<CString>,
+    signatures: Vec<CString>,
+    functions: Vec<HostClosure>,
+    native_symbols: Vec<NativeSymbol>,
+}
+
+impl fmt::Debug for HostModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HostModule")
+            .field("module_name", &self.module_name)
+            .field("function_names", &self.function_names)
+            .field("signatures", &self.signatures)
+            .finish()
+    }
+}
+
+impl HostModule {
+    pub fn new(module_name: &str) -> Self {
+        HostModule {
+            module_name: CString::new(module_name).unwrap(),
+            function_names: Vec::new(),
+            signatures: Vec::new(),
+            functions: Vec::new(),
+            native_symbols: Vec::new(),
+        }
+    }
+
+    /// add a closure as the host function `function_name`. See `RuntimeBuilder::register()`
+    pub fn function<Params, Results, H>(mut self, function_name: &str, function: H) -> Self
+    where
+        H: IntoHostFunction<Params, Results>,
+    {
+        self.add(function_name, function);
+        self
+    }
+
+    pub fn module_name(&self) -> &str {
+        self.module_name.to_str().unwrap_or_default()
+    }
+
+    /// add a closure as the host function `function_name`, which takes arguments as they
+    /// are. See `RuntimeBuilder::register_raw()`
+    ///
+    /// # Panics
+    ///
+    /// if `signature` is not like `(ii)i`, of `i`, `I`, `f` and `F`
+    pub fn raw<F>(mut self, function_name: &str, signature: &str, function: F) -> Self
+    where
+        F: Fn(wasm_exec_env_t, &mut [u64]) + Send + Sync + 'static,
+    {
+        self.add_raw(function_name, signature, function);
+        self
+    }
+
+    pub(crate) fn add<Params, Results, H>(&mut self, function_name: &str, function: H)
+    where
+        H: IntoHostFunction<Params, Results>,
+    {
+        self.push(function_name, H::signature(), function.into_raw());
+    }
+
+    // a function which traps as an unlinked import, in place of a missing one
+    pub(crate) fn add_stub(&mut self, function_name: &str, signature: &str) {
+        raw_slot_count(signature);
+        let message = format!(
+            "{}{}.{} called",
+            UNLINKED_IMPORT_PREFIX,
+            self.module_name(),
+            function_name
+        );
+        self.push(
+            function_name,
+            String::from(signature),
+            Box::new(move |exec_env, _| unsafe { set_exception(exec_env, &message) }),
+        );
+    }
+
+    fn add_raw<F>(&mut self, function_name: &str, signature: &str, function: F)
+    where
+        F: Fn(wasm_exec_env_t, &mut [u64]) + Send + Sync + 'static,
+    {
+        let slots = raw_slot_count(signature);
+        let function: RawHostFunction = Box::new(move |exec_env, args| {
+            function(exec_env, unsafe {
+                std::slice::from_raw_parts_mut(args, slots)
+            })
+        });
+        self.push(function_name, String::from(signature), function);
+    }
+
+    fn push(&mut self, function_name: &str, signature: String, function: RawHostFunction) {
+        self.function_names
+            .push(CString::new(function_name).unwrap());
+        self.signatures
+            .push(CString::new(signature.as_str()).unwrap());
+        self.functions.push(HostClosure {
+            import: format!("{}.{}", self.module_name(), function_name),
+            signature,
+            function,
+        });
+    }
+
+    // after all closures are added, which never move from then on. WAMR sorts the
+    // symbols in place
+    fn register(&mut self) -> bool {
+        self.native_symbols = self
+            .function_names
+            .iter()
+            .zip(&self.signatures)
+            .zip(&self.functions)
+            .map(|((function_name, signature), function)| NativeSymbol {
+                symbol: function_name.as_ptr(),
+                func_ptr: trampoline as *mut c_void,
+                signature: signature.as_ptr(),
+                attachment: function as *const HostClosure as *mut c_void,
+            })
+            .collect();
+
+        unsafe {
+            wasm_runtime_register_natives_raw(
+                self.module_name.as_ptr(),
+                self.native_symbols.as_mut_ptr(),
+                self.native_symbols.len() as u32,
+            )
+        }
+    }
+
+    fn unregister(&mut self) {
+        if !self.native_symbols.is_empty() {
+            unsafe {
+                wasm_runtime_unregister_natives(
+                    self.module_name.as_ptr(),
+                    self.native_symbols.as_mut_ptr(),
+                )
+            };
+        }
+    }
+}
+
+// WAMR keeps the symbols registered, so they live as long as the runtime
+#[derive(Debug, Default)]
+pub(crate) struct HostClosures {
+    modules: Vec<HostModule>,
+    // unregistered ones. Modules loaded before keep pointers to their closures
+    retired: Vec<HostModule>,
+}
+
+impl HostClosures {
+    pub(crate) fn add<Params, Results, H>(
+        &mut self,
+        module_name: &str,
+        function_name: &str,
+        function: H,
+    ) where
+        H: IntoHostFunction<Params, Results>,
+    {
+        self.module_mut(module_name).add(function_name, function);
+    }
+
+    pub(crate) fn add_raw<F>(
+        &mut self,
+        module_name: &str,
+        function_name: &str,
+        signature: &str,
+        function: F,
+    ) where
+        F: Fn(wasm_exec_env_t, &mut [u64]) + Send + Sync + 'static,
+    {
+        self.module_mut(module_name)
+            .add_raw(function_name, signature, function);
+    }
+
+    fn module_mut(&mut self, module_name: &str) -> &mut HostModule {
+        let index = match self
+            .modules
+            .iter()
+            .position(|module| module.module_name() == module_name)
+        {
+            Some(index) => index,
+            None => {
+                self.modules.push(HostModule::new(module_name));
+                self.modules.len() - 1
+            }
+        };
+        &mut self.modules[index]
+    }
+
+    // those added by `add()`
+    pub(crate) fn register(&mut self) -> bool {
+        self.modules.iter_mut().all(HostModule::register)
+    }
+
+    // register `module`, in place of the one of the same module name if any
+    pub(crate) fn replace(&mut self, mut module: HostModule) -> bool {
+        self.remove(module.module_name());
+        if !module.register() {
+            return false;
+        }
+        self.modules.push(module);
+        true
+    }
+
+    pub(crate) fn remove(&mut self, module_name: &str) -> bool {
+        let index = self
+            .modules
+            .iter()
+            .position(|module| module.module_name() == module_name);
+        match index {
+            Some(index) => {
+                let mut module = self.modules.remove(index);
+                module.unregister();
+                self.retired.push(module);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn unregister(&mut self) {
+        self.modules.iter_mut().for_each(HostModule::unregister);
+    }
+
+    // bind `stubs` to unlinked imports of `module` only. They are unregistered right after,
+    // so modules loaded afterwards don't link to them
+    pub(crate) fn stub(&mut self, mut stubs: Vec<HostModule>, module: wasm_module_t) -> bool {
+        let registered = stubs.iter_mut().all(HostModule::register);
+        let resolved = registered && unsafe { wasm_runtime_resolve_symbols(module) };
+        stubs.iter_mut().for_each(HostModule::unregister);
+        // the module keeps pointers to their closures
+        self.retired.append(&mut stubs);
+        resolved
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        function::Function, instance::Instance, module::Module, runtime::Runtime, value::WasmValue,
+        function::Function,
+        instance::Instance,
+        module::Module,
+        quota::HostQuota,
+        runtime::Runtime,
+        trap::{Trap, TrapKind},
+        value::WasmValue,
     };
     use std::env;
     use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
 
     extern "C" fn extra() -> i32 {
         100
     }
 
+    #[test]
+    fn test_register_closures() {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&logs);
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register(
+                "test_closures",
+                "log",
+                move |caller: &Caller, ptr: i32, len: i32| {
+                    let message = caller.read_str(ptr as u64, len as u64)?;
+                    sink.lock().unwrap().push(message);
+                    Ok::<(), RuntimeError>(())
+                },
+            )
+            .register("test_closures", "scale", |_: &Caller, a: i64, b: f32| {
+                a as f64 * b as f64
+            })
+            .register("test_closures", "check", |_: &Caller, a: i32| {
+                if a < 0 {
+                    return Err(Trap::new(format!("negative {}", a)));
+                }
+                Ok(a)
+            })
+            .register("test_closures", "panic", |_: &Caller| -> i32 {
+                panic!("host function bug")
+            })
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_closures" "log" (func $log (param i32 i32)))
+              (import "test_closures" "scale" (func $scale (param i64 f32) (result f64)))
+              (import "test_closures" "check" (func $check (param i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 16) "hello")
+              (func (export "hello")
+                (call $log (i32.const 16) (i32.const 5))
+              )
+              (func (export "scale") (param i64 f32) (result f64)
+                (call $scale (local.get 0) (local.get 1))
+              )
+              (func (export "check") (param i32) (result i32)
+                (call $check (local.get 0))
+              )
+              (import "test_closures" "panic" (func $panic (result i32)))
+              (func (export "panic") (result i32)
+                (call $panic)
+              )
+              (func (export "log_oob")
+                (call $log (i32.const 65530) (i32.const 16))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        instance.call("hello").invoke().unwrap();
+        assert_eq!(*logs.lock().unwrap(), vec![String::from("hello")]);
+
+        let scaled: f64 = instance
+            .call("scale")
+            .args((-3i64, 2.5f32))
+            .invoke_typed()
+            .unwrap();
+        assert_eq!(scaled, -7.5);
+
+        let checked: i32 = instance.call("check").arg(7i32).invoke_typed().unwrap();
+        assert_eq!(checked, 7);
+
+        // an `Err` or a panic traps the caller
+        match instance.call("check").arg(-1i32).invoke() {
+            Err(RuntimeError::Trap {
+                kind: TrapKind::Host,
+                message,
+                ..
+            }) => assert!(message.contains("negative -1")),
+            other => panic!("expect a trap, got {:?}", other),
+        }
+        match instance.call("panic").invoke() {
+            Err(RuntimeError::Trap {
+                kind: TrapKind::Host,
+                message,
+                ..
+            }) => assert!(message.contains("host function panicked: host function bug")),
+            other => panic!("expect a trap, got {:?}", other),
+        }
+        // the instance is still usable after a panic
+        let checked: i32 = instance.call("check").arg(3i32).invoke_typed().unwrap();
+        assert_eq!(checked, 3);
+        assert!(matches!(
+            instance.call("log_oob").invoke(),
+            Err(RuntimeError::Trap {
+                kind: TrapKind::Host,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stateful_closures() {
+        struct Counter {
+            hits: Mutex<Vec<i32>>,
+        }
+
+        let counter = Arc::new(Counter {
+            hits: Mutex::new(Vec::new()),
+        });
+        let state = Arc::clone(&counter);
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register("test_state", "hit", move |_: &Caller, value: i32| {
+                let mut hits = state.hits.lock().unwrap();
+                hits.push(value);
+                hits.len() as i32
+            })
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_state" "hit" (func $hit (param i32) (result i32)))
+              (func (export "hit") (param i32) (result i32)
+                (call $hit (local.get 0))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let first = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let second = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        // shared by instances
+        let hits: i32 = first.call("hit").arg(3i32).invoke_typed().unwrap();
+        assert_eq!(hits, 1);
+        let hits: i32 = second.call("hit").arg(5i32).invoke_typed().unwrap();
+        assert_eq!(hits, 2);
+        assert_eq!(*counter.hits.lock().unwrap(), vec![3, 5]);
+
+        // the module keeps the closure alive
+        assert_eq!(Arc::strong_count(&counter), 2);
+        drop(runtime);
+        let hits: i32 = first.call("hit").arg(7i32).invoke_typed().unwrap();
+        assert_eq!(hits, 3);
+
+        drop(first);
+        drop(second);
+        drop(module);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_caller() {
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register("test_caller", "shout", |caller: &Caller, ptr: i32| {
+                let name = caller.read_cstr(ptr as u64)?;
+                let upper = name.to_uppercase();
+                caller.write(ptr as u64, upper.as_bytes())?;
+                let doubled = caller.call("double", &[WasmValue::I32(upper.len() as i32)])?;
+                match doubled.as_slice() {
+                    [WasmValue::I32(len)] => Ok(*len),
+                    _ => Err(RuntimeError::ConversionError(format!("{:?}", doubled))),
+                }
+            })
+            .register("test_caller", "recover", |caller: &Caller| {
+                // the exception of a trapped callback is cleared
+                let trapped = caller.call("boom", &[]).is_err();
+                let mismatched = matches!(
+                    caller.call("double", &[]),
+                    Err(RuntimeError::ArgumentMismatch { .. })
+                );
+                let missing = matches!(
+                    caller.call("unknown", &[]),
+                    Err(RuntimeError::FunctionNotFound)
+                );
+                (trapped && mismatched && missing) as i32
+            })
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_caller" "shout" (func $shout (param i32) (result i32)))
+              (import "test_caller" "recover" (func $recover (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 16) "wamr\00")
+              (func (export "double") (param i32) (result i32)
+                (i32.mul (local.get 0) (i32.const 2))
+              )
+              (func (export "boom")
+                unreachable
+              )
+              (func (export "shout") (result i32)
+                (call $shout (i32.const 16))
+              )
+              (func (export "recover") (result i32)
+                (call $recover)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let len: i32 = instance.call("shout").invoke_typed().unwrap();
+        assert_eq!(len, 8);
+        assert_eq!(instance.memory().unwrap().read_cstr(16).unwrap(), "WAMR");
+
+        let recovered: i32 = instance.call("recover").invoke_typed().unwrap();
+        assert_eq!(recovered, 1);
+    }
+
+    #[test]
+    fn test_reentrancy() {
+        thread_local! {
+            // only to reach the instance from a host function, which is illegal
+            static INSTANCE: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+        }
+
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register(
+                "test_reentrancy",
+                "recurse",
+                |caller: &Caller, n: i32| match n {
+                    0 => Ok(0),
+                    n => match caller.call("down", &[WasmValue::I32(n - 1)])?.as_slice() {
+                        [WasmValue::I32(depth)] => Ok(depth + 1),
+                        other => Err(Trap::new(format!("{:?}", other))),
+                    },
+                },
+            )
+            .register("test_reentrancy", "escape", |_: &Caller| {
+                let instance = unsafe { &*(INSTANCE.with(|i| i.get()) as *const Instance) };
+                let down = Function::find_export_func(instance, "down").unwrap();
+                match down.call(instance, &vec![WasmValue::I32(0)]) {
+                    Err(RuntimeError::IllegalReentrancy(_)) => 1,
+                    _ => 0,
+                }
+            })
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_reentrancy" "recurse" (func $recurse (param i32) (result i32)))
+              (import "test_reentrancy" "escape" (func $escape (result i32)))
+              (func (export "down") (param i32) (result i32)
+                (call $recurse (local.get 0))
+              )
+              (func (export "escape") (result i32)
+                (call $escape)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        // host -> guest -> host ... on the same execution environment
+        let depth: i32 = instance.call("down").arg(10i32).invoke_typed().unwrap();
+        assert_eq!(depth, 10);
+
+        match instance
+            .call("down")
+            .arg(MAX_REENTRANT_DEPTH as i32 + 1)
+            .invoke()
+        {
+            Err(RuntimeError::Trap {
+                kind: TrapKind::Host,
+                message,
+                ..
+            }) => assert!(message.contains("host functions nested")),
+            other => panic!("expect a trap, got {:?}", other),
+        }
+        // the guest is still usable
+        let depth: i32 = instance.call("down").arg(1i32).invoke_typed().unwrap();
+        assert_eq!(depth, 1);
+
+        // calling the instance on its own execution environment from a host function
+        // running on another one
+        INSTANCE.with(|i| i.set(&instance as *const Instance as usize));
+        let exec_env = ExecEnv::new(&instance, 1024 * 64).unwrap();
+        let escape = Function::find_export_func(&instance, "escape").unwrap();
+        let detected = escape.call_with_env(&exec_env, &[]).unwrap();
+        assert_eq!(detected, vec![WasmValue::I32(1)]);
+    }
+
+    #[test]
+    fn test_host_quota() {
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register("test_quota", "ping", |_: &Caller| 1i32)
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_quota" "ping" (func $ping (result i32)))
+              (func (export "run") (param $n i32) (result i32)
+                (local $sum i32)
+                (block $done
+                  (loop $next
+                    (br_if $done (i32.eqz (local.get $n)))
+                    (local.set $sum (i32.add (local.get $sum) (call $ping)))
+                    (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                    (br $next)
+                  )
+                )
+                (local.get $sum)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let limited = Instance::builder(&runtime, &module)
+            .host_quota("test_quota", "ping", HostQuota::new().per_call(3))
+            .build()
+            .unwrap();
+
+        let sum: i32 = limited.call("run").arg(3i32).invoke_typed().unwrap();
+        assert_eq!(sum, 3);
+        match limited.call("run").arg(4i32).invoke() {
+            Err(RuntimeError::Trap {
+                kind: TrapKind::HostQuotaExceeded,
+                message,
+                ..
+            }) => assert!(message.contains("test_quota.ping")),
+            other => panic!("expect a trap, got {:?}", other),
+        }
+        // counted per call
+        let sum: i32 = limited.call("run").arg(3i32).invoke_typed().unwrap();
+        assert_eq!(sum, 3);
+
+        let unlimited = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let sum: i32 = unlimited.call("run").arg(10i32).invoke_typed().unwrap();
+        assert_eq!(sum, 10);
+    }
+
+    #[test]
+    fn test_host_call_hook() {
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register("test_audit", "read", |_: &Caller, fd: i32| fd)
+            .register("test_audit", "write", |_: &Caller, _: i32| {
+                Err::<(), _>("read-only")
+            })
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_audit" "read" (func $read (param i32) (result i32)))
+              (import "test_audit" "write" (func $write (param i32)))
+              (func (export "read") (param i32) (result i32)
+                (call $read (local.get 0))
+              )
+              (func (export "write") (param i32)
+                (call $write (local.get 0))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let audit = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&audit);
+        let instance = Instance::builder(&runtime, &module)
+            .on_host_call(move |event: &CallEvent, outcome: &CallOutcome| {
+                log.lock().unwrap().push((
+                    event.name.unwrap().to_string(),
+                    event.params.to_vec(),
+                    outcome
+                        .result
+                        .map(<[WasmValue]>::to_vec)
+                        .map_err(|e| match e {
+                            RuntimeError::Trap { kind, .. } => *kind,
+                            _ => unreachable!(),
+                        }),
+                ))
+            })
+            .build()
+            .unwrap();
+
+        let fd: i32 = instance.call("read").arg(3i32).invoke_typed().unwrap();
+        assert_eq!(fd, 3);
+        assert!(instance.call("write").arg(4i32).invoke().is_err());
+
+        assert_eq!(
+            *audit.lock().unwrap(),
+            vec![
+                (
+                    String::from("test_audit.read"),
+                    vec![WasmValue::I32(3)],
+                    Ok(vec![WasmValue::I32(3)])
+                ),
+                (
+                    String::from("test_audit.write"),
+                    vec![WasmValue::I32(4)],
+                    Err(TrapKind::Host)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_register_raw() {
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register_raw("test_raw", "add", "(iI)I", |_, slots: &mut [u64]| {
+                assert_eq!(slots.len(), 2);
+                slots[0] = (slots[0] as i32 as i64 + slots[1] as i64) as u64;
+            })
+            .register_raw("test_raw", "half", "(f)f", |_, slots: &mut [u64]| {
+                let half = f32::from_slot(slots[0]) / 2.0;
+                slots[0] = half.into_slot();
+            })
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_raw" "add" (func $add (param i32 i64) (result i64)))
+              (import "test_raw" "half" (func $half (param f32) (result f32)))
+              (func (export "add") (param i32 i64) (result i64)
+                (call $add (local.get 0) (local.get 1))
+              )
+              (func (export "half") (param f32) (result f32)
+                (call $half (local.get 0))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let sum: i64 = instance
+            .call("add")
+            .arg(-2i32)
+            .arg(1i64 << 40)
+            .invoke_typed()
+            .unwrap();
+        assert_eq!(sum, (1i64 << 40) - 2);
+        let half: f32 = instance.call("half").arg(3.0f32).invoke_typed().unwrap();
+        assert_eq!(half, 1.5);
+    }
+
+    #[test]
+    fn test_raw_slot_count() {
+        assert_eq!(raw_slot_count("()"), 0);
+        assert_eq!(raw_slot_count("()i"), 1);
+        assert_eq!(raw_slot_count("(iIfF)F"), 4);
+        assert!(panic::catch_unwind(|| raw_slot_count("ii)i")).is_err());
+        assert!(panic::catch_unwind(|| raw_slot_count("(i*)")).is_err());
+        assert!(panic::catch_unwind(|| raw_slot_count("(i)ii")).is_err());
+    }
+
+    #[test]
+    fn test_handles() {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&closed);
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register("test_handles", "open", move |caller: &Caller, id: i32| {
+                let log = Arc::clone(&log);
+                let mut handles = caller.handles().unwrap();
+                handles.insert_with(id, move |id| log.lock().unwrap().push(id)) as i32
+            })
+            .register("test_handles", "get", |caller: &Caller, handle: i32| {
+                let handles = caller.handles().unwrap();
+                handles.get::<i32>(handle as u32).copied().unwrap_or(-1)
+            })
+            .register("test_handles", "close", |caller: &Caller, handle: i32| {
+                caller.handles().unwrap().release(handle as u32) as i32
+            })
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_handles" "open" (func $open (param i32) (result i32)))
+              (import "test_handles" "get" (func $get (param i32) (result i32)))
+              (import "test_handles" "close" (func $close (param i32) (result i32)))
+              (func (export "open") (param i32) (result i32)
+                (call $open (local.get 0))
+              )
+              (func (export "get") (param i32) (result i32)
+                (call $get (local.get 0))
+              )
+              (func (export "close") (param i32) (result i32)
+                (call $close (local.get 0))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let mut instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let first: i32 = instance.call("open").arg(10i32).invoke_typed().unwrap();
+        let second: i32 = instance.call("open").arg(20i32).invoke_typed().unwrap();
+        let value: i32 = instance.call("get").arg(second).invoke_typed().unwrap();
+        assert_eq!(value, 20);
+        assert_eq!(instance.handles().unwrap().len(), 2);
+
+        let released: i32 = instance.call("close").arg(first).invoke_typed().unwrap();
+        assert_eq!(released, 1);
+        let released: i32 = instance.call("close").arg(first).invoke_typed().unwrap();
+        assert_eq!(released, 0);
+        let value: i32 = instance.call("get").arg(first).invoke_typed().unwrap();
+        assert_eq!(value, -1);
+        assert_eq!(*closed.lock().unwrap(), vec![10]);
+
+        // released by a reset, or with the instance
+        instance.reset().unwrap();
+        assert_eq!(*closed.lock().unwrap(), vec![10, 20]);
+        let _: i32 = instance.call("open").arg(30i32).invoke_typed().unwrap();
+        drop(instance);
+        assert_eq!(*closed.lock().unwrap(), vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn test_hot_swap() {
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register("test_swap", "version", |_: &Caller| 1i32)
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_swap" "version" (func $version (result i32)))
+              (func (export "version") (result i32)
+                (call $version)
+              )
+            )
+        "#;
+        let old_module = Module::from_wat(&runtime, wat).unwrap();
+        let old_instance = Instance::new(&runtime, &old_module, 1024 * 64).unwrap();
+
+        let v2 = HostModule::new("test_swap").function("version", |_: &Caller| 2i32);
+        assert_eq!(v2.module_name(), "test_swap");
+        runtime.register_host_module(v2).unwrap();
+
+        let new_module = Module::from_wat(&runtime, wat).unwrap();
+        let new_instance = Instance::new(&runtime, &new_module, 1024 * 64).unwrap();
+        let version: i32 = new_instance.call("version").invoke_typed().unwrap();
+        assert_eq!(version, 2);
+        // loaded before the swap
+        let version: i32 = old_instance.call("version").invoke_typed().unwrap();
+        assert_eq!(version, 1);
+
+        assert!(runtime.unregister_host_module("test_swap"));
+        assert!(!runtime.unregister_host_module("test_swap"));
+        let version: i32 = new_instance.call("version").invoke_typed().unwrap();
+        assert_eq!(version, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "macros")]
+    fn test_host_function_macro() {
+        #[crate::host_function]
+        fn greet(caller: &Caller, name: &str, times: i32) -> Result<i32, String> {
+            let prefix = caller.host_data::<String>().cloned().unwrap_or_default();
+            if times < 0 {
+                return Err(format!("negative times {}", times));
+            }
+            Ok((prefix.len() + name.len()) as i32 * times)
+        }
+
+        #[crate::host_function]
+        fn checksum(bytes: &[u8]) -> i64 {
+            bytes.iter().map(|b| *b as i64).sum()
+        }
+
+        let runtime = Runtime::builder()
+            .use_system_allocator()
+            .register("test_macros", "greet", greet())
+            .register("test_macros", "checksum", checksum())
+            .build()
+            .unwrap();
+
+        let wat = r#"
+            (module
+              (import "test_macros" "greet" (func $greet (param i32 i32 i32) (result i32)))
+              (import "test_macros" "checksum" (func $checksum (param i32 i32) (result i64)))
+              (memory (export "memory") 1)
+              (data (i32.const 16) "wamr")
+              (func (export "greet") (param i32) (result i32)
+                (call $greet (i32.const 16) (i32.const 4) (local.get 0))
+              )
+              (func (export "checksum") (result i64)
+                (call $checksum (i32.const 16) (i32.const 4))
+              )
+              (func (export "greet_oob") (result i32)
+                (call $greet (i32.const 65534) (i32.const 4) (i32.const 1))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::builder(&runtime, &module)
+            .host_data(String::from("hi "))
+            .build()
+            .unwrap();
+
+        let greeted: i32 = instance.call("greet").arg(2i32).invoke_typed().unwrap();
+        assert_eq!(greeted, 14);
+        let checksum: i64 = instance.call("checksum").invoke_typed().unwrap();
+        assert_eq!(checksum, b"wamr".iter().map(|b| *b as i64).sum::<i64>());
+
+        assert!(instance.call("greet").arg(-1i32).invoke().is_err());
+        assert!(instance.call("greet_oob").invoke().is_err());
+    }
+
+    #[test]
+    fn test_panic_message() {
+        assert_eq!(panic_message(&"bug"), "host function panicked: bug");
+        assert_eq!(
+            panic_message(&format!("bug {}", 1)),
+            "host function panicked: bug 1"
+        );
+        assert_eq!(panic_message(&42), "host function panicked");
+    }
+
+    #[test]
+    fn test_host_function_signature() {
+        fn signature<Params, Results, H: IntoHostFunction<Params, Results>>(_: H) -> String {
+            H::signature()
+        }
+
+        assert_eq!(signature(|_: &Caller| {}), "()");
+        assert_eq!(signature(|_: &Caller, a: i32, b: i32| a + b), "(ii)i");
+        assert_eq!(
+            signature(|_: &Caller, _: i64, _: f32, _: f64| Ok::<i64, String>(0)),
+            "(IfF)I"
+        );
+    }
+
+    #[test]
+    fn test_native_function_signature() {
+        fn signature<F: NativeFunction>(_: F) -> String {
+            F::signature()
+        }
+
+        extern "C" fn extra() -> i32 {
+            100
+        }
+        extern "C" fn log(_: wasm_exec_env_t, _: i32, _: i64) {}
+        extern "C" fn scale(_: f32, _: f64) -> f64 {
+            0.0
+        }
+
+        assert_eq!(signature(extra as extern "C" fn() -> i32), "()i");
+        assert_eq!(
+            signature(log as extern "C" fn(wasm_exec_env_t, i32, i64)),
+            "(iI)"
+        );
+        assert_eq!(signature(scale as extern "C" fn(f32, f64) -> f64), "(fF)F");
+
+        let mut host_functions = HostFunctionList::new("host");
+        host_functions.register_host_function("raw", extra as *mut c_void);
+        host_functions.register_native_function("extra", extra as extern "C" fn() -> i32);
+        let native_symbols = host_functions.get_native_symbols();
+        assert!(native_symbols[0].signature.is_null());
+        let derived = unsafe { std::ffi::CStr::from_ptr(native_symbols[1].signature) };
+        assert_eq!(derived.to_str(), Ok("()i"));
+    }
+
     #[test]
     #[ignore]
     fn test_host_function() {
@@ -101,6 +1608,6 @@ mod tests {
 
         let params: Vec<WasmValue> = vec![WasmValue::I32(8), WasmValue::I32(8)];
         let result = function.call(instance, &params);
-        assert_eq!(result.unwrap(), WasmValue::I32(116));
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(116)]);
     }
 }
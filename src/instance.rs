@@ -8,30 +8,301 @@
 
 #![allow(unused_variables)]
 
-use core::ffi::c_char;
+use core::ffi::{c_char, c_void};
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::HashMap,
+    ffi::CString,
+    fmt,
+    marker::PhantomData,
+    ops::Deref,
+    panic::Location,
+    ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::Instant,
+};
 
 use wamr_sys::{
-    wasm_module_inst_t, wasm_runtime_deinstantiate, wasm_runtime_destroy_thread_env,
-    wasm_runtime_init_thread_env, wasm_runtime_instantiate,
+    enlarge_memory_error_reason_t, enlarge_memory_error_reason_t_MAX_SIZE_REACHED,
+    mem_alloc_info_t, wasm_exec_env_t, wasm_export_t, wasm_externref_obj2ref,
+    wasm_externref_objdel, wasm_externref_ref2obj, wasm_global_inst_t,
+    wasm_memory_get_bytes_per_page, wasm_memory_get_cur_page_count, wasm_memory_get_max_page_count,
+    wasm_module_inst_t, wasm_module_t, wasm_runtime_addr_app_to_native,
+    wasm_runtime_addr_native_to_app, wasm_runtime_call_wasm, wasm_runtime_clear_exception,
+    wasm_runtime_deinstantiate, wasm_runtime_destroy_spawned_exec_env,
+    wasm_runtime_destroy_thread_env, wasm_runtime_dump_call_stack_to_buf,
+    wasm_runtime_get_call_stack_buf_size, wasm_runtime_get_custom_data,
+    wasm_runtime_get_default_memory, wasm_runtime_get_exception,
+    wasm_runtime_get_exec_env_singleton, wasm_runtime_get_export_count,
+    wasm_runtime_get_export_global_inst, wasm_runtime_get_export_type,
+    wasm_runtime_get_mem_alloc_info, wasm_runtime_get_memory, wasm_runtime_get_module_inst,
+    wasm_runtime_get_wasi_exit_code, wasm_runtime_init_thread_env, wasm_runtime_instantiate_ex,
+    wasm_runtime_lookup_function, wasm_runtime_lookup_memory, wasm_runtime_module_free,
+    wasm_runtime_module_malloc, wasm_runtime_set_custom_data,
+    wasm_runtime_set_enlarge_mem_error_callback, wasm_runtime_spawn_exec_env,
+    wasm_runtime_validate_app_addr, wasm_runtime_validate_native_addr, InstantiationArgs,
 };
 
 use crate::{
-    helper::error_buf_to_string, helper::DEFAULT_ERROR_BUF_SIZE, module::Module, runtime::Runtime,
+    cancel::{CancelHandle, CancelState},
+    function::{CallBuilder, CallEvent, CallOutcome, Function},
+    global::Global,
+    handle::HandleTable,
+    helper::error_buf_to_string,
+    helper::exception_to_string,
+    helper::DEFAULT_ERROR_BUF_SIZE,
+    memory::Memory,
+    module::{Module, ModuleKind},
+    quota::{HostQuota, QuotaUsage},
+    runtime::Runtime,
+    snapshot::Snapshot,
+    table::{self, Table},
+    trap::{parse_call_stack, Frame},
+    types::{ExportType, ExternType, FuncType, MemoryType, TableType},
+    value::{ExternRef, FuncRef},
+    wasi_natives::WasiState,
+    wasi_staged_dir::MaterializedDir,
+    wasm_binary::{resolve_function_names, MEMORY_GROW_TRAP_GLOBAL},
     RuntimeError,
 };
 
+#[cfg(feature = "memory-profiling")]
+use crate::helper::capture_output;
+#[cfg(feature = "shared-heap")]
+use crate::shared_heap::SharedHeap;
+#[cfg(target_os = "linux")]
+use crate::snapshot::MappedSnapshot;
+#[cfg(feature = "memory-profiling")]
+use wamr_sys::wasm_runtime_dump_mem_consumption;
+#[cfg(feature = "shared-heap")]
+use wamr_sys::{wasm_runtime_shared_heap_free, wasm_runtime_shared_heap_malloc};
+
+const DEFAULT_STACK_SIZE: u32 = 64 * 1024;
+// 4GiB
+const MAX_MEMORY_PAGES: u32 = 65536;
+
 #[derive(Debug)]
-pub struct Instance {
+pub struct Instance<'module> {
     instance: wasm_module_inst_t,
+    // never reused, unlike the address of `instance`. `Function`s and `FuncRef`s are bound
+    // to it. A new one after `reset()`
+    id: u64,
+    // keeps the module loaded. to list exports and to instantiate again
+    module: Module<'module>,
+    // to instantiate again in `reset()`
+    args: InstantiationArgs,
+    // shared with the module. to name functions in call stacks
+    function_names: Arc<HashMap<u32, String>>,
+    // boxed to have a stable address as the custom data of the instance. An instance created
+    // by `spawn()` shares the one of its parent
+    context: Option<Box<InstanceContext>>,
+    // of an instance created by `spawn()`
+    spawned_exec_env: Option<wasm_exec_env_t>,
+    // see `InstanceBuilder::leak_report()`
+    leaks: Option<LeakTracker>,
+    // whether `_initialize` of a reactor runs at instantiation. Also in `reset()`
+    initialize: bool,
+    // shared with `CancelHandle`s
+    cancel: Arc<CancelState>,
+    // see `InstanceBuilder::canonicalize_result_nans()`
+    canonicalize_result_nans: bool,
+    // writable layers of overlays of the Wasi context. An instance created by `spawn()`
+    // uses those of its parent
+    _wasi_layers: Vec<MaterializedDir>,
+}
+
+type HostData = Box<dyn Any + Send>;
+
+type HostObject = Box<dyn Any + Send>;
+
+type GrowFailureCallback = Box<dyn Fn(&MemoryGrowDenied) + Send + Sync>;
+
+type CallStartHook = Box<dyn Fn(&CallEvent) + Send + Sync>;
+
+type CallEndHook = Box<dyn Fn(&CallEvent, &CallOutcome) + Send + Sync>;
+
+/// why growing a linear memory is denied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrowDeniedReason {
+    /// beyond the maximum of the memory, or the cap of `InstanceBuilder::memory_limit()`
+    MaximumReached,
+    /// the runtime failed to allocate memory
+    AllocationFailed,
 }
 
-impl Instance {
+/// a denied attempt to grow a linear memory. See `InstanceBuilder::on_memory_grow_failure()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryGrowDenied {
+    /// the underlying instance, as `Instance::get_inner_instance()`
+    pub instance: wasm_module_inst_t,
+    pub memory_index: u32,
+    pub current_pages: u64,
+    pub delta_pages: u32,
+    pub reason: GrowDeniedReason,
+}
+
+// the instance is a handle to compare with, never dereferenced
+unsafe impl Send for MemoryGrowDenied {}
+unsafe impl Sync for MemoryGrowDenied {}
+
+// see `Instance::id`
+static NEXT_INSTANCE_ID: AtomicU64 = AtomicU64::new(1);
+
+// the custom data of an instance
+#[derive(Default)]
+struct InstanceContext {
+    host_data: Option<HostData>,
+    // of the instance and those from `spawn()`, by their addresses
+    instance_ids: Mutex<HashMap<usize, u64>>,
+    // see `InstanceBuilder::memory_limit()`
+    memory_limit: Option<u32>,
+    // pages requested by the last `memory.grow` beyond the limit. 0 for none
+    memory_limit_exceeded: AtomicU64,
+    on_memory_grow_failure: Option<GrowFailureCallback>,
+    on_call_start: Option<CallStartHook>,
+    on_call_end: Option<CallEndHook>,
+    on_host_call: Option<CallEndHook>,
+    // host objects of externrefs, by indexes in WAMR. double boxed to have a unique
+    // address, even for zero-sized objects, as the key of WAMR
+    externrefs: Mutex<HashMap<u32, Box<HostObject>>>,
+    handles: Mutex<HandleTable>,
+    // by imports like `env.log`. see `InstanceBuilder::host_quota()`
+    host_quotas: Mutex<HashMap<String, QuotaUsage>>,
+    // see `wasi_natives`
+    wasi: WasiState,
+    // attached again in `reset()`
+    #[cfg(feature = "shared-heap")]
+    shared_heap: Option<SharedHeap>,
+}
+
+impl fmt::Debug for InstanceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstanceContext")
+            .field("host_data", &self.host_data)
+            .field("memory_limit", &self.memory_limit)
+            .field("memory_limit_exceeded", &self.memory_limit_exceeded)
+            .finish_non_exhaustive()
+    }
+}
+
+impl InstanceContext {
+    // of an instance created by this crate
+    unsafe fn of<'a>(instance: wasm_module_inst_t) -> Option<&'a InstanceContext> {
+        (wasm_runtime_get_custom_data(instance) as *const InstanceContext).as_ref()
+    }
+
+    fn as_ptr(&self) -> *mut c_void {
+        self as *const InstanceContext as *mut c_void
+    }
+
+    // a new id of `instance`, which has been instantiated with the context, or spawned
+    // from one
+    unsafe fn assign_id(instance: wasm_module_inst_t) -> u64 {
+        let id = NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed);
+        if let Some(context) = InstanceContext::of(instance) {
+            context
+                .instance_ids
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(instance as usize, id);
+        }
+        id
+    }
+
+    // before `instance` is destroyed, so another one at the same address doesn't take it
+    unsafe fn release_id(instance: wasm_module_inst_t) {
+        if let Some(context) = InstanceContext::of(instance) {
+            context
+                .instance_ids
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&(instance as usize));
+        }
+    }
+}
+
+type LeakReport = Box<dyn FnOnce(&[Allocation])>;
+
+/// a buffer allocated by the host in the instance, via `Instance::malloc()` or
+/// `Memory::write_str()`, and not released yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    /// the address in the memory
+    pub offset: u64,
+    pub size: u64,
+    /// where the host allocates it
+    pub location: &'static Location<'static>,
+}
+
+/// memory consumed by an instance. Get one via `Instance::memory_stats()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// the number of linear memories
+    pub memory_count: u32,
+    /// bytes of all linear memories
+    pub linear_memory_size: u64,
+    /// bytes of all linear memories when they reach their maximums
+    pub linear_memory_max_size: u64,
+    /// bytes of the host managed heap, in the default memory
+    pub host_heap_size: u32,
+    /// bytes the host allocated in the instance and not released yet. Only tracked with
+    /// `InstanceBuilder::leak_report()`
+    pub host_allocated_size: Option<u64>,
+    /// bytes of the wasm operand stack and the native stack of calls
+    pub stack_size: u32,
+    /// the most bytes of the host managed heap ever in use. Only with the
+    /// `memory-profiling` feature, like others below
+    pub host_heap_used: Option<u32>,
+    /// the most bytes of the wasm operand stack ever in use, by calls of the interpreter
+    pub wasm_stack_used: Option<u32>,
+    /// the most bytes of the aux stack ever in use. `None` if WAMR doesn't know where it
+    /// is, like for a module not compiled from C or Rust
+    pub aux_stack_used: Option<u32>,
+    /// the least bytes of the native stack ever left, by calls of the instance
+    pub native_stack_left: Option<u64>,
+    /// the pool of the runtime, if the runtime allocates from a pool. see
+    /// `RuntimeBuilder::use_memory_pool()`
+    pub runtime_pool: Option<PoolStats>,
+}
+
+/// usage of the memory pool of the runtime, shared by all instances
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub total_size: u32,
+    pub free_size: u32,
+    /// the most bytes ever in use
+    pub highmark_size: u32,
+}
+
+struct LeakTracker {
+    // by offset
+    allocations: RefCell<HashMap<u64, Allocation>>,
+    report: Option<LeakReport>,
+}
+
+impl fmt::Debug for LeakTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LeakTracker")
+            .field("allocations", &self.allocations)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'module> Instance<'module> {
     /// instantiate a module with stack size
     ///
     /// # Error
     ///
     /// Return `RuntimeError::CompilationError` if failed.
-    pub fn new(runtime: &Runtime, module: &Module, stack_size: u32) -> Result<Self, RuntimeError> {
+    pub fn new(
+        runtime: &Runtime,
+        module: &Module<'module>,
+        stack_size: u32,
+    ) -> Result<Self, RuntimeError> {
         Self::new_with_args(runtime, module, stack_size, 0)
     }
 
@@ -43,11 +314,1155 @@ impl Instance {
     ///
     /// Return `RuntimeError::CompilationError` if failed.
     pub fn new_with_args(
-        _runtime: &Runtime,
-        module: &Module,
+        runtime: &Runtime,
+        module: &Module<'module>,
         stack_size: u32,
         heap_size: u32,
     ) -> Result<Self, RuntimeError> {
+        Self::builder(runtime, module)
+            .stack_size(stack_size)
+            .host_heap_size(heap_size)
+            .build()
+    }
+
+    /// return an `InstanceBuilder` to instantiate the module with more configuration
+    pub fn builder(runtime: &Runtime, module: &Module<'module>) -> InstanceBuilder<'module> {
+        InstanceBuilder::new(runtime, module)
+    }
+
+    /// attach data to the instance, like a tenant id or a request context. It replaces
+    /// the previous one
+    ///
+    /// It is the custom data of the underlying instance. Host functions get it back via
+    /// `Instance::host_data_of_exec_env()`.
+    pub fn set_host_data<T: Any + Send>(&mut self, data: T) {
+        let instance = self.instance;
+        let context = self.context.get_or_insert_with(|| {
+            let context = Box::<InstanceContext>::default();
+            unsafe { wasm_runtime_set_custom_data(instance, context.as_ptr()) };
+            context
+        });
+        context.host_data = Some(Box::new(data));
+    }
+
+    /// the data attached by `set_host_data()` or `InstanceBuilder::host_data()`, if it is a `T`
+    pub fn host_data<T: Any>(&self) -> Option<&T> {
+        self.context
+            .as_ref()?
+            .host_data
+            .as_ref()?
+            .downcast_ref::<T>()
+    }
+
+    /// the data attached to the instance running in `exec_env`, if it is a `T`. It is
+    /// for host functions, which get an `exec_env` as the first parameter
+    ///
+    /// # Safety
+    ///
+    /// `exec_env` should be a valid execution environment of an `Instance`, and the
+    /// custom data of the instance should only be set by this crate.
+    pub unsafe fn host_data_of_exec_env<'a, T: Any>(exec_env: wasm_exec_env_t) -> Option<&'a T> {
+        let instance = wasm_runtime_get_module_inst(exec_env);
+        if instance.is_null() {
+            return None;
+        }
+
+        InstanceContext::of(instance)?
+            .host_data
+            .as_ref()?
+            .downcast_ref::<T>()
+    }
+
+    // see `Instance::id`
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    // the id of an instance created by this crate, or 0
+    pub(crate) unsafe fn id_of(instance: wasm_module_inst_t) -> u64 {
+        InstanceContext::of(instance)
+            .and_then(|context| {
+                context
+                    .instance_ids
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get(&(instance as usize))
+                    .copied()
+            })
+            .unwrap_or_default()
+    }
+
+    /// the table of host objects the instance holds handles to. See `HandleTable`.
+    /// Shared with instances from `spawn()`
+    pub fn handles(&self) -> Option<MutexGuard<'_, HandleTable>> {
+        unsafe { Self::handles_of(self.instance) }
+    }
+
+    // the handle table of an instance created by this crate
+    pub(crate) unsafe fn handles_of<'a>(
+        instance: wasm_module_inst_t,
+    ) -> Option<MutexGuard<'a, HandleTable>> {
+        let context = InstanceContext::of(instance)?;
+        Some(
+            context
+                .handles
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    /// wrap a host object into an externref, to pass it to wasm. The object lives as long
+    /// as the instance, or until `remove_externref()`
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::ExternRefFailure` if WAMR failed to register the object.
+    pub fn new_externref<T: Any + Send>(&self, object: T) -> Result<ExternRef, RuntimeError> {
+        let context = unsafe { InstanceContext::of(self.instance) }
+            .ok_or_else(|| RuntimeError::ExternRefFailure(String::from("unknown instance")))?;
+
+        let object: Box<HostObject> = Box::new(Box::new(object));
+        let mut index = 0;
+        let registered =
+            unsafe { wasm_externref_obj2ref(self.instance, host_object_ptr(&object), &mut index) };
+        if !registered {
+            return Err(RuntimeError::ExternRefFailure(String::from(
+                "failed to register the object",
+            )));
+        }
+
+        context
+            .externrefs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(index, object);
+        Ok(ExternRef::from_index(index))
+    }
+
+    /// the host object of an externref, if it is a `T` created by this instance, or by an
+    /// instance sharing the host data, like one from `spawn()`
+    pub fn externref<T: Any>(&self, externref: ExternRef) -> Option<&T> {
+        let context = unsafe { InstanceContext::of(self.instance) }?;
+        let externrefs = context
+            .externrefs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let object = externrefs.get(&externref.index())?;
+
+        // an index is reused after WAMR drops it, like after `reset()`
+        let mut registered = ptr::null_mut();
+        let found = unsafe { wasm_externref_ref2obj(externref.index(), &mut registered) };
+        if !found || registered != host_object_ptr(object) {
+            return None;
+        }
+
+        let object = object.downcast_ref::<T>()? as *const T;
+        // the object is boxed, and only dropped by `remove_externref()` or `reset()` which
+        // borrow the instance mutably, or with the instance
+        Some(unsafe { &*object })
+    }
+
+    /// unregister an externref, and return its host object. Wasm holding the externref
+    /// can't get the object anymore
+    ///
+    /// Return `None` if the externref isn't created by this instance, or by one from
+    /// `spawn()` whose objects are kept by the parent.
+    pub fn remove_externref(&mut self, externref: ExternRef) -> Option<Box<dyn Any + Send>> {
+        let object = self
+            .context
+            .as_mut()?
+            .externrefs
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&externref.index())?;
+        unsafe { wasm_externref_objdel(self.instance, host_object_ptr(&object)) };
+        Some(*object)
+    }
+
+    // count a call of a host closure `import` by `instance` against its quota, or tell
+    // which limit it exceeds
+    pub(crate) unsafe fn charge_host_call(
+        instance: wasm_module_inst_t,
+        import: &str,
+    ) -> Result<(), String> {
+        let Some(context) = InstanceContext::of(instance) else {
+            return Ok(());
+        };
+        let mut host_quotas = context
+            .host_quotas
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match host_quotas.get_mut(import) {
+            Some(usage) => usage.charge(Instant::now()),
+            None => Ok(()),
+        }
+    }
+
+    // of the Wasi context of the module of `instance`
+    pub(crate) unsafe fn wasi_state<'a>(instance: wasm_module_inst_t) -> Option<&'a WasiState> {
+        InstanceContext::of(instance).map(|context| &context.wasi)
+    }
+
+    // whether `InstanceBuilder::on_host_call()` is set for `instance`
+    pub(crate) unsafe fn has_host_call_hook(instance: wasm_module_inst_t) -> bool {
+        InstanceContext::of(instance).is_some_and(|context| context.on_host_call.is_some())
+    }
+
+    pub(crate) unsafe fn report_host_call(
+        instance: wasm_module_inst_t,
+        event: &CallEvent,
+        outcome: &CallOutcome,
+    ) {
+        let context = InstanceContext::of(instance);
+        if let Some(hook) = context.and_then(|context| context.on_host_call.as_ref()) {
+            hook(event, outcome);
+        }
+    }
+
+    // a call into the instance from the host starts, so quotas per call start over
+    pub(crate) fn reset_host_quotas(&self) {
+        let Some(context) = (unsafe { InstanceContext::of(self.instance) }) else {
+            return;
+        };
+        context
+            .host_quotas
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values_mut()
+            .for_each(QuotaUsage::reset_call);
+    }
+
+    // the error of the last failure caused by `InstanceBuilder::memory_limit()`, if any
+    pub(crate) fn take_memory_limit_error(&self) -> Option<RuntimeError> {
+        let context = unsafe { InstanceContext::of(self.instance) }?;
+        let requested = context.memory_limit_exceeded.swap(0, Ordering::Relaxed);
+        if requested == 0 {
+            return None;
+        }
+
+        unsafe { set_memory_grow_trap(self.instance, false) };
+        Some(RuntimeError::MemoryLimitExceeded {
+            limit: context.memory_limit.unwrap_or_default(),
+            requested,
+        })
+    }
+
+    // whether any hook of `InstanceBuilder::on_call_start()` or `on_call_end()` is set
+    pub(crate) fn has_call_hooks(&self) -> bool {
+        unsafe { InstanceContext::of(self.instance) }
+            .is_some_and(|context| context.on_call_start.is_some() || context.on_call_end.is_some())
+    }
+
+    pub(crate) fn report_call_start(&self, event: &CallEvent) {
+        let context = unsafe { InstanceContext::of(self.instance) };
+        if let Some(hook) = context.and_then(|context| context.on_call_start.as_ref()) {
+            hook(event);
+        }
+    }
+
+    pub(crate) fn report_call_end(&self, event: &CallEvent, outcome: &CallOutcome) {
+        let context = unsafe { InstanceContext::of(self.instance) };
+        if let Some(hook) = context.and_then(|context| context.on_call_end.as_ref()) {
+            hook(event, outcome);
+        }
+    }
+
+    /// a handle to cancel calls into the instance from other threads. It stays valid
+    /// after `reset()`
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle::new(&self.cancel)
+    }
+
+    // whether the instance is cancelled since the last time. clear the exception of it, if so
+    pub(crate) fn take_cancelled(&self) -> bool {
+        let cancelled = self.cancel.take();
+        if cancelled {
+            unsafe { wasm_runtime_clear_exception(self.instance) };
+        }
+        cancelled
+    }
+
+    /// allocate a buffer of `size` bytes in the instance. return the address of the buffer
+    /// in the memory, which can be passed to wasm functions, and the buffer itself to fill
+    ///
+    /// The buffer is allocated from the host managed heap, see `InstanceBuilder::host_heap_size()`,
+    /// or by the exported `malloc` of the module. Release it via `free()`. The returned slice
+    /// borrows the instance mutably, since calling into the instance may move the memory.
+    ///
+    /// # Error
+    ///
+    /// If the allocation fails, an `RuntimeError::ExecutionError` will be returned.
+    #[track_caller]
+    pub fn malloc(&mut self, size: u64) -> Result<(u64, &mut [u8]), RuntimeError> {
+        let (offset, native) = self.module_malloc(size)?;
+        let buf = unsafe { std::slice::from_raw_parts_mut(native as *mut u8, size as usize) };
+        Ok((offset, buf))
+    }
+
+    /// release a buffer allocated by `malloc()` or `Memory::write_str()`
+    pub fn free(&self, offset: u64) {
+        unsafe { wasm_runtime_module_free(self.instance, offset) };
+
+        if let Some(leaks) = &self.leaks {
+            leaks.allocations.borrow_mut().remove(&offset);
+        }
+    }
+
+    /// allocate a buffer in the shared heap the instance is attached to. Return its wasm
+    /// address, which is the same in all instances attached to the heap, and its native
+    /// address. Both are valid until `shared_heap_free()`. See `SharedHeap`
+    ///
+    /// # Error
+    ///
+    /// If there is no shared heap, or it is full, an `RuntimeError::ExecutionError` will be
+    /// returned.
+    #[cfg(feature = "shared-heap")]
+    pub fn shared_heap_malloc(&self, size: u64) -> Result<(u64, *mut u8), RuntimeError> {
+        let mut native: *mut c_void = ptr::null_mut();
+        let offset = unsafe { wasm_runtime_shared_heap_malloc(self.instance, size, &mut native) };
+        if offset == 0 || native.is_null() {
+            unsafe { wasm_runtime_clear_exception(self.instance) };
+            return Err(RuntimeError::ExecutionError(String::from(
+                "allocate shared heap failed",
+            )));
+        }
+        Ok((offset, native as *mut u8))
+    }
+
+    /// release a buffer allocated by `shared_heap_malloc()`, maybe of another instance
+    /// attached to the same heap
+    #[cfg(feature = "shared-heap")]
+    pub fn shared_heap_free(&self, offset: u64) {
+        unsafe { wasm_runtime_shared_heap_free(self.instance, offset) };
+    }
+
+    #[track_caller]
+    pub(crate) fn module_malloc(&self, size: u64) -> Result<(u64, *mut c_void), RuntimeError> {
+        let mut native: *mut c_void = ptr::null_mut();
+        let offset = unsafe { wasm_runtime_module_malloc(self.instance, size, &mut native) };
+
+        if offset == 0 || native.is_null() {
+            let exception = unsafe { wasm_runtime_get_exception(self.instance) };
+            let message = match exception.is_null() {
+                true => String::from("allocate memory failed"),
+                false => exception_to_string(exception),
+            };
+            unsafe { wasm_runtime_clear_exception(self.instance) };
+            return Err(RuntimeError::ExecutionError(message));
+        }
+
+        if let Some(leaks) = &self.leaks {
+            let allocation = Allocation {
+                offset,
+                size,
+                location: Location::caller(),
+            };
+            leaks.allocations.borrow_mut().insert(offset, allocation);
+        }
+
+        Ok((offset, native))
+    }
+
+    /// whether [app_offset, app_offset + size) is inside the default linear memory
+    pub fn validate_app_addr(&self, app_offset: u64, size: u64) -> bool {
+        let valid = unsafe { wasm_runtime_validate_app_addr(self.instance, app_offset, size) };
+        if !valid {
+            // WAMR raises an "out of bounds memory access"
+            unsafe { wasm_runtime_clear_exception(self.instance) };
+        }
+        valid
+    }
+
+    /// the native address of a wasm address, like a pointer passed as an i32 argument,
+    /// after checking [app_offset, app_offset + size) is inside the default linear memory
+    ///
+    /// The address is invalid once the memory grows.
+    ///
+    /// # Error
+    ///
+    /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+    pub fn app_to_native(&self, app_offset: u64, size: u64) -> Result<*mut u8, RuntimeError> {
+        let native = match self.validate_app_addr(app_offset, size) {
+            true => unsafe { wasm_runtime_addr_app_to_native(self.instance, app_offset) },
+            false => ptr::null_mut(),
+        };
+
+        match native.is_null() {
+            true => Err(RuntimeError::MemoryAccessOutOfBounds {
+                offset: app_offset,
+                len: size,
+            }),
+            false => Ok(native as *mut u8),
+        }
+    }
+
+    /// the wasm address of a native address, after checking [native, native + size) is
+    /// inside the default linear memory
+    ///
+    /// # Error
+    ///
+    /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be
+    /// returned, with the native address as the offset.
+    pub fn native_to_app(&self, native: *const u8, size: u64) -> Result<u64, RuntimeError> {
+        let native = native as *mut c_void;
+        let valid = unsafe { wasm_runtime_validate_native_addr(self.instance, native, size) };
+        if !valid {
+            unsafe { wasm_runtime_clear_exception(self.instance) };
+            return Err(RuntimeError::MemoryAccessOutOfBounds {
+                offset: native as u64,
+                len: size,
+            });
+        }
+
+        Ok(unsafe { wasm_runtime_addr_native_to_app(self.instance, native) })
+    }
+
+    /// create another instance of the same module, which shares memories declared as
+    /// `shared` with this one. Other memories, globals and tables are not shared
+    ///
+    /// Cooperating instances, maybe on other threads, are able to exchange large data via
+    /// the shared memory without copies through the host.
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::InstantiationFailure` if failed.
+    pub fn spawn(&self) -> Result<SpawnedInstance<'_>, RuntimeError> {
+        let exec_env = unsafe {
+            let parent = wasm_runtime_get_exec_env_singleton(self.instance);
+            if parent.is_null() {
+                ptr::null_mut()
+            } else {
+                wasm_runtime_spawn_exec_env(parent)
+            }
+        };
+        if exec_env.is_null() {
+            return Err(RuntimeError::InstantiationFailure(String::from(
+                "spawn instance failed",
+            )));
+        }
+
+        let inner = unsafe { wasm_runtime_get_module_inst(exec_env) };
+        // WAMR may have attached it while spawning, which is fine
+        #[cfg(feature = "shared-heap")]
+        if let Some(heap) = self
+            .context
+            .as_ref()
+            .and_then(|context| context.shared_heap.as_ref())
+        {
+            let _ = heap.attach(inner);
+        }
+        let instance = Instance {
+            instance: inner,
+            id: unsafe { InstanceContext::assign_id(inner) },
+            module: self.module.clone(),
+            args: self.args,
+            function_names: Arc::clone(&self.function_names),
+            context: None,
+            spawned_exec_env: Some(exec_env),
+            leaks: None,
+            initialize: self.initialize,
+            cancel: CancelState::new(inner),
+            canonicalize_result_nans: self.canonicalize_result_nans,
+            _wasi_layers: Vec::new(),
+        };
+
+        Ok(SpawnedInstance {
+            instance,
+            _parent: PhantomData,
+        })
+    }
+
+    /// capture linear memories, exported mutable globals and exported tables. See `Snapshot`
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::NotImplemented` if a captured global is a reference, or if a
+    /// table is exported without the `table-internals` feature.
+    pub fn snapshot(&self) -> Result<Snapshot, RuntimeError> {
+        Snapshot::capture(self)
+    }
+
+    /// restore what a snapshot captured. Memories can't shrink, pages grown after the
+    /// snapshot are kept but cleared
+    ///
+    /// It is much cheaper than `reset()` for small instances, and is able to restore to any
+    /// point, like after initialization.
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::SnapshotMismatch` if the snapshot is taken from an instance
+    /// of another module.
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<(), RuntimeError> {
+        snapshot.restore(self)
+    }
+
+    /// restore a snapshot by mapping its memories copy-on-write. See `MappedSnapshot`
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::SnapshotMismatch` if the snapshot is taken from an instance
+    /// of another module.
+    #[cfg(target_os = "linux")]
+    pub fn restore_mapped(&self, snapshot: &MappedSnapshot) -> Result<(), RuntimeError> {
+        snapshot.restore(self)
+    }
+
+    /// restore linear memories, globals, tables and the WASI state to the state right after
+    /// instantiation. So the instance can be reused without leaking information between
+    /// invocations
+    ///
+    /// It instantiates the module again with the same configuration and custom data, calls
+    /// `_initialize` of a reactor again, and replaces the underlying instance. `Function`s and
+    /// `FuncRef`s got before are rejected after that,
+    /// and host objects of externrefs and handles are released.
+    /// An instance created by `spawn()` becomes a standalone one, without shared memories.
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::InstantiationFailure` if failed. The instance is untouched then.
+    pub fn reset(&mut self) -> Result<(), RuntimeError> {
+        let (instance, wasi_layers) = instantiate(
+            &self.module,
+            &self.args,
+            self.context.as_deref(),
+            self.initialize,
+        )?;
+
+        // `CancelHandle`s wait until the replacement is done
+        let mut cancel = self.cancel.instance();
+        unsafe {
+            InstanceContext::release_id(self.instance);
+            match self.spawned_exec_env.take() {
+                Some(exec_env) => wasm_runtime_destroy_spawned_exec_env(exec_env),
+                None => wasm_runtime_deinstantiate(self.instance),
+            }
+        }
+        self.instance = instance;
+        self.id = unsafe { InstanceContext::assign_id(instance) };
+        *cancel = instance as usize;
+        drop(cancel);
+        self._wasi_layers = wasi_layers;
+
+        // gone with the heap
+        if let Some(leaks) = &self.leaks {
+            leaks.allocations.borrow_mut().clear();
+        }
+        // WAMR forgets externrefs of the old instance
+        if let Some(context) = &mut self.context {
+            context
+                .externrefs
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+            // the new instance knows none of them
+            context
+                .handles
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+            context.wasi.reset();
+        }
+        Ok(())
+    }
+
+    /// list all exports of the instance, with their kinds and types
+    ///
+    /// Unlike `Module::exports()`, types are resolved against the instance. Signatures come
+    /// from instantiated functions, and memories and tables are in their current sizes.
+    pub fn exports(&self) -> impl Iterator<Item = ExportType> + '_ {
+        let export_count = unsafe { wasm_runtime_get_export_count(self.get_inner_module()) };
+
+        let exports = (0..export_count).map(move |i| {
+            let mut export = wasm_export_t::default();
+            unsafe { wasm_runtime_get_export_type(self.get_inner_module(), i, &mut export) };
+            let declared = ExportType::from_raw(&export);
+            let name = declared.name().to_string();
+
+            let ty = match declared.ty() {
+                ExternType::Func(func_type) => {
+                    let function =
+                        unsafe { wasm_runtime_lookup_function(self.instance, export.name) };
+                    match function.is_null() {
+                        true => ExternType::Func(func_type.clone()),
+                        false => ExternType::Func(FuncType::from_function(function, self.instance)),
+                    }
+                }
+                ExternType::Memory(memory_type) => {
+                    let memory = unsafe { wasm_runtime_lookup_memory(self.instance, export.name) };
+                    match memory.is_null() {
+                        true => ExternType::Memory(memory_type.clone()),
+                        false => ExternType::Memory(MemoryType {
+                            shared: memory_type.shared,
+                            min: unsafe { wasm_memory_get_cur_page_count(memory) } as u32,
+                            max: unsafe { wasm_memory_get_max_page_count(memory) } as u32,
+                        }),
+                    }
+                }
+                ExternType::Table(table_type) => match self.table(&name) {
+                    Ok(table) => ExternType::Table(TableType {
+                        min: table.len(),
+                        max: table.max_size(),
+                        ..table_type.clone()
+                    }),
+                    Err(_) => ExternType::Table(table_type.clone()),
+                },
+                ty => ty.clone(),
+            };
+
+            ExportType::new(name, ty)
+        });
+        // see `LoadOptions::guard_memory_grow()`
+        exports.filter(|export| export.name() != MEMORY_GROW_TRAP_GLOBAL)
+    }
+
+    /// the default linear memory, if the module defines or imports one
+    pub fn memory(&self) -> Option<Memory<'_>> {
+        let memory = unsafe { wasm_runtime_get_default_memory(self.instance) };
+        match memory.is_null() {
+            true => None,
+            false => Some(Memory::from_raw(self, memory)),
+        }
+    }
+
+    /// the linear memory with the index, for modules with multiple memories. the index
+    /// space includes imported memories. `memory_at(0)` is the default memory
+    pub fn memory_at(&self, index: u32) -> Option<Memory<'_>> {
+        let memory = unsafe { wasm_runtime_get_memory(self.instance, index) };
+        match memory.is_null() {
+            true => None,
+            false => Some(Memory::from_raw(self, memory)),
+        }
+    }
+
+    /// collect memory consumption of the instance, to export as metrics
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut memory_count = 0;
+        let mut linear_memory_size = 0;
+        let mut linear_memory_max_size = 0;
+        for memory in self.memories() {
+            memory_count += 1;
+            linear_memory_size += memory.data_size();
+            linear_memory_max_size += memory.max_pages() * memory.page_size();
+        }
+
+        let mut info = mem_alloc_info_t::default();
+        let runtime_pool =
+            unsafe { wasm_runtime_get_mem_alloc_info(&mut info) }.then_some(PoolStats {
+                total_size: info.total_size,
+                free_size: info.total_free_size,
+                highmark_size: info.highmark_size,
+            });
+
+        #[allow(unused_mut)]
+        let mut stats = MemoryStats {
+            memory_count,
+            linear_memory_size,
+            linear_memory_max_size,
+            host_heap_size: self.args.host_managed_heap_size,
+            host_allocated_size: self.leaks.as_ref().map(|leaks| {
+                leaks
+                    .allocations
+                    .borrow()
+                    .values()
+                    .map(|allocation| allocation.size)
+                    .sum()
+            }),
+            stack_size: self.args.default_stack_size,
+            host_heap_used: None,
+            wasm_stack_used: None,
+            aux_stack_used: None,
+            native_stack_left: None,
+            runtime_pool,
+        };
+
+        // WAMR only prints them
+        #[cfg(feature = "memory-profiling")]
+        unsafe {
+            let exec_env = wasm_runtime_get_exec_env_singleton(self.instance);
+            if !exec_env.is_null() {
+                let dump = capture_output(|| wasm_runtime_dump_mem_consumption(exec_env));
+                parse_mem_consumption(&dump, &mut stats);
+            }
+        }
+
+        stats
+    }
+
+    /// all linear memories, in the order of their indexes
+    pub fn memories(&self) -> impl Iterator<Item = Memory<'_>> + '_ {
+        (0..).map_while(move |index| self.memory_at(index))
+    }
+
+    /// the exported global with the name
+    ///
+    /// # Error
+    ///
+    /// If there is no such exported global, an `RuntimeError::GlobalNotFound` will be returned.
+    pub fn global(&self, name: &str) -> Result<Global<'_>, RuntimeError> {
+        let name = CString::new(name).expect("CString::new failed");
+
+        let mut global: wasm_global_inst_t = unsafe { std::mem::zeroed() };
+        let found = unsafe {
+            wasm_runtime_get_export_global_inst(self.instance, name.as_ptr(), &mut global)
+        };
+        match found {
+            true => Ok(Global::from_raw(global)),
+            false => Err(RuntimeError::GlobalNotFound),
+        }
+    }
+
+    /// the exported table with the name
+    ///
+    /// # Error
+    ///
+    /// If there is no such exported table, an `RuntimeError::TableNotFound` will be returned.
+    pub fn table(&self, name: &str) -> Result<Table<'_>, RuntimeError> {
+        Table::new(self, name)
+    }
+
+    /// the function at `index` in the function index space of the module, imported
+    /// functions first. It reaches functions which are not exported
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::FunctionNotFound` if `index` is beyond all functions, or
+    /// `RuntimeError::ModuleInfoUnavailable` if the module is an .aot, whose functions are
+    /// unknown.
+    pub fn function_by_index(&self, index: u32) -> Result<Function, RuntimeError> {
+        self.check_function_index(index)?;
+
+        let function = table::function_by_index(self, index);
+        match function.is_null() {
+            true => Err(RuntimeError::FunctionNotFound),
+            false => {
+                let function = Function::from_raw(function, self);
+                Ok(match self.module.function_name(index) {
+                    Some(name) => function.with_name(name),
+                    None => function,
+                })
+            }
+        }
+    }
+
+    /// a `funcref` to the function at `index`, to pass to wasm or to write into a table.
+    /// See `function_by_index()`
+    ///
+    /// # Error
+    ///
+    /// The same as `function_by_index()`
+    pub fn funcref(&self, index: u32) -> Result<FuncRef, RuntimeError> {
+        self.check_function_index(index)?;
+        Ok(FuncRef::new(index, self.instance))
+    }
+
+    /// the function a `funcref` refers to, to call it
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::InvalidFuncRef` if the `funcref` comes from another instance.
+    /// Others are the same as `function_by_index()`.
+    pub fn function_of(&self, funcref: FuncRef) -> Result<Function, RuntimeError> {
+        funcref.check_instance(self.instance)?;
+        self.function_by_index(funcref.index())
+    }
+
+    fn check_function_index(&self, index: u32) -> Result<(), RuntimeError> {
+        let info = self.module.info()?;
+        match index < info.imported_function_count + info.function_count {
+            true => Ok(()),
+            false => Err(RuntimeError::FunctionNotFound),
+        }
+    }
+
+    /// return a `CallBuilder` to call the export function with the name
+    pub fn call(&self, name: &str) -> CallBuilder<'_> {
+        CallBuilder::new(self, name)
+    }
+
+    /// run the instance as a command, and return its exit code
+    ///
+    /// A WASI command exports `_start`, which reads the arguments set by
+    /// `WasiCtxBuilder::set_arguments()`, so `args` should be empty then. Calling `proc_exit`
+    /// is a normal exit with the code it passes. Otherwise, `main` is called with `args`,
+    /// whose first one is the program name as usual. They are copied into the host managed
+    /// heap, see `InstanceBuilder::host_heap_size()`.
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::FunctionNotFound` if there is neither `_start` nor `main`,
+    /// `RuntimeError::InvalidInstantiationArgs` if `args` is passed to a WASI command, or
+    /// `RuntimeError::SignatureMismatch` if `main` isn't `() -> i32` or `(i32, i32) -> i32`.
+    /// Return `RuntimeError::Trap` if it traps.
+    pub fn run_main(&self, args: &[&str]) -> Result<i32, RuntimeError> {
+        if let Ok(start) = Function::find_export_func(self, "_start") {
+            if !args.is_empty() {
+                return Err(RuntimeError::InvalidInstantiationArgs(String::from(
+                    "arguments of a WASI command are set by WasiCtxBuilder::set_arguments()",
+                )));
+            }
+
+            match start.call(self, &vec![]) {
+                Ok(_) => {}
+                // WAMR clears the exception of `proc_exit` usually, but not always
+                Err(RuntimeError::ExecutionError(message))
+                    if message.contains("wasi proc exit") => {}
+                Err(error) => return Err(error),
+            }
+            return Ok(unsafe { wasm_runtime_get_wasi_exit_code(self.instance) } as i32);
+        }
+
+        // clang names `int main(int, char **)` as `__main_argc_argv`
+        let main = Function::find_export_func(self, "main")
+            .or_else(|_| Function::find_export_func(self, "__main_argc_argv"))?;
+        if main.signature().params().is_empty() {
+            return main.call_typed(self, ());
+        }
+
+        let argv = self.write_argv(args)?;
+        let result = main.call_typed(self, (args.len() as i32, argv as i32));
+        self.free(argv);
+        result
+    }
+
+    // `argv` of C `main()`: null terminated pointers, followed by strings they point to
+    fn write_argv(&self, args: &[&str]) -> Result<u64, RuntimeError> {
+        let pointers_len = (args.len() + 1) * 4;
+        let size = pointers_len + args.iter().map(|arg| arg.len() + 1).sum::<usize>();
+        let (offset, native) = self.module_malloc(size as u64)?;
+        let buf = unsafe { std::slice::from_raw_parts_mut(native as *mut u8, size) };
+        buf.fill(0);
+
+        let mut string = pointers_len;
+        for (i, arg) in args.iter().enumerate() {
+            let pointer = (offset + string as u64) as u32;
+            buf[i * 4..i * 4 + 4].copy_from_slice(&pointer.to_le_bytes());
+            buf[string..string + arg.len()].copy_from_slice(arg.as_bytes());
+            string += arg.len() + 1;
+        }
+        Ok(offset)
+    }
+
+    pub fn get_inner_instance(&self) -> wasm_module_inst_t {
+        self.instance
+    }
+
+    // see `InstanceBuilder::canonicalize_result_nans()`
+    pub(crate) fn canonicalizes_result_nans(&self) -> bool {
+        self.canonicalize_result_nans
+    }
+
+    // whether `_initialize` has run at instantiation
+    pub(crate) fn is_initialized(&self) -> bool {
+        self.initialize
+    }
+
+    /// the module it is instantiated from
+    pub fn module(&self) -> &Module<'module> {
+        &self.module
+    }
+
+    pub(crate) fn get_inner_module(&self) -> wasm_module_t {
+        self.module.get_inner_module()
+    }
+
+    /// the call stack recorded by the last exception in `exec_env`, in text and in frames.
+    /// functions are named after the `name` section if possible
+    pub(crate) fn dump_call_stack(
+        &self,
+        exec_env: wasm_exec_env_t,
+    ) -> Option<(String, Vec<Frame>)> {
+        let buf_size = unsafe { wasm_runtime_get_call_stack_buf_size(exec_env) };
+        if buf_size == 0 {
+            return None;
+        }
+
+        let mut buf = vec![0 as c_char; buf_size as usize];
+        let written =
+            unsafe { wasm_runtime_dump_call_stack_to_buf(exec_env, buf.as_mut_ptr(), buf_size) };
+        if written == 0 {
+            return None;
+        }
+
+        let call_stack: Vec<u8> = buf[..written as usize]
+            .iter()
+            .map(|c| *c as u8)
+            .take_while(|c| *c > 0)
+            .collect();
+        let call_stack = String::from_utf8_lossy(&call_stack);
+
+        Some((
+            resolve_function_names(&call_stack, &self.function_names),
+            parse_call_stack(&call_stack, &self.function_names),
+        ))
+    }
+}
+
+/// The builder of `Instance`. Get one via `Instance::builder()`
+pub struct InstanceBuilder<'a> {
+    module: Module<'a>,
+    args: InstantiationArgs,
+    host_data: Option<HostData>,
+    memory_limit: Option<u32>,
+    on_memory_grow_failure: Option<GrowFailureCallback>,
+    on_call_start: Option<CallStartHook>,
+    on_call_end: Option<CallEndHook>,
+    on_host_call: Option<CallEndHook>,
+    host_quotas: HashMap<String, QuotaUsage>,
+    leak_report: Option<LeakReport>,
+    initialize: bool,
+    canonicalize_result_nans: bool,
+    #[cfg(feature = "shared-heap")]
+    shared_heap: Option<SharedHeap>,
+    #[cfg(target_os = "linux")]
+    snapshot: Option<&'a MappedSnapshot>,
+}
+
+impl<'a> InstanceBuilder<'a> {
+    fn new(_runtime: &Runtime, module: &Module<'a>) -> Self {
+        InstanceBuilder {
+            module: module.clone(),
+            args: InstantiationArgs {
+                default_stack_size: DEFAULT_STACK_SIZE,
+                ..InstantiationArgs::default()
+            },
+            host_data: None,
+            memory_limit: None,
+            on_memory_grow_failure: None,
+            on_call_start: None,
+            on_call_end: None,
+            on_host_call: None,
+            host_quotas: HashMap::new(),
+            leak_report: None,
+            initialize: true,
+            canonicalize_result_nans: false,
+            #[cfg(feature = "shared-heap")]
+            shared_heap: None,
+            #[cfg(target_os = "linux")]
+            snapshot: None,
+        }
+    }
+
+    /// the size of the wasm operand stack and the native stack of calls. 64KiB by default
+    ///
+    /// There is no setter of the aux stack, the stack in the linear memory which code
+    /// compiled from C or Rust keeps its locals on. Its place and size are fixed when the
+    /// module is linked, like `-Wl,-z,stack-size=<bytes>`, and WAMR only reads them from
+    /// `__stack_pointer` and `__heap_base` while instantiating. Moving it would mean moving
+    /// the data and the heap of the guest as well.
+    pub fn stack_size(mut self, stack_size: u32) -> Self {
+        self.args.default_stack_size = stack_size;
+        self
+    }
+
+    /// the size of the host managed heap. It is used for `-nostdlib` Wasm and wasm32-unknown,
+    /// and by `Memory::write_str()`. 0 by default
+    pub fn host_heap_size(mut self, heap_size: u32) -> Self {
+        self.args.host_managed_heap_size = heap_size;
+        self
+    }
+
+    /// the maximum pages of the default linear memory. It overrides the maximum declared
+    /// by the module if it is smaller. No limit by default
+    pub fn max_memory_pages(mut self, max_memory_pages: u32) -> Self {
+        self.args.max_memory_pages = max_memory_pages;
+        self
+    }
+
+    /// a hard cap on pages of the default linear memory, whatever maximum the module declares.
+    /// It sets `max_memory_pages()` as well
+    ///
+    /// Unlike `max_memory_pages()`, where `memory.grow` beyond it returns -1 to the module,
+    /// growing beyond the cap traps at the `memory.grow`, and no more wasm code runs. The
+    /// call fails with `RuntimeError::MemoryLimitExceeded`. The module has to be loaded with
+    /// `LoadOptions::guard_memory_grow()`, otherwise `build()` fails with
+    /// `RuntimeError::InvalidInstantiationArgs`.
+    pub fn memory_limit(mut self, pages: u32) -> Self {
+        self.args.max_memory_pages = pages;
+        self.memory_limit = Some(pages);
+        self
+    }
+
+    /// call `callback` whenever growing a linear memory of the instance is denied, by
+    /// `memory.grow` or `Memory::grow()`. To log, or to release caches of the host
+    ///
+    /// It runs on the thread running the instance, before `memory.grow` returns -1 or traps.
+    /// `MemoryGrowDenied::delta_pages` is the requested delta.
+    ///
+    /// The callback can't raise the limit and retry the grow. WAMR fixes the maximum pages
+    /// of a memory at instantiation, and offers no hook before a grow succeeds, so neither
+    /// `max_memory_pages()` nor `memory_limit()` can change afterwards. Instantiate with the
+    /// highest limit the host may grant instead.
+    pub fn on_memory_grow_failure<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&MemoryGrowDenied) + Send + Sync + 'static,
+    {
+        self.on_memory_grow_failure = Some(Box::new(callback));
+        self
+    }
+
+    /// limit how often the instance calls the host function `module_name`.`function_name`,
+    /// registered as a closure via `RuntimeBuilder::register()` or `HostModule`. Calls
+    /// beyond trap the guest, and the call into wasm fails with `RuntimeError::Trap` of
+    /// `TrapKind::HostQuotaExceeded`. See `HostQuota`
+    ///
+    /// Usage is kept by the instance, and shared with instances from `spawn()`.
+    pub fn host_quota(mut self, module_name: &str, function_name: &str, quota: HostQuota) -> Self {
+        self.host_quotas.insert(
+            format!("{}.{}", module_name, function_name),
+            QuotaUsage::new(quota),
+        );
+        self
+    }
+
+    /// call `hook` before every call of a wasm function from the host, via `Function` or
+    /// `CallBuilder`. To audit, or to trace
+    ///
+    /// It runs on the thread running the instance. Calls of host functions from wasm are
+    /// reported to `on_host_call()` instead.
+    pub fn on_call_start<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&CallEvent) + Send + Sync + 'static,
+    {
+        self.on_call_start = Some(Box::new(hook));
+        self
+    }
+
+    /// call `hook` after every call reported to `on_call_start()`, with how long it takes
+    /// and its results or error
+    pub fn on_call_end<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&CallEvent, &CallOutcome) + Send + Sync + 'static,
+    {
+        self.on_call_end = Some(Box::new(hook));
+        self
+    }
+
+    /// call `hook` after every call of a host closure from the instance, registered via
+    /// `RuntimeBuilder::register()` or `HostModule`. To audit which host APIs a guest uses
+    ///
+    /// It takes the same as `on_call_end()`. The name is the import name, like `env.log`,
+    /// and a call trapping the guest ends with `RuntimeError::Trap`, without frames. It
+    /// runs on the thread running the instance, and is shared with instances from
+    /// `spawn()`. Calls denied by `host_quota()` are reported as trapped.
+    pub fn on_host_call<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&CallEvent, &CallOutcome) + Send + Sync + 'static,
+    {
+        self.on_host_call = Some(Box::new(hook));
+        self
+    }
+
+    /// attach data to the instance. See `Instance::set_host_data()`
+    pub fn host_data<T: Any + Send>(mut self, data: T) -> Self {
+        self.host_data = Some(Box::new(data));
+        self
+    }
+
+    /// track buffers the host allocates in the instance. Those not released when the instance
+    /// is dropped are passed to `report`, sorted by address
+    ///
+    /// ```ignore
+    /// let instance = Instance::builder(&runtime, &module)
+    ///     .leak_report(|leaks| {
+    ///         for leak in leaks {
+    ///             eprintln!("{} bytes leaked at {}", leak.size, leak.location);
+    ///         }
+    ///     })
+    ///     .build()?;
+    /// ```
+    pub fn leak_report<F>(mut self, report: F) -> Self
+    where
+        F: FnOnce(&[Allocation]) + 'static,
+    {
+        self.leak_report = Some(Box::new(report));
+        self
+    }
+
+    /// whether to call `_initialize` of a reactor at instantiation and in `Instance::reset()`.
+    /// It is not called for a command, nor when starting from a snapshot. true by default
+    pub fn initialize(mut self, initialize: bool) -> Self {
+        self.initialize = initialize;
+        self
+    }
+
+    /// replace NaNs in `f32` and `f64` results of calls from the host with the canonical
+    /// NaN, whose payload has only the most significant bit set. false by default
+    ///
+    /// Only results are canonicalized. WAMR has no option to canonicalize NaNs during
+    /// execution, so NaNs stored in memories and globals, passed to host functions, or
+    /// inspected by `reinterpret` in wasm, are kept as the running mode produces them.
+    /// This doesn't make execution deterministic across running modes. Workloads needing
+    /// that should reject modules using floats.
+    pub fn canonicalize_result_nans(mut self, canonicalize: bool) -> Self {
+        self.canonicalize_result_nans = canonicalize;
+        self
+    }
+
+    /// attach the instance to a heap shared with the host and other instances. Also after
+    /// `reset()`. See `SharedHeap`
+    #[cfg(feature = "shared-heap")]
+    pub fn shared_heap(mut self, heap: &SharedHeap) -> Self {
+        self.shared_heap = Some(heap.clone());
+        self
+    }
+
+    /// start from a snapshot of a warmed instance of the same module, instead of the state
+    /// right after instantiation. See `MappedSnapshot`
+    #[cfg(target_os = "linux")]
+    pub fn snapshot(mut self, snapshot: &'a MappedSnapshot) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    fn validate(&self) -> Result<(), RuntimeError> {
+        if self.args.default_stack_size == 0 {
+            return Err(RuntimeError::InvalidInstantiationArgs(String::from(
+                "stack size should be greater than 0",
+            )));
+        }
+
+        let max_memory_pages = self.args.max_memory_pages;
+        if max_memory_pages > MAX_MEMORY_PAGES {
+            return Err(RuntimeError::InvalidInstantiationArgs(format!(
+                "max memory pages {} is beyond {}",
+                max_memory_pages, MAX_MEMORY_PAGES
+            )));
+        }
+
+        if self.memory_limit.is_some() && !self.module.is_memory_grow_guarded() {
+            return Err(RuntimeError::InvalidInstantiationArgs(String::from(
+                "load the module with LoadOptions::guard_memory_grow() to limit its memory",
+            )));
+        }
+
+        let min_memory_pages = self
+            .module
+            .info()
+            .ok()
+            .and_then(|info| info.memories.first())
+            .map(|memory| memory.min);
+        if let Some(min_memory_pages) = min_memory_pages {
+            if max_memory_pages > 0 && max_memory_pages < min_memory_pages {
+                return Err(RuntimeError::InvalidInstantiationArgs(format!(
+                    "max memory pages {} is less than {} pages the module requires",
+                    max_memory_pages, min_memory_pages
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// instantiate the module
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::InvalidInstantiationArgs` if the configuration is invalid
+    /// for the module. Return `RuntimeError::InstantiationFailure` if failed, including
+    /// a trap in `_initialize`.
+    pub fn build(self) -> Result<Instance<'a>, RuntimeError> {
+        self.validate()?;
+
         let init_thd_env = unsafe { wasm_runtime_init_thread_env() };
         if !init_thd_env {
             return Err(RuntimeError::InstantiationFailure(String::from(
@@ -55,45 +1470,279 @@ impl Instance {
             )));
         }
 
-        let mut error_buf = [0 as c_char; DEFAULT_ERROR_BUF_SIZE];
-        let instance = unsafe {
-            wasm_runtime_instantiate(
-                module.get_inner_module(),
-                stack_size,
-                heap_size,
-                error_buf.as_mut_ptr(),
-                error_buf.len() as u32,
-            )
+        let context = Box::new(InstanceContext {
+            host_data: self.host_data,
+            memory_limit: self.memory_limit,
+            on_memory_grow_failure: self.on_memory_grow_failure,
+            on_call_start: self.on_call_start,
+            on_call_end: self.on_call_end,
+            on_host_call: self.on_host_call,
+            host_quotas: Mutex::new(self.host_quotas),
+            wasi: WasiState::new(self.module.wasi_ctx()),
+            #[cfg(feature = "shared-heap")]
+            shared_heap: self.shared_heap,
+            ..InstanceContext::default()
+        });
+        let initialize = self.initialize && self.module.kind() == ModuleKind::Reactor;
+        #[cfg(target_os = "linux")]
+        let warmed = self.snapshot.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let warmed = false;
+        let (instance, wasi_layers) = instantiate(
+            &self.module,
+            &self.args,
+            Some(&context),
+            initialize && !warmed,
+        )?;
+
+        let instance = Instance {
+            instance,
+            id: unsafe { InstanceContext::assign_id(instance) },
+            function_names: self.module.get_function_names(),
+            module: self.module,
+            args: self.args,
+            context: Some(context),
+            spawned_exec_env: None,
+            leaks: self.leak_report.map(|report| LeakTracker {
+                allocations: RefCell::new(HashMap::new()),
+                report: Some(report),
+            }),
+            initialize,
+            cancel: CancelState::new(instance),
+            canonicalize_result_nans: self.canonicalize_result_nans,
+            _wasi_layers: wasi_layers,
         };
 
-        if instance.is_null() {
-            match error_buf.len() {
-                0 => {
-                    return Err(RuntimeError::InstantiationFailure(String::from(
-                        "instantiation failed",
-                    )))
-                }
-                _ => {
-                    return Err(RuntimeError::InstantiationFailure(error_buf_to_string(
-                        &error_buf,
-                    )))
-                }
+        #[cfg(target_os = "linux")]
+        if let Some(snapshot) = self.snapshot {
+            instance.restore_mapped(snapshot)?;
+        }
+
+        Ok(instance)
+    }
+}
+
+// peaks in what `wasm_runtime_dump_mem_consumption()` prints. like
+// `Total interpreter stack used: 1024`
+#[cfg(feature = "memory-profiling")]
+fn parse_mem_consumption(dump: &str, stats: &mut MemoryStats) {
+    for line in dump.lines() {
+        let Some((name, value)) = line.trim().rsplit_once(": ") else {
+            continue;
+        };
+        match name {
+            "Total interpreter stack used" => stats.wasm_stack_used = value.parse().ok(),
+            "Total auxiliary stack used" => stats.aux_stack_used = value.parse().ok(),
+            "Native stack left" => stats.native_stack_left = value.parse().ok(),
+            "Total app heap used" => stats.host_heap_used = value.parse().ok(),
+            _ => {}
+        }
+    }
+}
+
+// the key of a host object in WAMR
+fn host_object_ptr(object: &HostObject) -> *mut c_void {
+    object as *const HostObject as *mut c_void
+}
+
+fn instantiate(
+    module: &Module,
+    args: &InstantiationArgs,
+    context: Option<&InstanceContext>,
+    initialize: bool,
+) -> Result<(wasm_module_inst_t, Vec<MaterializedDir>), RuntimeError> {
+    // the callback is process-wide, and cheap to set again
+    unsafe {
+        wasm_runtime_set_enlarge_mem_error_callback(Some(on_enlarge_memory_error), ptr::null_mut())
+    };
+
+    let (instance, wasi_layers) = module
+        .wasi_ctx()
+        .instantiate_with_layers(module.get_inner_module(), || {
+            instantiate_module(module, args)
+        })?;
+
+    if let Some(context) = context {
+        unsafe { wasm_runtime_set_custom_data(instance, context.as_ptr()) };
+
+        // before `_initialize`, which may use it
+        #[cfg(feature = "shared-heap")]
+        if let Some(heap) = &context.shared_heap {
+            if let Err(error) = heap.attach(instance) {
+                unsafe { wasm_runtime_deinstantiate(instance) };
+                return Err(error);
+            }
+        }
+    }
+
+    if let Err(error) = module.wasi_ctx().restrict_pre_opens(instance) {
+        unsafe { wasm_runtime_deinstantiate(instance) };
+        return Err(error);
+    }
+
+    if initialize {
+        if let Err(error) = call_initialize(instance) {
+            unsafe { wasm_runtime_deinstantiate(instance) };
+            return Err(error);
+        }
+    }
+
+    Ok((instance, wasi_layers))
+}
+
+fn instantiate_module(
+    module: &Module,
+    args: &InstantiationArgs,
+) -> Result<wasm_module_inst_t, RuntimeError> {
+    let mut error_buf = [0 as c_char; DEFAULT_ERROR_BUF_SIZE];
+    let instance = unsafe {
+        wasm_runtime_instantiate_ex(
+            module.get_inner_module(),
+            args,
+            error_buf.as_mut_ptr(),
+            error_buf.len() as u32,
+        )
+    };
+
+    if instance.is_null() {
+        match error_buf.len() {
+            0 => {
+                return Err(RuntimeError::InstantiationFailure(String::from(
+                    "instantiation failed",
+                )))
             }
+            _ => {
+                return Err(RuntimeError::InstantiationFailure(error_buf_to_string(
+                    &error_buf,
+                )))
+            }
+        }
+    }
+
+    Ok(instance)
+}
+
+// `_initialize` of a reactor, before anything else runs in the instance
+fn call_initialize(instance: wasm_module_inst_t) -> Result<(), RuntimeError> {
+    let function = unsafe { wasm_runtime_lookup_function(instance, c"_initialize".as_ptr()) };
+    if function.is_null() {
+        return Ok(());
+    }
+
+    let mut argv = [0u32; 0];
+    let called = unsafe {
+        let exec_env = wasm_runtime_get_exec_env_singleton(instance);
+        wasm_runtime_call_wasm(exec_env, function, 0, argv.as_mut_ptr())
+    };
+    match called {
+        true => Ok(()),
+        false => {
+            let exception = unsafe { wasm_runtime_get_exception(instance) };
+            Err(RuntimeError::InstantiationFailure(format!(
+                "_initialize failed: {}",
+                exception_to_string(exception)
+            )))
         }
+    }
+}
+
+// reports to `InstanceBuilder::on_memory_grow_failure()`, and makes the guarded
+// `memory.grow` trap if it grows the default memory beyond `InstanceBuilder::memory_limit()`
+unsafe extern "C" fn on_enlarge_memory_error(
+    inc_page_count: u32,
+    current_memory_size: u64,
+    memory_index: u32,
+    failure_reason: enlarge_memory_error_reason_t,
+    instance: wasm_module_inst_t,
+    _exec_env: wasm_exec_env_t,
+    _user_data: *mut c_void,
+) {
+    let Some(context) = InstanceContext::of(instance) else {
+        return;
+    };
+
+    let maximum_reached = failure_reason == enlarge_memory_error_reason_t_MAX_SIZE_REACHED;
+    let page_size = wasm_memory_get_bytes_per_page(wasm_runtime_get_memory(instance, memory_index));
+    let current_pages = current_memory_size / page_size;
+
+    if let Some(callback) = &context.on_memory_grow_failure {
+        callback(&MemoryGrowDenied {
+            instance,
+            memory_index,
+            current_pages,
+            delta_pages: inc_page_count,
+            reason: match maximum_reached {
+                true => GrowDeniedReason::MaximumReached,
+                false => GrowDeniedReason::AllocationFailed,
+            },
+        });
+    }
+
+    let Some(limit) = context.memory_limit else {
+        return;
+    };
 
-        Ok(Instance { instance })
+    let requested = current_pages + inc_page_count as u64;
+    if memory_index == 0 && maximum_reached && requested > limit as u64 {
+        context
+            .memory_limit_exceeded
+            .store(requested, Ordering::Relaxed);
+        set_memory_grow_trap(instance, true);
     }
+}
 
-    pub fn get_inner_instance(&self) -> wasm_module_inst_t {
-        self.instance
+// see `LoadOptions::guard_memory_grow()`
+unsafe fn set_memory_grow_trap(instance: wasm_module_inst_t, trap: bool) {
+    let name = CString::new(MEMORY_GROW_TRAP_GLOBAL).expect("CString::new failed");
+    let mut global: wasm_global_inst_t = std::mem::zeroed();
+    if wasm_runtime_get_export_global_inst(instance, name.as_ptr(), &mut global) {
+        ptr::write_unaligned(global.global_data as *mut i32, trap as i32);
+    }
+}
+
+/// An instance spawned by `Instance::spawn()`. It shares the shared memories of its parent,
+/// and can't outlive it.
+#[derive(Debug)]
+pub struct SpawnedInstance<'parent> {
+    instance: Instance<'parent>,
+    _parent: PhantomData<&'parent Instance<'parent>>,
+}
+
+impl<'parent> Deref for SpawnedInstance<'parent> {
+    type Target = Instance<'parent>;
+
+    fn deref(&self) -> &Instance<'parent> {
+        &self.instance
     }
 }
 
-impl Drop for Instance {
+impl Drop for Instance<'_> {
     fn drop(&mut self) {
+        if let Some(leaks) = self.leaks.as_mut() {
+            let mut allocations = leaks
+                .allocations
+                .get_mut()
+                .drain()
+                .map(|(_, allocation)| allocation)
+                .collect::<Vec<Allocation>>();
+            if let (false, Some(report)) = (allocations.is_empty(), leaks.report.take()) {
+                allocations.sort_by_key(|allocation| allocation.offset);
+                report(&allocations);
+            }
+        }
+
+        // `CancelHandle`s do nothing from now on
+        *self.cancel.instance() = 0;
         unsafe {
-            wasm_runtime_destroy_thread_env();
-            wasm_runtime_deinstantiate(self.instance);
+            InstanceContext::release_id(self.instance);
+            match self.spawned_exec_env {
+                // it destroys the instance as well
+                Some(exec_env) => wasm_runtime_destroy_spawned_exec_env(exec_env),
+                None => {
+                    wasm_runtime_destroy_thread_env();
+                    wasm_runtime_deinstantiate(self.instance);
+                }
+            }
         }
     }
 }
@@ -102,8 +1751,11 @@ impl Drop for Instance {
 mod tests {
     use super::*;
     use crate::runtime::Runtime;
+    use crate::types::{ExternKind, ValType};
+    use crate::value::WasmValue;
     use wamr_sys::{
-        wasm_runtime_get_running_mode, RunningMode_Mode_Interp, RunningMode_Mode_LLVM_JIT,
+        wasm_runtime_get_exec_env_singleton, wasm_runtime_get_running_mode,
+        RunningMode_Mode_Interp, RunningMode_Mode_LLVM_JIT,
     };
 
     #[test]
@@ -146,6 +1798,901 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_instance_outlives_module_and_runtime() {
+        use crate::function::Function;
+
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(
+            &runtime,
+            r#"(module (func (export "answer") (result i32) (i32.const 42)))"#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        drop(module);
+        drop(runtime);
+
+        let function = Function::find_export_func(&instance, "answer").unwrap();
+        let result = function.call(&instance, &vec![]);
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(42)]);
+        assert_eq!(instance.module().exports().count(), 1);
+    }
+
+    #[test]
+    fn test_function_by_index() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (func $double (param i32) (result i32)
+                (i32.mul (local.get 0) (i32.const 2))
+              )
+              (func (export "answer") (result i32) (i32.const 42))
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        // not exported
+        let double = instance.function_by_index(0).unwrap();
+        let result = double.call(&instance, &vec![WasmValue::I32(21)]);
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(42)]);
+
+        let answer = instance.function_by_index(1).unwrap();
+        assert_eq!(answer.signature().results(), &[ValType::I32]);
+        let result = answer.call(&instance, &vec![]);
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(42)]);
+
+        assert!(matches!(
+            instance.function_by_index(2),
+            Err(RuntimeError::FunctionNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_externref() {
+        use crate::function::Function;
+
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (func (export "echo") (param externref) (result externref)
+                (local.get 0)
+              )
+              (func (export "is_null") (param externref) (result i32)
+                (ref.is_null (local.get 0))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let mut instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let tenant = instance.new_externref(String::from("tenant")).unwrap();
+        let echo = Function::find_export_func(&instance, "echo").unwrap();
+        let result = echo.call(&instance, &vec![WasmValue::ExternRef(Some(tenant))]);
+        assert_eq!(result.unwrap(), vec![WasmValue::ExternRef(Some(tenant))]);
+
+        let echoed: Option<ExternRef> = echo.call_typed(&instance, Some(tenant)).unwrap();
+        let echoed = echoed.unwrap();
+        assert_eq!(instance.externref::<String>(echoed).unwrap(), "tenant");
+        assert!(instance.externref::<u32>(echoed).is_none());
+
+        let is_null = Function::find_export_func(&instance, "is_null").unwrap();
+        let result: i32 = is_null.call_typed(&instance, None::<ExternRef>).unwrap();
+        assert_eq!(result, 1);
+        let result: i32 = is_null.call_typed(&instance, Some(tenant)).unwrap();
+        assert_eq!(result, 0);
+
+        // objects are kept per instance
+        let another = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        assert!(another.externref::<String>(tenant).is_none());
+
+        let object = instance.remove_externref(tenant).unwrap();
+        assert_eq!(object.downcast_ref::<String>().unwrap(), "tenant");
+        assert!(instance.externref::<String>(tenant).is_none());
+        assert!(instance.remove_externref(tenant).is_none());
+
+        let unit = instance.new_externref(()).unwrap();
+        assert!(instance.externref::<()>(unit).is_some());
+        instance.reset().unwrap();
+        assert!(instance.externref::<()>(unit).is_none());
+    }
+
+    #[test]
+    fn test_reactor() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (global $count (mut i32) (i32.const 0))
+              (func (export "_initialize")
+                (global.set $count (i32.const 100))
+              )
+              (func (export "next") (result i32)
+                (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                (global.get $count)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        assert_eq!(module.kind(), ModuleKind::Reactor);
+
+        // initialized once, then called repeatedly
+        let mut instance = Instance::new(&runtime, &module, 0).unwrap();
+        let next: i32 = instance.call("next").invoke_typed().unwrap();
+        assert_eq!(next, 101);
+        let next: i32 = instance.call("next").invoke_typed().unwrap();
+        assert_eq!(next, 102);
+
+        // and again after a reset
+        instance.reset().unwrap();
+        let next: i32 = instance.call("next").invoke_typed().unwrap();
+        assert_eq!(next, 101);
+
+        let instance = Instance::builder(&runtime, &module)
+            .initialize(false)
+            .build()
+            .unwrap();
+        let next: i32 = instance.call("next").invoke_typed().unwrap();
+        assert_eq!(next, 1);
+
+        let wat = r#"
+            (module
+              (func (export "_initialize") (unreachable))
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        assert!(matches!(
+            Instance::new(&runtime, &module, 0),
+            Err(RuntimeError::InstantiationFailure(_))
+        ));
+    }
+
+    #[test]
+    fn test_cancel_handle() {
+        use std::{thread, time::Duration};
+
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "spin")
+                (loop (br 0))
+              )
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 0).unwrap();
+
+        // from another thread, while running
+        let handle = instance.cancel_handle();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.cancel();
+        });
+        assert!(matches!(
+            instance.call("spin").invoke(),
+            Err(RuntimeError::Cancelled)
+        ));
+        canceller.join().unwrap();
+
+        // the instance is still usable
+        let sum: i32 = instance.call("add").args((1, 2)).invoke_typed().unwrap();
+        assert_eq!(sum, 3);
+
+        // before a call
+        let handle = instance.cancel_handle();
+        handle.clone().cancel();
+        assert!(matches!(
+            instance.call("add").args((1, 2)).invoke(),
+            Err(RuntimeError::Cancelled)
+        ));
+        let sum: i32 = instance.call("add").args((1, 2)).invoke_typed().unwrap();
+        assert_eq!(sum, 3);
+
+        // nothing happens once the instance is dropped
+        drop(instance);
+        handle.cancel();
+    }
+
+    #[test]
+    fn test_call_hooks() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func $add (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+              (func (export "trap") (unreachable))
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let ended = Arc::new(Mutex::new(Vec::new()));
+        let instance = Instance::builder(&runtime, &module)
+            .on_call_start({
+                let started = Arc::clone(&started);
+                move |event| {
+                    let name = event.name.map(String::from);
+                    started.lock().unwrap().push((name, event.params.to_vec()));
+                }
+            })
+            .on_call_end({
+                let ended = Arc::clone(&ended);
+                move |event, outcome| {
+                    let name = event.name.map(String::from);
+                    let result = outcome.result.map(|results| results.to_vec()).is_ok();
+                    ended.lock().unwrap().push((name, result));
+                }
+            })
+            .build()
+            .unwrap();
+
+        let sum: i32 = instance.call("add").args((1, 2)).invoke_typed().unwrap();
+        assert_eq!(sum, 3);
+        assert!(instance.call("trap").invoke().is_err());
+        // by index, named after the `name` section
+        let add = instance.function_by_index(0).unwrap();
+        add.call(&instance, &vec![WasmValue::I32(3), WasmValue::I32(4)])
+            .unwrap();
+
+        assert_eq!(
+            *started.lock().unwrap(),
+            vec![
+                (
+                    Some(String::from("add")),
+                    vec![WasmValue::I32(1), WasmValue::I32(2)]
+                ),
+                (Some(String::from("trap")), vec![]),
+                (
+                    Some(String::from("add")),
+                    vec![WasmValue::I32(3), WasmValue::I32(4)]
+                ),
+            ]
+        );
+        assert_eq!(
+            *ended.lock().unwrap(),
+            vec![
+                (Some(String::from("add")), true),
+                (Some(String::from("trap")), false),
+                (Some(String::from("add")), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_result_nans() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "nan") (result f32 f64)
+                (f32.reinterpret_i32 (i32.const 0xFFC01234))
+                (f64.reinterpret_i64 (i64.const 0x7FF0000000000001))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+
+        let instance = Instance::new(&runtime, &module, 0).unwrap();
+        let (a, b): (f32, f64) = instance.call("nan").invoke_typed().unwrap();
+        assert_eq!(a.to_bits(), 0xFFC0_1234);
+        assert_eq!(b.to_bits(), 0x7FF0_0000_0000_0001);
+
+        let instance = Instance::builder(&runtime, &module)
+            .canonicalize_result_nans(true)
+            .build()
+            .unwrap();
+        let (a, b): (f32, f64) = instance.call("nan").invoke_typed().unwrap();
+        assert_eq!(a.to_bits(), 0x7FC0_0000);
+        assert_eq!(b.to_bits(), 0x7FF8_0000_0000_0000);
+    }
+
+    #[test]
+    fn test_run_main() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+              (memory (export "memory") 1)
+              (func (export "_start")
+                (call $exit (i32.const 3))
+                (unreachable)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 0).unwrap();
+        assert_eq!(instance.run_main(&[]).unwrap(), 3);
+        assert!(matches!(
+            instance.run_main(&["command"]),
+            Err(RuntimeError::InvalidInstantiationArgs(_))
+        ));
+
+        let wat = r#"
+            (module
+              (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+              (memory (export "memory") 1)
+              (func (export "_start"))
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 0).unwrap();
+        assert_eq!(instance.run_main(&[]).unwrap(), 0);
+
+        // argc * 100 + the first byte of argv[1]
+        let wat = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "main") (param i32 i32) (result i32)
+                (i32.add
+                  (i32.mul (local.get 0) (i32.const 100))
+                  (i32.load8_u (i32.load offset=4 (local.get 1)))
+                )
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+        assert_eq!(instance.run_main(&["prog", "a"]).unwrap(), 200 + 'a' as i32);
+
+        let wat = r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024).unwrap();
+        assert!(matches!(
+            instance.run_main(&[]),
+            Err(RuntimeError::FunctionNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_instance_builder() {
+        let runtime = Runtime::new().unwrap();
+
+        let module =
+            Module::from_wat(&runtime, "(module (memory (export \"memory\") 2 100))").unwrap();
+
+        let instance = Instance::builder(&runtime, &module)
+            .stack_size(1024 * 32)
+            .host_heap_size(1024)
+            .max_memory_pages(4)
+            .host_data(String::from("tenant-1"))
+            .build()
+            .unwrap();
+        assert_eq!(
+            instance.host_data::<String>().map(String::as_str),
+            Some("tenant-1")
+        );
+        assert_eq!(instance.memory().unwrap().max_pages(), 4);
+
+        let result = Instance::builder(&runtime, &module)
+            .max_memory_pages(1)
+            .build();
+        assert!(matches!(
+            result,
+            Err(RuntimeError::InvalidInstantiationArgs(_))
+        ));
+
+        let result = Instance::builder(&runtime, &module).stack_size(0).build();
+        assert!(matches!(
+            result,
+            Err(RuntimeError::InvalidInstantiationArgs(_))
+        ));
+    }
+
+    #[test]
+    fn test_instance_memory_limit() {
+        use crate::function::Function;
+        use crate::module::LoadOptions;
+
+        let runtime = Runtime::new().unwrap();
+
+        let grow = r#"
+            (module
+              (memory (export "memory") 1 {max})
+              (global $after (export "after") (mut i32) (i32.const 0))
+              (func (export "grow") (param i32) (result i32)
+                (memory.grow (local.get 0))
+                (global.set $after (i32.const 1))
+              )
+            )
+            "#;
+        let load = |max: &str| {
+            let binary = wat::parse_str(grow.replace("{max}", max)).unwrap();
+            let options = LoadOptions::default().guard_memory_grow(true);
+            Module::from_vec_with_options(&runtime, binary, options).unwrap()
+        };
+
+        // the guard is required
+        let module = Module::from_wat(&runtime, &grow.replace("{max}", "")).unwrap();
+        assert!(!module.is_memory_grow_guarded());
+        assert!(matches!(
+            Instance::builder(&runtime, &module).memory_limit(2).build(),
+            Err(RuntimeError::InvalidInstantiationArgs(_))
+        ));
+
+        let module = load("");
+        assert!(module.is_memory_grow_guarded());
+        assert_eq!(module.exports().count(), 3);
+        let instance = Instance::builder(&runtime, &module)
+            .memory_limit(2)
+            .build()
+            .unwrap();
+        assert_eq!(instance.memory().unwrap().max_pages(), 2);
+        assert_eq!(instance.exports().count(), 3);
+
+        let function = Function::find_export_func(&instance, "grow").unwrap();
+        let after = instance.global("after").unwrap();
+        let result = function.call(&instance, &vec![WasmValue::I32(1)]);
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(1)]);
+        assert_eq!(after.get().unwrap(), WasmValue::I32(1));
+
+        // it traps right at `memory.grow`
+        after.set(WasmValue::I32(0)).unwrap();
+        let result = function.call(&instance, &vec![WasmValue::I32(1)]);
+        assert!(matches!(
+            result,
+            Err(RuntimeError::MemoryLimitExceeded {
+                limit: 2,
+                requested: 3
+            })
+        ));
+        assert_eq!(after.get().unwrap(), WasmValue::I32(0));
+
+        // and again
+        let result = function.call(&instance, &vec![WasmValue::I32(1)]);
+        assert!(matches!(
+            result,
+            Err(RuntimeError::MemoryLimitExceeded { .. })
+        ));
+
+        assert!(matches!(
+            instance.memory().unwrap().grow(2),
+            Err(RuntimeError::MemoryLimitExceeded {
+                limit: 2,
+                requested: 4
+            })
+        ));
+        let result = function.call(&instance, &vec![WasmValue::I32(0)]);
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(2)]);
+
+        // the maximum of the module is below the cap. memory.grow fails as usual
+        let module = load("1");
+        let instance = Instance::builder(&runtime, &module)
+            .memory_limit(4)
+            .build()
+            .unwrap();
+        let function = Function::find_export_func(&instance, "grow").unwrap();
+        let result = function.call(&instance, &vec![WasmValue::I32(1)]);
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(-1)]);
+        assert_eq!(
+            instance.global("after").unwrap().get().unwrap(),
+            WasmValue::I32(1)
+        );
+    }
+
+    #[test]
+    fn test_on_memory_grow_failure() {
+        use crate::function::Function;
+
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory (export "memory") 1 2)
+              (func (export "grow") (param i32) (result i32)
+                (memory.grow (local.get 0))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let denied = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&denied);
+        let instance = Instance::builder(&runtime, &module)
+            .on_memory_grow_failure(move |denied| log.lock().unwrap().push(*denied))
+            .build()
+            .unwrap();
+
+        let function = Function::find_export_func(&instance, "grow").unwrap();
+        let result = function.call(&instance, &vec![WasmValue::I32(2)]);
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(-1)]);
+        assert!(matches!(
+            instance.memory().unwrap().grow(5),
+            Err(RuntimeError::MemoryGrowFailure {
+                current: 1,
+                delta: 5
+            })
+        ));
+
+        let denied = denied.lock().unwrap();
+        assert_eq!(denied.len(), 2);
+        assert_eq!(denied[0].instance, instance.get_inner_instance());
+        assert_eq!(denied[0].memory_index, 0);
+        assert_eq!(denied[0].current_pages, 1);
+        assert_eq!(denied[0].delta_pages, 2);
+        assert_eq!(denied[0].reason, GrowDeniedReason::MaximumReached);
+        assert_eq!(denied[1].delta_pages, 5);
+    }
+
+    #[test]
+    fn test_instance_host_data() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(&runtime, "(module (func (export \"run\")))").unwrap();
+        let mut instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        assert!(instance.host_data::<u32>().is_none());
+
+        instance.set_host_data(42u32);
+        assert_eq!(instance.host_data::<u32>(), Some(&42));
+        assert!(instance.host_data::<String>().is_none());
+
+        instance.set_host_data(String::from("tenant-2"));
+        assert!(instance.host_data::<u32>().is_none());
+
+        let exec_env =
+            unsafe { wasm_runtime_get_exec_env_singleton(instance.get_inner_instance()) };
+        let data = unsafe { Instance::host_data_of_exec_env::<String>(exec_env) };
+        assert_eq!(data.map(String::as_str), Some("tenant-2"));
+        assert!(unsafe { Instance::host_data_of_exec_env::<u32>(exec_env) }.is_none());
+    }
+
+    #[test]
+    fn test_instance_malloc() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(&runtime, "(module (memory (export \"memory\") 1))").unwrap();
+        let mut instance = Instance::new_with_args(&runtime, &module, 1024 * 64, 1024).unwrap();
+
+        let (offset, buf) = instance.malloc(4).unwrap();
+        assert_ne!(offset, 0);
+        assert_eq!(buf.len(), 4);
+        buf.copy_from_slice(&[1, 2, 3, 4]);
+        assert_eq!(
+            instance.memory().unwrap().read_u32_le(offset).unwrap(),
+            0x04030201
+        );
+        instance.free(offset);
+
+        // beyond the host managed heap
+        assert!(matches!(
+            instance.malloc(4096),
+            Err(RuntimeError::ExecutionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_leak_report() {
+        use std::{cell::Cell, rc::Rc};
+
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(&runtime, "(module (memory (export \"memory\") 1))").unwrap();
+        let reported = Rc::new(Cell::new(Vec::new()));
+        let leaks = Rc::clone(&reported);
+        let mut instance = Instance::builder(&runtime, &module)
+            .host_heap_size(1024)
+            .leak_report(move |allocations| leaks.set(allocations.to_vec()))
+            .build()
+            .unwrap();
+
+        let (freed, _) = instance.malloc(8).unwrap();
+        let (leaked, _) = instance.malloc(16).unwrap();
+        let line = line!() - 1;
+        let s = instance.memory().unwrap().write_str("leak").unwrap();
+        instance.free(freed);
+        drop(instance);
+
+        let allocations = reported.take();
+        assert_eq!(allocations.len(), 2);
+        let leak = allocations.iter().find(|a| a.offset == leaked).unwrap();
+        assert_eq!(leak.size, 16);
+        assert_eq!(leak.location.file(), file!());
+        assert_eq!(leak.location.line(), line);
+        let leak = allocations.iter().find(|a| a.offset == s).unwrap();
+        assert_eq!(leak.size, 5);
+    }
+
+    #[test]
+    fn test_memory_stats() {
+        let runtime = Runtime::new().unwrap();
+
+        let module =
+            Module::from_wat(&runtime, "(module (memory (export \"memory\") 2 4))").unwrap();
+        let mut instance = Instance::builder(&runtime, &module)
+            .stack_size(1024 * 32)
+            .host_heap_size(1024)
+            .leak_report(|_| {})
+            .build()
+            .unwrap();
+        instance.malloc(16).unwrap();
+
+        let stats = instance.memory_stats();
+        assert_eq!(stats.memory_count, 1);
+        assert_eq!(stats.linear_memory_max_size, 4 * 65536);
+        // plus the host managed heap
+        assert!(stats.linear_memory_size >= 2 * 65536);
+        assert_eq!(stats.host_heap_size, 1024);
+        assert_eq!(stats.host_allocated_size, Some(16));
+        assert_eq!(stats.stack_size, 1024 * 32);
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        assert_eq!(instance.memory_stats().host_allocated_size, None);
+    }
+
+    #[test]
+    #[cfg(feature = "memory-profiling")]
+    fn test_memory_stats_profiled() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory 1)
+              (func $recurse (export "recurse") (param i32)
+                (if (local.get 0)
+                  (then (call $recurse (i32.sub (local.get 0) (i32.const 1)))))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let mut instance = Instance::builder(&runtime, &module)
+            .host_heap_size(4096)
+            .build()
+            .unwrap();
+
+        instance.call("recurse").arg(1).invoke().unwrap();
+        let shallow = instance.memory_stats();
+        instance.call("recurse").arg(100).invoke().unwrap();
+        let deep = instance.memory_stats();
+        assert!(deep.wasm_stack_used.unwrap() > shallow.wasm_stack_used.unwrap());
+        assert!(deep.native_stack_left.is_some());
+
+        let used = deep.host_heap_used.unwrap();
+        instance.malloc(1024).unwrap();
+        assert!(instance.memory_stats().host_heap_used.unwrap() >= used + 1024);
+    }
+
+    #[test]
+    #[cfg(feature = "memory-profiling")]
+    fn test_parse_mem_consumption() {
+        let mut stats = MemoryStats {
+            memory_count: 0,
+            linear_memory_size: 0,
+            linear_memory_max_size: 0,
+            host_heap_size: 0,
+            host_allocated_size: None,
+            stack_size: 0,
+            host_heap_used: None,
+            wasm_stack_used: None,
+            aux_stack_used: None,
+            native_stack_left: None,
+            runtime_pool: None,
+        };
+
+        let dump = "\
+Memory consumption summary (bytes):
+    Module struct: 1024
+Total interpreter stack used: 320
+Total aux stack used: no enough info to profile
+Native stack left: 7340032
+Total app heap used: 48
+";
+        parse_mem_consumption(dump, &mut stats);
+        assert_eq!(stats.wasm_stack_used, Some(320));
+        assert_eq!(stats.aux_stack_used, None);
+        assert_eq!(stats.native_stack_left, Some(7340032));
+        assert_eq!(stats.host_heap_used, Some(48));
+    }
+
+    #[test]
+    fn test_instance_address_conversion() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(&runtime, "(module (memory (export \"memory\") 1))").unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        assert!(instance.validate_app_addr(0, 65536));
+        assert!(!instance.validate_app_addr(65535, 2));
+
+        let native = instance.app_to_native(16, 4).unwrap();
+        unsafe { native.copy_from_nonoverlapping([1u8, 0, 0, 0].as_ptr(), 4) };
+        assert_eq!(instance.memory().unwrap().read_u32_le(16).unwrap(), 1);
+        assert_eq!(instance.native_to_app(native, 4).unwrap(), 16);
+
+        assert!(matches!(
+            instance.app_to_native(65535, 2),
+            Err(RuntimeError::MemoryAccessOutOfBounds {
+                offset: 65535,
+                len: 2
+            })
+        ));
+        let outside = [0u8; 4];
+        assert!(instance.native_to_app(outside.as_ptr(), 4).is_err());
+    }
+
+    #[test]
+    fn test_instance_spawn() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory (export "memory") 1 1 shared)
+              (global (export "local") (mut i32) (i32.const 0))
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let spawned = instance.spawn().unwrap();
+        assert_ne!(spawned.get_inner_instance(), instance.get_inner_instance());
+
+        // the memory is shared
+        instance.memory().unwrap().write_u32_le(0, 42).unwrap();
+        assert_eq!(spawned.memory().unwrap().read_u32_le(0).unwrap(), 42);
+        spawned.memory().unwrap().write_u32_le(4, 7).unwrap();
+        assert_eq!(instance.memory().unwrap().read_u32_le(4).unwrap(), 7);
+
+        // globals are not
+        instance
+            .global("local")
+            .unwrap()
+            .set(WasmValue::I32(1))
+            .unwrap();
+        assert_eq!(
+            spawned.global("local").unwrap().get().unwrap(),
+            WasmValue::I32(0)
+        );
+    }
+
+    #[test]
+    fn test_instance_multi_memory() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory $a (export "a") 1)
+              (memory $b (export "b") 2)
+              (data (memory $b) (i32.const 0) "b")
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let sizes: Vec<u64> = instance
+            .memories()
+            .map(|memory| memory.size_pages())
+            .collect();
+        assert_eq!(sizes, vec![1, 2]);
+
+        let b = instance.memory_at(1).unwrap();
+        assert_eq!(b.read_u8(0).unwrap(), b'b');
+        assert_eq!(instance.memory_at(0).unwrap().read_u8(0).unwrap(), 0);
+        assert!(instance.memory_at(2).is_none());
+    }
+
+    #[test]
+    fn test_instance_reset() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (global (export "count") (mut i32) (i32.const 0))
+              (data (i32.const 0) "clean")
+            )
+            "#,
+        )
+        .unwrap();
+        let mut instance = Instance::builder(&runtime, &module)
+            .host_data(7u32)
+            .build()
+            .unwrap();
+
+        instance.memory().unwrap().write(0, b"dirty").unwrap();
+        instance
+            .global("count")
+            .unwrap()
+            .set(WasmValue::I32(42))
+            .unwrap();
+
+        assert!(instance.reset().is_ok());
+
+        let mut buf = [0u8; 5];
+        instance.memory().unwrap().read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"clean");
+        assert_eq!(
+            instance.global("count").unwrap().get().unwrap(),
+            WasmValue::I32(0)
+        );
+        assert_eq!(instance.host_data::<u32>(), Some(&7));
+    }
+
+    #[test]
+    fn test_instance_exports() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (func (export "add") (param i32 i64) (result f64)
+                (f64.const 0)
+              )
+              (memory (export "memory") 1 4)
+              (table (export "table") 2 funcref)
+              (global (export "flag") (mut i32) (i32.const 0))
+              (func (export "grow") (result i32)
+                (memory.grow (i32.const 1))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        crate::function::Function::find_export_func(&instance, "grow")
+            .unwrap()
+            .call(&instance, &vec![])
+            .unwrap();
+
+        let exports: Vec<ExportType> = instance.exports().collect();
+        assert_eq!(exports.len(), 5);
+
+        assert_eq!(exports[0].name(), "add");
+        assert_eq!(
+            exports[0].ty(),
+            &ExternType::Func(FuncType::new(
+                vec![ValType::I32, ValType::I64],
+                vec![ValType::F64]
+            ))
+        );
+
+        assert_eq!(exports[1].name(), "memory");
+        assert!(matches!(
+            exports[1].ty(),
+            ExternType::Memory(MemoryType { min: 2, max: 4, .. })
+        ));
+
+        assert_eq!(exports[2].name(), "table");
+        assert!(matches!(
+            exports[2].ty(),
+            ExternType::Table(TableType { min: 2, .. })
+        ));
+
+        assert_eq!(exports[3].kind(), ExternKind::Global);
+        assert_eq!(exports[4].kind(), ExternKind::Func);
+    }
+
     #[test]
     #[ignore]
     fn test_instance_running_mode_default() {
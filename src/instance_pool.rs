@@ -0,0 +1,242 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! a pool of pre-instantiated instances, to take instantiation off the critical path.
+//! get one via `InstancePool::new()`
+
+use std::{cell::RefCell, fmt, ops::Deref};
+
+use wamr_sys::wasm_runtime_get_exception;
+
+use crate::{instance::Instance, RuntimeError};
+
+type InstanceFactory<'a> = Box<dyn Fn() -> Result<Instance<'a>, RuntimeError> + 'a>;
+
+/// what happens to an instance when it is returned to the pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecyclePolicy {
+    /// keep the instance, including its memory, globals and host data, for the next user.
+    /// Instances which trapped are always reset. Only for users trusting each other, opt in
+    /// via `InstancePool::with_policy()`
+    Reuse,
+    /// reset the instance via `Instance::reset()`. Every user gets a clean state. The
+    /// default of `InstancePool::new()`
+    Reinstantiate,
+}
+
+/// It keeps up to `size` idle instances created by a factory. `get()` hands out one of
+/// them, or a new one if all are in use. The instance goes back to the pool when the
+/// guard is dropped.
+///
+/// Instances are bound to the thread creating them, and so is the pool.
+pub struct InstancePool<'a> {
+    factory: InstanceFactory<'a>,
+    idle: RefCell<Vec<Instance<'a>>>,
+    size: usize,
+    policy: RecyclePolicy,
+}
+
+impl<'a> InstancePool<'a> {
+    /// create a pool with `size` instances, which are reset after return. See
+    /// `RecyclePolicy::Reinstantiate`
+    ///
+    /// ```ignore
+    /// let pool = InstancePool::new(4, || Instance::new(&runtime, &module, 1024 * 64))?;
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// The first error of `factory`.
+    pub fn new<F>(size: usize, factory: F) -> Result<Self, RuntimeError>
+    where
+        F: Fn() -> Result<Instance<'a>, RuntimeError> + 'a,
+    {
+        Self::with_policy(size, RecyclePolicy::Reinstantiate, factory)
+    }
+
+    /// create a pool with `size` instances, which are recycled by `policy`
+    ///
+    /// # Error
+    ///
+    /// The first error of `factory`.
+    pub fn with_policy<F>(
+        size: usize,
+        policy: RecyclePolicy,
+        factory: F,
+    ) -> Result<Self, RuntimeError>
+    where
+        F: Fn() -> Result<Instance<'a>, RuntimeError> + 'a,
+    {
+        let idle = (0..size)
+            .map(|_| factory())
+            .collect::<Result<Vec<Instance>, RuntimeError>>()?;
+
+        Ok(InstancePool {
+            factory: Box::new(factory),
+            idle: RefCell::new(idle),
+            size,
+            policy,
+        })
+    }
+
+    /// take an idle instance, or create one if there is none
+    ///
+    /// # Error
+    ///
+    /// The error of the factory.
+    pub fn get(&self) -> Result<PooledInstance<'_, 'a>, RuntimeError> {
+        let instance = match self.idle.borrow_mut().pop() {
+            Some(instance) => instance,
+            None => (self.factory)()?,
+        };
+
+        Ok(PooledInstance {
+            pool: self,
+            instance: Some(instance),
+        })
+    }
+
+    /// the number of idle instances
+    pub fn idle(&self) -> usize {
+        self.idle.borrow().len()
+    }
+
+    /// the number of idle instances the pool keeps at most
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn recycle(&self, mut instance: Instance<'a>) {
+        let trapped =
+            !unsafe { wasm_runtime_get_exception(instance.get_inner_instance()) }.is_null();
+
+        let instance = match (self.policy, trapped) {
+            (RecyclePolicy::Reuse, false) => Some(instance),
+            // the pool shrinks if it fails. `get()` makes it up later
+            _ => instance.reset().ok().map(|_| instance),
+        };
+
+        let mut idle = self.idle.borrow_mut();
+        if let Some(instance) = instance {
+            if idle.len() < self.size {
+                idle.push(instance);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for InstancePool<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InstancePool")
+            .field("idle", &self.idle())
+            .field("size", &self.size)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+/// An instance borrowed from an `InstancePool`. It returns to the pool when dropped.
+#[derive(Debug)]
+pub struct PooledInstance<'pool, 'a> {
+    pool: &'pool InstancePool<'a>,
+    instance: Option<Instance<'a>>,
+}
+
+impl<'a> Deref for PooledInstance<'_, 'a> {
+    type Target = Instance<'a>;
+
+    fn deref(&self) -> &Instance<'a> {
+        self.instance.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledInstance<'_, '_> {
+    fn drop(&mut self) {
+        if let Some(instance) = self.instance.take() {
+            self.pool.recycle(instance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{function::Function, module::Module, runtime::Runtime, value::WasmValue};
+
+    const COUNTER: &str = r#"
+        (module
+          (global $count (mut i32) (i32.const 0))
+          (func (export "count") (result i32)
+            (global.set $count (i32.add (global.get $count) (i32.const 1)))
+            (global.get $count)
+          )
+          (func (export "trap")
+            unreachable
+          )
+        )
+        "#;
+
+    fn count(instance: &Instance) -> Vec<WasmValue> {
+        let function = Function::find_export_func(instance, "count").unwrap();
+        function.call(instance, &vec![]).unwrap()
+    }
+
+    #[test]
+    fn test_instance_pool_reuse() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, COUNTER).unwrap();
+
+        let pool = InstancePool::with_policy(2, RecyclePolicy::Reuse, || {
+            Instance::new(&runtime, &module, 1024 * 64)
+        })
+        .unwrap();
+        assert_eq!(pool.idle(), 2);
+
+        {
+            let first = pool.get().unwrap();
+            let second = pool.get().unwrap();
+            let third = pool.get().unwrap();
+            assert_eq!(pool.idle(), 0);
+            assert_eq!(count(&first), vec![WasmValue::I32(1)]);
+            assert_eq!(count(&second), vec![WasmValue::I32(1)]);
+            assert_eq!(count(&third), vec![WasmValue::I32(1)]);
+        }
+        // only `size` instances are kept
+        assert_eq!(pool.idle(), 2);
+
+        // the state is kept
+        let instance = pool.get().unwrap();
+        assert_eq!(count(&instance), vec![WasmValue::I32(2)]);
+        drop(instance);
+
+        // a trapped instance is reset
+        let instance = pool.get().unwrap();
+        let trap = Function::find_export_func(&instance, "trap").unwrap();
+        assert!(trap.call(&instance, &vec![]).is_err());
+        drop(instance);
+        assert_eq!(pool.idle(), 2);
+
+        // the reset one, then the untouched one
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        assert_eq!(count(&first), vec![WasmValue::I32(1)]);
+        assert_eq!(count(&second), vec![WasmValue::I32(2)]);
+    }
+
+    #[test]
+    fn test_instance_pool_reinstantiate() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, COUNTER).unwrap();
+
+        // by default
+        let pool = InstancePool::new(1, || Instance::new(&runtime, &module, 1024 * 64)).unwrap();
+
+        for _ in 0..3 {
+            let instance = pool.get().unwrap();
+            assert_eq!(count(&instance), vec![WasmValue::I32(1)]);
+        }
+        assert_eq!(pool.idle(), 1);
+    }
+}
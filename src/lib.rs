@@ -29,6 +29,10 @@
 //! - *Module*. It is the compiled .wasm or .aot. It can be loaded into runtime and instantiated into instance.
 //! - *Instance*. It is the running instance of a module. It can be used to call export functions.
 //! - *Function*. It is the exported function.
+//! - *Memory*. It is the linear memory of an instance. It can be used to exchange data with the instance.
+//!
+//! An instance keeps its module loaded, and a module keeps the runtime alive. So they can be
+//! dropped in any order.
 //!
 //! ### WASI concepts
 //!
@@ -85,7 +89,7 @@
 //!
 //!     let params: Vec<WasmValue> = vec![WasmValue::I32(9), WasmValue::I32(27)];
 //!     let result = function.call(&instance, &params)?;
-//!     assert_eq!(result, WasmValue::I32(9));
+//!     assert_eq!(result, vec![WasmValue::I32(9)]);
 //!
 //!     Ok(())
 //! }
@@ -134,25 +138,75 @@
 //!
 //!     let params: Vec<WasmValue> = vec![WasmValue::I32(9), WasmValue::I32(27)];
 //!     let result = function.call(&instance, &params)?;
-//!     assert_eq!(result, WasmValue::I32(136));
+//!     assert_eq!(result, vec![WasmValue::I32(136)]);
 //!
 //!     Ok(())
 //! }
 //! ```
 //!
+//! Host functions may be Rust closures as well, registered with a module name via
+//! `RuntimeBuilder::register()`. The SDK generates their signatures from the Rust types.
+//! With the `macros` feature, `#[host_function]` turns a Rust fn taking `&str` or `&[u8]`
+//! of the guest memory into one.
+//!
+//! With the `exception-handling` feature, modules of the exception-handling proposal load
+//! and run. WAMR implements it in the classic interpreter only, so the feature builds
+//! WAMR with `WAMR_BUILD_FAST_INTERP=0`, and all modules run slower. An exception no
+//! guest catches fails the call with `TrapKind::UncaughtException`. WAMR exposes neither
+//! its tag nor its payload, and hosts can't throw into guests.
+//!
 
 use std::error;
 use std::fmt;
 use std::io;
 
+pub mod asyncify;
+pub mod cancel;
+#[cfg(target_os = "linux")]
+pub mod dirty_pages;
+pub mod exec_env;
 pub mod function;
+pub mod global;
+pub mod handle;
 mod helper;
 pub mod host_function;
 pub mod instance;
+pub mod instance_pool;
+pub mod linker;
+pub mod memory;
 pub mod module;
+pub mod module_cache;
+#[cfg(target_os = "linux")]
+pub mod native_lib;
+pub mod quota;
 pub mod runtime;
+#[cfg(feature = "shared-heap")]
+pub mod shared_heap;
+pub mod snapshot;
+pub mod table;
+pub mod trap;
+pub mod types;
 pub mod value;
+pub mod warm_template;
+pub mod wasi_clock;
 pub mod wasi_context;
+mod wasi_natives;
+pub mod wasi_overlay;
+pub mod wasi_quota;
+pub mod wasi_staged_dir;
+#[cfg(target_os = "linux")]
+pub mod wasi_stdio;
+pub mod wasi_virtual_file;
+mod wasm_binary;
+#[cfg(feature = "codec")]
+pub mod wasm_codec;
+
+#[cfg(feature = "macros")]
+pub use wamr_macros::host_function;
+
+// so that code generated by macros is able to refer to the crate by name in tests
+#[cfg(all(test, feature = "macros"))]
+extern crate self as wamr_rust_sdk;
 
 /// all kinds of exceptions raised by WAMR
 #[derive(Debug)]
@@ -164,12 +218,105 @@ pub enum RuntimeError {
     WasmFileFSError(std::io::Error),
     /// A compilation error. usually means that the .wasm file is invalid
     CompilationError(String),
+    /// the .aot file is generated by a `wamrc` whose AOT version differs from the runtime
+    AotVersionMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// the .aot file is compiled for another target (architecture, ABI, etc.)
+    AotTargetMismatch(String),
+    /// declarations of a module are unknown, with what isn't supported. See `Module::info()`
+    ModuleInfoUnavailable(String),
+    /// the content of a module is larger than `limit` bytes. See `LoadOptions::max_size()`
+    ModuleTooLarge {
+        limit: u64,
+    },
+    /// failed to register a module by name
+    ModuleRegistrationFailure(String),
+    /// the configuration of instantiation is invalid for the module
+    InvalidInstantiationArgs(String),
     /// instantiation failure
     InstantiationFailure(String),
     /// Error during execute wasm functions
     ExecutionError(String),
+    /// a negative `i32` a guest returns as an error code, with the message of its
+    /// `last_error()` if any. See `Function::call_checked()`
+    GuestError {
+        code: i32,
+        message: Option<String>,
+    },
+    /// a call is aborted since it runs longer than the timeout.
+    /// See `Function::call_with_timeout()`
+    Timeout(std::time::Duration),
+    /// a metered call runs out of its fuel. See `Function::call_metered()`
+    OutOfFuel(u32),
+    /// a call is interrupted by `CancelHandle::cancel()`
+    Cancelled,
+    /// a host function calls back into the guest in a way which would corrupt its stack,
+    /// like nesting too deep. See `Caller::call()`
+    IllegalReentrancy(String),
+    /// a wasm function traps. `message` is the exception of WAMR, with the call stack if
+    /// available. `frames` are the call stack, innermost first
+    Trap {
+        kind: trap::TrapKind,
+        message: String,
+        frames: Vec<trap::Frame>,
+    },
     /// usually returns by `find_export_func()`
     FunctionNotFound,
+    /// calling a function with parameters or results of other types
+    SignatureMismatch(String),
+    /// calling a function with `WasmValue`s whose count or types don't match its parameters
+    ArgumentMismatch {
+        expected: Vec<types::ValType>,
+        got: Vec<value::WasmValue>,
+    },
+    /// converting a `WasmValue` from or into a Rust value of another type or out of range
+    ConversionError(String),
+    /// failed to encode or decode a value in a linear memory. See `wasm_codec`
+    CodecError(String),
+    /// usually returns by `Instance::global()`
+    GlobalNotFound,
+    /// usually returns by `Instance::table()`
+    TableNotFound,
+    /// an access to a table is beyond its current size
+    TableAccessOutOfBounds {
+        index: u32,
+        size: u32,
+    },
+    /// writing an immutable global, or with a value of another type
+    InvalidGlobalAccess(String),
+    /// an access to a linear memory is beyond its current size
+    MemoryAccessOutOfBounds {
+        offset: u64,
+        len: u64,
+    },
+    /// growing a table beyond its maximum. See `Table::grow()`
+    TableGrowFailure {
+        current: u32,
+        delta: u32,
+    },
+    /// restoring a snapshot which doesn't match the instance
+    SnapshotMismatch(String),
+    /// failed to grow a linear memory. in pages
+    MemoryGrowFailure {
+        current: u64,
+        delta: u64,
+    },
+    /// failed to load a native library. See `RuntimeBuilder::native_lib()`
+    NativeLibFailure(String),
+    /// failed to wrap a host object into an externref
+    ExternRefFailure(String),
+    /// using a funcref with an instance other than the one it comes from
+    InvalidFuncRef(String),
+    /// growing the default memory beyond the cap of `InstanceBuilder::memory_limit()`.
+    /// in pages
+    MemoryLimitExceeded {
+        limit: u32,
+        requested: u64,
+    },
+    /// imports of a module which a `linker::Linker` doesn't define
+    UnresolvedImports(Vec<types::ImportType>),
 }
 
 impl fmt::Display for RuntimeError {
@@ -179,9 +326,92 @@ impl fmt::Display for RuntimeError {
             RuntimeError::InitializationFailure => write!(f, "Runtime initialization failure"),
             RuntimeError::WasmFileFSError(e) => write!(f, "Wasm file operation error: {}", e),
             RuntimeError::CompilationError(e) => write!(f, "Wasm compilation error: {}", e),
+            RuntimeError::AotVersionMismatch { expected, actual } => write!(
+                f,
+                "AOT version mismatch: expected {}, actual {}",
+                expected, actual
+            ),
+            RuntimeError::AotTargetMismatch(e) => write!(f, "AOT target mismatch: {}", e),
+            RuntimeError::ModuleInfoUnavailable(e) => {
+                write!(f, "Module info unavailable: {}", e)
+            }
+            RuntimeError::ModuleTooLarge { limit } => {
+                write!(f, "Module too large: more than {} bytes", limit)
+            }
+            RuntimeError::ModuleRegistrationFailure(e) => {
+                write!(f, "Wasm module registration failure: {}", e)
+            }
+            RuntimeError::InvalidInstantiationArgs(e) => {
+                write!(f, "Invalid instantiation arguments: {}", e)
+            }
             RuntimeError::InstantiationFailure(e) => write!(f, "Wasm instantiation failure: {}", e),
             RuntimeError::ExecutionError(e) => write!(f, "Wasm execution error: {}", e),
+            RuntimeError::Trap { message, .. } => write!(f, "Wasm trap: {}", message),
+            RuntimeError::GuestError { code, message } => match message {
+                Some(message) => write!(f, "Guest error {}: {}", code, message),
+                None => write!(f, "Guest error {}", code),
+            },
+            RuntimeError::Timeout(timeout) => write!(f, "Execution timeout after {:?}", timeout),
+            RuntimeError::OutOfFuel(fuel) => {
+                write!(f, "Execution runs out of fuel: {} instructions", fuel)
+            }
+            RuntimeError::Cancelled => write!(f, "Execution cancelled"),
+            RuntimeError::IllegalReentrancy(e) => write!(f, "Illegal reentrancy: {}", e),
             RuntimeError::FunctionNotFound => write!(f, "Function not found"),
+            RuntimeError::SignatureMismatch(e) => write!(f, "Signature mismatch: {}", e),
+            RuntimeError::ArgumentMismatch { expected, got } => write!(
+                f,
+                "Argument mismatch: expect {:?}, but got {:?}",
+                expected, got
+            ),
+            RuntimeError::ConversionError(e) => write!(f, "Value conversion error: {}", e),
+            RuntimeError::CodecError(e) => write!(f, "Codec error: {}", e),
+            RuntimeError::GlobalNotFound => write!(f, "Global not found"),
+            RuntimeError::TableNotFound => write!(f, "Table not found"),
+            RuntimeError::TableAccessOutOfBounds { index, size } => write!(
+                f,
+                "Table access out of bounds: index {} of size {}",
+                index, size
+            ),
+            RuntimeError::InvalidGlobalAccess(e) => write!(f, "Invalid global access: {}", e),
+            RuntimeError::MemoryAccessOutOfBounds { offset, len } => write!(
+                f,
+                "Memory access out of bounds: {} bytes at offset {}",
+                len, offset
+            ),
+            RuntimeError::TableGrowFailure { current, delta } => write!(
+                f,
+                "Table grow failure: {} elements by {} elements",
+                current, delta
+            ),
+            RuntimeError::SnapshotMismatch(e) => write!(f, "Snapshot mismatch: {}", e),
+            RuntimeError::MemoryGrowFailure { current, delta } => write!(
+                f,
+                "Memory grow failure: {} pages by {} pages",
+                current, delta
+            ),
+            RuntimeError::NativeLibFailure(e) => write!(f, "Native library failure: {}", e),
+            RuntimeError::ExternRefFailure(e) => write!(f, "Externref failure: {}", e),
+            RuntimeError::InvalidFuncRef(e) => write!(f, "Invalid funcref: {}", e),
+            RuntimeError::MemoryLimitExceeded { limit, requested } => write!(
+                f,
+                "Memory limit exceeded: {} pages requested beyond the limit of {} pages",
+                requested, limit
+            ),
+            RuntimeError::UnresolvedImports(imports) => write!(
+                f,
+                "Unresolved imports: {}",
+                imports
+                    .iter()
+                    .map(|import| format!(
+                        "{}.{} ({})",
+                        import.module(),
+                        import.name(),
+                        import.ty()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
         }
     }
 }
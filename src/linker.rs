@@ -0,0 +1,470 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! define imports once, and instantiate many modules with them.
+//! get one via `Linker::new()`
+
+use std::fmt;
+
+use crate::{
+    host_function::{HostModule, IntoHostFunction},
+    instance::{Instance, InstanceBuilder},
+    module::Module,
+    runtime::Runtime,
+    types::{ExternKind, ExternType, FuncType, ImportType, ValType},
+    RuntimeError,
+};
+
+/// imports by module names and names, defined once for all modules to instantiate
+///
+/// A definition is a Rust closure as a host function, or an export of a wasm module,
+/// like a memory or a global. WAMR resolves host functions while loading modules, so
+/// `define()` them into the runtime before loading modules which import them.
+///
+/// ```ignore
+/// let mut linker = Linker::new()
+///     .func("env", "log", |caller: &Caller, ptr: i32, len: i32| { ... })
+///     .module("libc", &libc);
+/// linker.define(&runtime)?;
+///
+/// let module = Module::from_file(&runtime, path)?;
+/// let instance = linker.instantiate(&runtime, &module)?;
+/// ```
+#[derive(Debug, Default)]
+pub struct Linker {
+    // not defined yet
+    host_modules: Vec<HostModule>,
+    modules: Vec<(String, Module<'static>)>,
+    defined: bool,
+    stub_unresolved: bool,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Linker::default()
+    }
+
+    /// define a Rust closure as the function `module_name`.`name`.
+    /// See `RuntimeBuilder::register()`
+    pub fn func<Params, Results, H>(mut self, module_name: &str, name: &str, function: H) -> Self
+    where
+        H: IntoHostFunction<Params, Results>,
+    {
+        host_module(&mut self.host_modules, module_name).add(name, function);
+        self
+    }
+
+    /// define all exports of `module`, like memories, globals and functions, as those of
+    /// `module_name`. See `Runtime::register_module()`
+    pub fn module(mut self, module_name: &str, module: &Module<'static>) -> Self {
+        self.modules
+            .push((String::from(module_name), module.clone()));
+        self
+    }
+
+    /// fill unresolved function imports with stubs, instead of failing. A stub traps with
+    /// `unlinked import module.name called`, of `TrapKind::UnlinkedImport`. So a partially
+    /// linked module runs as long as it doesn't call missing functions
+    ///
+    /// Memories, globals and tables, and functions with `v128` or references in their
+    /// types, are still unresolved.
+    pub fn stub_unresolved(mut self) -> Self {
+        self.stub_unresolved = true;
+        self
+    }
+
+    /// define everything into the runtime. Modules loaded afterwards are able to import them.
+    /// Functions of a module name replace those of the same module name defined before
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::ModuleRegistrationFailure` if failed, like a module name of
+    /// a wasm module which has been taken.
+    pub fn define(&mut self, runtime: &Runtime) -> Result<(), RuntimeError> {
+        for host_module in std::mem::take(&mut self.host_modules) {
+            runtime.register_host_module(host_module)?;
+        }
+        for (module_name, module) in &self.modules {
+            if runtime.find_module(module_name).is_none() {
+                runtime.register_module(module_name, module)?;
+            }
+        }
+        self.defined = true;
+        Ok(())
+    }
+
+    /// an `InstanceBuilder` of `module`, after all its imports are checked to be resolved,
+    /// by the linker or by the runtime, like WASI functions
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::UnresolvedImports` with all unresolved imports. A function
+    /// defined by the linker is unresolved if its type differs, or `module` is loaded
+    /// before `define()`. Return `RuntimeError::ModuleRegistrationFailure` if stubs of
+    /// `stub_unresolved()` fail to bind.
+    pub fn builder<'module>(
+        &self,
+        runtime: &Runtime,
+        module: &Module<'module>,
+    ) -> Result<InstanceBuilder<'module>, RuntimeError> {
+        let mut unresolved = self.check(module).missing;
+        if self.stub_unresolved {
+            unresolved = stub(runtime, module, unresolved)?;
+        }
+        if !unresolved.is_empty() {
+            return Err(RuntimeError::UnresolvedImports(unresolved));
+        }
+        Ok(Instance::builder(runtime, module))
+    }
+
+    /// instantiate `module` with the default configuration. See `builder()`
+    ///
+    /// # Error
+    ///
+    /// The same as `builder()` and `InstanceBuilder::build()`.
+    pub fn instantiate<'module>(
+        &self,
+        runtime: &Runtime,
+        module: &Module<'module>,
+    ) -> Result<Instance<'module>, RuntimeError> {
+        self.builder(runtime, module)?.build()
+    }
+
+    /// tell which imports of `module` are satisfied, and which are missing, all at once.
+    /// An import is satisfied if WAMR resolves it while loading, like host functions
+    /// and WASI functions, or a defined wasm module exports it
+    ///
+    /// Stubs of `stub_unresolved()` are not taken into account.
+    ///
+    /// ```ignore
+    /// let report = linker.check(&plugin);
+    /// if !report.is_satisfied() {
+    ///     eprintln!("{}", report);
+    /// }
+    /// ```
+    pub fn check(&self, module: &Module) -> ImportReport {
+        let (satisfied, missing) = module
+            .imports()
+            .partition(|import| import.is_linked() || self.exports(import));
+        ImportReport { satisfied, missing }
+    }
+
+    // whether a defined wasm module exports the import
+    fn exports(&self, import: &ImportType) -> bool {
+        self.defined
+            && self
+                .modules
+                .iter()
+                .filter(|(module_name, _)| module_name == import.module())
+                .any(|(_, module)| {
+                    module.exports().any(|export| {
+                        export.name() == import.name()
+                            && match import.kind() {
+                                // limits of memories and tables are checked while instantiating
+                                ExternKind::Func | ExternKind::Global => export.ty() == import.ty(),
+                                kind => export.kind() == kind,
+                            }
+                    })
+                })
+    }
+}
+
+/// imports of a module, satisfied or missing, in the order of the module.
+/// See `Linker::check()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    satisfied: Vec<ImportType>,
+    missing: Vec<ImportType>,
+}
+
+impl ImportReport {
+    pub fn satisfied(&self) -> &[ImportType] {
+        &self.satisfied
+    }
+
+    /// with the expected types, like `ImportType::ty()`
+    pub fn missing(&self) -> &[ImportType] {
+        &self.missing
+    }
+
+    /// whether nothing is missing
+    pub fn is_satisfied(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// a line per missing import, with the expected type. like `env.log: func (param i32 i32)`
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.missing.is_empty() {
+            return write!(f, "all {} imports satisfied", self.satisfied.len());
+        }
+        write!(
+            f,
+            "{} of {} imports missing:",
+            self.missing.len(),
+            self.missing.len() + self.satisfied.len()
+        )?;
+        for import in &self.missing {
+            write!(
+                f,
+                "\n  {}.{}: {}",
+                import.module(),
+                import.name(),
+                import.ty()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// the one of `module_name`, added if missing
+fn host_module<'a>(host_modules: &'a mut Vec<HostModule>, module_name: &str) -> &'a mut HostModule {
+    let index = match host_modules
+        .iter()
+        .position(|host_module| host_module.module_name() == module_name)
+    {
+        Some(index) => index,
+        None => {
+            host_modules.push(HostModule::new(module_name));
+            host_modules.len() - 1
+        }
+    };
+    &mut host_modules[index]
+}
+
+// bind stubs to unresolved functions of `module`, and return imports which can't be stubbed
+fn stub(
+    runtime: &Runtime,
+    module: &Module,
+    unresolved: Vec<ImportType>,
+) -> Result<Vec<ImportType>, RuntimeError> {
+    let mut stubs = Vec::new();
+    let mut rest = Vec::new();
+    for import in unresolved {
+        match import.ty() {
+            ExternType::Func(ty) => match stub_signature(ty) {
+                Some(signature) => {
+                    host_module(&mut stubs, import.module()).add_stub(import.name(), &signature)
+                }
+                None => rest.push(import),
+            },
+            _ => rest.push(import),
+        }
+    }
+
+    if !stubs.is_empty() && !runtime.stub_imports(module, stubs) {
+        return Err(RuntimeError::ModuleRegistrationFailure(String::from(
+            "bind stubs of unresolved imports failed",
+        )));
+    }
+    Ok(rest)
+}
+
+// the WAMR signature, if a raw host function is able to take the type
+fn stub_signature(ty: &FuncType) -> Option<String> {
+    let letter = |ty: &ValType| match ty {
+        ValType::I32 => Some('i'),
+        ValType::I64 => Some('I'),
+        ValType::F32 => Some('f'),
+        ValType::F64 => Some('F'),
+        _ => None,
+    };
+    if ty.results().len() > 1 {
+        return None;
+    }
+    let params = ty.params().iter().map(letter).collect::<Option<String>>()?;
+    let results = ty
+        .results()
+        .iter()
+        .map(letter)
+        .collect::<Option<String>>()?;
+    Some(format!("({}){}", params, results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{host_function::Caller, trap::TrapKind};
+
+    #[test]
+    fn test_linker() {
+        let runtime = Runtime::new().unwrap();
+        let mut linker = Linker::new()
+            .func("test_linker", "double", |_: &Caller, x: i32| x * 2)
+            .func("test_linker", "negate", |_: &Caller, x: i32| -x);
+        linker.define(&runtime).unwrap();
+
+        let linked = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "test_linker" "double" (func $double (param i32) (result i32)))
+              (import "test_linker" "negate" (func $negate (param i32) (result i32)))
+              (func (export "run") (param i32) (result i32)
+                (call $negate (call $double (local.get 0)))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = linker.instantiate(&runtime, &linked).unwrap();
+        let result: i32 = instance.call("run").arg(21i32).invoke_typed().unwrap();
+        assert_eq!(result, -42);
+
+        let partial = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "test_linker" "double" (func (param i32) (result i32)))
+              (import "test_linker" "double" (func (param i64) (result i64)))
+              (import "test_linker" "missing" (func))
+              (import "test_linker_env" "memory" (memory 1))
+            )
+            "#,
+        )
+        .unwrap();
+        match linker.instantiate(&runtime, &partial) {
+            Err(RuntimeError::UnresolvedImports(imports)) => {
+                let unresolved = imports
+                    .iter()
+                    .map(|import| (import.name(), import.kind()))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    unresolved,
+                    vec![
+                        ("double", ExternKind::Func),
+                        ("missing", ExternKind::Func),
+                        ("memory", ExternKind::Memory),
+                    ]
+                );
+            }
+            other => panic!("expect unresolved imports, got {:?}", other),
+        }
+
+        let report = linker.check(&partial);
+        assert_eq!(report.satisfied().len(), 1);
+        // the max of a memory without one depends on WAMR
+        assert!(report.to_string().starts_with(
+            "3 of 4 imports missing:\n  test_linker.double: func (param i64) (result i64)\n  \
+             test_linker.missing: func\n  test_linker_env.memory: memory 1 "
+        ));
+        assert!(linker.check(&linked).is_satisfied());
+    }
+
+    #[test]
+    fn test_linker_module() {
+        let runtime = Runtime::new().unwrap();
+        let provider = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (global (export "base") i32 (i32.const 16))
+            )
+            "#,
+        )
+        .unwrap();
+        let mut linker = Linker::new().module("test_linker_provider", &provider);
+        linker.define(&runtime).unwrap();
+        // defined once
+        linker.define(&runtime).unwrap();
+
+        let consumer = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "test_linker_provider" "memory" (memory 1))
+              (import "test_linker_provider" "base" (global i32))
+              (import "test_linker_provider" "limit" (global i32))
+            )
+            "#,
+        )
+        .unwrap();
+        let report = linker.check(&consumer);
+        assert!(!report.is_satisfied());
+        let satisfied = report
+            .satisfied()
+            .iter()
+            .map(ImportType::name)
+            .collect::<Vec<_>>();
+        assert_eq!(satisfied, vec!["memory", "base"]);
+        assert_eq!(report.missing().len(), 1);
+        assert_eq!(report.missing()[0].name(), "limit");
+        assert_eq!(
+            report.to_string(),
+            "1 of 3 imports missing:\n  test_linker_provider.limit: global i32"
+        );
+    }
+
+    #[test]
+    fn test_stub_unresolved() {
+        let runtime = Runtime::new().unwrap();
+        let mut linker = Linker::new()
+            .func("test_linker_stub", "double", |_: &Caller, x: i32| x * 2)
+            .stub_unresolved();
+        linker.define(&runtime).unwrap();
+
+        let partial = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "test_linker_stub" "double" (func $double (param i32) (result i32)))
+              (import "test_linker_stub" "missing" (func $missing (param i64) (result f64)))
+              (func (export "run") (param i32) (result i32)
+                (call $double (local.get 0))
+              )
+              (func (export "run_missing") (result f64)
+                (call $missing (i64.const 1))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = linker.instantiate(&runtime, &partial).unwrap();
+        let result: i32 = instance.call("run").arg(4i32).invoke_typed().unwrap();
+        assert_eq!(result, 8);
+        match instance.call("run_missing").invoke() {
+            Err(RuntimeError::Trap {
+                kind: TrapKind::UnlinkedImport,
+                message,
+                ..
+            }) => assert!(message.contains("unlinked import test_linker_stub.missing called")),
+            other => panic!("expect a trap, got {:?}", other),
+        }
+
+        // stubs are not visible to modules loaded afterwards
+        let missing = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "test_linker_stub" "missing" (func (param i64) (result f64)))
+              (import "test_linker_stub" "memory" (memory 1))
+            )
+            "#,
+        )
+        .unwrap();
+        assert!(!missing.imports().next().unwrap().is_linked());
+        match linker.instantiate(&runtime, &missing) {
+            Err(RuntimeError::UnresolvedImports(imports)) => {
+                assert_eq!(imports.len(), 1);
+                assert_eq!(imports[0].kind(), ExternKind::Memory);
+            }
+            other => panic!("expect unresolved imports, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stub_signature() {
+        let ty = FuncType::new(vec![ValType::I32, ValType::I64], vec![ValType::F32]);
+        assert_eq!(stub_signature(&ty).as_deref(), Some("(iI)f"));
+        let ty = FuncType::new(vec![ValType::F64], vec![]);
+        assert_eq!(stub_signature(&ty).as_deref(), Some("(F)"));
+        let ty = FuncType::new(vec![ValType::V128], vec![]);
+        assert_eq!(stub_signature(&ty), None);
+        let ty = FuncType::new(vec![], vec![ValType::I32, ValType::I32]);
+        assert_eq!(stub_signature(&ty), None);
+    }
+}
@@ -0,0 +1,527 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! the linear memory of an instance. exchange bytes with a running module.
+//! get one via `Instance::memory()`, or `Instance::memory_at()` for modules with multiple memories
+//!
+//! Memories are always allocated by WAMR. An imported memory is resolved while loading,
+//! like other imports, from a module registered via `Runtime::register_module()`. WAMR
+//! doesn't accept a host-allocated buffer, or a memory of another instance, as the memory
+//! of an instance. To share a region of the host with several instances, attach them to a
+//! `shared_heap::SharedHeap`.
+
+use std::ptr;
+
+use wamr_sys::{
+    wasm_memory_enlarge, wasm_memory_get_base_address, wasm_memory_get_bytes_per_page,
+    wasm_memory_get_cur_page_count, wasm_memory_get_max_page_count, wasm_memory_get_shared,
+    wasm_memory_inst_t,
+};
+
+use crate::{
+    function::Function, instance::Instance, types::ValType, value::WasmValue, RuntimeError,
+};
+
+// little-endian accessors of a primitive type
+macro_rules! le_accessors {
+    ($ty:ty, $read:ident, $write:ident) => {
+        #[doc = concat!("read a little-endian `", stringify!($ty), "` at `offset`")]
+        ///
+        /// # Error
+        ///
+        /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+        pub fn $read(&self, offset: u64) -> Result<$ty, RuntimeError> {
+            let mut bytes = [0u8; std::mem::size_of::<$ty>()];
+            self.read(offset, &mut bytes)?;
+            Ok(<$ty>::from_le_bytes(bytes))
+        }
+
+        #[doc = concat!("write a `", stringify!($ty), "` at `offset` in little-endian")]
+        ///
+        /// # Error
+        ///
+        /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+        pub fn $write(&self, offset: u64, value: $ty) -> Result<(), RuntimeError> {
+            self.write(offset, &value.to_le_bytes())
+        }
+    };
+}
+
+/// A view of a linear memory. It is only valid as long as the instance is alive.
+///
+/// Every access is checked against the current size of the memory. The memory may move
+/// when it grows, so the base address is never cached.
+#[derive(Debug)]
+pub struct Memory<'instance> {
+    instance: &'instance Instance<'instance>,
+    memory: wasm_memory_inst_t,
+}
+
+impl<'instance> Memory<'instance> {
+    pub(crate) fn from_raw(
+        instance: &'instance Instance<'instance>,
+        memory: wasm_memory_inst_t,
+    ) -> Self {
+        Memory { instance, memory }
+    }
+
+    /// the current size of the memory in bytes
+    pub fn data_size(&self) -> u64 {
+        unsafe {
+            wasm_memory_get_cur_page_count(self.memory)
+                * wasm_memory_get_bytes_per_page(self.memory)
+        }
+    }
+
+    /// the current size of the memory in pages
+    pub fn size_pages(&self) -> u64 {
+        unsafe { wasm_memory_get_cur_page_count(self.memory) }
+    }
+
+    /// the maximum size of the memory in pages
+    pub fn max_pages(&self) -> u64 {
+        unsafe { wasm_memory_get_max_page_count(self.memory) }
+    }
+
+    /// the size of a page in bytes. usually 64KiB
+    pub fn page_size(&self) -> u64 {
+        unsafe { wasm_memory_get_bytes_per_page(self.memory) }
+    }
+
+    pub fn is_shared(&self) -> bool {
+        unsafe { wasm_memory_get_shared(self.memory) }
+    }
+
+    /// grow the memory by `delta_pages` pages. return the previous size in pages
+    ///
+    /// It is the same as `memory.grow` in wasm. Addresses got before growing are invalid.
+    ///
+    /// # Error
+    ///
+    /// If the memory can't grow, like exceeding the maximum, an `RuntimeError::MemoryGrowFailure`
+    /// will be returned.
+    pub fn grow(&self, delta_pages: u64) -> Result<u64, RuntimeError> {
+        let previous = self.size_pages();
+        if unsafe { wasm_memory_enlarge(self.memory, delta_pages) } {
+            return Ok(previous);
+        }
+
+        // it disarms the guard of `memory.grow`, which is for wasm code only
+        if let Some(error) = self.instance.take_memory_limit_error() {
+            return Err(error);
+        }
+
+        Err(RuntimeError::MemoryGrowFailure {
+            current: previous,
+            delta: delta_pages,
+        })
+    }
+
+    /// copy `buf.len()` bytes starting at `offset` of the memory into `buf`
+    ///
+    /// # Error
+    ///
+    /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), RuntimeError> {
+        let src = self.address_of(offset, buf.len())?;
+        unsafe { ptr::copy_nonoverlapping(src, buf.as_mut_ptr(), buf.len()) };
+        Ok(())
+    }
+
+    /// copy `buf` into the memory starting at `offset`
+    ///
+    /// # Error
+    ///
+    /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+    pub fn write(&self, offset: u64, buf: &[u8]) -> Result<(), RuntimeError> {
+        let dst = self.address_of(offset, buf.len())?;
+        unsafe { ptr::copy_nonoverlapping(buf.as_ptr(), dst, buf.len()) };
+        Ok(())
+    }
+
+    /// read a byte at `offset`
+    ///
+    /// # Error
+    ///
+    /// If the offset is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+    pub fn read_u8(&self, offset: u64) -> Result<u8, RuntimeError> {
+        let mut byte = [0u8];
+        self.read(offset, &mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// write a byte at `offset`
+    ///
+    /// # Error
+    ///
+    /// If the offset is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+    pub fn write_u8(&self, offset: u64, value: u8) -> Result<(), RuntimeError> {
+        self.write(offset, &[value])
+    }
+
+    le_accessors!(u16, read_u16_le, write_u16_le);
+    le_accessors!(u32, read_u32_le, write_u32_le);
+    le_accessors!(u64, read_u64_le, write_u64_le);
+    le_accessors!(i32, read_i32_le, write_i32_le);
+    le_accessors!(i64, read_i64_le, write_i64_le);
+    le_accessors!(f32, read_f32_le, write_f32_le);
+    le_accessors!(f64, read_f64_le, write_f64_le);
+
+    /// read a NUL-terminated string at `offset`. Invalid UTF-8 sequences are replaced
+    /// with `U+FFFD`
+    ///
+    /// # Error
+    ///
+    /// If there is no NUL before the end of the memory, an `RuntimeError::MemoryAccessOutOfBounds`
+    /// will be returned.
+    pub fn read_cstr(&self, offset: u64) -> Result<String, RuntimeError> {
+        let start = self.address_of(offset, 0)?;
+        let remaining = (self.data_size() - offset) as usize;
+
+        let bytes = unsafe { std::slice::from_raw_parts(start, remaining) };
+        match bytes.iter().position(|b| *b == 0) {
+            Some(len) => Ok(String::from_utf8_lossy(&bytes[..len]).into_owned()),
+            None => Err(RuntimeError::MemoryAccessOutOfBounds {
+                offset,
+                len: remaining as u64 + 1,
+            }),
+        }
+    }
+
+    /// allocate a buffer in the instance and copy `s` into it with a NUL terminator.
+    /// return the address of the buffer in the memory, which can be passed to wasm functions
+    ///
+    /// The buffer is allocated like `Instance::malloc()`. Release it via `Instance::free()`.
+    ///
+    /// # Error
+    ///
+    /// If the allocation fails, an `RuntimeError::ExecutionError` will be returned.
+    #[track_caller]
+    pub fn write_str(&self, s: &str) -> Result<u64, RuntimeError> {
+        let (offset, _) = self.instance.module_malloc(s.len() as u64 + 1)?;
+
+        self.write(offset, s.as_bytes())?;
+        self.write_u8(offset + s.len() as u64, 0)?;
+        Ok(offset)
+    }
+
+    /// allocate a buffer in the instance like `write_str()` and copy `s` into it, without
+    /// a NUL terminator. return the address and the length, for wasm functions taking a
+    /// string as a pointer and a length
+    ///
+    /// # Error
+    ///
+    /// If the allocation fails, an `RuntimeError::ExecutionError` will be returned.
+    #[track_caller]
+    pub fn alloc_str(&self, s: &str) -> Result<(u64, u64), RuntimeError> {
+        self.alloc_bytes(s.as_bytes())
+    }
+
+    /// the same as `alloc_str()`, but allocate the buffer by the function exported as
+    /// `allocator`, which takes a size and returns an address in this memory, like
+    /// `alloc(size: usize) -> *mut u8` of a guest. Both are `i32`s, or `i64`s for a 64-bit
+    /// memory. The buffer belongs to the guest then, release it the way the guest does
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::FunctionNotFound` if there is no such function,
+    /// `RuntimeError::SignatureMismatch` if it isn't an allocator, or
+    /// `RuntimeError::ExecutionError` if it fails or returns null.
+    pub fn alloc_str_with(&self, allocator: &str, s: &str) -> Result<(u64, u64), RuntimeError> {
+        self.alloc_bytes_with(allocator, s.as_bytes())
+    }
+
+    #[track_caller]
+    pub(crate) fn alloc_bytes(&self, bytes: &[u8]) -> Result<(u64, u64), RuntimeError> {
+        // a zero-sized allocation may fail
+        let (offset, _) = self.instance.module_malloc(bytes.len().max(1) as u64)?;
+
+        self.write(offset, bytes)?;
+        Ok((offset, bytes.len() as u64))
+    }
+
+    pub(crate) fn alloc_bytes_with(
+        &self,
+        allocator: &str,
+        bytes: &[u8],
+    ) -> Result<(u64, u64), RuntimeError> {
+        let function = Function::find_export_func(self.instance, allocator)?;
+        let size = match function.signature().params() {
+            [ValType::I32] => WasmValue::try_from(bytes.len())?,
+            [ValType::I64] => WasmValue::I64(bytes.len() as i64),
+            params => {
+                return Err(RuntimeError::SignatureMismatch(format!(
+                    "{} takes {:?}, but an allocator takes a size",
+                    allocator, params
+                )))
+            }
+        };
+
+        let offset = match function.call(self.instance, &vec![size])?.as_slice() {
+            [WasmValue::I32(offset)] => *offset as u32 as u64,
+            [WasmValue::I64(offset)] => *offset as u64,
+            results => {
+                return Err(RuntimeError::SignatureMismatch(format!(
+                    "{} returns {:?}, but an allocator returns an address",
+                    allocator, results
+                )))
+            }
+        };
+        if offset == 0 {
+            return Err(RuntimeError::ExecutionError(format!(
+                "{} returns null",
+                allocator
+            )));
+        }
+
+        self.write(offset, bytes)?;
+        Ok((offset, bytes.len() as u64))
+    }
+
+    /// read `len` bytes at `offset` as a string, like a pointer and a length returned by
+    /// wasm. Invalid UTF-8 sequences are replaced with `U+FFFD`
+    ///
+    /// # Error
+    ///
+    /// If the range is beyond the memory, an `RuntimeError::MemoryAccessOutOfBounds` will be returned.
+    pub fn read_str(&self, offset: u64, len: u64) -> Result<String, RuntimeError> {
+        let start = self.address_of(offset, len as usize)?;
+        let bytes = unsafe { std::slice::from_raw_parts(start, len as usize) };
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    pub(crate) fn base_address(&self) -> *mut u8 {
+        unsafe { wasm_memory_get_base_address(self.memory) as *mut u8 }
+    }
+
+    pub fn get_inner_memory(&self) -> wasm_memory_inst_t {
+        self.memory
+    }
+
+    // the native address of [offset, offset + len)
+    fn address_of(&self, offset: u64, len: usize) -> Result<*mut u8, RuntimeError> {
+        let out_of_bounds = RuntimeError::MemoryAccessOutOfBounds {
+            offset,
+            len: len as u64,
+        };
+
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.data_size() => {}
+            _ => return Err(out_of_bounds),
+        }
+
+        let base = unsafe { wasm_memory_get_base_address(self.memory) } as *mut u8;
+        if base.is_null() {
+            return Err(out_of_bounds);
+        }
+
+        Ok(unsafe { base.add(offset as usize) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{module::Module, runtime::Runtime, RuntimeError};
+
+    use super::*;
+
+    #[test]
+    fn test_memory_read_write() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 16) "hello")
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let memory = instance.memory().unwrap();
+        assert_eq!(memory.data_size(), 65536);
+
+        let mut buf = [0u8; 5];
+        assert!(memory.read(16, &mut buf).is_ok());
+        assert_eq!(&buf, b"hello");
+
+        assert!(memory.write(65536 - 5, b"world").is_ok());
+        assert!(memory.read(65536 - 5, &mut buf).is_ok());
+        assert_eq!(&buf, b"world");
+
+        assert!(matches!(
+            memory.write(65536 - 4, b"world"),
+            Err(RuntimeError::MemoryAccessOutOfBounds { .. })
+        ));
+        assert!(matches!(
+            memory.read(u64::MAX, &mut buf),
+            Err(RuntimeError::MemoryAccessOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_memory_typed_access() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 0) "\01\02\03\04")
+              (data (i32.const 8) "hi\00")
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new_with_args(&runtime, &module, 1024 * 64, 1024).unwrap();
+        let memory = instance.memory().unwrap();
+
+        assert_eq!(memory.read_u32_le(0).unwrap(), 0x04030201);
+        assert_eq!(memory.read_u16_le(2).unwrap(), 0x0403);
+        assert_eq!(memory.read_u8(3).unwrap(), 0x04);
+
+        assert!(memory.write_i64_le(16, -2).is_ok());
+        assert_eq!(memory.read_i64_le(16).unwrap(), -2);
+        assert_eq!(memory.read_u32_le(16).unwrap(), 0xffff_fffe);
+        assert!(memory.write_f64_le(24, 1.5).is_ok());
+        assert_eq!(memory.read_f64_le(24).unwrap(), 1.5);
+        assert!(matches!(
+            memory.read_u64_le(65536 - 4),
+            Err(RuntimeError::MemoryAccessOutOfBounds { .. })
+        ));
+
+        assert_eq!(memory.read_cstr(8).unwrap(), "hi");
+        assert!(memory.write_u8(65535, b'x').is_ok());
+        assert!(matches!(
+            memory.read_cstr(65535),
+            Err(RuntimeError::MemoryAccessOutOfBounds { .. })
+        ));
+
+        let offset = memory.write_str("hello").unwrap();
+        assert_ne!(offset, 0);
+        assert_eq!(memory.read_cstr(offset).unwrap(), "hello");
+        instance.free(offset);
+    }
+
+    #[test]
+    fn test_memory_str() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (global $next (mut i32) (i32.const 1024))
+              (data (i32.const 16) "hello, wasm")
+              (func (export "alloc") (param i32) (result i32)
+                (global.get $next)
+                (global.set $next (i32.add (global.get $next) (local.get 0)))
+              )
+              (func (export "greeting") (result i32 i32)
+                (i32.const 16)
+                (i32.const 11)
+              )
+              (func (export "count_l") (param $ptr i32) (param $len i32) (result i32)
+                (local $count i32)
+                (block $done
+                  (loop $next
+                    (br_if $done (i32.eqz (local.get $len)))
+                    (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 108))
+                      (then (local.set $count (i32.add (local.get $count) (i32.const 1))))
+                    )
+                    (local.set $ptr (i32.add (local.get $ptr) (i32.const 1)))
+                    (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+                    (br $next)
+                  )
+                )
+                (local.get $count)
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new_with_args(&runtime, &module, 1024 * 64, 1024).unwrap();
+        let memory = instance.memory().unwrap();
+        let count_l = Function::find_export_func(&instance, "count_l").unwrap();
+
+        let (offset, len) = memory.alloc_str_with("alloc", "hello").unwrap();
+        assert_eq!((offset, len), (1024, 5));
+        let count: i32 = count_l
+            .call_typed(&instance, (offset as i32, len as i32))
+            .unwrap();
+        assert_eq!(count, 2);
+
+        let (offset, len) = memory.alloc_str("all well").unwrap();
+        assert_eq!(memory.read_str(offset, len).unwrap(), "all well");
+        let count: i32 = count_l
+            .call_typed(&instance, (offset as i32, len as i32))
+            .unwrap();
+        assert_eq!(count, 4);
+        instance.free(offset);
+
+        let greeting = Function::find_export_func(&instance, "greeting").unwrap();
+        let (offset, len): (i32, i32) = greeting.call_typed(&instance, ()).unwrap();
+        assert_eq!(
+            memory.read_str(offset as u64, len as u64).unwrap(),
+            "hello, wasm"
+        );
+
+        assert!(matches!(
+            memory.alloc_str_with("malloc", "hello"),
+            Err(RuntimeError::FunctionNotFound)
+        ));
+        assert!(matches!(
+            memory.alloc_str_with("count_l", "hello"),
+            Err(RuntimeError::SignatureMismatch(_))
+        ));
+        assert!(matches!(
+            memory.read_str(65536 - 4, 5),
+            Err(RuntimeError::MemoryAccessOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_memory_grow() {
+        let runtime = Runtime::new().unwrap();
+
+        let module =
+            Module::from_wat(&runtime, "(module (memory (export \"memory\") 1 4))").unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let memory = instance.memory().unwrap();
+
+        assert_eq!(memory.size_pages(), 1);
+        assert_eq!(memory.max_pages(), 4);
+        assert_eq!(memory.page_size(), 65536);
+        assert!(!memory.is_shared());
+
+        assert_eq!(memory.grow(2).unwrap(), 1);
+        assert_eq!(memory.size_pages(), 3);
+        assert_eq!(memory.data_size(), 3 * 65536);
+        assert!(memory.write_u32_le(3 * 65536 - 4, 42).is_ok());
+
+        assert!(matches!(
+            memory.grow(2),
+            Err(RuntimeError::MemoryGrowFailure {
+                current: 3,
+                delta: 2
+            })
+        ));
+        assert_eq!(memory.size_pages(), 3);
+    }
+
+    #[test]
+    fn test_memory_not_exist() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(&runtime, "(module)").unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        assert!(instance.memory().is_none());
+    }
+}
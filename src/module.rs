@@ -4,28 +4,250 @@
  */
 
 //! .wasm compiled, in-memory representation
-//! get one via `Module::from_file()` or `Module::from_buf()`
+//! get one via `Module::from_file()`, `Module::from_buf()` or `Module::from_wat()`
+//! precompiled .aot can be loaded via `Module::from_aot_file()` or `Module::from_aot_buf()`
 
 use crate::{
-    helper::error_buf_to_string, helper::DEFAULT_ERROR_BUF_SIZE, runtime::Runtime,
-    wasi_context::WasiCtx, RuntimeError,
+    helper::error_buf_to_string,
+    helper::DEFAULT_ERROR_BUF_SIZE,
+    runtime::{Runtime, RuntimeHandle},
+    types::{ExportType, ExternKind, ImportType},
+    wasi_context::WasiCtx,
+    wasm_binary::{
+        guard_memory_grow, parse_function_names, parse_module_info, strip_custom_sections,
+        MEMORY_GROW_TRAP_GLOBAL,
+    },
+    RuntimeError,
+};
+use memmap2::{MmapMut, MmapOptions};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    ffi::{c_char, CStr, CString},
+    fmt, fs,
+    fs::File,
+    io::Read,
+    path::Path,
+    string::String,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    vec::Vec,
 };
-use std::{fs::File, io::Read, path::Path, ptr, string::String, vec::Vec};
 use wamr_sys::{
-    wasm_module_t, wasm_runtime_load, wasm_runtime_set_wasi_addr_pool, wasm_runtime_set_wasi_args,
-    wasm_runtime_set_wasi_ns_lookup_pool, wasm_runtime_unload,
+    package_type_t_Wasm_Module_AoT, package_type_t_Wasm_Module_Bytecode, wasm_export_t,
+    wasm_import_t, wasm_module_t, wasm_runtime_get_current_package_version,
+    wasm_runtime_get_export_count, wasm_runtime_get_export_type,
+    wasm_runtime_get_file_package_type, wasm_runtime_get_file_package_version,
+    wasm_runtime_get_import_count, wasm_runtime_get_import_type, wasm_runtime_get_module_name,
+    wasm_runtime_is_underlying_binary_freeable, wasm_runtime_load_ex, wasm_runtime_set_module_name,
+    wasm_runtime_unload, LoadArgs,
 };
 
+pub use crate::wasm_binary::{ModuleInfo, DEFAULT_MAX_MEMORY_PAGES, DEFAULT_MAX_TABLE_SIZE};
+
+/// SHA-256 of the original content of a module
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ModuleDigest([u8; 32]);
+
+impl ModuleDigest {
+    /// calculate the digest of a .wasm or .aot content
+    pub fn of(content: &[u8]) -> Self {
+        ModuleDigest(Sha256::digest(content).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// in lowercase hex
+impl fmt::Display for ModuleDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// options of loading a module. Get one via `LoadOptions::default()`, and pass it to a
+/// `Module::from_*_with_options()`
+///
+/// Other loaders take the default options. `Module::from_file_mmap()` and
+/// `Module::from_borrowed()` load the content in place, so can't strip or instrument it.
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    custom_sections: bool,
+    bytes: bool,
+    guard_memory_grow: bool,
+    max_size: u64,
+}
+
+/// retain custom sections and the content, without guarding `memory.grow`. The content is
+/// up to `u32::MAX` bytes, the most WAMR loads
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            custom_sections: true,
+            bytes: true,
+            guard_memory_grow: false,
+            max_size: u32::MAX as u64,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// whether to keep custom sections, like debug information and the `name` section,
+    /// in memory. Only affects .wasm
+    ///
+    /// Stripping them cuts memory consumption on constrained devices. But functions in call
+    /// stacks are no longer named.
+    pub fn retain_custom_sections(mut self, retain: bool) -> LoadOptions {
+        self.custom_sections = retain;
+        self
+    }
+
+    /// whether to keep the content in memory after loading
+    ///
+    /// If not, WAMR is asked to copy what it needs out of the content. And the content is
+    /// released once WAMR reports it is no longer referred to. Otherwise, the content is
+    /// kept for the lifetime of the module, as usual. Large modules, especially .aot, won't
+    /// pin their full binary in memory.
+    pub fn retain_bytes(mut self, retain: bool) -> LoadOptions {
+        self.bytes = retain;
+        self
+    }
+
+    /// whether to instrument `memory.grow` of the default memory, so a grow beyond
+    /// `InstanceBuilder::memory_limit()` traps right there. Only affects .wasm
+    ///
+    /// It is required by `InstanceBuilder::memory_limit()`. WAMR doesn't stop the guest on
+    /// a failed grow, so the guard checks the result of each `memory.grow` and traps if the
+    /// host asks so. Guarded code is a bit larger and slower to grow, and its code offsets,
+    /// like those of DWARF, are shifted.
+    pub fn guard_memory_grow(mut self, guard: bool) -> LoadOptions {
+        self.guard_memory_grow = guard;
+        self
+    }
+
+    /// the maximum size of the content in bytes. It is capped at `u32::MAX`
+    ///
+    /// A larger content fails loading with `RuntimeError::ModuleTooLarge`.
+    /// `Module::from_reader_with_options()` stops reading once the content grows beyond it.
+    /// So an untrusted source, like a socket, can't exhaust the memory of the host.
+    pub fn max_size(mut self, max_size: u64) -> LoadOptions {
+        self.max_size = max_size.min(u32::MAX as u64);
+        self
+    }
+}
+
+/// the format of a module binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleFormat {
+    /// .wasm, WebAssembly bytecode
+    Bytecode,
+    /// .aot, precompiled by `wamrc`
+    Aot,
+    Unknown,
+}
+
+impl ModuleFormat {
+    /// detect the format of a module binary by its magic number
+    #[allow(non_upper_case_globals)]
+    pub fn detect(buf: &[u8]) -> ModuleFormat {
+        match unsafe { wasm_runtime_get_file_package_type(buf.as_ptr(), buf.len() as u32) } {
+            package_type_t_Wasm_Module_Bytecode => ModuleFormat::Bytecode,
+            package_type_t_Wasm_Module_AoT => ModuleFormat::Aot,
+            _ => ModuleFormat::Unknown,
+        }
+    }
+}
+
+/// how a module runs, by the WASI application ABI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleKind {
+    /// exports `_start`. It runs once, see `Instance::run_main()`
+    Command,
+    /// exports no `_start`. Its `_initialize`, if exported, is called at instantiation.
+    /// Then exports can be called repeatedly
+    Reactor,
+}
+
+/// A handle of a loaded module. Cloning it is cheap. All clones refer to the same loaded
+/// module, which is unloaded when the last one is dropped. So worker threads are able to
+/// hold their own handles and instantiate it independently.
+///
+/// Instances hold a handle of their module, and modules keep the `Runtime` alive. So they
+/// can be dropped in any order.
+#[derive(Debug, Clone)]
+pub struct Module<'a> {
+    inner: Arc<ModuleInner<'a>>,
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
-pub struct Module {
+struct ModuleInner<'a> {
     module: wasm_module_t,
+    // never reused, unlike the address of `module`. See `Module::id`
+    id: u64,
     // to keep the module content in memory
-    content: Vec<u8>,
+    content: ModuleContent<'a>,
     wasi_ctx: WasiCtx,
+    // from the `name` section. function index -> function name
+    function_names: Arc<HashMap<u32, String>>,
+    // or what isn't supported
+    info: Result<ModuleInfo, String>,
+    digest: ModuleDigest,
+    // see `LoadOptions::guard_memory_grow()`
+    memory_grow_guarded: bool,
+    // the runtime is destroyed after all modules are unloaded
+    _runtime: Arc<RuntimeHandle>,
+}
+
+/// A loaded module is read-only for WAMR. Instantiating the same module on multiple
+/// threads at the same time is supported. The only mutable operation, `set_wasi_context()`,
+/// requires a `&mut Module`. So it is safe to load a module on one thread and share it
+/// with others.
+unsafe impl Send for ModuleInner<'_> {}
+unsafe impl Sync for ModuleInner<'_> {}
+
+// see `Module::id`
+static NEXT_MODULE_ID: AtomicU64 = AtomicU64::new(1);
+
+// WAMR keeps referring to, and may even modify, the buffer after loading
+#[derive(Debug)]
+enum ModuleContent<'a> {
+    Owned(Vec<u8>),
+    Borrowed(&'a mut [u8]),
+    // a private, copy-on-write, mapping of a file
+    Mapped(MmapMut),
+    // WAMR doesn't refer to the buffer anymore
+    Released,
+}
+
+impl ModuleContent<'_> {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            ModuleContent::Owned(content) => content.as_slice(),
+            ModuleContent::Borrowed(content) => content,
+            ModuleContent::Mapped(content) => content,
+            ModuleContent::Released => &[],
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        match self {
+            ModuleContent::Owned(content) => content.as_mut_slice(),
+            ModuleContent::Borrowed(content) => content,
+            ModuleContent::Mapped(content) => content,
+            ModuleContent::Released => &mut [],
+        }
+    }
 }
 
-impl Module {
+impl<'a> Module<'a> {
     /// compile a module with the given wasm file path
     ///
     /// # Error
@@ -33,12 +255,83 @@ impl Module {
     /// If the file does not exist or the file cannot be read, an `RuntimeError::WasmFileFSError` will be returned.
     /// If the wasm file is not a valid wasm file, an `RuntimeError::CompilationError` will be returned.
     pub fn from_file(runtime: &Runtime, wasm_file: &Path) -> Result<Self, RuntimeError> {
-        let mut wasm_file = File::open(wasm_file)?;
+        Self::from_file_with_options(runtime, wasm_file, LoadOptions::default())
+    }
+
+    /// compile a module with the given wasm file path, with options
+    ///
+    /// # Error
+    ///
+    /// The same as `Module::from_file()`
+    pub fn from_file_with_options(
+        runtime: &Runtime,
+        wasm_file: &Path,
+        options: LoadOptions,
+    ) -> Result<Self, RuntimeError> {
+        let wasm_file = File::open(wasm_file)?;
+
+        Self::from_reader_with_options(runtime, wasm_file, options)
+    }
+
+    /// compile a module with content from a reader. like a socket, a gRPC stream, etc.
+    ///
+    /// Content is accumulated chunk by chunk until the end of the reader, then loaded. Up to
+    /// `u32::MAX` bytes are read. Use `Module::from_reader_with_options()` with
+    /// `LoadOptions::max_size()` for a lower limit.
+    ///
+    /// # Error
+    ///
+    /// If the reader fails, an `RuntimeError::WasmFileFSError` will be returned.
+    /// If the content is larger than the limit, an `RuntimeError::ModuleTooLarge` will be returned.
+    /// If the wasm file is not a valid wasm file, an `RuntimeError::CompilationError` will be returned.
+    pub fn from_reader<R: Read>(runtime: &Runtime, reader: R) -> Result<Self, RuntimeError> {
+        Self::from_reader_with_options(runtime, reader, LoadOptions::default())
+    }
 
+    /// compile a module with content from a reader, with options
+    ///
+    /// # Error
+    ///
+    /// The same as `Module::from_reader()`
+    pub fn from_reader_with_options<R: Read>(
+        runtime: &Runtime,
+        reader: R,
+        options: LoadOptions,
+    ) -> Result<Self, RuntimeError> {
+        // one more byte to tell a content of exactly `max_size` bytes from a larger one
         let mut binary: Vec<u8> = Vec::new();
-        wasm_file.read_to_end(&mut binary)?;
+        reader.take(options.max_size + 1).read_to_end(&mut binary)?;
 
-        Self::from_buf(runtime, &binary)
+        Self::from_vec_with_options(runtime, binary, options)
+    }
+
+    /// compile a module with the given wasm or aot file path by mapping the file into memory
+    /// instead of reading it into a buffer. the mapping is kept until the module is dropped
+    ///
+    /// The mapping is private. Modifications made by WAMR, if any, are not written back to
+    /// the file. Untouched pages are shared with the page cache. It cuts startup time and
+    /// memory consumption of large .aot files.
+    ///
+    /// The file should not be truncated or modified by others while the module is alive.
+    ///
+    /// # Error
+    ///
+    /// If the file does not exist or the file cannot be mapped, an `RuntimeError::WasmFileFSError` will be returned.
+    /// If the wasm file is not a valid wasm file, an `RuntimeError::CompilationError` will be returned.
+    pub fn from_file_mmap(runtime: &Runtime, wasm_file: &Path) -> Result<Self, RuntimeError> {
+        let wasm_file = File::open(wasm_file)?;
+
+        // the mapping is private, so changes of the module content are invisible to others
+        let content = unsafe { MmapOptions::new().map_copy(&wasm_file)? };
+        let digest = ModuleDigest::of(&content);
+
+        Self::from_content(
+            runtime,
+            ModuleContent::Mapped(content),
+            digest,
+            &LoadOptions::default(),
+            false,
+        )
     }
 
     /// compile a module int the given buffer
@@ -47,114 +340,443 @@ impl Module {
     ///
     /// If the file does not exist or the file cannot be read, an `RuntimeError::WasmFileFSError` will be returned.
     /// If the wasm file is not a valid wasm file, an `RuntimeError::CompilationError` will be returned.
-    pub fn from_buf(_runtime: &Runtime, buf: &[u8]) -> Result<Self, RuntimeError> {
-        let mut content = buf.to_vec();
-        let mut error_buf = [0i8; DEFAULT_ERROR_BUF_SIZE];
-        let module = unsafe {
-            wasm_runtime_load(
-                content.as_mut_ptr(),
-                content.len() as u32,
-                error_buf.as_mut_ptr(),
-                error_buf.len() as u32,
-            )
-        };
+    pub fn from_buf(runtime: &Runtime, buf: &[u8]) -> Result<Self, RuntimeError> {
+        Self::from_vec(runtime, buf.to_vec())
+    }
 
-        if module.is_null() {
-            match error_buf.len() {
-                0 => {
-                    return Err(RuntimeError::CompilationError(String::from(
-                        "load module failed",
-                    )))
-                }
-                _ => {
-                    return Err(RuntimeError::CompilationError(error_buf_to_string(
-                        &error_buf,
-                    )))
-                }
-            }
+    /// compile a module in the given buffer, with options
+    ///
+    /// # Error
+    ///
+    /// The same as `Module::from_buf()`
+    pub fn from_buf_with_options(
+        runtime: &Runtime,
+        buf: &[u8],
+        options: LoadOptions,
+    ) -> Result<Self, RuntimeError> {
+        Self::from_vec_with_options(runtime, buf.to_vec(), options)
+    }
+
+    /// compile a module in the given buffer and take the ownership of the buffer.
+    /// there is no copy of the buffer
+    ///
+    /// # Error
+    ///
+    /// If the wasm file is not a valid wasm file, an `RuntimeError::CompilationError` will be returned.
+    pub fn from_vec(runtime: &Runtime, content: Vec<u8>) -> Result<Self, RuntimeError> {
+        Self::from_vec_with_options(runtime, content, LoadOptions::default())
+    }
+
+    /// compile a module in the given buffer, with options, and take the ownership of the buffer.
+    ///
+    /// # Error
+    ///
+    /// If the buffer is larger than `LoadOptions::max_size()`, an `RuntimeError::ModuleTooLarge` will be returned.
+    /// If the wasm file is not a valid wasm file, an `RuntimeError::CompilationError` will be returned.
+    pub fn from_vec_with_options(
+        runtime: &Runtime,
+        mut content: Vec<u8>,
+        options: LoadOptions,
+    ) -> Result<Self, RuntimeError> {
+        if content.len() as u64 > options.max_size {
+            return Err(RuntimeError::ModuleTooLarge {
+                limit: options.max_size,
+            });
+        }
+
+        // of the original content
+        let digest = ModuleDigest::of(&content);
+
+        if !options.custom_sections {
+            strip_custom_sections(&mut content);
+        }
+        let memory_grow_guarded = options.guard_memory_grow && guard_memory_grow(&mut content);
+
+        Self::from_content(
+            runtime,
+            ModuleContent::Owned(content),
+            digest,
+            &options,
+            memory_grow_guarded,
+        )
+    }
+
+    /// compile a module in the given buffer without a copy. the buffer has to outlive the module
+    ///
+    /// WAMR may modify the buffer in place during loading. Don't use the buffer to load
+    /// another module.
+    ///
+    /// # Error
+    ///
+    /// If the wasm file is not a valid wasm file, an `RuntimeError::CompilationError` will be returned.
+    pub fn from_borrowed(runtime: &Runtime, buf: &'a mut [u8]) -> Result<Self, RuntimeError> {
+        let digest = ModuleDigest::of(buf);
+        Self::from_content(
+            runtime,
+            ModuleContent::Borrowed(buf),
+            digest,
+            &LoadOptions::default(),
+            false,
+        )
+    }
+
+    fn from_content(
+        runtime: &Runtime,
+        mut content: ModuleContent<'a>,
+        digest: ModuleDigest,
+        options: &LoadOptions,
+        memory_grow_guarded: bool,
+    ) -> Result<Self, RuntimeError> {
+        // before loading, since the loader may rewrite the buffer
+        let function_names = Arc::new(parse_function_names(content.as_slice()));
+        let info = parse_module_info(content.as_slice());
+
+        let module = load(content.as_mut_slice(), !options.bytes)?;
+
+        if !options.bytes && unsafe { wasm_runtime_is_underlying_binary_freeable(module) } {
+            content = ModuleContent::Released;
         }
 
         Ok(Module {
-            module,
-            content,
-            wasi_ctx: WasiCtx::default(),
+            inner: Arc::new(ModuleInner {
+                module,
+                id: NEXT_MODULE_ID.fetch_add(1, Ordering::Relaxed),
+                content,
+                wasi_ctx: WasiCtx::default(),
+                function_names,
+                info,
+                digest,
+                memory_grow_guarded,
+                _runtime: runtime.handle(),
+            }),
+        })
+    }
+
+    /// validate a .wasm or an .aot in the given buffer without keeping a `Module`
+    ///
+    /// # Error
+    ///
+    /// If the binary is not valid, an `RuntimeError::CompilationError` will be returned.
+    pub fn validate(_runtime: &Runtime, buf: &[u8]) -> Result<(), RuntimeError> {
+        // wasm_runtime_load() requires a mutable buffer
+        let mut content = buf.to_vec();
+        let module = load(&mut content, false)?;
+
+        unsafe { wasm_runtime_unload(module) };
+        Ok(())
+    }
+
+    /// load a precompiled module with the given .aot file path
+    ///
+    /// # Error
+    ///
+    /// If the file does not exist or the file cannot be read, an `RuntimeError::WasmFileFSError` will be returned.
+    /// Otherwise, the same errors as `Module::from_aot_buf()` will be returned.
+    pub fn from_aot_file(runtime: &Runtime, aot_file: &Path) -> Result<Self, RuntimeError> {
+        Self::from_aot_file_with_options(runtime, aot_file, LoadOptions::default())
+    }
+
+    /// load a precompiled module with the given .aot file path, with options
+    ///
+    /// # Error
+    ///
+    /// The same as `Module::from_aot_file()`
+    pub fn from_aot_file_with_options(
+        runtime: &Runtime,
+        aot_file: &Path,
+        options: LoadOptions,
+    ) -> Result<Self, RuntimeError> {
+        let binary = fs::read(aot_file)?;
+
+        Self::from_aot_buf_with_options(runtime, &binary, options)
+    }
+
+    /// load a precompiled module in the given buffer
+    ///
+    /// # Error
+    ///
+    /// If the buffer is not an .aot, an `RuntimeError::CompilationError` will be returned.
+    /// If the .aot is generated by an incompatible `wamrc`, an `RuntimeError::AotVersionMismatch` will be returned.
+    /// If the .aot is compiled for another target, an `RuntimeError::AotTargetMismatch` will be returned.
+    pub fn from_aot_buf(runtime: &Runtime, buf: &[u8]) -> Result<Self, RuntimeError> {
+        Self::from_aot_buf_with_options(runtime, buf, LoadOptions::default())
+    }
+
+    /// load a precompiled module in the given buffer, with options
+    ///
+    /// # Error
+    ///
+    /// The same as `Module::from_aot_buf()`
+    pub fn from_aot_buf_with_options(
+        runtime: &Runtime,
+        buf: &[u8],
+        options: LoadOptions,
+    ) -> Result<Self, RuntimeError> {
+        if ModuleFormat::detect(buf) != ModuleFormat::Aot {
+            return Err(RuntimeError::CompilationError(String::from(
+                "not an AOT module",
+            )));
+        }
+
+        let expected =
+            unsafe { wasm_runtime_get_current_package_version(package_type_t_Wasm_Module_AoT) };
+        let actual =
+            unsafe { wasm_runtime_get_file_package_version(buf.as_ptr(), buf.len() as u32) };
+        if expected != actual {
+            return Err(RuntimeError::AotVersionMismatch { expected, actual });
+        }
+
+        Self::from_buf_with_options(runtime, buf, options).map_err(|e| match e {
+            // aot_loader reports "invalid target type, expected .. but got .." and alike
+            RuntimeError::CompilationError(msg)
+                if msg.contains("target") || msg.contains("machine") =>
+            {
+                RuntimeError::AotTargetMismatch(msg)
+            }
+            e => e,
         })
     }
 
+    /// compile a module with the given WebAssembly text format
+    ///
+    /// the text is converted to a wasm binary before being loaded
+    ///
+    /// # Error
+    ///
+    /// If the text is not a valid WebAssembly text format, or the converted binary is not a valid wasm
+    /// module, an `RuntimeError::CompilationError` will be returned.
+    pub fn from_wat(runtime: &Runtime, wat: &str) -> Result<Self, RuntimeError> {
+        let binary =
+            wat::parse_str(wat).map_err(|e| RuntimeError::CompilationError(e.to_string()))?;
+
+        Self::from_vec(runtime, binary)
+    }
+
+    /// compile a module with the given WebAssembly text format file path
+    ///
+    /// # Error
+    ///
+    /// If the file does not exist or the file cannot be read, an `RuntimeError::WasmFileFSError` will be returned.
+    /// If the text is not a valid WebAssembly text format, an `RuntimeError::CompilationError` will be returned.
+    pub fn from_wat_file(runtime: &Runtime, wat_file: &Path) -> Result<Self, RuntimeError> {
+        let wat = fs::read_to_string(wat_file)?;
+
+        Self::from_wat(runtime, &wat)
+    }
+
     /// set Wasi context for a module
     ///
     /// This function should be called before `Instance::new`
-    pub fn set_wasi_context(&mut self, wasi_ctx: WasiCtx) {
-        self.wasi_ctx = wasi_ctx;
+    ///
+    /// # Error
+    ///
+    /// If the module has been cloned, or instantiated, and other handles are still alive,
+    /// an `RuntimeError::InvalidInstantiationArgs` will be returned. The Wasi context is a
+    /// part of the loaded module, which is shared by all handles.
+    pub fn set_wasi_context(&mut self, wasi_ctx: WasiCtx) -> Result<(), RuntimeError> {
+        let inner = Arc::get_mut(&mut self.inner).ok_or_else(|| {
+            RuntimeError::InvalidInstantiationArgs(String::from(
+                "set the Wasi context of a module before sharing it",
+            ))
+        })?;
+        inner.wasi_ctx = wasi_ctx;
+        inner.wasi_ctx.apply(inner.module);
+        Ok(())
+    }
 
-        let real_paths = if self.wasi_ctx.get_preopen_real_paths().is_empty() {
-            ptr::null_mut()
-        } else {
-            self.wasi_ctx.get_preopen_real_paths().as_ptr() as *mut *const i8
-        };
+    pub(crate) fn wasi_ctx(&self) -> &WasiCtx {
+        &self.inner.wasi_ctx
+    }
 
-        let mapped_paths = if self.wasi_ctx.get_preopen_mapped_paths().is_empty() {
-            ptr::null_mut()
-        } else {
-            self.wasi_ctx.get_preopen_mapped_paths().as_ptr() as *mut *const i8
-        };
+    /// set the name of the module. It shows up in error messages of WAMR, and is the
+    /// default name when being looked up
+    ///
+    /// # Error
+    ///
+    /// If the module has been cloned, or instantiated, and other handles are still alive,
+    /// an `RuntimeError::InvalidInstantiationArgs` will be returned. The same as
+    /// `set_wasi_context()`, the name is a part of the loaded module.
+    /// If WAMR fails to keep a copy of the name, an `RuntimeError::ModuleRegistrationFailure`
+    /// will be returned.
+    pub fn set_name(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let inner = Arc::get_mut(&mut self.inner).ok_or_else(|| {
+            RuntimeError::InvalidInstantiationArgs(String::from(
+                "set the name of a module before sharing it",
+            ))
+        })?;
+        let name = CString::new(name).expect("CString::new failed");
 
-        let env = if self.wasi_ctx.get_env_vars().is_empty() {
-            ptr::null_mut()
-        } else {
-            self.wasi_ctx.get_env_vars().as_ptr() as *mut *const i8
+        let mut error_buf = [0 as c_char; DEFAULT_ERROR_BUF_SIZE];
+        let succeeded = unsafe {
+            wasm_runtime_set_module_name(
+                inner.module,
+                name.as_ptr(),
+                error_buf.as_mut_ptr(),
+                error_buf.len() as u32,
+            )
         };
 
-        let args = if self.wasi_ctx.get_arguments().is_empty() {
-            ptr::null_mut()
-        } else {
-            self.wasi_ctx.get_arguments().as_ptr() as *mut *mut i8
-        };
+        match succeeded {
+            true => Ok(()),
+            false => Err(RuntimeError::ModuleRegistrationFailure(
+                error_buf_to_string(&error_buf),
+            )),
+        }
+    }
 
-        unsafe {
-            wasm_runtime_set_wasi_args(
-                self.get_inner_module(),
-                real_paths,
-                self.wasi_ctx.get_preopen_real_paths().len() as u32,
-                mapped_paths,
-                self.wasi_ctx.get_preopen_mapped_paths().len() as u32,
-                env,
-                self.wasi_ctx.get_env_vars().len() as u32,
-                args,
-                self.wasi_ctx.get_arguments().len() as i32,
-            );
-
-            let ns_lookup_pool = if self.wasi_ctx.get_allowed_dns().is_empty() {
-                ptr::null_mut()
-            } else {
-                self.wasi_ctx.get_allowed_dns().as_ptr() as *mut *const i8
-            };
-
-            wasm_runtime_set_wasi_ns_lookup_pool(
-                self.get_inner_module(),
-                ns_lookup_pool,
-                self.wasi_ctx.get_allowed_dns().len() as u32,
-            );
-
-            let addr_pool = if self.wasi_ctx.get_allowed_address().is_empty() {
-                ptr::null_mut()
-            } else {
-                self.wasi_ctx.get_allowed_address().as_ptr() as *mut *const i8
-            };
-            wasm_runtime_set_wasi_addr_pool(
-                self.get_inner_module(),
-                addr_pool,
-                self.wasi_ctx.get_allowed_address().len() as u32,
-            );
+    /// the name of the module, if any
+    pub fn name(&self) -> Option<String> {
+        let name = unsafe { wasm_runtime_get_module_name(self.inner.module) };
+        if name.is_null() {
+            return None;
+        }
+
+        let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+        match name.is_empty() {
+            true => None,
+            false => Some(name.into_owned()),
+        }
+    }
+
+    /// list all exports of the module, with their kinds and types
+    ///
+    /// It is available before instantiation. So embedders are able to discover exported
+    /// functions, tables, memories and globals in advance.
+    pub fn exports(&self) -> impl Iterator<Item = ExportType> + '_ {
+        let export_count = unsafe { wasm_runtime_get_export_count(self.inner.module) };
+
+        let exports = (0..export_count).map(move |i| {
+            let mut export = wasm_export_t::default();
+            unsafe { wasm_runtime_get_export_type(self.inner.module, i, &mut export) };
+            ExportType::from_raw(&export)
+        });
+        // see `LoadOptions::guard_memory_grow()`
+        exports.filter(|export| export.name() != MEMORY_GROW_TRAP_GLOBAL)
+    }
+
+    /// a command or a reactor, by whether `_start` is exported
+    pub fn kind(&self) -> ModuleKind {
+        let is_command = self
+            .exports()
+            .any(|export| export.kind() == ExternKind::Func && export.name() == "_start");
+        match is_command {
+            true => ModuleKind::Command,
+            false => ModuleKind::Reactor,
         }
     }
 
+    /// list all imports of the module, with their module names, field names and types
+    ///
+    /// It is available before instantiation. Use `ImportType::is_linked()` to find out
+    /// imports which are not provided by host functions, WASI or other modules.
+    pub fn imports(&self) -> impl Iterator<Item = ImportType> + '_ {
+        let import_count = unsafe { wasm_runtime_get_import_count(self.inner.module) };
+
+        (0..import_count).map(move |i| {
+            let mut import = wasm_import_t::default();
+            unsafe { wasm_runtime_get_import_type(self.inner.module, i, &mut import) };
+            ImportType::from_raw(&import)
+        })
+    }
+
+    /// the function name, from the `name` section, of a function index
+    ///
+    /// The index space includes imported functions. Return `None` if the function
+    /// doesn't have a name or the module isn't a .wasm
+    pub fn function_name(&self, index: u32) -> Option<&str> {
+        self.inner.function_names.get(&index).map(String::as_str)
+    }
+
+    /// all function names in the `name` section. function index -> function name
+    pub fn function_names(&self) -> &HashMap<u32, String> {
+        &self.inner.function_names
+    }
+
+    /// declarations of the module, like memories, tables, functions, etc.
+    ///
+    /// It is available before instantiation. So hosts are able to reject oversized
+    /// modules up front.
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::ModuleInfoUnavailable`, naming what isn't supported, if the
+    /// module is an .aot, or a section of the .wasm has a form unknown to the parser.
+    pub fn info(&self) -> Result<&ModuleInfo, RuntimeError> {
+        self.inner
+            .info
+            .as_ref()
+            .map_err(|e| RuntimeError::ModuleInfoUnavailable(e.clone()))
+    }
+
+    /// SHA-256 of the original content. It is calculated while loading.
+    ///
+    /// It identifies a module. Modules with the same content have the same digest.
+    pub fn digest(&self) -> ModuleDigest {
+        self.inner.digest
+    }
+
+    /// whether `memory.grow` is guarded. See `LoadOptions::guard_memory_grow()`
+    pub fn is_memory_grow_guarded(&self) -> bool {
+        self.inner.memory_grow_guarded
+    }
+
+    /// whether the content is still kept in memory. See `LoadOptions::retain_bytes()`
+    pub fn is_content_retained(&self) -> bool {
+        !matches!(self.inner.content, ModuleContent::Released)
+    }
+
+    pub(crate) fn get_function_names(&self) -> Arc<HashMap<u32, String>> {
+        Arc::clone(&self.inner.function_names)
+    }
+
     pub fn get_inner_module(&self) -> wasm_module_t {
-        self.module
+        self.inner.module
+    }
+
+    // tells a module from another one loaded later at the same address. Clones share it
+    pub(crate) fn id(&self) -> u64 {
+        self.inner.id
     }
 }
 
-impl Drop for Module {
+// with `freeable`, the loader copies what it needs, to make releasing `content` possible
+fn load(content: &mut [u8], freeable: bool) -> Result<wasm_module_t, RuntimeError> {
+    // the same as wasm_runtime_load(). WAMR keeps the pointer as the module name
+    // without copying it, so it has to outlive the module
+    let mut args: LoadArgs = unsafe { std::mem::zeroed() };
+    args.name = c"".as_ptr() as *mut _;
+    args.wasm_binary_freeable = freeable;
+
+    let mut error_buf = [0i8; DEFAULT_ERROR_BUF_SIZE];
+    let module = unsafe {
+        wasm_runtime_load_ex(
+            content.as_mut_ptr(),
+            content.len() as u32,
+            &args,
+            error_buf.as_mut_ptr(),
+            error_buf.len() as u32,
+        )
+    };
+
+    if module.is_null() {
+        match error_buf.len() {
+            0 => {
+                return Err(RuntimeError::CompilationError(String::from(
+                    "load module failed",
+                )))
+            }
+            _ => {
+                return Err(RuntimeError::CompilationError(error_buf_to_string(
+                    &error_buf,
+                )))
+            }
+        }
+    }
+
+    Ok(module)
+}
+
+impl Drop for ModuleInner<'_> {
     fn drop(&mut self) {
         unsafe {
             wasm_runtime_unload(self.module);
@@ -165,7 +787,14 @@ impl Drop for Module {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{runtime::Runtime, wasi_context::WasiCtxBuilder};
+    use crate::{
+        function::Function,
+        instance::Instance,
+        runtime::Runtime,
+        types::{ExternKind, ExternType, FuncType, GlobalType, MemoryType, ValType},
+        value::WasmValue,
+        wasi_context::WasiCtxBuilder,
+    };
     use std::path::PathBuf;
 
     #[test]
@@ -201,6 +830,572 @@ mod tests {
         assert!(module.is_ok());
     }
 
+    #[test]
+    fn test_module_from_vec() {
+        let runtime = Runtime::new().unwrap();
+
+        // (module
+        //   (func (export "add") (param i32 i32) (result i32)
+        //     (local.get 0)
+        //     (local.get 1)
+        //     (i32.add)
+        //   )
+        // )
+        let binary = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+            0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x61, 0x64, 0x64,
+            0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+        ];
+        let binary_ptr = binary.as_ptr();
+
+        let module = Module::from_vec(&runtime, binary).unwrap();
+        match &module.inner.content {
+            ModuleContent::Owned(content) => assert_eq!(content.as_ptr(), binary_ptr),
+            _ => panic!("expect an owned content"),
+        }
+    }
+
+    #[test]
+    fn test_module_from_borrowed() {
+        let runtime = Runtime::new().unwrap();
+
+        let mut binary = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+            0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x61, 0x64, 0x64,
+            0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+        ];
+
+        let module = Module::from_borrowed(&runtime, &mut binary);
+        assert!(module.is_ok());
+        assert_eq!(module.unwrap().exports().count(), 1);
+    }
+
+    #[test]
+    fn test_module_from_reader() {
+        // return at most 3 bytes per read()
+        struct ChunkedReader<'a>(&'a [u8]);
+
+        impl Read for ChunkedReader<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = self.0.len().min(buf.len()).min(3);
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let runtime = Runtime::new().unwrap();
+
+        let binary = wat::parse_str(
+            r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let module = Module::from_reader(&runtime, ChunkedReader(&binary));
+        assert!(module.is_ok());
+        assert_eq!(module.unwrap().digest(), ModuleDigest::of(&binary));
+
+        let module = Module::from_reader(&runtime, std::io::Cursor::new(&binary[..10]));
+        assert!(matches!(module, Err(RuntimeError::CompilationError(_))));
+
+        let options = LoadOptions::default().max_size(binary.len() as u64);
+        let module =
+            Module::from_reader_with_options(&runtime, std::io::Cursor::new(&binary), options);
+        assert!(module.is_ok());
+
+        // an endless source
+        let options = LoadOptions::default().max_size(binary.len() as u64 - 1);
+        let module = Module::from_reader_with_options(&runtime, std::io::repeat(0), options);
+        assert!(matches!(
+            module,
+            Err(RuntimeError::ModuleTooLarge { limit }) if limit == binary.len() as u64 - 1
+        ));
+    }
+
+    #[test]
+    fn test_module_strip_custom_sections() {
+        let runtime = Runtime::new().unwrap();
+
+        let binary = wat::parse_str(
+            r#"
+            (module
+              (func $add (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let module = Module::from_vec_with_options(
+            &runtime,
+            binary.clone(),
+            LoadOptions::default().retain_custom_sections(true),
+        )
+        .unwrap();
+        assert_eq!(module.function_name(0), Some("add"));
+        assert_eq!(module.inner.content.as_slice().len(), binary.len());
+
+        let module = Module::from_vec_with_options(
+            &runtime,
+            binary.clone(),
+            LoadOptions::default().retain_custom_sections(false),
+        )
+        .unwrap();
+        assert_eq!(module.function_name(0), None);
+        assert!(module.inner.content.as_slice().len() < binary.len());
+        assert_eq!(module.digest(), ModuleDigest::of(&binary));
+        assert_eq!(module.exports().count(), 1);
+
+        // through other loaders
+        let options = LoadOptions::default().retain_custom_sections(false);
+        let module = Module::from_buf_with_options(&runtime, &binary, options.clone()).unwrap();
+        assert_eq!(module.function_name(0), None);
+        let module = Module::from_reader_with_options(
+            &runtime,
+            std::io::Cursor::new(&binary),
+            options.clone(),
+        )
+        .unwrap();
+        assert_eq!(module.function_name(0), None);
+
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test");
+        d.push("gcd_wasm32_wasi.wasm");
+        let original = fs::read(d.as_path()).unwrap();
+        let module =
+            Module::from_file_with_options(&runtime, d.as_path(), options.clone()).unwrap();
+        assert!(module.inner.content.as_slice().len() < original.len());
+        assert_eq!(module.digest(), ModuleDigest::of(&original));
+        assert!(matches!(
+            Module::from_aot_buf_with_options(&runtime, &binary, options),
+            Err(RuntimeError::CompilationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_module_release_bytes() {
+        let runtime = Runtime::new().unwrap();
+
+        let binary = wat::parse_str(
+            r#"
+            (module
+              (func (export "answer") (result i32)
+                (i32.const 42)
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let module = Module::from_vec(&runtime, binary.clone()).unwrap();
+        assert!(module.is_content_retained());
+
+        let module = Module::from_vec_with_options(
+            &runtime,
+            binary.clone(),
+            LoadOptions::default().retain_bytes(false),
+        )
+        .unwrap();
+        // depends on the running mode. the classic interpreter always refers to the content
+        if !module.is_content_retained() {
+            assert!(module.inner.content.as_slice().is_empty());
+        }
+        assert_eq!(module.digest(), ModuleDigest::of(&binary));
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let function = Function::find_export_func(&instance, "answer").unwrap();
+        assert_eq!(
+            function.call(&instance, &Vec::new()).unwrap(),
+            vec![WasmValue::I32(42)]
+        );
+    }
+
+    #[test]
+    fn test_module_validate() {
+        let runtime = Runtime::new().unwrap();
+
+        // (module
+        //   (func (export "add") (param i32 i32) (result i32)
+        //     (local.get 0)
+        //     (local.get 1)
+        //     (i32.add)
+        //   )
+        // )
+        let binary = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+            0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x61, 0x64, 0x64,
+            0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+        ];
+        assert!(Module::validate(&runtime, &binary).is_ok());
+
+        // truncated
+        let result = Module::validate(&runtime, &binary[..binary.len() - 4]);
+        assert!(matches!(result, Err(RuntimeError::CompilationError(_))));
+    }
+
+    #[test]
+    fn test_module_from_wat() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                (local.get 0)
+                (local.get 1)
+                (i32.add)
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat);
+        assert!(module.is_ok());
+
+        let module = Module::from_wat(&runtime, "(module (func (export \"add\")");
+        assert!(matches!(module, Err(RuntimeError::CompilationError(_))));
+    }
+
+    #[test]
+    fn test_module_from_wat_file_not_exist() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat_file(&runtime, Path::new("not_exist.wat"));
+        assert!(matches!(module, Err(RuntimeError::WasmFileFSError(_))));
+    }
+
+    #[test]
+    fn test_module_exports() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+              (memory (export "memory") 1 2)
+              (global (export "counter") (mut i64) (i64.const 0))
+              (table (export "table") 3 funcref)
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+
+        let exports: Vec<ExportType> = module.exports().collect();
+        assert_eq!(exports.len(), 4);
+
+        assert_eq!(exports[0].name(), "add");
+        assert_eq!(
+            exports[0].ty(),
+            &ExternType::Func(FuncType::new(
+                vec![ValType::I32, ValType::I32],
+                vec![ValType::I32]
+            ))
+        );
+
+        assert_eq!(exports[1].name(), "memory");
+        assert_eq!(
+            exports[1].ty(),
+            &ExternType::Memory(MemoryType {
+                shared: false,
+                min: 1,
+                max: 2
+            })
+        );
+
+        assert_eq!(exports[2].name(), "counter");
+        assert_eq!(
+            exports[2].ty(),
+            &ExternType::Global(GlobalType {
+                content: ValType::I64,
+                mutable: true
+            })
+        );
+
+        assert_eq!(exports[3].name(), "table");
+        assert_eq!(exports[3].kind(), ExternKind::Table);
+    }
+
+    #[test]
+    fn test_module_imports() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (import "env" "log" (func (param i32 i32)))
+              (import "env" "memory" (memory 1))
+              (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+
+        let imports: Vec<ImportType> = module.imports().collect();
+        assert_eq!(imports.len(), 3);
+
+        assert_eq!(imports[0].module(), "env");
+        assert_eq!(imports[0].name(), "log");
+        assert_eq!(
+            imports[0].ty(),
+            &ExternType::Func(FuncType::new(vec![ValType::I32, ValType::I32], vec![]))
+        );
+        assert!(!imports[0].is_linked());
+
+        assert_eq!(imports[1].name(), "memory");
+        assert_eq!(imports[1].kind(), ExternKind::Memory);
+
+        assert_eq!(imports[2].module(), "wasi_snapshot_preview1");
+        assert!(imports[2].is_linked());
+    }
+
+    #[test]
+    fn test_module_kind() {
+        let runtime = Runtime::new().unwrap();
+
+        let command = Module::from_wat(&runtime, r#"(module (func (export "_start")))"#).unwrap();
+        assert_eq!(command.kind(), ModuleKind::Command);
+
+        let reactor =
+            Module::from_wat(&runtime, r#"(module (func (export "_initialize")))"#).unwrap();
+        assert_eq!(reactor.kind(), ModuleKind::Reactor);
+
+        let library = Module::from_wat(&runtime, r#"(module (func (export "add")))"#).unwrap();
+        assert_eq!(library.kind(), ModuleKind::Reactor);
+    }
+
+    #[test]
+    fn test_module_function_names() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (import "env" "log" (func $log (param i32)))
+              (func $add (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+
+        assert_eq!(module.function_name(0), Some("log"));
+        assert_eq!(module.function_name(1), Some("add"));
+        assert_eq!(module.function_name(2), None);
+        assert_eq!(module.function_names().len(), 2);
+    }
+
+    #[test]
+    fn test_module_info() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (import "env" "log" (func (param i32)))
+              (memory 1 16)
+              (table (export "t") 4 funcref)
+              (func (export "run"))
+            )
+        "#;
+        let module = Module::from_wat(&runtime, wat).unwrap();
+
+        let info = module.info().unwrap();
+        assert_eq!(info.import_count, 1);
+        assert_eq!(info.function_count, 1);
+        assert!(!info.has_start_function);
+        assert_eq!(info.memories.len(), 1);
+        assert_eq!(info.memories[0].max, 16);
+        assert_eq!(info.tables.len(), 1);
+        assert_eq!(info.tables[0].min, 4);
+        assert_eq!(info.tables[0].max, DEFAULT_MAX_TABLE_SIZE);
+        assert_eq!(info.exported_tables, vec![(String::from("t"), 0)]);
+    }
+
+    #[test]
+    fn test_module_digest() {
+        let runtime = Runtime::new().unwrap();
+
+        let wat = r#"
+            (module
+              (func (export "run"))
+            )
+        "#;
+        let binary = wat::parse_str(wat).unwrap();
+
+        let module = Module::from_buf(&runtime, &binary).unwrap();
+        assert_eq!(module.digest(), ModuleDigest::of(&binary));
+
+        let other = Module::from_wat(&runtime, wat).unwrap();
+        assert_eq!(module.digest(), other.digest());
+
+        let other = Module::from_wat(&runtime, "(module)").unwrap();
+        assert_ne!(module.digest(), other.digest());
+
+        // SHA-256 of nothing
+        assert_eq!(
+            ModuleDigest::of(&[]).to_string(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn test_module_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Module>();
+
+        let runtime = Runtime::new().unwrap();
+
+        // load on one thread
+        let module = std::thread::scope(|s| {
+            s.spawn(|| {
+                Module::from_wat(
+                    &runtime,
+                    r#"
+                    (module
+                      (func (export "add") (param i32 i32) (result i32)
+                        (i32.add (local.get 0) (local.get 1))
+                      )
+                    )
+                    "#,
+                )
+                .unwrap()
+            })
+            .join()
+            .unwrap()
+        });
+
+        // instantiate on others
+        std::thread::scope(|s| {
+            for i in 0..4 {
+                let runtime = &runtime;
+                let module = &module;
+                s.spawn(move || {
+                    let instance = Instance::new(runtime, module, 1024 * 64).unwrap();
+                    let function = Function::find_export_func(&instance, "add").unwrap();
+                    let result =
+                        function.call(&instance, &vec![WasmValue::I32(i), WasmValue::I32(1)]);
+                    assert_eq!(result.unwrap(), vec![WasmValue::I32(i + 1)]);
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_module_clone() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let handles: Vec<Module> = (0..4).map(|_| module.clone()).collect();
+        assert!(handles
+            .iter()
+            .all(|handle| handle.get_inner_module() == module.get_inner_module()));
+
+        // the loaded module outlives the original handle
+        drop(module);
+
+        std::thread::scope(|s| {
+            for (i, module) in handles.into_iter().enumerate() {
+                let runtime = &runtime;
+                s.spawn(move || {
+                    let instance = Instance::new(runtime, &module, 1024 * 64).unwrap();
+                    let function = Function::find_export_func(&instance, "add").unwrap();
+                    let result = function.call(
+                        &instance,
+                        &vec![WasmValue::I32(i as i32), WasmValue::I32(1)],
+                    );
+                    assert_eq!(result.unwrap(), vec![WasmValue::I32(i as i32 + 1)]);
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_module_set_wasi_context_shared() {
+        let runtime = Runtime::new().unwrap();
+
+        let mut module = Module::from_wat(&runtime, "(module)").unwrap();
+        let other = module.clone();
+        assert!(matches!(
+            module.set_wasi_context(WasiCtxBuilder::new().build()),
+            Err(RuntimeError::InvalidInstantiationArgs(_))
+        ));
+
+        drop(other);
+        assert!(module
+            .set_wasi_context(WasiCtxBuilder::new().build())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_module_name() {
+        let runtime = Runtime::new().unwrap();
+
+        let mut module = Module::from_wat(&runtime, "(module)").unwrap();
+        assert_eq!(module.name(), None);
+
+        let other = module.clone();
+        assert!(matches!(
+            module.set_name("plugin"),
+            Err(RuntimeError::InvalidInstantiationArgs(_))
+        ));
+        assert_eq!(other.name(), None);
+
+        drop(other);
+        assert!(module.set_name("plugin").is_ok());
+        assert_eq!(module.name().as_deref(), Some("plugin"));
+    }
+
+    #[test]
+    fn test_module_format_detect() {
+        let _runtime = Runtime::new().unwrap();
+
+        let wasm = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        assert_eq!(ModuleFormat::detect(&wasm), ModuleFormat::Bytecode);
+
+        let aot = [0x00, 0x61, 0x6f, 0x74, 0x03, 0x00, 0x00, 0x00];
+        assert_eq!(ModuleFormat::detect(&aot), ModuleFormat::Aot);
+
+        assert_eq!(ModuleFormat::detect(&[0x01, 0x02]), ModuleFormat::Unknown);
+    }
+
+    #[test]
+    fn test_module_from_aot_buf_with_wasm() {
+        let runtime = Runtime::new().unwrap();
+
+        let binary = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+            0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x61, 0x64, 0x64,
+            0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+        ];
+
+        let module = Module::from_aot_buf(&runtime, &binary);
+        assert!(matches!(module, Err(RuntimeError::CompilationError(_))));
+    }
+
+    #[test]
+    fn test_module_from_aot_buf_version_mismatch() {
+        let runtime = Runtime::new().unwrap();
+
+        // magic number of .aot followed by an unsupported version
+        let binary = vec![0x00, 0x61, 0x6f, 0x74, 0xff, 0x00, 0x00, 0x00];
+
+        let module = Module::from_aot_buf(&runtime, &binary);
+        assert!(matches!(
+            module,
+            Err(RuntimeError::AotVersionMismatch { .. })
+        ));
+    }
+
     #[test]
     fn test_module_from_file() {
         let runtime = Runtime::new().unwrap();
@@ -212,6 +1407,20 @@ mod tests {
         assert!(module.is_ok());
     }
 
+    #[test]
+    fn test_module_from_file_mmap() {
+        let runtime = Runtime::new().unwrap();
+
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test");
+        d.push("gcd_wasm32_wasi.wasm");
+        let module = Module::from_file_mmap(&runtime, d.as_path());
+        assert!(module.is_ok());
+
+        let module = Module::from_file_mmap(&runtime, Path::new("not_exist"));
+        assert!(matches!(module, Err(RuntimeError::WasmFileFSError(_))));
+    }
+
     #[test]
     fn test_module_with_wasi_args() {
         let runtime = Runtime::new().unwrap();
@@ -241,6 +1450,6 @@ mod tests {
             .set_allowed_dns(vec![])
             .build();
 
-        module.set_wasi_context(wasi_ctx);
+        module.set_wasi_context(wasi_ctx).unwrap();
     }
 }
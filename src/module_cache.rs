@@ -0,0 +1,212 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! a cache of loaded modules, keyed by the digest of their content.
+//! get one via `ModuleCache::new()`
+
+use std::collections::HashMap;
+
+use crate::{
+    module::{Module, ModuleDigest},
+    runtime::Runtime,
+    RuntimeError,
+};
+
+#[derive(Debug)]
+struct CacheEntry {
+    module: Module<'static>,
+    size: usize,
+    last_used: u64,
+}
+
+/// It deduplicates module loading. The same content is loaded only once as long as
+/// it is in the cache. Cached modules are handed out as clones of the same `Module`
+/// and can be instantiated on any thread.
+///
+/// The least recently used module is evicted when the number of modules or the total
+/// size of their content exceeds the limits. Evicted modules are still alive if others
+/// hold them.
+#[derive(Debug)]
+pub struct ModuleCache {
+    entries: HashMap<ModuleDigest, CacheEntry>,
+    max_modules: usize,
+    max_bytes: usize,
+    total_bytes: usize,
+    clock: u64,
+}
+
+impl ModuleCache {
+    /// create a cache holding at most `max_modules` modules and `max_bytes` bytes of content
+    pub fn new(max_modules: usize, max_bytes: usize) -> Self {
+        ModuleCache {
+            entries: HashMap::new(),
+            max_modules,
+            max_bytes,
+            total_bytes: 0,
+            clock: 0,
+        }
+    }
+
+    /// return the cached module with the same content, or load one and cache it
+    ///
+    /// A module whose content is larger than `max_bytes` is loaded but not cached.
+    ///
+    /// # Error
+    ///
+    /// The same as `Module::from_buf()`
+    pub fn get_or_load(
+        &mut self,
+        runtime: &Runtime,
+        buf: &[u8],
+    ) -> Result<Module<'static>, RuntimeError> {
+        let digest = ModuleDigest::of(buf);
+        if let Some(module) = self.get(&digest) {
+            return Ok(module);
+        }
+
+        let module = Module::from_buf(runtime, buf)?;
+        if buf.len() > self.max_bytes {
+            return Ok(module);
+        }
+
+        self.total_bytes += buf.len();
+        self.clock += 1;
+        self.entries.insert(
+            digest,
+            CacheEntry {
+                module: module.clone(),
+                size: buf.len(),
+                last_used: self.clock,
+            },
+        );
+        self.evict();
+
+        Ok(module)
+    }
+
+    /// return the cached module with the digest
+    pub fn get(&mut self, digest: &ModuleDigest) -> Option<Module<'static>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(digest)?;
+        entry.last_used = self.clock;
+        Some(entry.module.clone())
+    }
+
+    /// remove the module with the digest from the cache
+    pub fn remove(&mut self, digest: &ModuleDigest) -> Option<Module<'static>> {
+        let entry = self.entries.remove(digest)?;
+        self.total_bytes -= entry.size;
+        Some(entry.module)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.total_bytes = 0;
+    }
+
+    pub fn contains(&self, digest: &ModuleDigest) -> bool {
+        self.entries.contains_key(digest)
+    }
+
+    /// the number of cached modules
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// the total size of content of cached modules
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.max_modules || self.total_bytes > self.max_bytes {
+            let lru = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(digest, _)| *digest);
+
+            match lru {
+                Some(digest) => {
+                    self.remove(&digest);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module_with_export(name: &str) -> Vec<u8> {
+        wat::parse_str(format!("(module (func (export \"{}\")))", name)).unwrap()
+    }
+
+    #[test]
+    fn test_module_cache_dedup() {
+        let runtime = Runtime::new().unwrap();
+        let mut cache = ModuleCache::new(8, 1024 * 1024);
+
+        let binary = module_with_export("run");
+        let first = cache.get_or_load(&runtime, &binary).unwrap();
+        let second = cache.get_or_load(&runtime, &binary).unwrap();
+        assert_eq!(first.get_inner_module(), second.get_inner_module());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.total_bytes(), binary.len());
+
+        let third = cache
+            .get_or_load(&runtime, &module_with_export("stop"))
+            .unwrap();
+        assert_ne!(first.get_inner_module(), third.get_inner_module());
+        assert_eq!(cache.len(), 2);
+
+        assert!(cache.remove(&first.digest()).is_some());
+        assert!(!cache.contains(&first.digest()));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_module_cache_lru() {
+        let runtime = Runtime::new().unwrap();
+        let mut cache = ModuleCache::new(2, 1024 * 1024);
+
+        let a = cache
+            .get_or_load(&runtime, &module_with_export("a"))
+            .unwrap();
+        let b = cache
+            .get_or_load(&runtime, &module_with_export("b"))
+            .unwrap();
+
+        // `a` is used more recently than `b`
+        assert!(cache.get(&a.digest()).is_some());
+
+        let c = cache
+            .get_or_load(&runtime, &module_with_export("c"))
+            .unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(&a.digest()));
+        assert!(!cache.contains(&b.digest()));
+        assert!(cache.contains(&c.digest()));
+    }
+
+    #[test]
+    fn test_module_cache_size_limit() {
+        let runtime = Runtime::new().unwrap();
+
+        let binary = module_with_export("run");
+        let mut cache = ModuleCache::new(8, binary.len() - 1);
+
+        let module = cache.get_or_load(&runtime, &binary);
+        assert!(module.is_ok());
+        assert!(cache.is_empty());
+        assert_eq!(cache.total_bytes(), 0);
+    }
+}
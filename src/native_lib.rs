@@ -0,0 +1,151 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! native libraries, the plugins of `iwasm --native-lib`. load them via
+//! `RuntimeBuilder::native_lib()`
+//!
+//! A native library is a shared library which exports
+//! `uint32_t get_native_lib(char **p_module_name, NativeSymbol **p_native_symbols)`, and
+//! optionally `int init_native_lib(void)`, which returns 0 on success, and
+//! `void deinit_native_lib(void)`. Libraries built for iwasm work as they are
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    mem,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    ptr,
+};
+
+use wamr_sys::{wasm_runtime_register_natives, wasm_runtime_unregister_natives, NativeSymbol};
+
+use crate::RuntimeError;
+
+type GetNativeLib = unsafe extern "C" fn(*mut *mut c_char, *mut *mut NativeSymbol) -> u32;
+type InitNativeLib = unsafe extern "C" fn() -> c_int;
+type DeinitNativeLib = unsafe extern "C" fn();
+
+// a loaded library whose natives are registered. They are unregistered, and the library
+// is closed, when it is dropped. Kept by the runtime
+#[derive(Debug)]
+pub(crate) struct NativeLib {
+    handle: *mut c_void,
+    // owned by the library
+    module_name: *const c_char,
+    native_symbols: *mut NativeSymbol,
+}
+
+impl NativeLib {
+    // in the same steps as iwasm. But it fails instead of skipping the library
+    pub(crate) fn load(path: &Path) -> Result<Self, RuntimeError> {
+        let failure = |reason: &str| {
+            RuntimeError::NativeLibFailure(format!("{}: {}", path.display(), reason))
+        };
+
+        let c_path =
+            CString::new(path.as_os_str().as_bytes()).map_err(|_| failure("invalid path"))?;
+        let handle = unsafe {
+            match libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_GLOBAL) {
+                handle if handle.is_null() => libc::dlopen(c_path.as_ptr(), libc::RTLD_LAZY),
+                handle => handle,
+            }
+        };
+        if handle.is_null() {
+            return Err(failure(&dl_error()));
+        }
+
+        let mut lib = NativeLib {
+            handle,
+            module_name: ptr::null(),
+            native_symbols: ptr::null_mut(),
+        };
+
+        if let Some(init_native_lib) = lib.symbol(c"init_native_lib") {
+            let init_native_lib =
+                unsafe { mem::transmute::<*mut c_void, InitNativeLib>(init_native_lib) };
+            if unsafe { init_native_lib() } != 0 {
+                // not initialized, so without deinit_native_lib()
+                unsafe { libc::dlclose(mem::replace(&mut lib.handle, ptr::null_mut())) };
+                return Err(failure("init_native_lib() failed"));
+            }
+        }
+
+        // dropping `lib` deinitializes and closes the library from now on
+        let get_native_lib = lib
+            .symbol(c"get_native_lib")
+            .ok_or_else(|| failure("get_native_lib() not found"))?;
+        let get_native_lib = unsafe { mem::transmute::<*mut c_void, GetNativeLib>(get_native_lib) };
+
+        let mut module_name = ptr::null_mut();
+        let mut native_symbols = ptr::null_mut();
+        let n_native_symbols = unsafe { get_native_lib(&mut module_name, &mut native_symbols) };
+        if n_native_symbols == 0 || module_name.is_null() || native_symbols.is_null() {
+            return Err(failure("get_native_lib() returns no natives"));
+        }
+
+        if !unsafe { wasm_runtime_register_natives(module_name, native_symbols, n_native_symbols) }
+        {
+            return Err(failure("register natives failed"));
+        }
+        lib.module_name = module_name;
+        lib.native_symbols = native_symbols;
+        Ok(lib)
+    }
+
+    fn symbol(&self, name: &CStr) -> Option<*mut c_void> {
+        let symbol = unsafe { libc::dlsym(self.handle, name.as_ptr()) };
+        (!symbol.is_null()).then_some(symbol)
+    }
+}
+
+impl Drop for NativeLib {
+    fn drop(&mut self) {
+        if self.handle.is_null() {
+            return;
+        }
+
+        unsafe {
+            if !self.native_symbols.is_null() {
+                wasm_runtime_unregister_natives(self.module_name, self.native_symbols);
+            }
+            if let Some(deinit_native_lib) = self.symbol(c"deinit_native_lib") {
+                mem::transmute::<*mut c_void, DeinitNativeLib>(deinit_native_lib)();
+            }
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+fn dl_error() -> String {
+    let error = unsafe { libc::dlerror() };
+    match error.is_null() {
+        true => String::from("unknown error"),
+        false => unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Runtime;
+
+    #[test]
+    fn test_load_native_lib() {
+        let _runtime = Runtime::new().unwrap();
+
+        let missing = NativeLib::load(Path::new("/nonexistent/libmissing.so"));
+        assert!(matches!(missing, Err(RuntimeError::NativeLibFailure(_))));
+
+        // a shared library, but not a native library
+        match NativeLib::load(Path::new("libc.so.6")) {
+            Err(RuntimeError::NativeLibFailure(message)) => {
+                assert!(message.ends_with("get_native_lib() not found"))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
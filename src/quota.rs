@@ -0,0 +1,128 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! limits on how often an instance calls a host function. set them via
+//! `InstanceBuilder::host_quota()`
+
+use std::time::{Duration, Instant};
+
+/// how often an instance may call a host function registered as a closure. A call beyond
+/// traps the guest with `TrapKind::HostQuotaExceeded`, without running the host function
+///
+/// ```ignore
+/// let instance = Instance::builder(&runtime, &module)
+///     .host_quota("env", "fetch", HostQuota::new().per_call(10).per_second(100))
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostQuota {
+    per_call: Option<u32>,
+    per_second: Option<u32>,
+}
+
+impl HostQuota {
+    /// no limit
+    pub fn new() -> Self {
+        HostQuota::default()
+    }
+
+    /// at most `calls` during a call into the instance from the host, including those
+    /// nested via `Caller::call()`
+    pub fn per_call(mut self, calls: u32) -> Self {
+        self.per_call = Some(calls);
+        self
+    }
+
+    /// at most `calls` in a second. Counted in windows of one second since the first call
+    pub fn per_second(mut self, calls: u32) -> Self {
+        self.per_second = Some(calls);
+        self
+    }
+}
+
+// calls of a host function by an instance
+#[derive(Debug)]
+pub(crate) struct QuotaUsage {
+    quota: HostQuota,
+    // since the current call into the instance
+    calls: u32,
+    window_start: Option<Instant>,
+    window_calls: u32,
+}
+
+impl QuotaUsage {
+    pub(crate) fn new(quota: HostQuota) -> Self {
+        QuotaUsage {
+            quota,
+            calls: 0,
+            window_start: None,
+            window_calls: 0,
+        }
+    }
+
+    // count a call at `now`, or tell which limit it exceeds
+    pub(crate) fn charge(&mut self, now: Instant) -> Result<(), String> {
+        if let Some(limit) = self.quota.per_call {
+            if self.calls >= limit {
+                return Err(format!("more than {} calls in a call", limit));
+            }
+        }
+
+        if let Some(limit) = self.quota.per_second {
+            match self.window_start {
+                Some(start) if now.duration_since(start) < Duration::from_secs(1) => {}
+                _ => {
+                    self.window_start = Some(now);
+                    self.window_calls = 0;
+                }
+            }
+            if self.window_calls >= limit {
+                return Err(format!("more than {} calls in a second", limit));
+            }
+            self.window_calls += 1;
+        }
+
+        self.calls += 1;
+        Ok(())
+    }
+
+    // a new call into the instance starts
+    pub(crate) fn reset_call(&mut self) {
+        self.calls = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_usage() {
+        let now = Instant::now();
+        let mut usage = QuotaUsage::new(HostQuota::new().per_call(2));
+        assert!(usage.charge(now).is_ok());
+        assert!(usage.charge(now).is_ok());
+        assert_eq!(
+            usage.charge(now),
+            Err(String::from("more than 2 calls in a call"))
+        );
+        usage.reset_call();
+        assert!(usage.charge(now).is_ok());
+
+        let mut usage = QuotaUsage::new(HostQuota::new().per_second(1));
+        assert!(usage.charge(now).is_ok());
+        assert_eq!(
+            usage.charge(now + Duration::from_millis(999)),
+            Err(String::from("more than 1 calls in a second"))
+        );
+        // not reset by a new call
+        usage.reset_call();
+        assert!(usage.charge(now + Duration::from_millis(500)).is_err());
+        assert!(usage.charge(now + Duration::from_secs(1)).is_ok());
+
+        let mut usage = QuotaUsage::new(HostQuota::new());
+        assert!((0..1000).all(|_| usage.charge(now).is_ok()));
+    }
+}
@@ -7,25 +7,118 @@
 //! Every process should have only one instance of this runtime by call
 //! `Runtime::new()` or `Runtime::builder().build()` once.
 
-use std::ffi::c_void;
-use std::sync::Mutex;
+use std::ffi::{c_char, c_void, CStr, CString};
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
 use wamr_sys::{
-    mem_alloc_type_t_Alloc_With_Pool, mem_alloc_type_t_Alloc_With_System_Allocator,
-    wasm_runtime_destroy, wasm_runtime_full_init, wasm_runtime_init, NativeSymbol,
+    mem_alloc_type_t_Alloc_With_Pool, mem_alloc_type_t_Alloc_With_System_Allocator, package_type_t,
+    wasm_exec_env_t, wasm_runtime_destroy, wasm_runtime_find_module_registered,
+    wasm_runtime_full_init, wasm_runtime_init, wasm_runtime_register_module,
+    wasm_runtime_set_module_reader, wasm_runtime_unregister_module, NativeSymbol,
     RunningMode_Mode_Interp, RunningMode_Mode_LLVM_JIT, RuntimeInitArgs,
 };
 
-use crate::{host_function::HostFunctionList, RuntimeError};
+#[cfg(target_os = "linux")]
+use crate::native_lib::NativeLib;
+use crate::{
+    helper::error_buf_to_string,
+    helper::DEFAULT_ERROR_BUF_SIZE,
+    host_function::{HostClosures, HostFunctionList, HostModule, IntoHostFunction, NativeFunction},
+    module::Module,
+    wasi_natives, RuntimeError,
+};
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Runtime {
+    handle: Arc<RuntimeHandle>,
+    // WAMR doesn't copy names of registered modules, and doesn't keep modules alive
+    registered_modules: Mutex<Vec<(CString, Module<'static>)>>,
+}
+
+// It keeps WAMR initialized, and host functions registered. Shared by a runtime and
+// modules loaded by it, so the runtime is destroyed after the last of them is dropped
+#[allow(dead_code)]
+#[derive(Debug)]
+pub(crate) struct RuntimeHandle {
     host_functions: HostFunctionList,
+    // replaced via `Runtime::register_host_module()`
+    host_closures: Mutex<HostClosures>,
+    #[cfg(target_os = "linux")]
+    native_libs: Vec<NativeLib>,
+}
+
+unsafe impl Send for RuntimeHandle {}
+unsafe impl Sync for RuntimeHandle {}
+
+impl Drop for RuntimeHandle {
+    fn drop(&mut self) {
+        let mut ref_cnt = SINGLETON_REF_CNT.lock().unwrap();
+
+        self.host_closures
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .unregister();
+        #[cfg(target_os = "linux")]
+        self.native_libs.clear();
+
+        *ref_cnt -= 1;
+
+        if *ref_cnt == 0 {
+            unsafe {
+                wasm_runtime_destroy();
+            }
+        }
+    }
 }
 
+/// WAMR runtime APIs are thread-safe after initialization. Host functions are read-only
+/// once the runtime is built. So a runtime can be shared by threads which load modules and
+/// instantiate them.
+unsafe impl Send for Runtime {}
+unsafe impl Sync for Runtime {}
+
 static SINGLETON_REF_CNT: Mutex<i32> = Mutex::new(0);
 
+type ModuleReader = Arc<dyn Fn(&str) -> Option<Vec<u8>> + Send + Sync>;
+
+// WAMR accepts only one reader per process, and without user data
+static MODULE_READER: Mutex<Option<ModuleReader>> = Mutex::new(None);
+
+unsafe extern "C" fn read_module(
+    _module_type: package_type_t,
+    module_name: *const c_char,
+    p_buffer: *mut *mut u8,
+    p_size: *mut u32,
+) -> bool {
+    let module_name = CStr::from_ptr(module_name).to_string_lossy();
+
+    // not called with the lock held, since a reader may load modules itself
+    let reader = match MODULE_READER.lock() {
+        Ok(reader) => reader.clone(),
+        Err(_) => return false,
+    };
+
+    match reader.and_then(|reader| reader(&module_name)) {
+        Some(content) => {
+            let content = content.into_boxed_slice();
+            *p_size = content.len() as u32;
+            *p_buffer = Box::into_raw(content) as *mut u8;
+            true
+        }
+        None => false,
+    }
+}
+
+unsafe extern "C" fn destroy_module(buffer: *mut u8, size: u32) {
+    // the buffer is a leaked `Box<[u8]>` by `read_module()`
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        buffer,
+        size as usize,
+    )));
+}
+
 impl Runtime {
     /// return a `RuntimeBuilder` instance
     ///
@@ -49,30 +142,165 @@ impl Runtime {
         *ref_cnt += 1;
 
         match *ref_cnt {
-            1 => match unsafe { wasm_runtime_init() } {
-                true => Ok(Runtime {
-                    host_functions: HostFunctionList::new("empty"),
-                }),
+            1 => match unsafe { wasm_runtime_init() } && wasi_natives::register() {
+                true => Ok(Runtime::with_host_functions(
+                    HostFunctionList::new("empty"),
+                    HostClosures::default(),
+                )),
                 false => Err(RuntimeError::InitializationFailure),
             },
-            _ => Ok(Runtime {
-                host_functions: HostFunctionList::new("empty"),
+            _ => Ok(Runtime::with_host_functions(
+                HostFunctionList::new("empty"),
+                HostClosures::default(),
+            )),
+        }
+    }
+
+    fn with_host_functions(host_functions: HostFunctionList, host_closures: HostClosures) -> Self {
+        Runtime {
+            handle: Arc::new(RuntimeHandle {
+                host_functions,
+                host_closures: Mutex::new(host_closures),
+                #[cfg(target_os = "linux")]
+                native_libs: Vec::new(),
             }),
+            registered_modules: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn handle(&self) -> Arc<RuntimeHandle> {
+        Arc::clone(&self.handle)
+    }
+
+    /// register a module with a name. So other modules are able to import functions,
+    /// memories, tables and globals from it via the name.
+    ///
+    /// It should be called before loading modules which import from it. The runtime holds a
+    /// handle of the registered module. The name is taken until the runtime is dropped, which
+    /// unregisters the module.
+    ///
+    /// # Errors
+    ///
+    /// if the name has been registered, it will return `RuntimeError::ModuleRegistrationFailure`
+    pub fn register_module(
+        &self,
+        name: &str,
+        module: &Module<'static>,
+    ) -> Result<(), RuntimeError> {
+        let name = CString::new(name).expect("CString::new failed");
+
+        let mut error_buf = [0 as c_char; DEFAULT_ERROR_BUF_SIZE];
+        let registered = unsafe {
+            wasm_runtime_register_module(
+                name.as_ptr(),
+                module.get_inner_module(),
+                error_buf.as_mut_ptr(),
+                error_buf.len() as u32,
+            )
+        };
+
+        if !registered {
+            return Err(RuntimeError::ModuleRegistrationFailure(
+                error_buf_to_string(&error_buf),
+            ));
+        }
+
+        self.registered_modules
+            .lock()
+            .unwrap()
+            .push((name, module.clone()));
+        Ok(())
+    }
+
+    /// return the module registered with the name via `register_module()`
+    pub fn find_module(&self, name: &str) -> Option<Module<'static>> {
+        let name = CString::new(name).ok()?;
+
+        let module = unsafe { wasm_runtime_find_module_registered(name.as_ptr()) };
+        if module.is_null() {
+            return None;
         }
+
+        self.registered_modules
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, registered)| registered.get_inner_module() == module)
+            .map(|(_, registered)| registered.clone())
+    }
+
+    /// register a group of host closures while the runtime is alive. It replaces the group
+    /// of the same module name, registered by this or `RuntimeBuilder::register()`
+    ///
+    /// Only modules loaded afterwards link to the new group. Modules loaded before keep
+    /// calling the replaced closures, which are kept until the runtime is destroyed. So a
+    /// host is able to roll out another implementation of its host API while running.
+    ///
+    /// # Errors
+    ///
+    /// if WAMR fails to register the group, it will return
+    /// `RuntimeError::ModuleRegistrationFailure`
+    pub fn register_host_module(&self, module: HostModule) -> Result<(), RuntimeError> {
+        let module_name = String::from(module.module_name());
+        match self.host_closures().replace(module) {
+            true => Ok(()),
+            false => Err(RuntimeError::ModuleRegistrationFailure(format!(
+                "register host module {} failed",
+                module_name
+            ))),
+        }
+    }
+
+    /// unregister the group of host closures of the module name. Modules loaded afterwards
+    /// fail to link to them, and those loaded before are not affected.
+    /// return `false` if there is no such group
+    pub fn unregister_host_module(&self, module_name: &str) -> bool {
+        self.host_closures().remove(module_name)
+    }
+
+    // bind `stubs` to unlinked imports of `module`. See `Linker::stub_unresolved()`
+    pub(crate) fn stub_imports(&self, module: &Module, stubs: Vec<HostModule>) -> bool {
+        self.host_closures().stub(stubs, module.get_inner_module())
+    }
+
+    fn host_closures(&self) -> MutexGuard<'_, HostClosures> {
+        self.handle
+            .host_closures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// set a reader to provide content of modules which are imported by other modules but
+    /// haven't been registered. like `(import "math" "add" (func ...))`
+    ///
+    /// The reader is called with the module name while loading, and should return the
+    /// .wasm or .aot content, or `None` if it doesn't know the module. Content may come from
+    /// disk, a database, an HTTP registry, etc.
+    ///
+    /// There is only one reader per process. A new one replaces the previous one.
+    pub fn set_module_reader<F>(&self, reader: F)
+    where
+        F: Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        *MODULE_READER.lock().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(reader));
+
+        unsafe { wasm_runtime_set_module_reader(Some(read_module), Some(destroy_module)) };
     }
 }
 
 impl Drop for Runtime {
     fn drop(&mut self) {
-        let mut ref_cnt = SINGLETON_REF_CNT.lock().unwrap();
-
-        *ref_cnt -= 1;
-
-        if *ref_cnt == 0 {
-            unsafe {
-                wasm_runtime_destroy();
-            }
+        // WAMR doesn't unload registered modules, and keeps referring to their names and
+        // content until `wasm_runtime_destroy()`. Take them out of its registry first, so
+        // dropping the last handles unloads them
+        let mut registered_modules = self
+            .registered_modules
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (_, module) in registered_modules.iter() {
+            unsafe { wasm_runtime_unregister_module(module.get_inner_module()) };
         }
+        registered_modules.clear();
     }
 }
 
@@ -81,6 +309,9 @@ impl Drop for Runtime {
 pub struct RuntimeBuilder {
     args: RuntimeInitArgs,
     host_functions: HostFunctionList,
+    host_closures: HostClosures,
+    #[cfg(target_os = "linux")]
+    native_libs: Vec<PathBuf>,
 }
 
 /// Can't build() until config allocator mode
@@ -90,6 +321,9 @@ impl Default for RuntimeBuilder {
         RuntimeBuilder {
             args,
             host_functions: HostFunctionList::new("host"),
+            host_closures: HostClosures::default(),
+            #[cfg(target_os = "linux")]
+            native_libs: Vec::new(),
         }
     }
 }
@@ -136,12 +370,143 @@ impl RuntimeBuilder {
         self
     }
 
+    /// the same as `register_host_function()`, but the WAMR signature is derived from the
+    /// type of the function. So a module importing it with another type fails to link
+    /// to it, instead of calling it with wrong arguments
+    ///
+    /// ```ignore
+    /// extern "C" fn add(_: wasm_exec_env_t, a: i32, b: i64) -> i64 { ... }
+    ///
+    /// let runtime = Runtime::builder()
+    ///     .use_system_allocator()
+    ///     .register_native("add", add as extern "C" fn(wasm_exec_env_t, i32, i64) -> i64)
+    ///     .build()?;
+    /// ```
+    pub fn register_native<F: NativeFunction>(
+        mut self,
+        function_name: &str,
+        function: F,
+    ) -> RuntimeBuilder {
+        self.host_functions
+            .register_native_function(function_name, function);
+        self
+    }
+
+    /// register a Rust closure as the host function `module_name`.`function_name`, like
+    /// `(import "env" "log" (func (param i32 i32)))`. Modules importing it should be loaded
+    /// after `build()`
+    ///
+    /// The closure takes a `&Caller` and parameters of `i32`, `i64`, `f32` or `f64`, and
+    /// returns `()`, one of them, or a `Result` of them. An `Err`, like a `Trap`, or a panic
+    /// traps the wasm caller. The call into wasm fails with `RuntimeError::Trap` of
+    /// `TrapKind::Host`, with the message of the error or the panic. A panic never unwinds
+    /// into WAMR. The WAMR signature is generated from the types
+    ///
+    /// The closure may capture state, like an `Arc` of a connection pool or a config. The
+    /// runtime owns it, and drops it after the runtime and all modules loaded by it are
+    /// dropped. Calls may come from instances on any thread, so it is `Send + Sync`.
+    /// Per-instance state belongs to `InstanceBuilder::host_data()`, via `Caller::host_data()`
+    ///
+    /// ```ignore
+    /// let runtime = Runtime::builder()
+    ///     .use_system_allocator()
+    ///     .register("env", "log", |caller: &Caller, ptr: i32, len: i32| {
+    ///         println!("{}", caller.read_str(ptr as u64, len as u64)?);
+    ///         Ok::<(), RuntimeError>(())
+    ///     })
+    ///     .build()?;
+    /// ```
+    pub fn register<Params, Results, H>(
+        mut self,
+        module_name: &str,
+        function_name: &str,
+        function: H,
+    ) -> RuntimeBuilder
+    where
+        H: IntoHostFunction<Params, Results>,
+    {
+        self.host_closures.add(module_name, function_name, function);
+        self
+    }
+
+    /// register a closure as the host function `module_name`.`function_name`, which takes
+    /// arguments as they are. For hot host functions where converting arguments costs
+    ///
+    /// Each parameter takes a 64-bit slot, in the order of `signature`. A 32-bit value is
+    /// in the low bytes. The result, if any, is written into the first slot. A panic traps
+    /// the caller like `register()`. Otherwise, the closure traps it by itself via
+    /// `wasm_runtime_set_exception()`
+    ///
+    /// ```ignore
+    /// .register_raw("env", "add", "(iI)I", |_, slots: &mut [u64]| {
+    ///     slots[0] = (slots[0] as i32 as i64 + slots[1] as i64) as u64;
+    /// })
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// if `signature` is not like `(ii)i`, of `i`, `I`, `f` and `F`
+    pub fn register_raw<F>(
+        mut self,
+        module_name: &str,
+        function_name: &str,
+        signature: &str,
+        function: F,
+    ) -> RuntimeBuilder
+    where
+        F: Fn(wasm_exec_env_t, &mut [u64]) + Send + Sync + 'static,
+    {
+        self.host_closures
+            .add_raw(module_name, function_name, signature, function);
+        self
+    }
+
+    /// load a native library at `build()`, like `iwasm --native-lib`. It is a shared
+    /// library exporting `get_native_lib()`, whose natives are registered for modules
+    /// loaded afterwards. See `native_lib`. It is unloaded with the runtime
+    ///
+    /// # Errors
+    ///
+    /// if the library fails to load, or to register its natives, `build()` returns
+    /// `RuntimeError::NativeLibFailure`
+    #[cfg(target_os = "linux")]
+    pub fn native_lib(mut self, path: impl AsRef<Path>) -> RuntimeBuilder {
+        self.native_libs.push(path.as_ref().to_path_buf());
+        self
+    }
+
     /// create a `Runtime` instance with the configuration
     ///
     /// # Errors
     ///
     /// if the runtime initialization failed, it will return `RuntimeError::InitializationFailure`
     pub fn build(mut self) -> Result<Runtime, RuntimeError> {
+        let mut host_closures = std::mem::take(&mut self.host_closures);
+        #[cfg(target_os = "linux")]
+        let native_libs = std::mem::take(&mut self.native_libs);
+        #[allow(unused_mut)]
+        let mut runtime = self.init()?;
+
+        if !host_closures.register() {
+            host_closures.unregister();
+            return Err(RuntimeError::InitializationFailure);
+        }
+        *runtime.host_closures() = host_closures;
+
+        // dropping `runtime` on errors unloads those loaded
+        #[cfg(target_os = "linux")]
+        for path in native_libs {
+            let native_lib = NativeLib::load(&path)?;
+            // the runtime is just created, nothing else holds its handle
+            Arc::get_mut(&mut runtime.handle)
+                .unwrap()
+                .native_libs
+                .push(native_lib);
+        }
+        Ok(runtime)
+    }
+
+    fn init(mut self) -> Result<Runtime, RuntimeError> {
         let mut ref_cnt = SINGLETON_REF_CNT.lock().unwrap();
 
         *ref_cnt += 1;
@@ -156,15 +521,18 @@ impl RuntimeBuilder {
                 self.args.native_symbols = native_symbols.as_ptr() as *mut NativeSymbol;
 
                 wasm_runtime_full_init(&mut self.args)
-            } {
-                true => Ok(Runtime {
-                    host_functions: self.host_functions,
-                }),
+            } && wasi_natives::register()
+            {
+                true => Ok(Runtime::with_host_functions(
+                    self.host_functions,
+                    HostClosures::default(),
+                )),
                 false => Err(RuntimeError::InitializationFailure),
             },
-            _ => Ok(Runtime {
-                host_functions: self.host_functions,
-            }),
+            _ => Ok(Runtime::with_host_functions(
+                self.host_functions,
+                HostClosures::default(),
+            )),
         }
     }
 }
@@ -172,6 +540,7 @@ impl RuntimeBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{function::Function, instance::Instance, value::WasmValue};
     use wamr_sys::{wasm_runtime_free, wasm_runtime_malloc};
 
     #[test]
@@ -231,6 +600,102 @@ mod tests {
         unsafe { wasm_runtime_free(small_buf) };
     }
 
+    #[test]
+    fn test_runtime_register_module() {
+        let runtime = Runtime::new().unwrap();
+
+        let math = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        assert!(runtime.register_module("math", &math).is_ok());
+
+        // the name has been taken
+        let result = runtime.register_module("math", &math);
+        assert!(matches!(
+            result,
+            Err(RuntimeError::ModuleRegistrationFailure(_))
+        ));
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "math" "add" (func $add (param i32 i32) (result i32)))
+              (func (export "add_ten") (param i32) (result i32)
+                (call $add (local.get 0) (i32.const 10))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let function = Function::find_export_func(&instance, "add_ten").unwrap();
+        let result = function.call(&instance, &vec![WasmValue::I32(5)]);
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(15)]);
+    }
+
+    #[test]
+    fn test_runtime_find_module() {
+        let runtime = Runtime::new().unwrap();
+
+        let mut plugin = Module::from_wat(&runtime, "(module (func (export \"run\")))").unwrap();
+        assert!(plugin.set_name("plugin").is_ok());
+        assert_eq!(plugin.name().as_deref(), Some("plugin"));
+
+        assert!(runtime.find_module("plugin").is_none());
+        assert!(runtime.register_module("plugin", &plugin).is_ok());
+
+        let found = runtime.find_module("plugin").unwrap();
+        assert_eq!(found.get_inner_module(), plugin.get_inner_module());
+        assert!(runtime.find_module("unknown").is_none());
+    }
+
+    #[test]
+    fn test_runtime_set_module_reader() {
+        let runtime = Runtime::new().unwrap();
+
+        runtime.set_module_reader(|name| match name {
+            "calc" => wat::parse_str(
+                r#"
+                (module
+                  (func (export "mul") (param i32 i32) (result i32)
+                    (i32.mul (local.get 0) (local.get 1))
+                  )
+                )
+                "#,
+            )
+            .ok(),
+            _ => None,
+        });
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "calc" "mul" (func $mul (param i32 i32) (result i32)))
+              (func (export "square") (param i32) (result i32)
+                (call $mul (local.get 0) (local.get 0))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let function = Function::find_export_func(&instance, "square").unwrap();
+        let result = function.call(&instance, &vec![WasmValue::I32(7)]);
+        assert_eq!(result.unwrap(), vec![WasmValue::I32(49)]);
+    }
+
     #[test]
     #[cfg(feature = "llvmjit")]
     #[ignore]
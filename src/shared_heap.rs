@@ -0,0 +1,149 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! a heap of the host which instances attach to, besides their linear memories. Like a
+//! framebuffer several instances render into. See `SharedHeap`
+
+use std::sync::Arc;
+
+use wamr_sys::{
+    wasm_module_inst_t, wasm_runtime_attach_shared_heap, wasm_runtime_create_shared_heap,
+    wasm_shared_heap_t, SharedHeapInitArgs,
+};
+
+use crate::{
+    runtime::{Runtime, RuntimeHandle},
+    RuntimeError,
+};
+
+/// a heap allocated by the host, which instances attach to via
+/// `InstanceBuilder::shared_heap()`. All of them see it at the same wasm addresses, at
+/// the top of the 32-bit address space, beyond their linear memories
+///
+/// WAMR doesn't take a host buffer, or a memory of another instance, as the linear memory
+/// of an instance. A shared heap is what it offers instead. Allocate regions in it via
+/// `Instance::shared_heap_malloc()`, and pass their wasm addresses to guests. The host
+/// keeps accessing them via the native addresses.
+///
+/// It needs the `shared-heap` feature. AOT modules need `wamrc --enable-shared-heap`.
+///
+/// ```ignore
+/// let heap = SharedHeap::new(&runtime, 4 << 20)?;
+/// let top = Instance::builder(&runtime, &module).shared_heap(&heap).build()?;
+/// let bottom = Instance::builder(&runtime, &module).shared_heap(&heap).build()?;
+///
+/// let (framebuffer, pixels) = top.shared_heap_malloc(640 * 480 * 4)?;
+/// top.call("render").args((framebuffer as i32, 0)).invoke()?;
+/// bottom.call("render").args((framebuffer as i32, 240)).invoke()?;
+/// present(unsafe { std::slice::from_raw_parts(pixels, 640 * 480 * 4) });
+/// ```
+#[derive(Debug, Clone)]
+pub struct SharedHeap {
+    heap: wasm_shared_heap_t,
+    size: u32,
+    // WAMR releases shared heaps when it is destroyed
+    _runtime: Arc<RuntimeHandle>,
+}
+
+// WAMR serializes allocations in a shared heap
+unsafe impl Send for SharedHeap {}
+unsafe impl Sync for SharedHeap {}
+
+impl SharedHeap {
+    /// a heap of `size` bytes. WAMR rounds it up to pages of the host
+    ///
+    /// # Error
+    ///
+    /// If WAMR fails to allocate it, an `RuntimeError::InitializationFailure` will be returned.
+    pub fn new(runtime: &Runtime, size: u32) -> Result<Self, RuntimeError> {
+        let mut args = SharedHeapInitArgs {
+            size,
+            ..Default::default()
+        };
+        let heap = unsafe { wasm_runtime_create_shared_heap(&mut args) };
+        if heap.is_null() {
+            return Err(RuntimeError::InitializationFailure);
+        }
+
+        Ok(SharedHeap {
+            heap,
+            size,
+            _runtime: runtime.handle(),
+        })
+    }
+
+    /// the size requested while creating
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub(crate) fn attach(&self, instance: wasm_module_inst_t) -> Result<(), RuntimeError> {
+        match unsafe { wasm_runtime_attach_shared_heap(instance, self.heap) } {
+            true => Ok(()),
+            false => Err(RuntimeError::InstantiationFailure(String::from(
+                "attach the shared heap failed",
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instance::Instance, module::Module};
+
+    #[test]
+    fn test_shared_heap() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (memory 1)
+              (func (export "store") (param $dst i32) (param $value i32)
+                (i32.store (local.get $dst) (local.get $value))
+              )
+              (func (export "load") (param $src i32) (result i32)
+                (i32.load8_u (local.get $src))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let heap = SharedHeap::new(&runtime, 64 * 1024).unwrap();
+        let top = Instance::builder(&runtime, &module)
+            .shared_heap(&heap)
+            .build()
+            .unwrap();
+        let bottom = Instance::builder(&runtime, &module)
+            .shared_heap(&heap)
+            .build()
+            .unwrap();
+
+        let (offset, pixels) = top.shared_heap_malloc(8).unwrap();
+        assert!(offset >= 64 * 1024);
+        top.call("store")
+            .args((offset as i32, 0xaaaa_aaaau32 as i32))
+            .invoke()
+            .unwrap();
+        bottom
+            .call("store")
+            .args((offset as i32 + 4, 0xbbbb_bbbbu32 as i32))
+            .invoke()
+            .unwrap();
+
+        let pixels = unsafe { std::slice::from_raw_parts(pixels, 8) };
+        assert_eq!(pixels, [0xaa, 0xaa, 0xaa, 0xaa, 0xbb, 0xbb, 0xbb, 0xbb]);
+        let loaded: i32 = bottom
+            .call("load")
+            .arg(offset as i32)
+            .invoke_typed()
+            .unwrap();
+        assert_eq!(loaded, 0xaa);
+
+        top.shared_heap_free(offset);
+    }
+}
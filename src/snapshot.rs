@@ -0,0 +1,477 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! a copy of the state of an instance, to restore it later.
+//! get one via `Instance::snapshot()`
+
+#[cfg(target_os = "linux")]
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::unix::fs::FileExt,
+    os::unix::io::{AsRawFd, FromRawFd},
+};
+
+use crate::{
+    instance::Instance, memory::Memory, types::ExternType, value::WasmValue, RuntimeError,
+};
+
+/// It captures
+/// - contents of all linear memories
+/// - values of exported mutable globals
+/// - elements of exported tables
+///
+/// Globals and tables which are not exported are invisible to the host, and are not captured.
+/// Capturing tables needs the `table-internals` feature.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    // see `Module::id`
+    module: u64,
+    memories: Vec<Vec<u8>>,
+    globals: Vec<(String, WasmValue)>,
+    tables: Vec<(String, Vec<Option<u32>>)>,
+}
+
+impl Snapshot {
+    pub(crate) fn capture(instance: &Instance) -> Result<Self, RuntimeError> {
+        let memories = instance
+            .memories()
+            .map(|memory| {
+                let mut content = vec![0u8; memory.data_size() as usize];
+                memory.read(0, &mut content).map(|_| content)
+            })
+            .collect::<Result<Vec<Vec<u8>>, RuntimeError>>()?;
+        let (globals, tables) = capture_globals_and_tables(instance)?;
+
+        Ok(Snapshot {
+            module: instance.module().id(),
+            memories,
+            globals,
+            tables,
+        })
+    }
+
+    pub(crate) fn restore(&self, instance: &Instance) -> Result<(), RuntimeError> {
+        self.check_module(instance)?;
+
+        for (index, content) in self.memories.iter().enumerate() {
+            let memory = prepare_memory(instance, index, content.len() as u64)?;
+            memory.write(0, content)?;
+        }
+
+        self.restore_globals_and_tables(instance)
+    }
+
+    fn check_module(&self, instance: &Instance) -> Result<(), RuntimeError> {
+        if self.module != instance.module().id() {
+            return Err(RuntimeError::SnapshotMismatch(String::from(
+                "the snapshot is taken from an instance of another module",
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn restore_globals_and_tables(&self, instance: &Instance) -> Result<(), RuntimeError> {
+        for (name, value) in &self.globals {
+            instance.global(name)?.set(value.clone())?;
+        }
+
+        #[cfg(feature = "table-internals")]
+        for (name, elements) in &self.tables {
+            let table = instance.table(name)?;
+            for index in 0..table.len() {
+                let element = elements.get(index as usize).copied().flatten();
+                table.set(index, element)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// the total size of captured memories in bytes
+    pub fn memory_size(&self) -> usize {
+        self.memories.iter().map(Vec::len).sum()
+    }
+
+    /// bring the snapshot forward by the changes of a later incremental snapshot
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::SnapshotMismatch` if they are taken from instances of
+    /// different modules.
+    #[cfg(target_os = "linux")]
+    pub fn apply(&mut self, diff: &SnapshotDiff) -> Result<(), RuntimeError> {
+        if self.module != diff.module {
+            return Err(RuntimeError::SnapshotMismatch(String::from(
+                "the incremental snapshot is taken from an instance of another module",
+            )));
+        }
+
+        self.memories.resize(diff.memories.len(), Vec::new());
+        for (content, memory) in self.memories.iter_mut().zip(&diff.memories) {
+            content.resize(memory.size as usize, 0);
+            for (offset, bytes) in &memory.pages {
+                let offset = *offset as usize;
+                content[offset..offset + bytes.len()].copy_from_slice(bytes);
+            }
+        }
+
+        self.globals = diff.globals.clone();
+        self.tables = diff.tables.clone();
+        Ok(())
+    }
+
+    /// move captured memories into memory files, to restore them by copy-on-write mappings.
+    /// See `MappedSnapshot`
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::WasmFileFSError` if failed to create or fill memory files.
+    #[cfg(target_os = "linux")]
+    pub fn into_mapped(mut self) -> Result<MappedSnapshot, RuntimeError> {
+        let memories = self
+            .memories
+            .iter()
+            .map(|content| {
+                let fd =
+                    unsafe { libc::memfd_create(c"wamr-snapshot".as_ptr(), libc::MFD_CLOEXEC) };
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                let mut file = unsafe { File::from_raw_fd(fd) };
+                file.write_all(content)?;
+                Ok((file, content.len() as u64))
+            })
+            .collect::<Result<Vec<_>, io::Error>>()?;
+
+        self.memories.clear();
+        Ok(MappedSnapshot {
+            snapshot: self,
+            memories,
+        })
+    }
+}
+
+type CapturedGlobals = Vec<(String, WasmValue)>;
+type CapturedTables = Vec<(String, Vec<Option<u32>>)>;
+
+fn capture_globals_and_tables(
+    instance: &Instance,
+) -> Result<(CapturedGlobals, CapturedTables), RuntimeError> {
+    let mut globals = Vec::new();
+    let mut tables = Vec::new();
+    for export in instance.exports() {
+        match export.ty() {
+            ExternType::Global(global_type) if global_type.mutable => {
+                let value = instance.global(export.name())?.get()?;
+                globals.push((export.name().to_string(), value));
+            }
+            ExternType::Table(_) => {
+                let elements = capture_table(instance, export.name())?;
+                tables.push((export.name().to_string(), elements));
+            }
+            _ => {}
+        }
+    }
+
+    Ok((globals, tables))
+}
+
+#[cfg(feature = "table-internals")]
+fn capture_table(instance: &Instance, name: &str) -> Result<Vec<Option<u32>>, RuntimeError> {
+    let table = instance.table(name)?;
+    (0..table.len())
+        .map(|index| table.get_func_index(index))
+        .collect()
+}
+
+// reading elements as function indexes relies on WAMR internals. See `table`
+#[cfg(not(feature = "table-internals"))]
+fn capture_table(_instance: &Instance, _name: &str) -> Result<Vec<Option<u32>>, RuntimeError> {
+    Err(RuntimeError::NotImplemented)
+}
+
+// resize the memory to `size` bytes. Memories can't shrink, pages grown after the snapshot
+// are cleared
+fn prepare_memory<'a>(
+    instance: &'a Instance<'a>,
+    index: usize,
+    size: u64,
+) -> Result<Memory<'a>, RuntimeError> {
+    let memory = instance
+        .memory_at(index as u32)
+        .ok_or_else(|| RuntimeError::SnapshotMismatch(format!("memory {} not found", index)))?;
+
+    let current = memory.data_size();
+    if current < size {
+        memory.grow((size - current).div_ceil(memory.page_size()))?;
+    } else if current > size {
+        memory.write(size, &vec![0u8; (current - size) as usize])?;
+    }
+
+    Ok(memory)
+}
+
+/// A snapshot whose memories are kept in memory files (memfd). Get one via
+/// `Snapshot::into_mapped()`
+///
+/// Restoring it maps the files over linear memories privately. Nothing is copied up front
+/// and only pages written afterwards take memory. So minting an instance from a warmed one,
+/// via `InstanceBuilder::snapshot()`, or rolling an instance back, via
+/// `Instance::restore_mapped()`, costs about the same whatever the memory size.
+///
+/// It relies on WAMR reserving linear memories with `mmap()`, which is the case on 64-bit
+/// Linux. Shared memories, and memories not aligned to pages, are restored by copying.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct MappedSnapshot {
+    // with memories moved out
+    snapshot: Snapshot,
+    memories: Vec<(File, u64)>,
+}
+
+#[cfg(target_os = "linux")]
+impl MappedSnapshot {
+    pub(crate) fn restore(&self, instance: &Instance) -> Result<(), RuntimeError> {
+        self.snapshot.check_module(instance)?;
+
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        for (index, (file, size)) in self.memories.iter().enumerate() {
+            let memory = prepare_memory(instance, index, *size)?;
+            let base = memory.base_address();
+
+            let mappable = *size > 0
+                && !memory.is_shared()
+                && (base as u64).is_multiple_of(page_size)
+                && size.is_multiple_of(page_size);
+            let mapped = mappable
+                && unsafe {
+                    libc::mmap(
+                        base as *mut libc::c_void,
+                        *size as libc::size_t,
+                        libc::PROT_READ | libc::PROT_WRITE,
+                        libc::MAP_PRIVATE | libc::MAP_FIXED,
+                        file.as_raw_fd(),
+                        0,
+                    )
+                } != libc::MAP_FAILED;
+
+            if !mapped {
+                let mut content = vec![0u8; *size as usize];
+                file.read_exact_at(&mut content, 0)?;
+                memory.write(0, &content)?;
+            }
+        }
+
+        self.snapshot.restore_globals_and_tables(instance)
+    }
+
+    /// the total size of captured memories in bytes
+    pub fn memory_size(&self) -> u64 {
+        self.memories.iter().map(|(_, size)| size).sum()
+    }
+}
+
+/// Changes of an instance since a previous snapshot. Get one via
+/// `DirtyPageTracker::snapshot()`, and bring the previous snapshot forward via
+/// `Snapshot::apply()`
+///
+/// It captures written ranges of linear memories, and, like `Snapshot`, values of exported
+/// mutable globals and elements of exported tables.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+pub struct SnapshotDiff {
+    // see `Module::id`
+    module: u64,
+    memories: Vec<MemoryDiff>,
+    globals: CapturedGlobals,
+    tables: CapturedTables,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+pub(crate) struct MemoryDiff {
+    // of the whole memory
+    pub(crate) size: u64,
+    // (offset, content)
+    pub(crate) pages: Vec<(u64, Vec<u8>)>,
+}
+
+#[cfg(target_os = "linux")]
+impl SnapshotDiff {
+    pub(crate) fn capture(
+        instance: &Instance,
+        memories: Vec<MemoryDiff>,
+    ) -> Result<Self, RuntimeError> {
+        let (globals, tables) = capture_globals_and_tables(instance)?;
+
+        Ok(SnapshotDiff {
+            module: instance.module().id(),
+            memories,
+            globals,
+            tables,
+        })
+    }
+
+    /// the total size of captured ranges of memories in bytes
+    pub fn memory_size(&self) -> usize {
+        self.memories
+            .iter()
+            .flat_map(|memory| &memory.pages)
+            .map(|(_, content)| content.len())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{module::Module, runtime::Runtime};
+
+    use super::*;
+
+    const STATEFUL: &str = r#"
+        (module
+          (memory (export "memory") 1 4)
+          (global (export "count") (mut i32) (i32.const 0))
+          (global (export "answer") i32 (i32.const 42))
+          (data (i32.const 0) "init")
+        )
+        "#;
+
+    #[test]
+    fn test_snapshot_restore() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, STATEFUL).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let snapshot = instance.snapshot().unwrap();
+        assert_eq!(snapshot.memory_size(), 65536);
+
+        let memory = instance.memory().unwrap();
+        memory.write(0, b"used").unwrap();
+        memory.grow(1).unwrap();
+        memory.write_u32_le(65536, 7).unwrap();
+        let count = instance.global("count").unwrap();
+        count.set(WasmValue::I32(9)).unwrap();
+
+        assert!(instance.restore(&snapshot).is_ok());
+
+        let mut buf = [0u8; 4];
+        memory.read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"init");
+        // grown pages are kept, but cleared
+        assert_eq!(memory.size_pages(), 2);
+        assert_eq!(memory.read_u32_le(65536).unwrap(), 0);
+        assert_eq!(count.get().unwrap(), WasmValue::I32(0));
+    }
+
+    #[test]
+    fn test_snapshot_tables() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (type $f (func (result i32)))
+              (table (export "table") 2 funcref)
+              (elem (i32.const 0) $one)
+              (func $one (type $f) (i32.const 1))
+              (func $two (type $f) (i32.const 2))
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        #[cfg(not(feature = "table-internals"))]
+        assert!(matches!(
+            instance.snapshot(),
+            Err(RuntimeError::NotImplemented)
+        ));
+
+        #[cfg(feature = "table-internals")]
+        {
+            let snapshot = instance.snapshot().unwrap();
+            let table = instance.table("table").unwrap();
+            table.set(0, Some(1)).unwrap();
+            table.set(1, Some(0)).unwrap();
+
+            assert!(instance.restore(&snapshot).is_ok());
+            assert_eq!(table.get_func_index(0).unwrap(), Some(0));
+            assert_eq!(table.get_func_index(1).unwrap(), None);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_mapped_snapshot() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, STATEFUL).unwrap();
+
+        let warmed = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        warmed.memory().unwrap().write(0, b"warm").unwrap();
+        warmed
+            .global("count")
+            .unwrap()
+            .set(WasmValue::I32(3))
+            .unwrap();
+        let snapshot = warmed.snapshot().unwrap().into_mapped().unwrap();
+        assert_eq!(snapshot.memory_size(), 65536);
+
+        let instance = Instance::builder(&runtime, &module)
+            .snapshot(&snapshot)
+            .build()
+            .unwrap();
+        let memory = instance.memory().unwrap();
+        let mut buf = [0u8; 4];
+        memory.read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"warm");
+        let count = instance.global("count").unwrap();
+        assert_eq!(count.get().unwrap(), WasmValue::I32(3));
+
+        // writes are private to the instance
+        memory.write(0, b"used").unwrap();
+        warmed.memory().unwrap().read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"warm");
+
+        assert!(instance.restore_mapped(&snapshot).is_ok());
+        memory.read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"warm");
+    }
+
+    #[test]
+    fn test_snapshot_mismatch() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, STATEFUL).unwrap();
+        let other = Module::from_wat(&runtime, "(module (memory 1))").unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let snapshot = instance.snapshot().unwrap();
+
+        let other = Instance::new(&runtime, &other, 1024 * 64).unwrap();
+        assert!(matches!(
+            other.restore(&snapshot),
+            Err(RuntimeError::SnapshotMismatch(_))
+        ));
+
+        // nor another module loaded later, even at the same address
+        drop(instance);
+        drop(module);
+        let module = Module::from_wat(&runtime, STATEFUL).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        assert!(matches!(
+            instance.restore(&snapshot),
+            Err(RuntimeError::SnapshotMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Snapshot>();
+    }
+}
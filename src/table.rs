@@ -0,0 +1,580 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! an exported table of an instance. inspect and patch its elements from the host.
+//! get one via `Instance::table()`
+//!
+//! wasm_export.h only reads tables. Writing and growing them, `Table::set()` and others,
+//! rely on WAMR internals and need the `table-internals` feature
+
+use std::ffi::CString;
+
+#[cfg(feature = "table-internals")]
+use wamr_sys::wasm_module_inst_t;
+use wamr_sys::{
+    wasm_function_inst_t, wasm_runtime_get_export_table_inst, wasm_table_get_func_inst,
+    wasm_table_inst_t, wasm_valkind_enum_WASM_FUNCREF, wasm_valkind_t,
+};
+
+#[cfg(feature = "table-internals")]
+use crate::value::FuncRef;
+use crate::{
+    function::Function,
+    instance::Instance,
+    trap::TrapKind,
+    types::{FuncType, ValType},
+    value::{WasmTypes, WasmValue},
+    RuntimeError,
+};
+
+// `table_elem_type_t` of WAMR without GC. a function index, or `NULL_REF`. It is internal
+// to WAMR, `wasm_table_inst_t::elems` points to an array of them
+type TableElem = usize;
+#[cfg(feature = "table-internals")]
+const NULL_REF: TableElem = 0xFFFF_FFFF;
+
+// not in wasm_export.h. what `table.grow` runs for instances of .wasm modules
+#[cfg(feature = "table-internals")]
+extern "C" {
+    fn wasm_enlarge_table(
+        module_inst: wasm_module_inst_t,
+        table_idx: u32,
+        inc_size: u32,
+        init_val: TableElem,
+    ) -> bool;
+}
+
+/// A view of an exported table. It is only valid as long as the instance is alive.
+///
+/// Only tables of `funcref` are supported. An element is either the index of a function
+/// of the module or null.
+#[derive(Debug)]
+pub struct Table<'instance> {
+    instance: &'instance Instance<'instance>,
+    name: CString,
+}
+
+impl<'instance> Table<'instance> {
+    pub(crate) fn new(
+        instance: &'instance Instance<'instance>,
+        name: &str,
+    ) -> Result<Self, RuntimeError> {
+        let table = Table {
+            instance,
+            name: CString::new(name).expect("CString::new failed"),
+        };
+
+        match table.raw() {
+            Some(_) => Ok(table),
+            None => Err(RuntimeError::TableNotFound),
+        }
+    }
+
+    // the size changes if wasm grows the table. so always query the latest one
+    fn raw(&self) -> Option<wasm_table_inst_t> {
+        let mut table: wasm_table_inst_t = unsafe { std::mem::zeroed() };
+        let found = unsafe {
+            wasm_runtime_get_export_table_inst(
+                self.instance.get_inner_instance(),
+                self.name.as_ptr(),
+                &mut table,
+            )
+        };
+        found.then_some(table)
+    }
+
+    fn raw_checked(&self, index: u32) -> Result<wasm_table_inst_t, RuntimeError> {
+        let table = self.raw().ok_or(RuntimeError::TableNotFound)?;
+        if ValType::from_valkind(table.elem_kind) != ValType::FuncRef {
+            return Err(RuntimeError::NotImplemented);
+        }
+        if index >= table.cur_size {
+            return Err(RuntimeError::TableAccessOutOfBounds {
+                index,
+                size: table.cur_size,
+            });
+        }
+        Ok(table)
+    }
+
+    /// the type of elements
+    pub fn element_type(&self) -> ValType {
+        self.raw()
+            .map(|table| ValType::from_valkind(table.elem_kind))
+            .unwrap_or(ValType::FuncRef)
+    }
+
+    /// the current number of elements
+    pub fn len(&self) -> u32 {
+        self.raw().map(|table| table.cur_size).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the maximum number of elements
+    pub fn max_size(&self) -> u32 {
+        self.raw().map(|table| table.max_size).unwrap_or(0)
+    }
+
+    /// grow the table by `delta` elements, each of which is set to the function with
+    /// `func_index`, or null. Like `table.grow`, return the previous number of elements.
+    /// It needs the `table-internals` feature
+    ///
+    /// # Error
+    ///
+    /// If the table would grow beyond `max_size()`, an `RuntimeError::TableGrowFailure` will be
+    /// returned. If the table isn't a table of `funcref`, or the module is loaded from an .aot
+    /// file, an `RuntimeError::NotImplemented` will be returned.
+    #[cfg(feature = "table-internals")]
+    pub fn grow(&self, delta: u32, func_index: Option<u32>) -> Result<u32, RuntimeError> {
+        let table = self.raw().ok_or(RuntimeError::TableNotFound)?;
+        if ValType::from_valkind(table.elem_kind) != ValType::FuncRef {
+            return Err(RuntimeError::NotImplemented);
+        }
+
+        // WAMR grows tables by indexes. which are known from the .wasm only
+        let table_index = self
+            .instance
+            .module()
+            .info()
+            .ok()
+            .and_then(|info| {
+                info.exported_tables
+                    .iter()
+                    .find(|(name, _)| name.as_bytes() == self.name.as_bytes())
+            })
+            .map(|(_, index)| *index)
+            .ok_or(RuntimeError::NotImplemented)?;
+
+        let init_val = match func_index {
+            Some(func_index) => func_index as TableElem,
+            None => NULL_REF,
+        };
+        let grown = unsafe {
+            wasm_enlarge_table(
+                self.instance.get_inner_instance(),
+                table_index,
+                delta,
+                init_val,
+            )
+        };
+        match grown {
+            true => Ok(table.cur_size),
+            false => Err(RuntimeError::TableGrowFailure {
+                current: table.cur_size,
+                delta,
+            }),
+        }
+    }
+
+    /// the function at `index`, or `None` if the element is null
+    ///
+    /// # Error
+    ///
+    /// If `index` is beyond the table, an `RuntimeError::TableAccessOutOfBounds` will be returned.
+    /// If the table isn't a table of `funcref`, an `RuntimeError::NotImplemented` will be returned.
+    pub fn get(&self, index: u32) -> Result<Option<Function>, RuntimeError> {
+        let table = self.raw_checked(index)?;
+
+        let function =
+            unsafe { wasm_table_get_func_inst(self.instance.get_inner_instance(), &table, index) };
+        match function.is_null() {
+            true => Ok(None),
+            false => Ok(Some(Function::from_raw(function, self.instance))),
+        }
+    }
+
+    /// the index of the function at `index`, or `None` if the element is null. It needs the
+    /// `table-internals` feature
+    ///
+    /// # Error
+    ///
+    /// The same as `get()`
+    #[cfg(feature = "table-internals")]
+    pub fn get_func_index(&self, index: u32) -> Result<Option<u32>, RuntimeError> {
+        let table = self.raw_checked(index)?;
+
+        let elem = unsafe { *(table.elems as *const TableElem).add(index as usize) };
+        match elem {
+            NULL_REF => Ok(None),
+            func_index => Ok(Some(func_index as u32)),
+        }
+    }
+
+    /// set the element at `index` to the function with `func_index`, or null. Following
+    /// `call_indirect`s through the element call the new function. It needs the
+    /// `table-internals` feature
+    ///
+    /// `func_index` is in the function index space of the module, imported functions first.
+    /// It is not validated here. `call_indirect` traps with an unknown one.
+    ///
+    /// # Error
+    ///
+    /// The same as `get()`
+    #[cfg(feature = "table-internals")]
+    pub fn set(&self, index: u32, func_index: Option<u32>) -> Result<(), RuntimeError> {
+        let table = self.raw_checked(index)?;
+
+        let elem = match func_index {
+            Some(func_index) => func_index as TableElem,
+            None => NULL_REF,
+        };
+        unsafe { *(table.elems as *mut TableElem).add(index as usize) = elem };
+        Ok(())
+    }
+
+    /// the element at `index` as a `funcref`, or `None` if it is null. It needs the
+    /// `table-internals` feature
+    ///
+    /// # Error
+    ///
+    /// The same as `get()`
+    #[cfg(feature = "table-internals")]
+    pub fn get_funcref(&self, index: u32) -> Result<Option<FuncRef>, RuntimeError> {
+        Ok(self
+            .get_func_index(index)?
+            .map(|func_index| FuncRef::new(func_index, self.instance.get_inner_instance())))
+    }
+
+    /// set the element at `index` to a `funcref`, or null. It needs the `table-internals`
+    /// feature
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::InvalidFuncRef` if the `funcref` comes from another instance.
+    /// Others are the same as `get()`.
+    #[cfg(feature = "table-internals")]
+    pub fn set_funcref(&self, index: u32, funcref: Option<FuncRef>) -> Result<(), RuntimeError> {
+        if let Some(funcref) = funcref {
+            funcref.check_instance(self.instance.get_inner_instance())?;
+        }
+        self.set(index, funcref.map(|funcref| funcref.index()))
+    }
+
+    /// call the function at `index` like `call_indirect` does. The signature of the
+    /// function is checked against `ty` before the call
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::Trap` of `TrapKind::UninitializedElement` if the element is
+    /// null, `RuntimeError::SignatureMismatch` if the signature isn't `ty`. Others are the
+    /// same as `get()` and `Function::call()`.
+    pub fn call_indirect(
+        &self,
+        index: u32,
+        ty: &FuncType,
+        params: &Vec<WasmValue>,
+    ) -> Result<Vec<WasmValue>, RuntimeError> {
+        let function = self.get_non_null(index)?;
+        if function.signature() != ty {
+            return Err(RuntimeError::SignatureMismatch(format!(
+                "expect {:?}, but got {:?}",
+                function.signature(),
+                ty
+            )));
+        }
+
+        function.call(self.instance, params)
+    }
+
+    /// the same as `call_indirect()`, with the signature given by `Params` and `Results`.
+    /// See `Function::call_typed()`
+    ///
+    /// # Error
+    ///
+    /// The same as `call_indirect()`
+    pub fn call_indirect_typed<Params, Results>(
+        &self,
+        index: u32,
+        params: Params,
+    ) -> Result<Results, RuntimeError>
+    where
+        Params: WasmTypes,
+        Results: WasmTypes,
+    {
+        self.get_non_null(index)?.call_typed(self.instance, params)
+    }
+
+    fn get_non_null(&self, index: u32) -> Result<Function, RuntimeError> {
+        self.get(index)?.ok_or_else(|| RuntimeError::Trap {
+            kind: TrapKind::UninitializedElement,
+            message: format!("uninitialized element {}", index),
+            frames: Vec::new(),
+        })
+    }
+}
+
+/// the function with `func_index` in the function index space of the instance.
+/// WAMR only resolves function indexes through tables. so a table of one element, laid out
+/// as `TableElem`s, is faked.
+///
+/// `func_index` must be less than the number of functions, WAMR doesn't check it
+pub(crate) fn function_by_index(instance: &Instance, func_index: u32) -> wasm_function_inst_t {
+    let mut elem = func_index as TableElem;
+    let table = wasm_table_inst_t {
+        elem_kind: wasm_valkind_enum_WASM_FUNCREF as wasm_valkind_t,
+        cur_size: 1,
+        max_size: 1,
+        elems: &mut elem as *mut TableElem as *mut _,
+    };
+    unsafe { wasm_table_get_func_inst(instance.get_inner_instance(), &table, 0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{module::Module, runtime::Runtime, value::FuncRef};
+
+    #[test]
+    fn test_table_get_set() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (type $op (func (param i32 i32) (result i32)))
+              (table (export "ops") 3 10 funcref)
+              (elem (i32.const 0) $add $sub)
+              (func $add (type $op) (i32.add (local.get 0) (local.get 1)))
+              (func $sub (type $op) (i32.sub (local.get 0) (local.get 1)))
+              (func (export "dispatch") (param i32 i32 i32) (result i32)
+                (call_indirect (type $op) (local.get 1) (local.get 2) (local.get 0))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let dispatch = Function::find_export_func(&instance, "dispatch").unwrap();
+
+        let table = instance.table("ops").unwrap();
+        assert_eq!(table.element_type(), ValType::FuncRef);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.max_size(), 10);
+
+        assert!(table.get(0).unwrap().is_some());
+        assert!(table.get(2).unwrap().is_none());
+        assert!(matches!(
+            table.get(3),
+            Err(RuntimeError::TableAccessOutOfBounds { index: 3, size: 3 })
+        ));
+
+        let params = vec![WasmValue::I32(0), WasmValue::I32(5), WasmValue::I32(3)];
+        assert_eq!(
+            dispatch.call(&instance, &params).unwrap(),
+            vec![WasmValue::I32(8)]
+        );
+
+        #[cfg(feature = "table-internals")]
+        {
+            assert_eq!(table.get_func_index(0).unwrap(), Some(0));
+            assert_eq!(table.get_func_index(1).unwrap(), Some(1));
+            assert_eq!(table.get_func_index(2).unwrap(), None);
+
+            // patch the entry 0 to `$sub`
+            assert!(table.set(0, Some(1)).is_ok());
+            assert_eq!(
+                dispatch.call(&instance, &params).unwrap(),
+                vec![WasmValue::I32(2)]
+            );
+
+            assert!(table.set(0, None).is_ok());
+            assert!(dispatch.call(&instance, &params).is_err());
+        }
+
+        assert!(matches!(
+            instance.table("unknown"),
+            Err(RuntimeError::TableNotFound)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "table-internals")]
+    fn test_table_grow() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (type $op (func (param i32 i32) (result i32)))
+              (table (export "ops") 1 4 funcref)
+              (elem (i32.const 0) $add)
+              (func $add (type $op) (i32.add (local.get 0) (local.get 1)))
+              (func $mul (type $op) (i32.mul (local.get 0) (local.get 1)))
+              (func (export "dispatch") (param i32 i32 i32) (result i32)
+                (call_indirect (type $op) (local.get 1) (local.get 2) (local.get 0))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let dispatch = |index: i32| -> Result<i32, RuntimeError> {
+            instance.call("dispatch").args((index, 5, 3)).invoke_typed()
+        };
+        assert!(dispatch(1).is_err());
+
+        let table = instance.table("ops").unwrap();
+        assert_eq!(table.grow(2, None).unwrap(), 1);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get_func_index(2).unwrap(), None);
+
+        // patch a new slot to `$mul`
+        table.set(1, Some(1)).unwrap();
+        assert_eq!(dispatch(1).unwrap(), 15);
+        assert!(dispatch(2).is_err());
+
+        assert_eq!(table.grow(1, Some(0)).unwrap(), 3);
+        assert_eq!(dispatch(3).unwrap(), 8);
+        assert!(matches!(
+            table.grow(1, None),
+            Err(RuntimeError::TableGrowFailure {
+                current: 4,
+                delta: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_table_call_indirect() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (table (export "callbacks") 4 funcref)
+              (elem (i32.const 0) $on_add $on_halve)
+              (func $on_add (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+              (func $on_halve (param f64) (result f64)
+                (f64.div (local.get 0) (f64.const 2))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let table = instance.table("callbacks").unwrap();
+
+        let on_add = FuncType::new(vec![ValType::I32, ValType::I32], vec![ValType::I32]);
+        let params = vec![WasmValue::I32(5), WasmValue::I32(3)];
+        assert_eq!(
+            table.call_indirect(0, &on_add, &params).unwrap(),
+            vec![WasmValue::I32(8)]
+        );
+        let halved: f64 = table.call_indirect_typed(1, 5.0).unwrap();
+        assert_eq!(halved, 2.5);
+
+        assert!(matches!(
+            table.call_indirect(1, &on_add, &params),
+            Err(RuntimeError::SignatureMismatch(_))
+        ));
+        assert!(matches!(
+            table.call_indirect_typed::<i32, i32>(1, 5),
+            Err(RuntimeError::SignatureMismatch(_))
+        ));
+        assert!(matches!(
+            table.call_indirect(2, &on_add, &params),
+            Err(RuntimeError::Trap {
+                kind: TrapKind::UninitializedElement,
+                ..
+            })
+        ));
+        assert!(matches!(
+            table.call_indirect(4, &on_add, &params),
+            Err(RuntimeError::TableAccessOutOfBounds { index: 4, size: 4 })
+        ));
+    }
+
+    #[test]
+    fn test_table_funcref() {
+        let runtime = Runtime::new().unwrap();
+
+        let module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (type $op (func (param i32 i32) (result i32)))
+              (table (export "callbacks") 2 funcref)
+              (elem declare func $mul)
+              (func $mul (type $op) (i32.mul (local.get 0) (local.get 1)))
+              (func (export "get_mul") (result funcref) (ref.func $mul))
+              (func (export "register") (param funcref)
+                (table.set (i32.const 1) (local.get 0))
+              )
+              (func (export "dispatch") (param i32 i32 i32) (result i32)
+                (call_indirect (type $op) (local.get 1) (local.get 2) (local.get 0))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+        let mut instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        // returned from wasm
+        let get_mul = Function::find_export_func(&instance, "get_mul").unwrap();
+        let mul: Option<FuncRef> = get_mul.call_typed(&instance, ()).unwrap();
+        let mul = mul.unwrap();
+        assert_eq!(mul.index(), 0);
+        assert_eq!(mul, instance.funcref(0).unwrap());
+        let product: i32 = instance
+            .function_of(mul)
+            .unwrap()
+            .call_typed(&instance, (6, 7))
+            .unwrap();
+        assert_eq!(product, 42);
+
+        // passed to wasm
+        let register = Function::find_export_func(&instance, "register").unwrap();
+        let params = vec![WasmValue::FuncRef(Some(mul))];
+        assert_eq!(register.call(&instance, &params).unwrap(), vec![]);
+        let dispatch = Function::find_export_func(&instance, "dispatch").unwrap();
+        let product: i32 = dispatch.call_typed(&instance, (1, 6, 7)).unwrap();
+        assert_eq!(product, 42);
+
+        // written into a table by the host
+        #[cfg(feature = "table-internals")]
+        {
+            let table = instance.table("callbacks").unwrap();
+            assert_eq!(table.get_funcref(1).unwrap(), Some(mul));
+            table.set_funcref(0, Some(mul)).unwrap();
+            let product: i32 = dispatch.call_typed(&instance, (0, 6, 7)).unwrap();
+            assert_eq!(product, 42);
+        }
+
+        // indexes are meaningless in other instances
+        let another = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        assert!(matches!(
+            another.function_of(mul),
+            Err(RuntimeError::InvalidFuncRef(_))
+        ));
+        #[cfg(feature = "table-internals")]
+        assert!(matches!(
+            another
+                .table("callbacks")
+                .unwrap()
+                .set_funcref(0, Some(mul)),
+            Err(RuntimeError::InvalidFuncRef(_))
+        ));
+        let register = Function::find_export_func(&another, "register").unwrap();
+        assert!(matches!(
+            register.call(&another, &params),
+            Err(RuntimeError::InvalidFuncRef(_))
+        ));
+
+        // nor after a reset, even at the same address
+        instance.reset().unwrap();
+        assert!(matches!(
+            instance.function_of(mul),
+            Err(RuntimeError::InvalidFuncRef(_))
+        ));
+    }
+}
@@ -0,0 +1,309 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! kinds of traps, told from exceptions of WAMR, and call stacks of traps.
+//! See `RuntimeError::Trap`
+
+use std::{collections::HashMap, error, fmt};
+
+use crate::RuntimeError;
+
+/// why a wasm function traps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrapKind {
+    /// `unreachable` is executed
+    Unreachable,
+    /// a load or a store beyond the linear memory
+    OutOfBoundsMemory,
+    /// an access beyond the table
+    OutOfBoundsTable,
+    IntegerDivideByZero,
+    IntegerOverflow,
+    /// converting NaN or an out of range float into an integer
+    InvalidConversionToInteger,
+    /// the wasm operand stack, the auxiliary stack or the native stack is exhausted
+    StackOverflow,
+    /// `call_indirect` to a function of another signature
+    IndirectCallTypeMismatch,
+    /// `call_indirect` beyond the table
+    UndefinedElement,
+    /// `call_indirect` to a null element
+    UninitializedElement,
+    /// calling an import which is not linked, or a stub of it.
+    /// See `Linker::stub_unresolved()`
+    UnlinkedImport,
+    /// an atomic access to an unaligned address
+    UnalignedAtomic,
+    /// a wasm exception is thrown but not caught. It needs the `exception-handling` feature,
+    /// which runs modules in the classic interpreter. WAMR doesn't expose the tag and the
+    /// payload, so there is no error carrying them
+    UncaughtException,
+    /// a host function returns an `Err`, or panics. See `Trap`
+    Host,
+    /// a host function is called beyond its quota. See `InstanceBuilder::host_quota()`
+    HostQuotaExceeded,
+}
+
+// how exceptions raised by host functions start
+pub(crate) const HOST_TRAP_PREFIX: &str = "host trap: ";
+// how exceptions of calls beyond quotas start
+pub(crate) const HOST_QUOTA_PREFIX: &str = "host quota exceeded: ";
+
+// how exceptions of stubs of unlinked imports start
+pub(crate) const UNLINKED_IMPORT_PREFIX: &str = "unlinked import ";
+
+// messages of WAMR exceptions, which start with "Exception: "
+const EXCEPTIONS: [(&str, TrapKind); 18] = [
+    ("unreachable", TrapKind::Unreachable),
+    ("out of bounds memory access", TrapKind::OutOfBoundsMemory),
+    ("out of bounds table access", TrapKind::OutOfBoundsTable),
+    ("integer divide by zero", TrapKind::IntegerDivideByZero),
+    ("integer overflow", TrapKind::IntegerOverflow),
+    (
+        "invalid conversion to integer",
+        TrapKind::InvalidConversionToInteger,
+    ),
+    ("wasm operand stack overflow", TrapKind::StackOverflow),
+    ("native stack overflow", TrapKind::StackOverflow),
+    ("wasm auxiliary stack overflow", TrapKind::StackOverflow),
+    (
+        "indirect call type mismatch",
+        TrapKind::IndirectCallTypeMismatch,
+    ),
+    ("undefined element", TrapKind::UndefinedElement),
+    ("uninitialized element", TrapKind::UninitializedElement),
+    (
+        "failed to call unlinked import function",
+        TrapKind::UnlinkedImport,
+    ),
+    ("unaligned atomic", TrapKind::UnalignedAtomic),
+    ("uncaught wasm exception", TrapKind::UncaughtException),
+    (HOST_TRAP_PREFIX, TrapKind::Host),
+    (HOST_QUOTA_PREFIX, TrapKind::HostQuotaExceeded),
+    (UNLINKED_IMPORT_PREFIX, TrapKind::UnlinkedImport),
+];
+
+impl TrapKind {
+    /// tell the kind from an exception of WAMR. `None` if it isn't a trap, like
+    /// a wrong count of arguments, or is unknown
+    pub fn from_exception(exception: &str) -> Option<TrapKind> {
+        let message = exception.strip_prefix("Exception: ").unwrap_or(exception);
+        EXCEPTIONS
+            .iter()
+            .find(|(prefix, _)| message.starts_with(prefix))
+            .map(|(_, kind)| *kind)
+    }
+}
+
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            TrapKind::Unreachable => "unreachable",
+            TrapKind::OutOfBoundsMemory => "out of bounds memory access",
+            TrapKind::OutOfBoundsTable => "out of bounds table access",
+            TrapKind::IntegerDivideByZero => "integer divide by zero",
+            TrapKind::IntegerOverflow => "integer overflow",
+            TrapKind::InvalidConversionToInteger => "invalid conversion to integer",
+            TrapKind::StackOverflow => "stack overflow",
+            TrapKind::IndirectCallTypeMismatch => "indirect call type mismatch",
+            TrapKind::UndefinedElement => "undefined element",
+            TrapKind::UninitializedElement => "uninitialized element",
+            TrapKind::UnlinkedImport => "unlinked import",
+            TrapKind::UnalignedAtomic => "unaligned atomic",
+            TrapKind::UncaughtException => "uncaught wasm exception",
+            TrapKind::Host => "host trap",
+            TrapKind::HostQuotaExceeded => "host quota exceeded",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+/// an error a host function returns to trap the wasm caller. The call into wasm fails
+/// with `RuntimeError::Trap` of `TrapKind::Host`, which carries the message
+///
+/// ```ignore
+/// .register("env", "div", |_: &Caller, a: i32, b: i32| {
+///     if b == 0 {
+///         return Err(Trap::new("division by zero"));
+///     }
+///     Ok(a / b)
+/// })
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trap {
+    message: String,
+}
+
+impl Trap {
+    pub fn new(message: impl Into<String>) -> Self {
+        Trap {
+            message: message.into(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for Trap {}
+
+impl From<RuntimeError> for Trap {
+    fn from(error: RuntimeError) -> Self {
+        Trap::new(error.to_string())
+    }
+}
+
+impl From<String> for Trap {
+    fn from(message: String) -> Self {
+        Trap::new(message)
+    }
+}
+
+impl From<&str> for Trap {
+    fn from(message: &str) -> Self {
+        Trap::new(message)
+    }
+}
+
+/// a frame in the call stack of a trap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// in the function index space of the module, imported functions first. `None` if
+    /// WAMR reports the function by an export name which isn't in the `name` section
+    pub func_index: Option<u32>,
+    /// from the `name` section, or the export name
+    pub func_name: Option<String>,
+    /// the offset of the instruction WAMR reports, in the module binary
+    pub offset: u32,
+}
+
+/// parse a call stack dumped by WAMR, innermost frame first. Lines look like
+/// `#00: 0x0011 - $f1`, where `$f<index>` is a function without a name, or
+/// `#01: 0x0022 - run`. Lines of other forms are skipped
+pub(crate) fn parse_call_stack(call_stack: &str, names: &HashMap<u32, String>) -> Vec<Frame> {
+    call_stack
+        .lines()
+        .filter_map(|line| {
+            let (_, frame) = line.strip_prefix('#')?.split_once(": 0x")?;
+            let (offset, function) = frame.split_once(" - ")?;
+            let offset = u32::from_str_radix(offset, 16).ok()?;
+
+            let func_index = match function.strip_prefix("$f") {
+                Some(index) => index.parse::<u32>().ok(),
+                None => names
+                    .iter()
+                    .find(|(_, name)| name.as_str() == function)
+                    .map(|(index, _)| *index),
+            };
+            let func_name = match func_index.and_then(|index| names.get(&index)) {
+                Some(name) => Some(name.clone()),
+                None if function.starts_with("$f") => None,
+                None => Some(String::from(function)),
+            };
+
+            Some(Frame {
+                func_index,
+                func_name,
+                offset,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_exception() {
+        assert_eq!(
+            TrapKind::from_exception("Exception: unreachable"),
+            Some(TrapKind::Unreachable)
+        );
+        assert_eq!(
+            TrapKind::from_exception("Exception: out of bounds memory access"),
+            Some(TrapKind::OutOfBoundsMemory)
+        );
+        assert_eq!(
+            TrapKind::from_exception("Exception: native stack overflow"),
+            Some(TrapKind::StackOverflow)
+        );
+        assert_eq!(
+            TrapKind::from_exception("Exception: unaligned atomic"),
+            Some(TrapKind::UnalignedAtomic)
+        );
+        assert_eq!(
+            TrapKind::from_exception("Exception: unlinked import env.log called"),
+            Some(TrapKind::UnlinkedImport)
+        );
+        assert_eq!(
+            TrapKind::from_exception(
+                "Exception: invalid argument count 1, must be no smaller than 2"
+            ),
+            None
+        );
+        assert_eq!(
+            TrapKind::from_exception("Exception: uncaught wasm exception"),
+            Some(TrapKind::UncaughtException)
+        );
+        assert_eq!(TrapKind::from_exception("Exception: wasi proc exit"), None);
+        assert_eq!(
+            TrapKind::from_exception("Exception: host trap: division by zero"),
+            Some(TrapKind::Host)
+        );
+        assert_eq!(
+            TrapKind::from_exception("Exception: host quota exceeded: env.fetch"),
+            Some(TrapKind::HostQuotaExceeded)
+        );
+
+        assert_eq!(
+            TrapKind::IndirectCallTypeMismatch.to_string(),
+            "indirect call type mismatch"
+        );
+        assert_eq!(TrapKind::StackOverflow.to_string(), "stack overflow");
+    }
+
+    #[test]
+    fn test_parse_call_stack() {
+        let mut names = HashMap::new();
+        names.insert(1, String::from("boom"));
+        names.insert(2, String::from("run"));
+
+        let call_stack = "#00: 0x0011 - $f1\n#01: 0x0022 - run\n#02: 0x0a33 - $f3\n\
+                          #03: 0x0044 - main\n=== unknown ===";
+        assert_eq!(
+            parse_call_stack(call_stack, &names),
+            vec![
+                Frame {
+                    func_index: Some(1),
+                    func_name: Some(String::from("boom")),
+                    offset: 0x11,
+                },
+                Frame {
+                    func_index: Some(2),
+                    func_name: Some(String::from("run")),
+                    offset: 0x22,
+                },
+                Frame {
+                    func_index: Some(3),
+                    func_name: None,
+                    offset: 0xa33,
+                },
+                Frame {
+                    func_index: None,
+                    func_name: Some(String::from("main")),
+                    offset: 0x44,
+                },
+            ]
+        );
+    }
+}
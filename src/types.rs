@@ -0,0 +1,461 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! wasm types. describe what a function, a table, a memory or a global looks like.
+//! get them via `Module::exports()` or `Module::imports()`
+
+use std::{ffi::CStr, fmt};
+
+use wamr_sys::{
+    wasm_export_t, wasm_func_get_param_count, wasm_func_get_param_types,
+    wasm_func_get_result_count, wasm_func_get_result_types, wasm_func_type_get_param_count,
+    wasm_func_type_get_param_valkind, wasm_func_type_get_result_count,
+    wasm_func_type_get_result_valkind, wasm_func_type_t, wasm_function_inst_t,
+    wasm_global_type_get_mutable, wasm_global_type_get_valkind, wasm_global_type_t,
+    wasm_import_export_kind_t_WASM_IMPORT_EXPORT_KIND_FUNC,
+    wasm_import_export_kind_t_WASM_IMPORT_EXPORT_KIND_GLOBAL,
+    wasm_import_export_kind_t_WASM_IMPORT_EXPORT_KIND_MEMORY,
+    wasm_import_export_kind_t_WASM_IMPORT_EXPORT_KIND_TABLE, wasm_import_t,
+    wasm_memory_type_get_init_page_count, wasm_memory_type_get_max_page_count,
+    wasm_memory_type_get_shared, wasm_memory_type_t, wasm_module_inst_t,
+    wasm_table_type_get_elem_kind, wasm_table_type_get_init_size, wasm_table_type_get_max_size,
+    wasm_table_type_get_shared, wasm_table_type_t, wasm_valkind_enum_WASM_EXTERNREF,
+    wasm_valkind_enum_WASM_F32, wasm_valkind_enum_WASM_F64, wasm_valkind_enum_WASM_FUNCREF,
+    wasm_valkind_enum_WASM_I32, wasm_valkind_enum_WASM_I64, wasm_valkind_enum_WASM_V128,
+    wasm_valkind_t,
+};
+
+use crate::RuntimeError;
+
+/// type of a wasm value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+    V128,
+    ExternRef,
+    FuncRef,
+    /// a kind this crate doesn't know, like a GC reference. Functions taking or
+    /// returning it aren't callable
+    Unknown(u8),
+}
+
+impl ValType {
+    #[allow(non_upper_case_globals)]
+    pub(crate) fn from_valkind(kind: wasm_valkind_t) -> ValType {
+        match kind as u32 {
+            wasm_valkind_enum_WASM_I32 => ValType::I32,
+            wasm_valkind_enum_WASM_I64 => ValType::I64,
+            wasm_valkind_enum_WASM_F32 => ValType::F32,
+            wasm_valkind_enum_WASM_F64 => ValType::F64,
+            wasm_valkind_enum_WASM_V128 => ValType::V128,
+            wasm_valkind_enum_WASM_EXTERNREF => ValType::ExternRef,
+            wasm_valkind_enum_WASM_FUNCREF => ValType::FuncRef,
+            _ => ValType::Unknown(kind),
+        }
+    }
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+            ValType::F32 => "f32",
+            ValType::F64 => "f64",
+            ValType::V128 => "v128",
+            ValType::ExternRef => "externref",
+            ValType::FuncRef => "funcref",
+            ValType::Unknown(kind) => return write!(f, "valkind{}", kind),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// signature of a function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncType {
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+}
+
+impl FuncType {
+    pub fn new(params: Vec<ValType>, results: Vec<ValType>) -> Self {
+        FuncType { params, results }
+    }
+
+    pub fn params(&self) -> &[ValType] {
+        &self.params
+    }
+
+    pub fn results(&self) -> &[ValType] {
+        &self.results
+    }
+
+    // parameters of unknown types never match a `WasmValue`, but results are checked
+    // before calls
+    pub(crate) fn check_results_known(&self) -> Result<(), RuntimeError> {
+        match self
+            .results
+            .iter()
+            .find(|ty| matches!(ty, ValType::Unknown(_)))
+        {
+            Some(ty) => Err(RuntimeError::SignatureMismatch(format!(
+                "unsupported result type {}",
+                ty
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn from_raw(func_type: wasm_func_type_t) -> Self {
+        let param_count = unsafe { wasm_func_type_get_param_count(func_type) };
+        let params = (0..param_count)
+            .map(|i| {
+                ValType::from_valkind(unsafe { wasm_func_type_get_param_valkind(func_type, i) })
+            })
+            .collect();
+
+        let result_count = unsafe { wasm_func_type_get_result_count(func_type) };
+        let results = (0..result_count)
+            .map(|i| {
+                ValType::from_valkind(unsafe { wasm_func_type_get_result_valkind(func_type, i) })
+            })
+            .collect();
+
+        FuncType { params, results }
+    }
+
+    // the signature of an instantiated function
+    pub(crate) fn from_function(
+        function: wasm_function_inst_t,
+        instance: wasm_module_inst_t,
+    ) -> Self {
+        let param_count = unsafe { wasm_func_get_param_count(function, instance) };
+        let mut params = vec![0 as wasm_valkind_t; param_count as usize];
+        unsafe { wasm_func_get_param_types(function, instance, params.as_mut_ptr()) };
+
+        let result_count = unsafe { wasm_func_get_result_count(function, instance) };
+        let mut results = vec![0 as wasm_valkind_t; result_count as usize];
+        unsafe { wasm_func_get_result_types(function, instance, results.as_mut_ptr()) };
+
+        FuncType {
+            params: params.into_iter().map(ValType::from_valkind).collect(),
+            results: results.into_iter().map(ValType::from_valkind).collect(),
+        }
+    }
+}
+
+/// in the text format. like `(param i32 i32) (result i64)`
+impl fmt::Display for FuncType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |types: &[ValType]| {
+            types
+                .iter()
+                .map(ValType::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut clauses = Vec::new();
+        if !self.params.is_empty() {
+            clauses.push(format!("(param {})", list(&self.params)));
+        }
+        if !self.results.is_empty() {
+            clauses.push(format!("(result {})", list(&self.results)));
+        }
+        write!(f, "{}", clauses.join(" "))
+    }
+}
+
+/// type of a table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableType {
+    pub element: ValType,
+    pub shared: bool,
+    pub min: u32,
+    pub max: u32,
+}
+
+impl TableType {
+    pub(crate) fn from_raw(table_type: wasm_table_type_t) -> Self {
+        unsafe {
+            TableType {
+                element: ValType::from_valkind(wasm_table_type_get_elem_kind(table_type)),
+                shared: wasm_table_type_get_shared(table_type),
+                min: wasm_table_type_get_init_size(table_type),
+                max: wasm_table_type_get_max_size(table_type),
+            }
+        }
+    }
+}
+
+/// type of a linear memory. in pages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryType {
+    pub shared: bool,
+    pub min: u32,
+    pub max: u32,
+}
+
+impl MemoryType {
+    pub(crate) fn from_raw(memory_type: wasm_memory_type_t) -> Self {
+        unsafe {
+            MemoryType {
+                shared: wasm_memory_type_get_shared(memory_type),
+                min: wasm_memory_type_get_init_page_count(memory_type),
+                max: wasm_memory_type_get_max_page_count(memory_type),
+            }
+        }
+    }
+}
+
+/// type of a global
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalType {
+    pub content: ValType,
+    pub mutable: bool,
+}
+
+impl GlobalType {
+    pub(crate) fn from_raw(global_type: wasm_global_type_t) -> Self {
+        unsafe {
+            GlobalType {
+                content: ValType::from_valkind(wasm_global_type_get_valkind(global_type)),
+                mutable: wasm_global_type_get_mutable(global_type),
+            }
+        }
+    }
+}
+
+/// kind of an import or an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternKind {
+    Func,
+    Table,
+    Memory,
+    Global,
+    /// a kind this crate doesn't know
+    Unknown(u8),
+}
+
+/// type of an import or an export
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExternType {
+    Func(FuncType),
+    Table(TableType),
+    Memory(MemoryType),
+    Global(GlobalType),
+    /// a kind this crate doesn't know, as `wasm_import_export_kind_t`
+    Unknown(u8),
+}
+
+impl ExternType {
+    pub fn kind(&self) -> ExternKind {
+        match self {
+            ExternType::Func(_) => ExternKind::Func,
+            ExternType::Table(_) => ExternKind::Table,
+            ExternType::Memory(_) => ExternKind::Memory,
+            ExternType::Global(_) => ExternKind::Global,
+            ExternType::Unknown(kind) => ExternKind::Unknown(*kind),
+        }
+    }
+}
+
+/// in the text format. like `func (param i32)`, `memory 1 2` or `global (mut i64)`
+impl fmt::Display for ExternType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExternType::Func(ty) if ty.params.is_empty() && ty.results.is_empty() => {
+                write!(f, "func")
+            }
+            ExternType::Func(ty) => write!(f, "func {}", ty),
+            ExternType::Table(ty) => write!(f, "table {} {} {}", ty.min, ty.max, ty.element),
+            ExternType::Memory(ty) if ty.shared => write!(f, "memory {} {} shared", ty.min, ty.max),
+            ExternType::Memory(ty) => write!(f, "memory {} {}", ty.min, ty.max),
+            ExternType::Global(ty) if ty.mutable => write!(f, "global (mut {})", ty.content),
+            ExternType::Global(ty) => write!(f, "global {}", ty.content),
+            ExternType::Unknown(kind) => write!(f, "kind{}", kind),
+        }
+    }
+}
+
+// `wasm_import_t` and `wasm_export_t` carry the same union of type pointers
+macro_rules! extern_type_from_raw {
+    ($kind:expr, $u:expr) => {
+        unsafe {
+            match $kind {
+                wasm_import_export_kind_t_WASM_IMPORT_EXPORT_KIND_FUNC => {
+                    ExternType::Func(FuncType::from_raw($u.func_type))
+                }
+                wasm_import_export_kind_t_WASM_IMPORT_EXPORT_KIND_TABLE => {
+                    ExternType::Table(TableType::from_raw($u.table_type))
+                }
+                wasm_import_export_kind_t_WASM_IMPORT_EXPORT_KIND_MEMORY => {
+                    ExternType::Memory(MemoryType::from_raw($u.memory_type))
+                }
+                wasm_import_export_kind_t_WASM_IMPORT_EXPORT_KIND_GLOBAL => {
+                    ExternType::Global(GlobalType::from_raw($u.global_type))
+                }
+                kind => ExternType::Unknown(kind as u8),
+            }
+        }
+    };
+}
+
+/// an export of a module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportType {
+    name: String,
+    ty: ExternType,
+}
+
+impl ExportType {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> ExternKind {
+        self.ty.kind()
+    }
+
+    pub fn ty(&self) -> &ExternType {
+        &self.ty
+    }
+
+    pub(crate) fn new(name: String, ty: ExternType) -> Self {
+        ExportType { name, ty }
+    }
+
+    #[allow(non_upper_case_globals)]
+    pub(crate) fn from_raw(export: &wasm_export_t) -> Self {
+        let name = unsafe { CStr::from_ptr(export.name) }
+            .to_string_lossy()
+            .into_owned();
+
+        ExportType {
+            name,
+            ty: extern_type_from_raw!(export.kind, export.u),
+        }
+    }
+}
+
+/// an import of a module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportType {
+    module: String,
+    name: String,
+    ty: ExternType,
+    linked: bool,
+}
+
+impl ImportType {
+    /// the module name of the import. like "env" or "wasi_snapshot_preview1"
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// the field name of the import
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> ExternKind {
+        self.ty.kind()
+    }
+
+    pub fn ty(&self) -> &ExternType {
+        &self.ty
+    }
+
+    /// whether the import has been resolved by the runtime while loading.
+    /// like registered host functions, WASI functions, etc.
+    pub fn is_linked(&self) -> bool {
+        self.linked
+    }
+
+    #[allow(non_upper_case_globals)]
+    pub(crate) fn from_raw(import: &wasm_import_t) -> Self {
+        let module = unsafe { CStr::from_ptr(import.module_name) }
+            .to_string_lossy()
+            .into_owned();
+        let name = unsafe { CStr::from_ptr(import.name) }
+            .to_string_lossy()
+            .into_owned();
+
+        ImportType {
+            module,
+            name,
+            ty: extern_type_from_raw!(import.kind, import.u),
+            linked: import.linked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extern_type_kind() {
+        let func = ExternType::Func(FuncType::new(vec![ValType::I32], vec![]));
+        assert_eq!(func.kind(), ExternKind::Func);
+
+        let memory = ExternType::Memory(MemoryType {
+            shared: false,
+            min: 1,
+            max: 2,
+        });
+        assert_eq!(memory.kind(), ExternKind::Memory);
+    }
+
+    #[test]
+    fn test_extern_type_display() {
+        let func = FuncType::new(vec![ValType::I32, ValType::I64], vec![ValType::F64]);
+        assert_eq!(
+            ExternType::Func(func).to_string(),
+            "func (param i32 i64) (result f64)"
+        );
+        let func = FuncType::new(vec![], vec![]);
+        assert_eq!(ExternType::Func(func).to_string(), "func");
+
+        let memory = ExternType::Memory(MemoryType {
+            shared: false,
+            min: 1,
+            max: 2,
+        });
+        assert_eq!(memory.to_string(), "memory 1 2");
+
+        let global = ExternType::Global(GlobalType {
+            content: ValType::I32,
+            mutable: true,
+        });
+        assert_eq!(global.to_string(), "global (mut i32)");
+    }
+
+    #[test]
+    fn test_val_type_from_valkind() {
+        assert_eq!(
+            ValType::from_valkind(wasm_valkind_enum_WASM_I32 as wasm_valkind_t),
+            ValType::I32
+        );
+        assert_eq!(
+            ValType::from_valkind(wasm_valkind_enum_WASM_FUNCREF as wasm_valkind_t),
+            ValType::FuncRef
+        );
+
+        // like a GC reference
+        let unknown = ValType::from_valkind(0x64);
+        assert_eq!(unknown, ValType::Unknown(0x64));
+        let func = FuncType::new(vec![ValType::I32], vec![unknown]);
+        assert_eq!(func.to_string(), "(param i32) (result valkind100)");
+        assert!(matches!(
+            func.check_results_known(),
+            Err(RuntimeError::SignatureMismatch(_))
+        ));
+        assert_eq!(ExternType::Unknown(4).kind(), ExternKind::Unknown(4));
+    }
+}
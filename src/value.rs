@@ -5,17 +5,115 @@
 
 //! a wasm value. Always used as function parameters and results
 
-#[derive(Debug, PartialEq)]
+use wamr_sys::wasm_module_inst_t;
+
+use crate::{instance::Instance, types::ValType, RuntimeError};
+
+// a null reference of WAMR without GC
+const NULL_REF: u32 = 0xFFFF_FFFF;
+
+const CANONICAL_NAN_F32: u32 = 0x7FC0_0000;
+const CANONICAL_NAN_F64: u64 = 0x7FF8_0000_0000_0000;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum WasmValue {
     Void,
     I32(i32),
     I64(i64),
     F32(f32),
     F64(f64),
-    V128(i128),
+    /// bytes in the order of the memory, lane 0 first
+    V128([u8; 16]),
+    /// `None` for `ref.null extern`
+    ExternRef(Option<ExternRef>),
+    /// `None` for `ref.null func`
+    FuncRef(Option<FuncRef>),
+}
+
+/// a reference to a function, passed to and returned from wasm as a `funcref`. get one via
+/// `Instance::funcref()` or `Table::get_funcref()`, and call it via `Instance::function_of()`
+///
+/// WAMR without GC represents a `funcref` as an index in the function index space of an
+/// instance. So it is only valid for the instance it comes from, until `Instance::reset()`.
+/// To pass a host function, import it into the instance and refer to the imported one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FuncRef {
+    index: u32,
+    // the underlying instance, only to compare with. Its id tells it from another one at
+    // the same address
+    instance: usize,
+    id: u64,
+}
+
+impl FuncRef {
+    pub(crate) fn new(index: u32, instance: wasm_module_inst_t) -> Self {
+        FuncRef {
+            index,
+            instance: instance as usize,
+            id: unsafe { Instance::id_of(instance) },
+        }
+    }
+
+    /// the index in the function index space of the module, imported functions first
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    // `RuntimeError::InvalidFuncRef` if it comes from another instance
+    pub(crate) fn check_instance(&self, instance: wasm_module_inst_t) -> Result<(), RuntimeError> {
+        let id = unsafe { Instance::id_of(instance) };
+        match self.instance == instance as usize && self.id == id {
+            true => Ok(()),
+            false => Err(RuntimeError::InvalidFuncRef(format!(
+                "function {} of another instance",
+                self.index
+            ))),
+        }
+    }
+}
+
+/// a reference to a host object, passed to and returned from wasm as an `externref`.
+/// create one via `Instance::new_externref()`, and get the object back via
+/// `Instance::externref()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExternRef(u32);
+
+impl ExternRef {
+    pub(crate) fn from_index(index: u32) -> Self {
+        ExternRef(index)
+    }
+
+    /// the index in WAMR's table of host objects
+    pub(crate) fn index(&self) -> u32 {
+        self.0
+    }
 }
 
 impl WasmValue {
+    /// the type of the value. `None` for `Void`
+    pub fn ty(&self) -> Option<ValType> {
+        match self {
+            WasmValue::Void => None,
+            WasmValue::I32(_) => Some(ValType::I32),
+            WasmValue::I64(_) => Some(ValType::I64),
+            WasmValue::F32(_) => Some(ValType::F32),
+            WasmValue::F64(_) => Some(ValType::F64),
+            WasmValue::V128(_) => Some(ValType::V128),
+            WasmValue::ExternRef(_) => Some(ValType::ExternRef),
+            WasmValue::FuncRef(_) => Some(ValType::FuncRef),
+        }
+    }
+
+    // replace any NaN with the canonical one, which has only the most significant bit
+    // of the payload set. See `InstanceBuilder::canonicalize_result_nans()`
+    pub(crate) fn canonicalize_nan(&mut self) {
+        match self {
+            WasmValue::F32(value) if value.is_nan() => *value = f32::from_bits(CANONICAL_NAN_F32),
+            WasmValue::F64(value) if value.is_nan() => *value = f64::from_bits(CANONICAL_NAN_F64),
+            _ => {}
+        }
+    }
+
     pub fn encode(&self) -> Vec<u32> {
         match *self {
             WasmValue::Void => {
@@ -38,7 +136,7 @@ impl WasmValue {
                 vec![in_u32_array[0], in_u32_array[1]]
             }
             WasmValue::V128(value) => {
-                let in_u32_array = unsafe { std::mem::transmute::<i128, [u32; 4]>(value) };
+                let in_u32_array = unsafe { std::mem::transmute::<[u8; 16], [u32; 4]>(value) };
                 vec![
                     in_u32_array[0],
                     in_u32_array[1],
@@ -46,6 +144,82 @@ impl WasmValue {
                     in_u32_array[3],
                 ]
             }
+            WasmValue::ExternRef(value) => {
+                vec![value.map(|value| value.index()).unwrap_or(NULL_REF)]
+            }
+            WasmValue::FuncRef(value) => {
+                vec![value.map(|value| value.index()).unwrap_or(NULL_REF)]
+            }
+        }
+    }
+
+    // encode into the front of `cells` without allocating, return the number of cells written.
+    // `cells` should have room for 4 cells at most
+    pub(crate) fn encode_into(&self, cells: &mut [u32]) -> usize {
+        match *self {
+            WasmValue::Void => 0,
+            WasmValue::I32(value) => {
+                cells[0] = value as u32;
+                1
+            }
+            WasmValue::F32(value) => {
+                cells[0] = value.to_bits();
+                1
+            }
+            WasmValue::I64(value) => {
+                let in_u32_array = unsafe { std::mem::transmute::<i64, [u32; 2]>(value) };
+                cells[..2].copy_from_slice(&in_u32_array);
+                2
+            }
+            WasmValue::F64(value) => {
+                let in_u32_array = unsafe { std::mem::transmute::<f64, [u32; 2]>(value) };
+                cells[..2].copy_from_slice(&in_u32_array);
+                2
+            }
+            WasmValue::V128(value) => {
+                let in_u32_array = unsafe { std::mem::transmute::<[u8; 16], [u32; 4]>(value) };
+                cells[..4].copy_from_slice(&in_u32_array);
+                4
+            }
+            WasmValue::ExternRef(value) => {
+                cells[0] = value.map(|value| value.index()).unwrap_or(NULL_REF);
+                1
+            }
+            WasmValue::FuncRef(value) => {
+                cells[0] = value.map(|value| value.index()).unwrap_or(NULL_REF);
+                1
+            }
+        }
+    }
+
+    // the counterpart of `encode_into()`. `cells` starts with a value of `ty`
+    pub(crate) fn decode_from(
+        ty: ValType,
+        cells: &[u32],
+        instance: wasm_module_inst_t,
+    ) -> WasmValue {
+        match ty {
+            ValType::I32 => WasmValue::I32(cells[0] as i32),
+            ValType::F32 => WasmValue::F32(f32::from_bits(cells[0])),
+            ValType::I64 => WasmValue::I64(unsafe {
+                std::mem::transmute::<[u32; 2], i64>([cells[0], cells[1]])
+            }),
+            ValType::F64 => WasmValue::F64(unsafe {
+                std::mem::transmute::<[u32; 2], f64>([cells[0], cells[1]])
+            }),
+            ValType::V128 => WasmValue::V128(unsafe {
+                std::mem::transmute::<[u32; 4], [u8; 16]>([cells[0], cells[1], cells[2], cells[3]])
+            }),
+            ValType::ExternRef => match cells[0] {
+                NULL_REF => WasmValue::ExternRef(None),
+                index => WasmValue::ExternRef(Some(ExternRef::from_index(index))),
+            },
+            ValType::FuncRef => match cells[0] {
+                NULL_REF => WasmValue::FuncRef(None),
+                index => WasmValue::FuncRef(Some(FuncRef::new(index, instance))),
+            },
+            // rejected before calls
+            ValType::Unknown(_) => WasmValue::Void,
         }
     }
 
@@ -71,10 +245,296 @@ impl WasmValue {
 
     pub fn decode_to_v128(binary: Vec<u32>) -> WasmValue {
         let binary: [u32; 4] = [binary[0], binary[1], binary[2], binary[3]];
-        WasmValue::V128(unsafe { std::mem::transmute::<[u32; 4], i128>(binary) })
+        WasmValue::V128(unsafe { std::mem::transmute::<[u32; 4], [u8; 16]>(binary) })
+    }
+
+    pub fn decode_to_externref(binary: Vec<u32>) -> WasmValue {
+        match binary[0] {
+            NULL_REF => WasmValue::ExternRef(None),
+            index => WasmValue::ExternRef(Some(ExternRef::from_index(index))),
+        }
     }
 }
 
+/// a Rust scalar which maps to a wasm value
+pub trait WasmType: Sized {
+    /// the wasm type it maps to
+    const TYPE: ValType;
+
+    fn into_value(self) -> WasmValue;
+
+    /// `None` if `value` is of another type
+    fn from_value(value: WasmValue) -> Option<Self>;
+}
+
+macro_rules! impl_wasm_type {
+    ($ty:ty, $variant:ident) => {
+        impl WasmType for $ty {
+            const TYPE: ValType = ValType::$variant;
+
+            fn into_value(self) -> WasmValue {
+                WasmValue::$variant(self)
+            }
+
+            fn from_value(value: WasmValue) -> Option<Self> {
+                match value {
+                    WasmValue::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+
+        impl From<$ty> for WasmValue {
+            fn from(value: $ty) -> Self {
+                WasmValue::$variant(value)
+            }
+        }
+
+        impl TryFrom<WasmValue> for $ty {
+            type Error = RuntimeError;
+
+            fn try_from(value: WasmValue) -> Result<Self, Self::Error> {
+                match value {
+                    WasmValue::$variant(value) => Ok(value),
+                    value => Err(mismatch(&value, stringify!($ty))),
+                }
+            }
+        }
+    };
+}
+
+impl_wasm_type!(i32, I32);
+impl_wasm_type!(i64, I64);
+impl_wasm_type!(f32, F32);
+impl_wasm_type!(f64, F64);
+impl_wasm_type!([u8; 16], V128);
+impl_wasm_type!(Option<ExternRef>, ExternRef);
+impl_wasm_type!(Option<FuncRef>, FuncRef);
+
+fn mismatch(value: &WasmValue, ty: &str) -> RuntimeError {
+    RuntimeError::ConversionError(format!("expect a {} value, but got {:?}", ty, value))
+}
+
+fn out_of_range(value: &WasmValue, ty: &str) -> RuntimeError {
+    RuntimeError::ConversionError(format!("{:?} is out of the range of {}", value, ty))
+}
+
+// integers narrower than 32 bits. passed as an `i32`, like wasm does. Converting back
+// fails if the value doesn't fit
+macro_rules! impl_narrow_integer {
+    ($ty:ty) => {
+        impl From<$ty> for WasmValue {
+            fn from(value: $ty) -> Self {
+                WasmValue::I32(value.into())
+            }
+        }
+
+        impl TryFrom<WasmValue> for $ty {
+            type Error = RuntimeError;
+
+            fn try_from(value: WasmValue) -> Result<Self, Self::Error> {
+                match value {
+                    WasmValue::I32(v) => {
+                        <$ty>::try_from(v).map_err(|_| out_of_range(&value, stringify!($ty)))
+                    }
+                    value => Err(mismatch(&value, "i32")),
+                }
+            }
+        }
+    };
+}
+
+impl_narrow_integer!(i8);
+impl_narrow_integer!(i16);
+impl_narrow_integer!(u8);
+impl_narrow_integer!(u16);
+
+// unsigned integers share bits with the signed ones, wasm doesn't tell them apart
+impl From<u32> for WasmValue {
+    fn from(value: u32) -> Self {
+        WasmValue::I32(value as i32)
+    }
+}
+
+impl TryFrom<WasmValue> for u32 {
+    type Error = RuntimeError;
+
+    fn try_from(value: WasmValue) -> Result<Self, Self::Error> {
+        match value {
+            WasmValue::I32(v) => Ok(v as u32),
+            value => Err(mismatch(&value, "i32")),
+        }
+    }
+}
+
+impl From<u64> for WasmValue {
+    fn from(value: u64) -> Self {
+        WasmValue::I64(value as i64)
+    }
+}
+
+impl TryFrom<WasmValue> for u64 {
+    type Error = RuntimeError;
+
+    fn try_from(value: WasmValue) -> Result<Self, Self::Error> {
+        match value {
+            WasmValue::I64(v) => Ok(v as u64),
+            value => Err(mismatch(&value, "i64")),
+        }
+    }
+}
+
+// `0` or `1` in an `i32`
+impl From<bool> for WasmValue {
+    fn from(value: bool) -> Self {
+        WasmValue::I32(value.into())
+    }
+}
+
+impl TryFrom<WasmValue> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(value: WasmValue) -> Result<Self, Self::Error> {
+        match value {
+            WasmValue::I32(0) => Ok(false),
+            WasmValue::I32(1) => Ok(true),
+            WasmValue::I32(_) => Err(out_of_range(&value, "bool")),
+            value => Err(mismatch(&value, "i32")),
+        }
+    }
+}
+
+// an address or a length in a 32-bit memory. So an `i32`, and only fallible
+impl TryFrom<usize> for WasmValue {
+    type Error = RuntimeError;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        u32::try_from(value).map(WasmValue::from).map_err(|_| {
+            RuntimeError::ConversionError(format!("{} is out of the range of u32", value))
+        })
+    }
+}
+
+// from an `i32` as unsigned, or an `i64` of a 64-bit memory
+impl TryFrom<WasmValue> for usize {
+    type Error = RuntimeError;
+
+    fn try_from(value: WasmValue) -> Result<Self, Self::Error> {
+        match value {
+            WasmValue::I32(v) => {
+                usize::try_from(v as u32).map_err(|_| out_of_range(&value, "usize"))
+            }
+            WasmValue::I64(v) => {
+                usize::try_from(v as u64).map_err(|_| out_of_range(&value, "usize"))
+            }
+            value => Err(mismatch(&value, "i32 or i64")),
+        }
+    }
+}
+
+/// a fixed sequence of wasm values, as parameters or results of a function.
+/// It is `()`, a `WasmType`, or a tuple of up to 8 `WasmType`s
+pub trait WasmTypes: Sized {
+    fn types() -> Vec<ValType>;
+
+    fn into_values(self) -> Vec<WasmValue>;
+
+    /// `None` if the count or a type of `values` doesn't match
+    fn from_values(values: Vec<WasmValue>) -> Option<Self>;
+
+    /// the same as `from_values()`, like for results of `Function::call()`.
+    ///
+    /// ```ignore
+    /// let (quotient, remainder): (i32, i32) = WasmTypes::try_from_values(results)?;
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// Return `RuntimeError::SignatureMismatch` telling the count or the first value
+    /// which doesn't match.
+    fn try_from_values(values: Vec<WasmValue>) -> Result<Self, RuntimeError> {
+        let types = Self::types();
+        if values.len() != types.len() {
+            return Err(RuntimeError::SignatureMismatch(format!(
+                "expect {} values of {:?}, but got {}: {:?}",
+                types.len(),
+                types,
+                values.len(),
+                values
+            )));
+        }
+
+        let mismatched = values
+            .iter()
+            .zip(&types)
+            .enumerate()
+            .find(|(_, (value, ty))| value.ty() != Some(**ty));
+        if let Some((i, (value, ty))) = mismatched {
+            return Err(RuntimeError::SignatureMismatch(format!(
+                "expect {:?} at {} of {:?}, but got {:?}",
+                ty, i, types, value
+            )));
+        }
+
+        Self::from_values(values)
+            .ok_or_else(|| RuntimeError::SignatureMismatch(format!("expect {:?}", types)))
+    }
+}
+
+impl<T: WasmType> WasmTypes for T {
+    fn types() -> Vec<ValType> {
+        vec![T::TYPE]
+    }
+
+    fn into_values(self) -> Vec<WasmValue> {
+        vec![self.into_value()]
+    }
+
+    fn from_values(values: Vec<WasmValue>) -> Option<Self> {
+        let [value]: [WasmValue; 1] = values.try_into().ok()?;
+        T::from_value(value)
+    }
+}
+
+macro_rules! impl_wasm_types {
+    ($($name:ident)*) => {
+        impl<$($name: WasmType),*> WasmTypes for ($($name,)*) {
+            fn types() -> Vec<ValType> {
+                vec![$($name::TYPE),*]
+            }
+
+            #[allow(non_snake_case)]
+            fn into_values(self) -> Vec<WasmValue> {
+                let ($($name,)*) = self;
+                vec![$($name.into_value()),*]
+            }
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn from_values(values: Vec<WasmValue>) -> Option<Self> {
+                let [$($name),*]: [WasmValue; impl_wasm_types!(@count $($name)*)] =
+                    values.try_into().ok()?;
+                Some(($($name::from_value($name)?,)*))
+            }
+        }
+    };
+    (@count $($name:ident)*) => {
+        0 $(+ impl_wasm_types!(@one $name))*
+    };
+    (@one $name:ident) => {
+        1
+    };
+}
+
+impl_wasm_types!();
+impl_wasm_types!(A);
+impl_wasm_types!(A B);
+impl_wasm_types!(A B C);
+impl_wasm_types!(A B C D);
+impl_wasm_types!(A B C D E);
+impl_wasm_types!(A B C D E F);
+impl_wasm_types!(A B C D E F G);
+impl_wasm_types!(A B C D E F G H);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,7 +559,9 @@ mod tests {
             WasmValue::I64(2),
             WasmValue::F32(3.0),
             WasmValue::F64(4.0),
-            WasmValue::V128(5),
+            WasmValue::V128([5; 16]),
+            WasmValue::ExternRef(Some(ExternRef::from_index(6))),
+            WasmValue::ExternRef(None),
         ];
 
         let mut binary: Vec<u32> = Vec::new();
@@ -113,8 +575,124 @@ mod tests {
             WasmValue::decode_to_f32(binary[3..4].to_vec()),
             WasmValue::decode_to_f64(binary[4..6].to_vec()),
             WasmValue::decode_to_v128(binary[6..10].to_vec()),
+            WasmValue::decode_to_externref(binary[10..11].to_vec()),
+            WasmValue::decode_to_externref(binary[11..12].to_vec()),
         ];
 
         assert_eq!(values, decoded_values);
     }
+
+    #[test]
+    fn test_wasm_types() {
+        assert_eq!(<(i32, f64)>::types(), vec![ValType::I32, ValType::F64]);
+        assert_eq!(<()>::types(), vec![]);
+        assert_eq!(i64::types(), vec![ValType::I64]);
+
+        let values = (1i32, 2i64, 3.0f32).into_values();
+        assert_eq!(
+            values,
+            vec![WasmValue::I32(1), WasmValue::I64(2), WasmValue::F32(3.0)]
+        );
+        assert_eq!(
+            <(i32, i64, f32)>::from_values(values.clone()),
+            Some((1, 2, 3.0))
+        );
+
+        // count or types don't match
+        assert_eq!(<(i32, i64)>::from_values(values.clone()), None);
+        assert_eq!(<(i64, i64, f32)>::from_values(values), None);
+        assert_eq!(<()>::from_values(vec![]), Some(()));
+        assert_eq!(i32::from_values(vec![WasmValue::I32(7)]), Some(7));
+
+        // with errors
+        let values = vec![WasmValue::I32(1), WasmValue::F64(2.5)];
+        let (a, b): (i32, f64) = WasmTypes::try_from_values(values.clone()).unwrap();
+        assert_eq!((a, b), (1, 2.5));
+        match <(i32, f64, i32)>::try_from_values(values.clone()) {
+            Err(RuntimeError::SignatureMismatch(message)) => {
+                assert!(message.starts_with("expect 3 values"));
+            }
+            _ => panic!("expect a signature mismatch"),
+        }
+        match <(i32, f32)>::try_from_values(values) {
+            Err(RuntimeError::SignatureMismatch(message)) => {
+                assert!(message.starts_with("expect F32 at 1"));
+            }
+            _ => panic!("expect a signature mismatch"),
+        }
+    }
+
+    #[test]
+    fn test_canonicalize_nan() {
+        let mut value = WasmValue::F32(f32::from_bits(0xFFC0_1234));
+        value.canonicalize_nan();
+        assert!(matches!(value, WasmValue::F32(v) if v.to_bits() == 0x7FC0_0000));
+
+        let mut value = WasmValue::F64(f64::from_bits(0x7FF0_0000_0000_0001));
+        value.canonicalize_nan();
+        assert!(matches!(value, WasmValue::F64(v) if v.to_bits() == 0x7FF8_0000_0000_0000));
+
+        let mut value = WasmValue::F64(-1.5);
+        value.canonicalize_nan();
+        assert_eq!(value, WasmValue::F64(-1.5));
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(WasmValue::from(7i32), WasmValue::I32(7));
+        assert_eq!(WasmValue::from(2.5f64), WasmValue::F64(2.5));
+        assert_eq!(WasmValue::from(true), WasmValue::I32(1));
+        assert_eq!(WasmValue::from(-1i8), WasmValue::I32(-1));
+        assert_eq!(WasmValue::from(255u8), WasmValue::I32(255));
+        assert_eq!(WasmValue::from(u32::MAX), WasmValue::I32(-1));
+        assert_eq!(WasmValue::from(u64::MAX), WasmValue::I64(-1));
+        assert_eq!(WasmValue::try_from(16usize).unwrap(), WasmValue::I32(16));
+
+        assert_eq!(i32::try_from(WasmValue::I32(7)).unwrap(), 7);
+        assert_eq!(u32::try_from(WasmValue::I32(-1)).unwrap(), u32::MAX);
+        assert_eq!(u64::try_from(WasmValue::I64(-1)).unwrap(), u64::MAX);
+        assert!(bool::try_from(WasmValue::I32(1)).unwrap());
+        assert!(!bool::try_from(WasmValue::I32(0)).unwrap());
+        assert_eq!(i16::try_from(WasmValue::I32(-300)).unwrap(), -300);
+        assert_eq!(
+            usize::try_from(WasmValue::I32(-1)).unwrap(),
+            u32::MAX as usize
+        );
+        assert_eq!(usize::try_from(WasmValue::I64(64)).unwrap(), 64);
+        assert_eq!(
+            <[u8; 16]>::try_from(WasmValue::V128([3; 16])).unwrap(),
+            [3; 16]
+        );
+
+        // narrowing out of range, or another type
+        assert!(matches!(
+            u8::try_from(WasmValue::I32(256)),
+            Err(RuntimeError::ConversionError(_))
+        ));
+        assert!(matches!(
+            i8::try_from(WasmValue::I32(-129)),
+            Err(RuntimeError::ConversionError(_))
+        ));
+        assert!(matches!(
+            bool::try_from(WasmValue::I32(2)),
+            Err(RuntimeError::ConversionError(_))
+        ));
+        assert!(matches!(
+            i32::try_from(WasmValue::I64(7)),
+            Err(RuntimeError::ConversionError(_))
+        ));
+        assert!(matches!(
+            f32::try_from(WasmValue::F64(1.0)),
+            Err(RuntimeError::ConversionError(_))
+        ));
+        #[cfg(target_pointer_width = "64")]
+        assert!(matches!(
+            WasmValue::try_from(1usize << 32),
+            Err(RuntimeError::ConversionError(_))
+        ));
+
+        let message = u8::try_from(WasmValue::I32(256)).unwrap_err().to_string();
+        assert!(message.contains("I32(256)"));
+        assert!(message.contains("u8"));
+    }
 }
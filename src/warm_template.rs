@@ -0,0 +1,190 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! instances initialized once and copied many times, to take initialization off cold starts.
+//! get one via `WarmTemplate::new()`
+
+#[cfg(target_os = "linux")]
+use crate::snapshot::MappedSnapshot;
+#[cfg(not(target_os = "linux"))]
+use crate::snapshot::Snapshot;
+use crate::{function::Function, instance::Instance, RuntimeError};
+
+type InstanceFactory<'a> = Box<dyn Fn() -> Result<Instance<'a>, RuntimeError> + 'a>;
+
+/// It creates an instance by a factory, initializes it once, and takes a snapshot of it.
+/// `instantiate()` creates new instances by the factory and restores the snapshot into them,
+/// so they start initialized without running the initialization again.
+///
+/// On Linux, memories are restored by copy-on-write mappings. See `MappedSnapshot`.
+///
+/// Only the state visible to the host is copied. See `Snapshot`. The initialization should
+/// not change globals which are not exported, nor keep state out of the instance, like
+/// opened files.
+pub struct WarmTemplate<'a> {
+    factory: InstanceFactory<'a>,
+    #[cfg(target_os = "linux")]
+    snapshot: MappedSnapshot,
+    #[cfg(not(target_os = "linux"))]
+    snapshot: Snapshot,
+}
+
+impl<'a> WarmTemplate<'a> {
+    /// create a template whose initialization is `_initialize`, the entry of WASI reactors.
+    /// If it is not exported, instances are kept as they are after instantiation
+    ///
+    /// Instances call `_initialize` at instantiation by default. Turn it off in the factory,
+    /// so it runs once for the template instead of once per instance
+    ///
+    /// ```ignore
+    /// let template = WarmTemplate::new(|| {
+    ///     Instance::builder(&runtime, &module).initialize(false).build()
+    /// })?;
+    /// let instance = template.instantiate()?;
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// The error of `factory`, of `_initialize`, or of taking the snapshot.
+    pub fn new<F>(factory: F) -> Result<Self, RuntimeError>
+    where
+        F: Fn() -> Result<Instance<'a>, RuntimeError> + 'a,
+    {
+        Self::with_init(factory, |instance| {
+            if instance.is_initialized() {
+                return Ok(());
+            }
+            match Function::find_export_func(instance, "_initialize") {
+                Ok(function) => function.call(instance, &Vec::new()).map(|_| ()),
+                Err(RuntimeError::FunctionNotFound) => Ok(()),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    /// create a template whose initialization is `init`
+    ///
+    /// # Error
+    ///
+    /// The error of `factory`, of `init`, or of taking the snapshot.
+    pub fn with_init<F, I>(factory: F, init: I) -> Result<Self, RuntimeError>
+    where
+        F: Fn() -> Result<Instance<'a>, RuntimeError> + 'a,
+        I: FnOnce(&Instance) -> Result<(), RuntimeError>,
+    {
+        let instance = factory()?;
+        init(&instance)?;
+
+        let snapshot = instance.snapshot()?;
+        #[cfg(target_os = "linux")]
+        let snapshot = snapshot.into_mapped()?;
+
+        Ok(WarmTemplate {
+            factory: Box::new(factory),
+            snapshot,
+        })
+    }
+
+    /// create an instance in the state after initialization
+    ///
+    /// # Error
+    ///
+    /// The error of the factory, or `RuntimeError::SnapshotMismatch` if the factory
+    /// switches to another module.
+    pub fn instantiate(&self) -> Result<Instance<'a>, RuntimeError> {
+        let instance = (self.factory)()?;
+
+        #[cfg(target_os = "linux")]
+        instance.restore_mapped(&self.snapshot)?;
+        #[cfg(not(target_os = "linux"))]
+        instance.restore(&self.snapshot)?;
+
+        Ok(instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{module::Module, runtime::Runtime, value::WasmValue};
+    use std::cell::Cell;
+
+    const REACTOR: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $ready (export "ready") (mut i32) (i32.const 0))
+          (func (export "_initialize")
+            (i32.store (i32.const 16) (i32.const 42))
+            (global.set $ready (i32.const 1))
+          )
+          (func (export "load") (result i32)
+            (i32.load (i32.const 16))
+          )
+          (func (export "setup")
+            (i32.store (i32.const 16) (i32.const 7))
+          )
+        )
+        "#;
+
+    #[test]
+    fn test_warm_template() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, REACTOR).unwrap();
+
+        let created = Cell::new(0);
+        let template = WarmTemplate::new(|| {
+            created.set(created.get() + 1);
+            Instance::builder(&runtime, &module)
+                .host_heap_size(1024 * 64)
+                .initialize(false)
+                .build()
+        })
+        .unwrap();
+
+        let instance = template.instantiate().unwrap();
+        let load = Function::find_export_func(&instance, "load").unwrap();
+        assert_eq!(
+            load.call(&instance, &Vec::new()).unwrap(),
+            vec![WasmValue::I32(42)]
+        );
+        let ready = instance.global("ready").unwrap();
+        assert_eq!(ready.get().unwrap(), WasmValue::I32(1));
+
+        // instances don't share memories
+        instance.memory().unwrap().write_u32_le(16, 0).unwrap();
+        let another = template.instantiate().unwrap();
+        let load = Function::find_export_func(&another, "load").unwrap();
+        assert_eq!(
+            load.call(&another, &Vec::new()).unwrap(),
+            vec![WasmValue::I32(42)]
+        );
+        assert_eq!(created.get(), 3);
+    }
+
+    #[test]
+    fn test_warm_template_with_init() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, REACTOR).unwrap();
+
+        let template = WarmTemplate::with_init(
+            || Instance::new(&runtime, &module, 1024 * 64),
+            |instance| {
+                let setup = Function::find_export_func(instance, "setup")?;
+                setup.call(instance, &Vec::new()).map(|_| ())
+            },
+        )
+        .unwrap();
+
+        let instance = template.instantiate().unwrap();
+        let load = Function::find_export_func(&instance, "load").unwrap();
+        assert_eq!(
+            load.call(&instance, &Vec::new()).unwrap(),
+            vec![WasmValue::I32(7)]
+        );
+        // `_initialize` runs at instantiation, before `setup`
+        let ready = instance.global("ready").unwrap();
+        assert_eq!(ready.get().unwrap(), WasmValue::I32(1));
+    }
+}
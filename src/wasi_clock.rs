@@ -0,0 +1,156 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! clocks guests see instead of those of the host. See `WasiCtxBuilder::clock()`
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// what `clock_time_get` tells guests, for all clocks. Runs are replayed and tests pass
+/// the same regardless of the wall time
+///
+/// ```ignore
+/// let clock = VirtualClock::new(Duration::from_secs(1_700_000_000));
+/// let wasi_ctx = WasiCtxBuilder::new()
+///     .clock(ClockSource::Virtual(clock.clone()))
+///     .build();
+/// ...
+/// clock.advance(Duration::from_millis(16));
+/// ```
+#[derive(Debug, Clone)]
+pub enum ClockSource {
+    /// stopped at the time, since the epoch of the realtime clock
+    Fixed(Duration),
+    /// as the host sets it
+    Virtual(VirtualClock),
+}
+
+impl ClockSource {
+    pub(crate) fn now(&self) -> u64 {
+        match self {
+            ClockSource::Fixed(time) => nanos(*time),
+            ClockSource::Virtual(clock) => clock.nanos.load(Ordering::Acquire),
+        }
+    }
+}
+
+/// a clock the host sets and advances. Clones share the time, so keep one to control
+/// the clock of guests
+///
+/// Guests expect the monotonic clock never to go back. `set()` to an earlier time breaks
+/// that.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    // since the epoch
+    nanos: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    /// at `time` since the epoch
+    pub fn new(time: Duration) -> Self {
+        VirtualClock {
+            nanos: Arc::new(AtomicU64::new(nanos(time))),
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Acquire))
+    }
+
+    pub fn set(&self, time: Duration) {
+        self.nanos.store(nanos(time), Ordering::Release);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let by = nanos(by);
+        let _ = self
+            .nanos
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |now| {
+                Some(now.saturating_add(by))
+            });
+    }
+}
+
+// `__wasi_timestamp_t`, saturated
+fn nanos(time: Duration) -> u64 {
+    u64::try_from(time.as_nanos()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instance::Instance, module::Module, runtime::Runtime, wasi_context::WasiCtxBuilder,
+    };
+
+    #[test]
+    fn test_virtual_clock() {
+        let clock = VirtualClock::new(Duration::from_secs(10));
+        let source = ClockSource::Virtual(clock.clone());
+        assert_eq!(source.now(), 10_000_000_000);
+
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(clock.now(), Duration::from_millis(10_005));
+        assert_eq!(source.now(), 10_005_000_000);
+
+        clock.set(Duration::from_secs(1));
+        assert_eq!(source.now(), 1_000_000_000);
+        clock.advance(Duration::MAX);
+        assert_eq!(source.now(), u64::MAX);
+
+        assert_eq!(ClockSource::Fixed(Duration::from_nanos(42)).now(), 42);
+    }
+
+    #[test]
+    fn test_wasi_clock() {
+        let runtime = Runtime::new().unwrap();
+        let wat = r#"
+            (module
+              (import "wasi_snapshot_preview1" "clock_time_get"
+                (func $clock_time_get (param i32 i64 i32) (result i32)))
+              (memory (export "memory") 1)
+              (func (export "now") (param $clock i32) (result i64)
+                (drop (call $clock_time_get (local.get $clock) (i64.const 1) (i32.const 8)))
+                (i64.load (i32.const 8))
+              )
+            )
+            "#;
+
+        let clock = VirtualClock::new(Duration::from_secs(100));
+        let mut module = Module::from_wat(&runtime, wat).unwrap();
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .clock(ClockSource::Virtual(clock.clone()))
+                    .build(),
+            )
+            .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let now =
+            |clock_id: i32| -> i64 { instance.call("now").arg(clock_id).invoke_typed().unwrap() };
+
+        // realtime and monotonic
+        assert_eq!(now(0), 100_000_000_000);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(now(1), 101_000_000_000);
+
+        let mut module = Module::from_wat(&runtime, wat).unwrap();
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .clock(ClockSource::Fixed(Duration::from_secs(7)))
+                    .build(),
+            )
+            .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let now: i64 = instance.call("now").arg(0).invoke_typed().unwrap();
+        assert_eq!(now, 7_000_000_000);
+    }
+}
@@ -5,7 +5,164 @@
 
 //! prepare wasi context
 
-use std::{ffi::CString, vec::Vec};
+use std::{
+    ffi::{c_char, CString},
+    ops::BitOr,
+    path::{Path, PathBuf},
+    ptr,
+    sync::{Arc, Mutex, PoisonError},
+    vec::Vec,
+};
+
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, OwnedFd};
+
+use wamr_sys::{
+    wasm_exec_env_t, wasm_module_inst_t, wasm_module_t, wasm_runtime_get_exec_env_singleton,
+    wasm_runtime_is_wasi_mode, wasm_runtime_set_wasi_addr_pool, wasm_runtime_set_wasi_args_ex,
+    wasm_runtime_set_wasi_ns_lookup_pool,
+};
+
+use crate::{
+    wasi_clock::ClockSource,
+    wasi_natives,
+    wasi_overlay::{Overlay, OverlayLayer},
+    wasi_quota::FsQuota,
+    wasi_staged_dir::{MaterializedDir, StagedDir},
+    wasi_virtual_file::{normalize, VirtualFile, VirtualFileMap},
+    RuntimeError,
+};
+
+#[cfg(target_os = "linux")]
+use crate::wasi_stdio::{Input, Pipe, Stdio};
+
+type FdStatSetRights = unsafe extern "C" fn(wasm_exec_env_t, u32, u64, u64) -> u16;
+
+// rights of WASI, `__wasi_rights_t`
+const FD_DATASYNC: u64 = 1 << 0;
+pub(crate) const FD_READ: u64 = 1 << 1;
+pub(crate) const FD_SEEK: u64 = 1 << 2;
+const FD_FDSTAT_SET_FLAGS: u64 = 1 << 3;
+pub(crate) const FD_SYNC: u64 = 1 << 4;
+pub(crate) const FD_TELL: u64 = 1 << 5;
+pub(crate) const FD_WRITE: u64 = 1 << 6;
+const FD_ADVISE: u64 = 1 << 7;
+const FD_ALLOCATE: u64 = 1 << 8;
+const PATH_CREATE_DIRECTORY: u64 = 1 << 9;
+pub(crate) const PATH_CREATE_FILE: u64 = 1 << 10;
+const PATH_LINK_SOURCE: u64 = 1 << 11;
+const PATH_LINK_TARGET: u64 = 1 << 12;
+const PATH_OPEN: u64 = 1 << 13;
+const FD_READDIR: u64 = 1 << 14;
+const PATH_READLINK: u64 = 1 << 15;
+const PATH_RENAME_SOURCE: u64 = 1 << 16;
+const PATH_RENAME_TARGET: u64 = 1 << 17;
+const PATH_FILESTAT_GET: u64 = 1 << 18;
+const PATH_FILESTAT_SET_SIZE: u64 = 1 << 19;
+const PATH_FILESTAT_SET_TIMES: u64 = 1 << 20;
+pub(crate) const FD_FILESTAT_GET: u64 = 1 << 21;
+const FD_FILESTAT_SET_SIZE: u64 = 1 << 22;
+const FD_FILESTAT_SET_TIMES: u64 = 1 << 23;
+const PATH_SYMLINK: u64 = 1 << 24;
+const PATH_REMOVE_DIRECTORY: u64 = 1 << 25;
+const POLL_FD_READWRITE: u64 = 1 << 26;
+const PATH_UNLINK_FILE: u64 = 1 << 27;
+
+// the guest path of an entry of `dir_list` or `map_dir_list`, like `/data` of
+// `/data::./data`
+fn guest_path(pre_open: &CString) -> PathBuf {
+    let pre_open = pre_open.to_string_lossy();
+    let guest_path = pre_open.split("::").next().unwrap_or_default();
+    normalize(Path::new(guest_path))
+}
+
+// what libc-wasi of WAMR grants a preopened directory itself. Rights only shrink
+const DIRECTORY_BASE: u64 = FD_FDSTAT_SET_FLAGS
+    | FD_SYNC
+    | FD_ADVISE
+    | PATH_CREATE_DIRECTORY
+    | PATH_CREATE_FILE
+    | PATH_LINK_SOURCE
+    | PATH_LINK_TARGET
+    | PATH_OPEN
+    | FD_READDIR
+    | PATH_READLINK
+    | PATH_RENAME_SOURCE
+    | PATH_RENAME_TARGET
+    | PATH_FILESTAT_GET
+    | PATH_FILESTAT_SET_SIZE
+    | PATH_FILESTAT_SET_TIMES
+    | FD_FILESTAT_GET
+    | FD_FILESTAT_SET_TIMES
+    | PATH_SYMLINK
+    | PATH_UNLINK_FILE
+    | PATH_REMOVE_DIRECTORY
+    | POLL_FD_READWRITE;
+
+/// what guests may do in a preopened directory, and its files and subdirectories.
+/// Combine them with `|`. See `WasiCtxBuilder::set_pre_open_path_with_rights()`
+///
+/// Renaming takes `UNLINK` of the source and `CREATE` of the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rights(u64);
+
+impl Rights {
+    /// open and read files, list directories, and get their stats
+    pub const READ: Rights = Rights(
+        FD_READ
+            | FD_SEEK
+            | FD_TELL
+            | FD_ADVISE
+            | PATH_OPEN
+            | FD_READDIR
+            | PATH_READLINK
+            | PATH_FILESTAT_GET
+            | FD_FILESTAT_GET
+            | POLL_FD_READWRITE,
+    );
+    /// write, truncate and sync existing files, and set their times
+    pub const WRITE: Rights = Rights(
+        FD_WRITE
+            | FD_DATASYNC
+            | FD_SYNC
+            | FD_ALLOCATE
+            | FD_FDSTAT_SET_FLAGS
+            | PATH_FILESTAT_SET_SIZE
+            | PATH_FILESTAT_SET_TIMES
+            | FD_FILESTAT_SET_SIZE
+            | FD_FILESTAT_SET_TIMES,
+    );
+    /// create files, directories and links
+    pub const CREATE: Rights = Rights(
+        PATH_CREATE_DIRECTORY
+            | PATH_CREATE_FILE
+            | PATH_LINK_TARGET
+            | PATH_RENAME_TARGET
+            | PATH_SYMLINK,
+    );
+    /// remove files and directories
+    pub const UNLINK: Rights =
+        Rights(PATH_UNLINK_FILE | PATH_REMOVE_DIRECTORY | PATH_RENAME_SOURCE | PATH_LINK_SOURCE);
+    /// everything, like `set_pre_open_path()`
+    pub const ALL: Rights =
+        Rights(Rights::READ.0 | Rights::WRITE.0 | Rights::CREATE.0 | Rights::UNLINK.0);
+
+    pub fn contains(self, other: Rights) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub(crate) const fn bits(self) -> u64 {
+        self.0
+    }
+}
+
+impl BitOr for Rights {
+    type Output = Rights;
+
+    fn bitor(self, rhs: Rights) -> Rights {
+        Rights(self.0 | rhs.0)
+    }
+}
 
 #[derive(Debug, Default)]
 struct PreOpen {
@@ -13,6 +170,47 @@ struct PreOpen {
     mapped_paths: Vec<CString>,
 }
 
+// `char **` of C strings for WAMR, which keeps referring to it. The strings are owned by
+// the Wasi context, and never modified after
+#[derive(Debug, Default)]
+struct CStrArray(Vec<*const c_char>);
+
+unsafe impl Send for CStrArray {}
+unsafe impl Sync for CStrArray {}
+
+impl CStrArray {
+    fn new(strings: &[CString]) -> Self {
+        CStrArray(strings.iter().map(|s| s.as_ptr()).collect())
+    }
+
+    fn as_ptr(&self) -> *mut *const c_char {
+        match self.0.is_empty() {
+            true => ptr::null_mut(),
+            false => self.0.as_ptr() as *mut *const c_char,
+        }
+    }
+
+    fn len(&self) -> u32 {
+        self.0.len() as u32
+    }
+}
+
+// what `wasm_runtime_set_wasi_args_ex()` and friends take
+#[derive(Debug, Default)]
+struct WasiArgs {
+    real_paths: CStrArray,
+    mapped_paths: CStrArray,
+    allowed_address: CStrArray,
+    allowed_dns: CStrArray,
+    env: CStrArray,
+    args: CStrArray,
+}
+
+/// WASI arguments of a module
+///
+/// By default, guests share stdin, stdout and stderr of the host. Replace them with
+/// `stdin()`, `stdout()` and `stderr()`, or cut them off with `inherit_stdin(false)`,
+/// `inherit_stdout(false)` and `inherit_stderr(false)`, each on its own.
 #[derive(Debug, Default)]
 pub struct WasiCtxBuilder {
     pre_open: PreOpen,
@@ -20,6 +218,20 @@ pub struct WasiCtxBuilder {
     allowed_dns: Vec<CString>,
     env: Vec<CString>,
     args: Vec<CString>,
+    // by guest paths
+    staged_dirs: Vec<(String, StagedDir)>,
+    // host paths, guest paths and rights
+    restricted_pre_opens: Vec<(String, String, Rights)>,
+    overlays: Vec<Overlay>,
+    fs_quota: Option<FsQuota>,
+    virtual_files: VirtualFileMap,
+    clock: Option<ClockSource>,
+    #[cfg(target_os = "linux")]
+    stdin: Stdio<Input>,
+    #[cfg(target_os = "linux")]
+    stdout: Stdio<Pipe>,
+    #[cfg(target_os = "linux")]
+    stderr: Stdio<Pipe>,
 }
 
 #[derive(Debug, Default)]
@@ -29,6 +241,28 @@ pub struct WasiCtx {
     allowed_dns: Vec<CString>,
     env: Vec<CString>,
     args: Vec<CString>,
+    // preopened via `pre_open.mapped_paths`, and removed when dropped
+    _staged_dirs: Vec<MaterializedDir>,
+    // rights by WASI file descriptors of preopens
+    pre_open_rights: Vec<(u32, Rights)>,
+    // after `pre_open.mapped_paths`, with a new layer for each instance
+    overlays: Vec<Overlay>,
+    // the module is pointed to other layers while an instance with overlays is created
+    overlay_lock: Mutex<()>,
+    wasi_args: WasiArgs,
+    // of each instance
+    fs_quota: Option<FsQuota>,
+    // shared by all instances
+    virtual_files: Option<Arc<VirtualFileMap>>,
+    clock: Option<ClockSource>,
+    // `None` for those of the host. Closing the read end of a stdin pipe stops the feeding,
+    // and closing write ends of stdout and stderr pipes ends delivery
+    #[cfg(target_os = "linux")]
+    stdin: Option<OwnedFd>,
+    #[cfg(target_os = "linux")]
+    stdout: Option<OwnedFd>,
+    #[cfg(target_os = "linux")]
+    stderr: Option<OwnedFd>,
 }
 
 impl WasiCtxBuilder {
@@ -36,14 +270,201 @@ impl WasiCtxBuilder {
         WasiCtxBuilder::default()
     }
 
+    /// # Panics
+    ///
+    /// If a directory of `pre_open_staged_dir()` can't be written, or an os pipe of
+    /// stdio can't be created. See `try_build()`
     pub fn build(self) -> WasiCtx {
-        WasiCtx {
+        self.try_build()
+            .unwrap_or_else(|e| panic!("build a Wasi context failed, {}", e))
+    }
+
+    /// the same as `build()`, but fails instead of panicking
+    ///
+    /// # Error
+    ///
+    /// If a directory of `pre_open_staged_dir()` can't be written, or an os pipe of stdio
+    /// can't be created, an `RuntimeError::WasmFileFSError` will be returned.
+    pub fn try_build(mut self) -> Result<WasiCtx, RuntimeError> {
+        // directories of virtual files are preopened, and empty, unless in another preopen
+        let pre_opened = self
+            .pre_open
+            .real_paths
+            .iter()
+            .chain(&self.pre_open.mapped_paths)
+            .map(guest_path)
+            .chain(
+                self.staged_dirs
+                    .iter()
+                    .map(|(path, _)| normalize(Path::new(path))),
+            )
+            .chain(
+                self.restricted_pre_opens
+                    .iter()
+                    .map(|(_, path, _)| normalize(Path::new(path))),
+            )
+            .chain(
+                self.overlays
+                    .iter()
+                    .map(|overlay| normalize(Path::new(overlay.mapped_path()))),
+            )
+            .collect::<Vec<_>>();
+        let mut virtual_dirs = Vec::new();
+        for dir in self.virtual_files.dirs() {
+            if !pre_opened.iter().any(|path| dir.starts_with(path)) && !virtual_dirs.contains(&dir)
+            {
+                virtual_dirs.push(dir);
+            }
+        }
+        let virtual_dirs = virtual_dirs
+            .into_iter()
+            .map(|dir| (dir.to_string_lossy().into_owned(), StagedDir::new()))
+            .collect::<Vec<_>>();
+        self.staged_dirs.extend(virtual_dirs);
+
+        let mut staged_dirs = Vec::new();
+        for (guest_path, dir) in &self.staged_dirs {
+            let materialized = dir.materialize()?;
+            let mapped_path = format!("{}::{}", guest_path, materialized.path().display());
+            self.pre_open
+                .mapped_paths
+                .push(CString::new(mapped_path).unwrap());
+            staged_dirs.push(materialized);
+        }
+
+        // preopens take file descriptors from 3, in the order of `dir_list` and then
+        // `map_dir_list`
+        let mut pre_open_rights = Vec::new();
+        for (real_path, mapped_path, rights) in &self.restricted_pre_opens {
+            let fd = 3 + self.pre_open.real_paths.len() + self.pre_open.mapped_paths.len();
+            let mapped_path = format!("{}::{}", mapped_path, real_path);
+            self.pre_open
+                .mapped_paths
+                .push(CString::new(mapped_path).unwrap());
+            pre_open_rights.push((fd as u32, *rights));
+        }
+
+        let virtual_files = match self.virtual_files.is_empty() {
+            true => None,
+            false => {
+                let pre_opens = self
+                    .pre_open
+                    .real_paths
+                    .iter()
+                    .chain(&self.pre_open.mapped_paths)
+                    .map(guest_path)
+                    .chain(
+                        self.overlays
+                            .iter()
+                            .map(|overlay| PathBuf::from(overlay.mapped_path())),
+                    )
+                    .zip(3u32..)
+                    .map(|(path, fd)| {
+                        let rights = pre_open_rights
+                            .iter()
+                            .find(|(pre_open, _)| *pre_open == fd)
+                            .map_or(Rights::ALL, |(_, rights)| *rights);
+                        (path, rights)
+                    })
+                    .collect();
+                self.virtual_files.set_pre_opens(pre_opens);
+                Some(Arc::new(self.virtual_files))
+            }
+        };
+
+        let wasi_args = WasiArgs {
+            real_paths: CStrArray::new(&self.pre_open.real_paths),
+            mapped_paths: CStrArray::new(&self.pre_open.mapped_paths),
+            allowed_address: CStrArray::new(&self.allowed_address),
+            allowed_dns: CStrArray::new(&self.allowed_dns),
+            env: CStrArray::new(&self.env),
+            args: CStrArray::new(&self.args),
+        };
+
+        Ok(WasiCtx {
             pre_open: self.pre_open,
+            _staged_dirs: staged_dirs,
+            pre_open_rights,
+            overlays: self.overlays,
+            overlay_lock: Mutex::new(()),
+            wasi_args,
+            fs_quota: self.fs_quota,
+            virtual_files,
+            clock: self.clock,
             allowed_address: self.allowed_address,
             allowed_dns: self.allowed_dns,
             env: self.env,
             args: self.args,
-        }
+            #[cfg(target_os = "linux")]
+            stdin: self.stdin.attach(Input::attach)?,
+            #[cfg(target_os = "linux")]
+            stdout: self.stdout.attach(|pipe| pipe.attach())?,
+            #[cfg(target_os = "linux")]
+            stderr: self.stderr.attach(|pipe| pipe.attach())?,
+        })
+    }
+
+    /// feed guest stdin from `reader`, instead of the stdin of the host. Guests see an EOF
+    /// at the end of `reader`
+    ///
+    /// All instances of the module share the stdin. The first one reading takes the content.
+    ///
+    /// ```ignore
+    /// let wasi_ctx = WasiCtxBuilder::new().stdin(File::open("input.txt")?).build();
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn stdin<R: std::io::Read + Send + 'static>(mut self, reader: R) -> WasiCtxBuilder {
+        self.stdin = Stdio::Redirect(Input::new(reader));
+        self
+    }
+
+    /// feed guest stdin with `bytes`. See `stdin()`
+    #[cfg(target_os = "linux")]
+    pub fn stdin_bytes(self, bytes: impl Into<Vec<u8>>) -> WasiCtxBuilder {
+        self.stdin(std::io::Cursor::new(bytes.into()))
+    }
+
+    /// send guest stdout to `pipe`, instead of the stdout of the host
+    ///
+    /// ```ignore
+    /// let stdout = Pipe::buffer();
+    /// let wasi_ctx = WasiCtxBuilder::new().stdout(stdout.clone()).build();
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn stdout(mut self, pipe: Pipe) -> WasiCtxBuilder {
+        self.stdout = Stdio::Redirect(pipe);
+        self
+    }
+
+    /// send guest stderr to `pipe`, instead of the stderr of the host
+    #[cfg(target_os = "linux")]
+    pub fn stderr(mut self, pipe: Pipe) -> WasiCtxBuilder {
+        self.stderr = Stdio::Redirect(pipe);
+        self
+    }
+
+    /// share the stdin of the host with guests, the default, or not. Without it, guests
+    /// read an EOF. Either replaces `stdin()`
+    #[cfg(target_os = "linux")]
+    pub fn inherit_stdin(mut self, inherit: bool) -> WasiCtxBuilder {
+        self.stdin = if inherit { Stdio::Inherit } else { Stdio::Null };
+        self
+    }
+
+    /// share the stdout of the host with guests, the default, or not. Without it, guest
+    /// output is discarded. Either replaces `stdout()`
+    #[cfg(target_os = "linux")]
+    pub fn inherit_stdout(mut self, inherit: bool) -> WasiCtxBuilder {
+        self.stdout = if inherit { Stdio::Inherit } else { Stdio::Null };
+        self
+    }
+
+    /// share the stderr of the host with guests, the default, or not. Without it, guest
+    /// output is discarded. Either replaces `stderr()`
+    #[cfg(target_os = "linux")]
+    pub fn inherit_stderr(mut self, inherit: bool) -> WasiCtxBuilder {
+        self.stderr = if inherit { Stdio::Inherit } else { Stdio::Null };
+        self
     }
 
     /// set pre-open directories and files, which are part of WASI arguments, for the module.
@@ -68,6 +489,115 @@ impl WasiCtxBuilder {
         self
     }
 
+    /// preopen `dir`, a directory tree built by the host, as `guest_path`. Each `build()`
+    /// writes it into a private temporary directory of the host, which is removed with the
+    /// Wasi context. See `StagedDir`
+    ///
+    /// It keeps working after `set_pre_open_path()`.
+    pub fn pre_open_staged_dir(mut self, guest_path: &str, dir: StagedDir) -> WasiCtxBuilder {
+        self.staged_dirs.push((String::from(guest_path), dir));
+        self
+    }
+
+    /// preopen the host directory `real_path` as `mapped_path`, where guests only have
+    /// `rights`. WASI of WAMR enforces them, and fails those beyond with `ENOTCAPABLE`
+    ///
+    /// It keeps working after `set_pre_open_path()`.
+    ///
+    /// ```ignore
+    /// let wasi_ctx = WasiCtxBuilder::new()
+    ///     .set_pre_open_path_with_rights("./data", "/data", Rights::READ)
+    ///     .set_pre_open_path_with_rights("./out", "/out", Rights::READ | Rights::CREATE)
+    ///     .build();
+    /// ```
+    pub fn set_pre_open_path_with_rights(
+        mut self,
+        real_path: &str,
+        mapped_path: &str,
+        rights: Rights,
+    ) -> WasiCtxBuilder {
+        self.restricted_pre_opens.push((
+            String::from(real_path),
+            String::from(mapped_path),
+            rights,
+        ));
+        self
+    }
+
+    /// preopen the host directory `base` as `mapped_path`, where each instance writes on a
+    /// layer of its own. So instances share a tree of assets, and never see what others
+    /// write, and `base` is never modified
+    ///
+    /// A layer starts with a copy of `base`, in the temporary directory or in memory as
+    /// `layer` says. It is removed with the instance, and replaced in `Instance::reset()`.
+    /// Symbolic links in `base` are left out. It suits small trees, since each instantiation
+    /// copies all of it.
+    ///
+    /// It keeps working after `set_pre_open_path()`.
+    ///
+    /// ```ignore
+    /// let wasi_ctx = WasiCtxBuilder::new()
+    ///     .pre_open_overlay("./assets", "/assets", OverlayLayer::Memory)
+    ///     .build();
+    /// ```
+    pub fn pre_open_overlay(
+        mut self,
+        base: &str,
+        mapped_path: &str,
+        layer: OverlayLayer,
+    ) -> WasiCtxBuilder {
+        self.overlays.push(Overlay::new(base, mapped_path, layer));
+        self
+    }
+
+    /// limit what each instance writes, creates and keeps open in preopened directories.
+    /// Each instance has a quota of its own. See `FsQuota`
+    pub fn fs_quota(mut self, quota: FsQuota) -> WasiCtxBuilder {
+        self.fs_quota = Some(quota);
+        self
+    }
+
+    /// make the file at `guest_path`, an absolute path, `file`. Guests open, read, write,
+    /// seek and stat it like a file, and each open has a position of its own. All instances
+    /// of the module share `file`
+    ///
+    /// The directory of `guest_path` is preopened as an empty one, unless it is in another
+    /// preopen. Virtual files are not listed in their directories, can't be renamed or
+    /// removed, and are not truncated by `O_TRUNC`. An error of `file` is `EIO` for guests,
+    /// or `EACCES` for `io::ErrorKind::PermissionDenied`
+    ///
+    /// Rights of `set_pre_open_path_with_rights()` and `fs_quota()` apply to them as to
+    /// real files of the preopen.
+    ///
+    /// ```ignore
+    /// let wasi_ctx = WasiCtxBuilder::new()
+    ///     .virtual_file("/data/blob.db", Box::new(Cursor::new(blob)))
+    ///     .build();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `guest_path` is not absolute
+    pub fn virtual_file(mut self, guest_path: &str, file: Box<dyn VirtualFile>) -> WasiCtxBuilder {
+        assert!(
+            guest_path.starts_with('/'),
+            "virtual file {:?} is not absolute",
+            guest_path
+        );
+        self.virtual_files.insert(guest_path, file);
+        self
+    }
+
+    /// what `clock_time_get` tells guests, instead of the clocks of the host. See
+    /// `ClockSource`
+    ///
+    /// It applies to the realtime, monotonic and cputime clocks alike, whose resolution
+    /// is 1ns then. Timeouts of `poll_oneoff` still go by the host.
+    pub fn clock(mut self, source: ClockSource) -> WasiCtxBuilder {
+        self.clock = Some(source);
+        self
+    }
+
     /// set environment variables, which are part of WASI arguments, for the module
     ///
     /// This function should be called before `Instance::new`
@@ -143,11 +673,139 @@ impl WasiCtx {
     pub fn get_arguments(&self) -> &Vec<CString> {
         &self.args
     }
+
+    pub(crate) fn fs_quota(&self) -> Option<FsQuota> {
+        self.fs_quota
+    }
+
+    pub(crate) fn virtual_files(&self) -> Option<&Arc<VirtualFileMap>> {
+        self.virtual_files.as_ref()
+    }
+
+    pub(crate) fn clock(&self) -> Option<&ClockSource> {
+        self.clock.as_ref()
+    }
+
+    // pass WASI arguments to the module
+    pub(crate) fn apply(&self, module: wasm_module_t) {
+        self.set_args(module, &self.wasi_args.mapped_paths);
+        unsafe {
+            wasm_runtime_set_wasi_ns_lookup_pool(
+                module,
+                self.wasi_args.allowed_dns.as_ptr(),
+                self.wasi_args.allowed_dns.len(),
+            );
+            wasm_runtime_set_wasi_addr_pool(
+                module,
+                self.wasi_args.allowed_address.as_ptr(),
+                self.wasi_args.allowed_address.len(),
+            );
+        }
+    }
+
+    fn set_args(&self, module: wasm_module_t, mapped_paths: &CStrArray) {
+        let [stdin, stdout, stderr] = self.stdio_fds();
+        unsafe {
+            wasm_runtime_set_wasi_args_ex(
+                module,
+                self.wasi_args.real_paths.as_ptr(),
+                self.wasi_args.real_paths.len(),
+                mapped_paths.as_ptr(),
+                mapped_paths.len(),
+                self.wasi_args.env.as_ptr(),
+                self.wasi_args.env.len(),
+                self.wasi_args.args.as_ptr() as *mut *mut c_char,
+                self.wasi_args.args.len() as i32,
+                stdin,
+                stdout,
+                stderr,
+            );
+        }
+    }
+
+    // run `instantiate` with new layers of overlays preopened, which the instance keeps
+    // until it is gone. WAMR reads WASI arguments of the module at instantiation, and
+    // copies what it keeps
+    pub(crate) fn instantiate_with_layers<T>(
+        &self,
+        module: wasm_module_t,
+        instantiate: impl FnOnce() -> Result<T, RuntimeError>,
+    ) -> Result<(T, Vec<MaterializedDir>), RuntimeError> {
+        if self.overlays.is_empty() {
+            return Ok((instantiate()?, Vec::new()));
+        }
+
+        let mut layers = Vec::new();
+        let mut mapped_paths = self.pre_open.mapped_paths.clone();
+        for overlay in &self.overlays {
+            let layer = overlay.materialize().map_err(|error| {
+                RuntimeError::InstantiationFailure(format!(
+                    "write the overlay of {} failed, {}",
+                    overlay.mapped_path(),
+                    error
+                ))
+            })?;
+            let mapped_path = format!("{}::{}", overlay.mapped_path(), layer.path().display());
+            mapped_paths.push(CString::new(mapped_path).unwrap());
+            layers.push(layer);
+        }
+
+        let _guard = self
+            .overlay_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.set_args(module, &CStrArray::new(&mapped_paths));
+        let instance = instantiate();
+        self.set_args(module, &self.wasi_args.mapped_paths);
+        Ok((instance?, layers))
+    }
+
+    // drop rights of preopens beyond those of `set_pre_open_path_with_rights()`, before
+    // anything runs in a new instance
+    pub(crate) fn restrict_pre_opens(
+        &self,
+        instance: wasm_module_inst_t,
+    ) -> Result<(), RuntimeError> {
+        if self.pre_open_rights.is_empty() || !unsafe { wasm_runtime_is_wasi_mode(instance) } {
+            return Ok(());
+        }
+
+        let set_rights = wasi_natives::original::<FdStatSetRights>("fd_fdstat_set_rights");
+        let set_rights = set_rights.ok_or_else(|| {
+            RuntimeError::InstantiationFailure(String::from("fd_fdstat_set_rights() not found"))
+        })?;
+        let exec_env = unsafe { wasm_runtime_get_exec_env_singleton(instance) };
+        for (fd, rights) in &self.pre_open_rights {
+            let errno = unsafe { set_rights(exec_env, *fd, rights.0 & DIRECTORY_BASE, rights.0) };
+            if errno != 0 {
+                return Err(RuntimeError::InstantiationFailure(format!(
+                    "restrict rights of the preopen fd {} failed, errno {}",
+                    fd, errno
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // file descriptors of stdin, stdout and stderr for WAMR. -1 for those of the host
+    pub(crate) fn stdio_fds(&self) -> [i64; 3] {
+        #[allow(unused_mut)]
+        let mut fds = [-1; 3];
+        #[cfg(target_os = "linux")]
+        {
+            let fd = |pipe: &Option<OwnedFd>| pipe.as_ref().map_or(-1, |fd| fd.as_raw_fd() as i64);
+            fds[0] = fd(&self.stdin);
+            fds[1] = fd(&self.stdout);
+            fds[2] = fd(&self.stderr);
+        }
+        fds
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{instance::Instance, module::Module, runtime::Runtime};
 
     #[test]
     fn test_wasi_ctx_build() {
@@ -188,4 +846,107 @@ mod tests {
         );
         assert_eq!(env_vars_iter.next(), None);
     }
+
+    #[test]
+    fn test_wasi_args() {
+        let wasi_ctx = WasiCtxBuilder::new()
+            .set_env_vars(vec!["A=1", "B=22", "C=333"])
+            .build();
+        let env = &wasi_ctx.wasi_args.env;
+        assert_eq!(env.len(), 3);
+        let env = unsafe { std::slice::from_raw_parts(env.as_ptr(), 3) }
+            .iter()
+            .map(|s| unsafe { std::ffi::CStr::from_ptr(*s) }.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(env, ["A=1", "B=22", "C=333"]);
+        assert!(wasi_ctx.wasi_args.args.as_ptr().is_null());
+    }
+
+    #[test]
+    fn test_rights() {
+        let rights = Rights::READ | Rights::CREATE;
+        assert!(rights.contains(Rights::READ));
+        assert!(!rights.contains(Rights::WRITE));
+        assert!(Rights::ALL.contains(rights | Rights::UNLINK));
+    }
+
+    #[test]
+    fn test_pre_open_rights() {
+        let runtime = Runtime::new().unwrap();
+        let mut module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 32) "data.txt")
+              (data (i32.const 48) "new.txt")
+              ;; open a file in the first preopen. return the fd, or the negative errno
+              (func (export "open") (param $path i32) (param $len i32) (param $oflags i32)
+                (param $rights i64) (result i32)
+                (local $errno i32)
+                (local.set $errno
+                  (call $path_open (i32.const 3) (i32.const 0) (local.get $path)
+                    (local.get $len) (local.get $oflags) (local.get $rights) (i64.const 0)
+                    (i32.const 0) (i32.const 16)))
+                (if (result i32) (local.get $errno)
+                  (then (i32.sub (i32.const 0) (local.get $errno)))
+                  (else (i32.load (i32.const 16))))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let dir = StagedDir::new()
+            .file("data.txt", "read only")
+            .materialize()
+            .unwrap();
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .set_pre_open_path_with_rights(
+                        dir.path().to_str().unwrap(),
+                        "/data",
+                        Rights::READ,
+                    )
+                    .build(),
+            )
+            .unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let open = |path: i32, len: i32, oflags: i32, rights: i64| -> i32 {
+            instance
+                .call("open")
+                .args((path, len, oflags, rights))
+                .invoke_typed()
+                .unwrap()
+        };
+        const ENOTCAPABLE: i32 = 76;
+        // FD_READ
+        assert!(open(32, 8, 0, 1 << 1) > 3);
+        // FD_WRITE
+        assert_eq!(open(32, 8, 0, 1 << 6), -ENOTCAPABLE);
+        // O_CREAT
+        assert_eq!(open(48, 7, 1, 1 << 1), -ENOTCAPABLE);
+        assert!(!dir.path().join("new.txt").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_wasi_ctx_stdio() {
+        // all inherited by default
+        assert_eq!(WasiCtxBuilder::new().build().stdio_fds(), [-1, -1, -1]);
+
+        let wasi_ctx = WasiCtxBuilder::new()
+            .inherit_stdin(false)
+            .stdout(Pipe::buffer())
+            .inherit_stdout(true)
+            .build();
+        let [stdin, stdout, stderr] = wasi_ctx.stdio_fds();
+        assert!(stdin >= 0);
+        assert_eq!(stdout, -1);
+        assert_eq!(stderr, -1);
+    }
 }
@@ -0,0 +1,899 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! natives of `wasi_snapshot_preview1` in front of those of libc-wasi of WAMR. They
+//! enforce what the Wasi context of an instance asks for, and leave the rest to libc-wasi
+
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_void, CStr},
+    mem, ptr,
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError},
+};
+
+use wamr_sys::{
+    wasm_exec_env_t, wasm_runtime_addr_app_to_native, wasm_runtime_get_module_inst,
+    wasm_runtime_register_natives, wasm_runtime_validate_app_addr,
+    wasm_runtime_validate_native_addr, NativeSymbol,
+};
+
+use crate::{
+    instance::Instance, wasi_clock::ClockSource, wasi_context::WasiCtx, wasi_quota::FsUsage,
+    wasi_virtual_file::VirtualFiles,
+};
+
+// not in wasm_export.h. natives of libc-wasi, which are registered by WAMR itself
+extern "C" {
+    fn get_libc_wasi_export_apis(p_libc_wasi_apis: *mut *mut NativeSymbol) -> u32;
+}
+
+// errnos of WASI, `__wasi_errno_t`
+pub(crate) const EACCES: u16 = 2;
+pub(crate) const EBADF: u16 = 8;
+pub(crate) const EEXIST: u16 = 20;
+pub(crate) const EFAULT: u16 = 21;
+pub(crate) const EINVAL: u16 = 28;
+pub(crate) const EIO: u16 = 29;
+pub(crate) const EMFILE: u16 = 33;
+pub(crate) const ENOENT: u16 = 44;
+pub(crate) const ENOSPC: u16 = 51;
+pub(crate) const ENOTDIR: u16 = 54;
+pub(crate) const ENOTSUP: u16 = 58;
+pub(crate) const ENOTCAPABLE: u16 = 76;
+
+// `__wasi_oflags_t`
+pub(crate) const O_CREAT: u32 = 1;
+pub(crate) const O_DIRECTORY: u32 = 2;
+pub(crate) const O_EXCL: u32 = 4;
+
+// `__WASI_FILETYPE_REGULAR_FILE`
+const FILETYPE_REGULAR_FILE: u8 = 4;
+// `__WASI_FDFLAG_APPEND`
+pub(crate) const FDFLAG_APPEND: u16 = 1;
+
+// `__wasi_ciovec_t` and `__wasi_iovec_t` in linear memory
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CIoVec {
+    buf: u32,
+    buf_len: u32,
+}
+
+// `__wasi_filestat_t`
+#[repr(C)]
+#[derive(Default)]
+struct Filestat {
+    dev: u64,
+    ino: u64,
+    filetype: u8,
+    nlink: u64,
+    size: u64,
+    atim: u64,
+    mtim: u64,
+    ctim: u64,
+}
+
+// `__wasi_fdstat_t`
+#[repr(C)]
+struct Fdstat {
+    filetype: u8,
+    flags: u16,
+    rights_base: u64,
+    rights_inheriting: u64,
+}
+
+// as natives of libc-wasi are declared, with pointers translated by WAMR
+type FdClose = unsafe extern "C" fn(wasm_exec_env_t, u32) -> u16;
+type FdRenumber = unsafe extern "C" fn(wasm_exec_env_t, u32, u32) -> u16;
+type FdRead = unsafe extern "C" fn(wasm_exec_env_t, u32, *const CIoVec, u32, *mut u32) -> u16;
+type FdPread = unsafe extern "C" fn(wasm_exec_env_t, u32, *const CIoVec, u32, u64, *mut u32) -> u16;
+type FdWrite = unsafe extern "C" fn(wasm_exec_env_t, u32, *const CIoVec, u32, *mut u32) -> u16;
+type FdPwrite =
+    unsafe extern "C" fn(wasm_exec_env_t, u32, *const CIoVec, u32, u64, *mut u32) -> u16;
+type PathOpen = unsafe extern "C" fn(
+    wasm_exec_env_t,
+    u32,
+    u32,
+    *const c_char,
+    u32,
+    u32,
+    u64,
+    u64,
+    u32,
+    *mut u32,
+) -> u16;
+type FdSeek = unsafe extern "C" fn(wasm_exec_env_t, u32, i64, u32, *mut u64) -> u16;
+type FdTell = unsafe extern "C" fn(wasm_exec_env_t, u32, *mut u64) -> u16;
+type FdSync = unsafe extern "C" fn(wasm_exec_env_t, u32) -> u16;
+type FdFilestatGet = unsafe extern "C" fn(wasm_exec_env_t, u32, *mut Filestat) -> u16;
+type FdFdstatGet = unsafe extern "C" fn(wasm_exec_env_t, u32, *mut Fdstat) -> u16;
+type PathFilestatGet =
+    unsafe extern "C" fn(wasm_exec_env_t, u32, u32, *const c_char, u32, *mut Filestat) -> u16;
+type ClockTimeGet = unsafe extern "C" fn(wasm_exec_env_t, u32, u64, *mut u64) -> u16;
+type ClockResGet = unsafe extern "C" fn(wasm_exec_env_t, u32, *mut u64) -> u16;
+type PathCreateDirectory = unsafe extern "C" fn(wasm_exec_env_t, u32, *const c_char, u32) -> u16;
+type FdAllocate = unsafe extern "C" fn(wasm_exec_env_t, u32, u64, u64) -> u16;
+type FdFilestatSetSize = unsafe extern "C" fn(wasm_exec_env_t, u32, u64) -> u16;
+type PathSymlink =
+    unsafe extern "C" fn(wasm_exec_env_t, *const c_char, u32, u32, *const c_char, u32) -> u16;
+type PathLink = unsafe extern "C" fn(
+    wasm_exec_env_t,
+    u32,
+    u32,
+    *const c_char,
+    u32,
+    u32,
+    *const c_char,
+    u32,
+) -> u16;
+
+// WAMR keeps referring to, and sorts, the symbols. They are registered again after each
+// initialization
+struct Symbols(Vec<NativeSymbol>);
+
+unsafe impl Send for Symbols {}
+
+static SYMBOLS: Mutex<Option<Symbols>> = Mutex::new(None);
+
+// natives of libc-wasi by names, as addresses of the function and the signature. They
+// are static in WAMR
+fn originals() -> &'static HashMap<String, (usize, usize)> {
+    static ORIGINALS: OnceLock<HashMap<String, (usize, usize)>> = OnceLock::new();
+    ORIGINALS.get_or_init(|| {
+        let mut native_symbols = ptr::null_mut();
+        let count = unsafe { get_libc_wasi_export_apis(&mut native_symbols) };
+        if native_symbols.is_null() {
+            return HashMap::new();
+        }
+        unsafe { std::slice::from_raw_parts(native_symbols, count as usize) }
+            .iter()
+            .map(|symbol| {
+                let name = unsafe { CStr::from_ptr(symbol.symbol) };
+                (
+                    name.to_string_lossy().into_owned(),
+                    (symbol.func_ptr as usize, symbol.signature as usize),
+                )
+            })
+            .collect()
+    })
+}
+
+// the native of libc-wasi `name`, as a function of type `F`
+pub(crate) fn original<F: Copy>(name: &str) -> Option<F> {
+    assert_eq!(mem::size_of::<F>(), mem::size_of::<usize>());
+    let (func_ptr, _) = originals().get(name)?;
+    Some(unsafe { mem::transmute_copy::<usize, F>(func_ptr) })
+}
+
+// one in front of which a native is registered
+fn libc_wasi<F: Copy>(name: &str) -> F {
+    original(name).expect("registered in front of a native of libc-wasi")
+}
+
+fn overrides() -> [(&'static CStr, *mut c_void); 20] {
+    [
+        (c"clock_time_get", clock_time_get as *mut c_void),
+        (c"clock_res_get", clock_res_get as *mut c_void),
+        (c"fd_close", fd_close as *mut c_void),
+        (c"fd_renumber", fd_renumber as *mut c_void),
+        (c"fd_read", fd_read as *mut c_void),
+        (c"fd_pread", fd_pread as *mut c_void),
+        (c"fd_write", fd_write as *mut c_void),
+        (c"fd_pwrite", fd_pwrite as *mut c_void),
+        (c"fd_seek", fd_seek as *mut c_void),
+        (c"fd_tell", fd_tell as *mut c_void),
+        (c"fd_sync", fd_sync as *mut c_void),
+        (c"fd_filestat_get", fd_filestat_get as *mut c_void),
+        (c"fd_fdstat_get", fd_fdstat_get as *mut c_void),
+        (c"fd_allocate", fd_allocate as *mut c_void),
+        (c"fd_filestat_set_size", fd_filestat_set_size as *mut c_void),
+        (c"path_open", path_open as *mut c_void),
+        (c"path_filestat_get", path_filestat_get as *mut c_void),
+        (
+            c"path_create_directory",
+            path_create_directory as *mut c_void,
+        ),
+        (c"path_symlink", path_symlink as *mut c_void),
+        (c"path_link", path_link as *mut c_void),
+    ]
+}
+
+// right after WAMR is initialized. Modules loaded afterwards link to the natives here,
+// which take the same signatures as those of libc-wasi. Nothing is registered without
+// libc-wasi
+pub(crate) fn register() -> bool {
+    let mut symbols = SYMBOLS.lock().unwrap_or_else(PoisonError::into_inner);
+    let symbols = symbols.get_or_insert_with(|| {
+        let symbols = overrides()
+            .into_iter()
+            .filter_map(|(name, func_ptr)| {
+                let (_, signature) = originals().get(name.to_str().unwrap())?;
+                Some(NativeSymbol {
+                    symbol: name.as_ptr(),
+                    func_ptr,
+                    signature: *signature as *const c_char,
+                    attachment: ptr::null_mut(),
+                })
+            })
+            .collect();
+        Symbols(symbols)
+    });
+    if symbols.0.is_empty() {
+        return true;
+    }
+
+    unsafe {
+        wasm_runtime_register_natives(
+            c"wasi_snapshot_preview1".as_ptr(),
+            symbols.0.as_mut_ptr(),
+            symbols.0.len() as u32,
+        )
+    }
+}
+
+// what the natives enforce for an instance, from the Wasi context of its module. In the
+// custom data of the instance, so shared with instances from `Instance::spawn()`
+#[derive(Debug, Default)]
+pub(crate) struct WasiState {
+    fs_usage: Option<Mutex<FsUsage>>,
+    virtual_files: Option<VirtualFiles>,
+    clock: Option<ClockSource>,
+}
+
+impl WasiState {
+    pub(crate) fn new(wasi_ctx: &WasiCtx) -> Self {
+        WasiState {
+            fs_usage: wasi_ctx
+                .fs_quota()
+                .map(|quota| Mutex::new(FsUsage::new(quota))),
+            virtual_files: wasi_ctx
+                .virtual_files()
+                .map(|map| VirtualFiles::new(Arc::clone(map))),
+            clock: wasi_ctx.clock().cloned(),
+        }
+    }
+
+    // the WASI environment is created again, by `Instance::reset()`
+    pub(crate) fn reset(&self) {
+        if let Some(fs_usage) = &self.fs_usage {
+            fs_usage
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear_fds();
+        }
+        if let Some(virtual_files) = &self.virtual_files {
+            virtual_files.clear();
+        }
+    }
+}
+
+unsafe fn fs_usage<'a>(exec_env: wasm_exec_env_t) -> Option<MutexGuard<'a, FsUsage>> {
+    let instance = wasm_runtime_get_module_inst(exec_env);
+    let fs_usage = Instance::wasi_state(instance)?.fs_usage.as_ref()?;
+    Some(fs_usage.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+// of the instance, whichever the fd is
+unsafe fn all_virtual_files<'a>(exec_env: wasm_exec_env_t) -> Option<&'a VirtualFiles> {
+    let instance = wasm_runtime_get_module_inst(exec_env);
+    Instance::wasi_state(instance)?.virtual_files.as_ref()
+}
+
+// the virtual files of the instance, if `fd` is one of them
+unsafe fn virtual_files<'a>(exec_env: wasm_exec_env_t, fd: u32) -> Option<&'a VirtualFiles> {
+    match VirtualFiles::is_virtual(fd) {
+        true => all_virtual_files(exec_env),
+        false => None,
+    }
+}
+
+// buffers of `iovs` in linear memory, as native addresses and lengths. `None` if any is
+// out of bounds
+unsafe fn buffers(
+    exec_env: wasm_exec_env_t,
+    iovs: *const CIoVec,
+    iovs_len: u32,
+) -> Option<Vec<(*mut u8, usize)>> {
+    let instance = wasm_runtime_get_module_inst(exec_env);
+    let size = mem::size_of::<CIoVec>() as u64 * iovs_len as u64;
+    if !wasm_runtime_validate_native_addr(instance, iovs as *mut c_void, size) {
+        return None;
+    }
+    (0..iovs_len as usize)
+        .map(|i| {
+            let iov = ptr::read_unaligned(iovs.add(i));
+            wasm_runtime_validate_app_addr(instance, iov.buf as u64, iov.buf_len as u64).then(
+                || {
+                    let buf = wasm_runtime_addr_app_to_native(instance, iov.buf as u64);
+                    (buf as *mut u8, iov.buf_len as usize)
+                },
+            )
+        })
+        .collect()
+}
+
+// `value` into linear memory at `ptr`, a native address
+unsafe fn store<T>(exec_env: wasm_exec_env_t, ptr: *mut T, value: T) -> u16 {
+    let instance = wasm_runtime_get_module_inst(exec_env);
+    if !wasm_runtime_validate_native_addr(instance, ptr as *mut c_void, mem::size_of::<T>() as u64)
+    {
+        return EFAULT;
+    }
+    ptr::write_unaligned(ptr, value);
+    0
+}
+
+fn errno_of(result: Result<(), u16>) -> u32 {
+    result.err().unwrap_or(0) as u32
+}
+
+unsafe fn read_virtual(
+    exec_env: wasm_exec_env_t,
+    files: &VirtualFiles,
+    fd: u32,
+    iovs: *const CIoVec,
+    iovs_len: u32,
+    offset: Option<u64>,
+    nread: *mut u32,
+) -> u32 {
+    let Some(buffers) = buffers(exec_env, iovs, iovs_len) else {
+        return EFAULT as u32;
+    };
+    let len = buffers.iter().map(|(_, len)| len).sum();
+    let read = match files.read(fd, len, offset) {
+        Ok(read) => read,
+        Err(errno) => return errno as u32,
+    };
+
+    let mut rest = read.as_slice();
+    for (buf, len) in buffers {
+        let n = len.min(rest.len());
+        ptr::copy_nonoverlapping(rest.as_ptr(), buf, n);
+        rest = &rest[n..];
+    }
+    store(exec_env, nread, read.len() as u32) as u32
+}
+
+unsafe fn write_virtual(
+    exec_env: wasm_exec_env_t,
+    files: &VirtualFiles,
+    fd: u32,
+    iovs: *const CIoVec,
+    iovs_len: u32,
+    offset: Option<u64>,
+    nwritten: *mut u32,
+) -> u32 {
+    let Some(buffers) = buffers(exec_env, iovs, iovs_len) else {
+        return EFAULT as u32;
+    };
+    let mut content = Vec::new();
+    for (buf, len) in buffers {
+        content.extend_from_slice(std::slice::from_raw_parts(buf, len));
+    }
+
+    // counted against the quota as writes to real files
+    let reserved = content.len() as u64;
+    if let Some(mut usage) = fs_usage(exec_env) {
+        if let Err(errno) = usage.reserve_bytes(reserved) {
+            return errno as u32;
+        }
+    }
+    let (written, errno) = match files.write(fd, &content, offset) {
+        Ok(written) => (written as u64, store(exec_env, nwritten, written as u32)),
+        Err(errno) => (0, errno),
+    };
+    if let Some(mut usage) = fs_usage(exec_env) {
+        usage.release_bytes(reserved - written);
+    }
+    errno as u32
+}
+
+fn filestat(size: u64) -> Filestat {
+    Filestat {
+        filetype: FILETYPE_REGULAR_FILE,
+        nlink: 1,
+        size,
+        ..Filestat::default()
+    }
+}
+
+// the clock of the instance, for `clock_id` of realtime, monotonic, process and thread
+// cputime. Others are left to libc-wasi to reject
+unsafe fn clock<'a>(exec_env: wasm_exec_env_t, clock_id: u32) -> Option<&'a ClockSource> {
+    if clock_id > 3 {
+        return None;
+    }
+    let instance = wasm_runtime_get_module_inst(exec_env);
+    Instance::wasi_state(instance)?.clock.as_ref()
+}
+
+unsafe extern "C" fn clock_time_get(
+    exec_env: wasm_exec_env_t,
+    clock_id: u32,
+    precision: u64,
+    time: *mut u64,
+) -> u32 {
+    match clock(exec_env, clock_id) {
+        Some(clock) => store(exec_env, time, clock.now()) as u32,
+        None => {
+            libc_wasi::<ClockTimeGet>("clock_time_get")(exec_env, clock_id, precision, time) as u32
+        }
+    }
+}
+
+unsafe extern "C" fn clock_res_get(
+    exec_env: wasm_exec_env_t,
+    clock_id: u32,
+    resolution: *mut u64,
+) -> u32 {
+    match clock(exec_env, clock_id) {
+        // in nanoseconds
+        Some(_) => store(exec_env, resolution, 1) as u32,
+        None => libc_wasi::<ClockResGet>("clock_res_get")(exec_env, clock_id, resolution) as u32,
+    }
+}
+
+unsafe extern "C" fn fd_close(exec_env: wasm_exec_env_t, fd: u32) -> u32 {
+    let errno = match virtual_files(exec_env, fd) {
+        Some(files) => files.close(fd).err().unwrap_or(0),
+        None => libc_wasi::<FdClose>("fd_close")(exec_env, fd),
+    };
+    if errno == 0 {
+        if let Some(mut usage) = fs_usage(exec_env) {
+            usage.close_fd(fd);
+        }
+    }
+    errno as u32
+}
+
+unsafe extern "C" fn fd_renumber(exec_env: wasm_exec_env_t, from: u32, to: u32) -> u32 {
+    if VirtualFiles::is_virtual(from) || VirtualFiles::is_virtual(to) {
+        return ENOTSUP as u32;
+    }
+
+    let errno = libc_wasi::<FdRenumber>("fd_renumber")(exec_env, from, to);
+    if errno == 0 {
+        if let Some(mut usage) = fs_usage(exec_env) {
+            usage.renumber_fd(from, to);
+        }
+    }
+    errno as u32
+}
+
+// reserve bytes of a write to `fd`. `None` if the write isn't counted, or is left to
+// libc-wasi to reject
+unsafe fn reserve_write(
+    exec_env: wasm_exec_env_t,
+    fd: u32,
+    iovs: *const CIoVec,
+    iovs_len: u32,
+) -> Option<Result<u64, u16>> {
+    // stdin, stdout and stderr
+    if fd <= 2 {
+        return None;
+    }
+    let mut usage = fs_usage(exec_env)?;
+    let size = mem::size_of::<CIoVec>() as u64 * iovs_len as u64;
+    let instance = wasm_runtime_get_module_inst(exec_env);
+    if !wasm_runtime_validate_native_addr(instance, iovs as *mut c_void, size) {
+        return None;
+    }
+
+    let bytes = (0..iovs_len as usize)
+        .map(|i| ptr::read_unaligned(iovs.add(i)).buf_len as u64)
+        .sum();
+    Some(usage.reserve_bytes(bytes).map(|()| bytes))
+}
+
+// give back what a write reserved but didn't write
+unsafe fn settle_write(exec_env: wasm_exec_env_t, reserved: u64, errno: u16, nwritten: *mut u32) {
+    // libc-wasi has checked `nwritten` if it succeeded
+    let written = match errno {
+        0 => ptr::read_unaligned(nwritten) as u64,
+        _ => 0,
+    };
+    if let Some(mut usage) = fs_usage(exec_env) {
+        usage.release_bytes(reserved.saturating_sub(written));
+    }
+}
+
+unsafe extern "C" fn fd_read(
+    exec_env: wasm_exec_env_t,
+    fd: u32,
+    iovs: *const CIoVec,
+    iovs_len: u32,
+    nread: *mut u32,
+) -> u32 {
+    match virtual_files(exec_env, fd) {
+        Some(files) => read_virtual(exec_env, files, fd, iovs, iovs_len, None, nread),
+        None => libc_wasi::<FdRead>("fd_read")(exec_env, fd, iovs, iovs_len, nread) as u32,
+    }
+}
+
+unsafe extern "C" fn fd_pread(
+    exec_env: wasm_exec_env_t,
+    fd: u32,
+    iovs: *const CIoVec,
+    iovs_len: u32,
+    offset: u64,
+    nread: *mut u32,
+) -> u32 {
+    match virtual_files(exec_env, fd) {
+        Some(files) => read_virtual(exec_env, files, fd, iovs, iovs_len, Some(offset), nread),
+        None => {
+            libc_wasi::<FdPread>("fd_pread")(exec_env, fd, iovs, iovs_len, offset, nread) as u32
+        }
+    }
+}
+
+unsafe extern "C" fn fd_write(
+    exec_env: wasm_exec_env_t,
+    fd: u32,
+    iovs: *const CIoVec,
+    iovs_len: u32,
+    nwritten: *mut u32,
+) -> u32 {
+    if let Some(files) = virtual_files(exec_env, fd) {
+        return write_virtual(exec_env, files, fd, iovs, iovs_len, None, nwritten);
+    }
+
+    let write = || libc_wasi::<FdWrite>("fd_write")(exec_env, fd, iovs, iovs_len, nwritten);
+    match reserve_write(exec_env, fd, iovs, iovs_len) {
+        None => write() as u32,
+        Some(Err(errno)) => errno as u32,
+        Some(Ok(reserved)) => {
+            let errno = write();
+            settle_write(exec_env, reserved, errno, nwritten);
+            errno as u32
+        }
+    }
+}
+
+unsafe extern "C" fn fd_pwrite(
+    exec_env: wasm_exec_env_t,
+    fd: u32,
+    iovs: *const CIoVec,
+    iovs_len: u32,
+    offset: u64,
+    nwritten: *mut u32,
+) -> u32 {
+    if let Some(files) = virtual_files(exec_env, fd) {
+        return write_virtual(exec_env, files, fd, iovs, iovs_len, Some(offset), nwritten);
+    }
+
+    let write =
+        || libc_wasi::<FdPwrite>("fd_pwrite")(exec_env, fd, iovs, iovs_len, offset, nwritten);
+    match reserve_write(exec_env, fd, iovs, iovs_len) {
+        None => write() as u32,
+        Some(Err(errno)) => errno as u32,
+        Some(Ok(reserved)) => {
+            let errno = write();
+            settle_write(exec_env, reserved, errno, nwritten);
+            errno as u32
+        }
+    }
+}
+
+unsafe extern "C" fn fd_seek(
+    exec_env: wasm_exec_env_t,
+    fd: u32,
+    offset: i64,
+    whence: u32,
+    new_offset: *mut u64,
+) -> u32 {
+    match virtual_files(exec_env, fd) {
+        Some(files) => match files.seek(fd, offset, whence) {
+            Ok(position) => store(exec_env, new_offset, position) as u32,
+            Err(errno) => errno as u32,
+        },
+        None => libc_wasi::<FdSeek>("fd_seek")(exec_env, fd, offset, whence, new_offset) as u32,
+    }
+}
+
+unsafe extern "C" fn fd_tell(exec_env: wasm_exec_env_t, fd: u32, offset: *mut u64) -> u32 {
+    match virtual_files(exec_env, fd) {
+        Some(files) => match files.seek(fd, 0, 1) {
+            Ok(position) => store(exec_env, offset, position) as u32,
+            Err(errno) => errno as u32,
+        },
+        None => libc_wasi::<FdTell>("fd_tell")(exec_env, fd, offset) as u32,
+    }
+}
+
+unsafe extern "C" fn fd_sync(exec_env: wasm_exec_env_t, fd: u32) -> u32 {
+    match virtual_files(exec_env, fd) {
+        Some(files) => errno_of(files.sync(fd)),
+        None => libc_wasi::<FdSync>("fd_sync")(exec_env, fd) as u32,
+    }
+}
+
+unsafe extern "C" fn fd_filestat_get(
+    exec_env: wasm_exec_env_t,
+    fd: u32,
+    buf: *mut Filestat,
+) -> u32 {
+    match virtual_files(exec_env, fd) {
+        Some(files) => match files.size(fd) {
+            Ok(size) => store(exec_env, buf, filestat(size)) as u32,
+            Err(errno) => errno as u32,
+        },
+        None => libc_wasi::<FdFilestatGet>("fd_filestat_get")(exec_env, fd, buf) as u32,
+    }
+}
+
+unsafe extern "C" fn fd_fdstat_get(exec_env: wasm_exec_env_t, fd: u32, buf: *mut Fdstat) -> u32 {
+    let Some(files) = virtual_files(exec_env, fd) else {
+        return libc_wasi::<FdFdstatGet>("fd_fdstat_get")(exec_env, fd, buf) as u32;
+    };
+    match files.fdstat(fd) {
+        Ok((append, rights)) => {
+            let fdstat = Fdstat {
+                filetype: FILETYPE_REGULAR_FILE,
+                flags: if append { FDFLAG_APPEND } else { 0 },
+                rights_base: rights,
+                rights_inheriting: 0,
+            };
+            store(exec_env, buf, fdstat) as u32
+        }
+        Err(errno) => errno as u32,
+    }
+}
+
+unsafe extern "C" fn path_filestat_get(
+    exec_env: wasm_exec_env_t,
+    fd: u32,
+    flags: u32,
+    path: *const c_char,
+    path_len: u32,
+    buf: *mut Filestat,
+) -> u32 {
+    // WAMR has checked the path
+    let size = all_virtual_files(exec_env).and_then(|files| {
+        files.path_size(
+            fd,
+            std::slice::from_raw_parts(path as *const u8, path_len as usize),
+        )
+    });
+    match size {
+        Some(Ok(size)) => store(exec_env, buf, filestat(size)) as u32,
+        Some(Err(errno)) => errno as u32,
+        None => libc_wasi::<PathFilestatGet>("path_filestat_get")(
+            exec_env, fd, flags, path, path_len, buf,
+        ) as u32,
+    }
+}
+
+// open a virtual file, counted against the quota as real ones. `None` if `path` isn't one
+#[allow(clippy::too_many_arguments)]
+unsafe fn open_virtual(
+    exec_env: wasm_exec_env_t,
+    files: &VirtualFiles,
+    dirfd: u32,
+    path: &[u8],
+    oflags: u32,
+    fs_rights_base: u64,
+    fs_flags: u32,
+    fd: *mut u32,
+) -> Option<u16> {
+    let virtual_fd = match files.open(dirfd, path, oflags, fs_rights_base, fs_flags)? {
+        Ok(virtual_fd) => virtual_fd,
+        Err(errno) => return Some(errno),
+    };
+
+    let mut usage = fs_usage(exec_env);
+    if let Some(Err(errno)) = usage.as_mut().map(|usage| usage.reserve_fd()) {
+        let _ = files.close(virtual_fd);
+        return Some(errno);
+    }
+    let errno = store(exec_env, fd, virtual_fd);
+    if errno != 0 {
+        let _ = files.close(virtual_fd);
+    }
+    if let Some(usage) = usage.as_mut() {
+        usage.settle_fd((errno == 0).then_some(virtual_fd));
+    }
+    Some(errno)
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "C" fn path_open(
+    exec_env: wasm_exec_env_t,
+    dirfd: u32,
+    dirflags: u32,
+    path: *const c_char,
+    path_len: u32,
+    oflags: u32,
+    fs_rights_base: u64,
+    fs_rights_inheriting: u64,
+    fs_flags: u32,
+    fd: *mut u32,
+) -> u32 {
+    let original = libc_wasi::<PathOpen>("path_open");
+    let open = |oflags: u32, fs_rights_base: u64, fs_rights_inheriting: u64, fs_flags: u32| {
+        original(
+            exec_env,
+            dirfd,
+            dirflags,
+            path,
+            path_len,
+            oflags,
+            fs_rights_base,
+            fs_rights_inheriting,
+            fs_flags,
+            fd,
+        )
+    };
+
+    if let Some(files) = all_virtual_files(exec_env) {
+        // WAMR has checked the path
+        let path = std::slice::from_raw_parts(path as *const u8, path_len as usize);
+        let opened = open_virtual(
+            exec_env,
+            files,
+            dirfd,
+            path,
+            oflags,
+            fs_rights_base,
+            fs_flags,
+            fd,
+        );
+        if let Some(errno) = opened {
+            return errno as u32;
+        }
+    }
+
+    let limits_files = match fs_usage(exec_env) {
+        None => return open(oflags, fs_rights_base, fs_rights_inheriting, fs_flags) as u32,
+        Some(mut usage) => match usage.reserve_fd() {
+            Ok(()) => usage.quota().limits_files(),
+            Err(errno) => return errno as u32,
+        },
+    };
+
+    let open = |oflags: u32| open(oflags, fs_rights_base, fs_rights_inheriting, fs_flags);
+    let errno = match limits_files && oflags & O_CREAT != 0 {
+        true => create_counted(exec_env, oflags, open),
+        false => open(oflags),
+    };
+    let mut usage = fs_usage(exec_env).unwrap();
+    usage.settle_fd((errno == 0).then(|| ptr::read_unaligned(fd)));
+    errno as u32
+}
+
+// open with `O_CREAT`, and count the file against the quota only if it is created. With
+// `O_EXCL` to tell, so others creating or removing it in the meantime don't fool the count
+unsafe fn create_counted(exec_env: wasm_exec_env_t, oflags: u32, open: impl Fn(u32) -> u16) -> u16 {
+    loop {
+        let reserved = fs_usage(exec_env).unwrap().reserve_file();
+        match reserved {
+            Ok(()) => {
+                let errno = open(oflags | O_EXCL);
+                if errno == 0 {
+                    return 0;
+                }
+                fs_usage(exec_env).unwrap().release_file();
+                if errno != EEXIST || oflags & O_EXCL != 0 {
+                    return errno;
+                }
+            }
+            Err(errno) if oflags & O_EXCL != 0 => return errno,
+            Err(_) => {}
+        }
+
+        // it exists, or there is no quota left to create it
+        match (open(oflags & !O_CREAT), reserved) {
+            // removed in the meantime
+            (ENOENT, Ok(())) => continue,
+            (ENOENT, Err(errno)) => return errno,
+            (errno, _) => return errno,
+        }
+    }
+}
+
+// `create` a file, directory or link, counted against the quota
+unsafe fn create_file(exec_env: wasm_exec_env_t, create: impl FnOnce() -> u16) -> u32 {
+    match fs_usage(exec_env) {
+        Some(mut usage) if usage.quota().limits_files() => {
+            if let Err(errno) = usage.reserve_file() {
+                return errno as u32;
+            }
+        }
+        _ => return create() as u32,
+    }
+
+    let errno = create();
+    if errno != 0 {
+        if let Some(mut usage) = fs_usage(exec_env) {
+            usage.release_file();
+        }
+    }
+    errno as u32
+}
+
+// `resize` a file up to `size` bytes, counted against the quota as written. What the file
+// had isn't known, so all of them are
+unsafe fn resize_file(exec_env: wasm_exec_env_t, size: u64, resize: impl FnOnce() -> u16) -> u32 {
+    if let Some(mut usage) = fs_usage(exec_env) {
+        if let Err(errno) = usage.reserve_bytes(size) {
+            return errno as u32;
+        }
+    }
+
+    let errno = resize();
+    if errno != 0 {
+        if let Some(mut usage) = fs_usage(exec_env) {
+            usage.release_bytes(size);
+        }
+    }
+    errno as u32
+}
+
+unsafe extern "C" fn path_create_directory(
+    exec_env: wasm_exec_env_t,
+    fd: u32,
+    path: *const c_char,
+    path_len: u32,
+) -> u32 {
+    create_file(exec_env, || {
+        libc_wasi::<PathCreateDirectory>("path_create_directory")(exec_env, fd, path, path_len)
+    })
+}
+
+unsafe extern "C" fn path_symlink(
+    exec_env: wasm_exec_env_t,
+    old_path: *const c_char,
+    old_path_len: u32,
+    fd: u32,
+    new_path: *const c_char,
+    new_path_len: u32,
+) -> u32 {
+    create_file(exec_env, || {
+        libc_wasi::<PathSymlink>("path_symlink")(
+            exec_env,
+            old_path,
+            old_path_len,
+            fd,
+            new_path,
+            new_path_len,
+        )
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "C" fn path_link(
+    exec_env: wasm_exec_env_t,
+    old_fd: u32,
+    old_flags: u32,
+    old_path: *const c_char,
+    old_path_len: u32,
+    new_fd: u32,
+    new_path: *const c_char,
+    new_path_len: u32,
+) -> u32 {
+    create_file(exec_env, || {
+        libc_wasi::<PathLink>("path_link")(
+            exec_env,
+            old_fd,
+            old_flags,
+            old_path,
+            old_path_len,
+            new_fd,
+            new_path,
+            new_path_len,
+        )
+    })
+}
+
+unsafe extern "C" fn fd_allocate(exec_env: wasm_exec_env_t, fd: u32, offset: u64, len: u64) -> u32 {
+    resize_file(exec_env, len, || {
+        libc_wasi::<FdAllocate>("fd_allocate")(exec_env, fd, offset, len)
+    })
+}
+
+unsafe extern "C" fn fd_filestat_set_size(exec_env: wasm_exec_env_t, fd: u32, size: u64) -> u32 {
+    resize_file(exec_env, size, || {
+        libc_wasi::<FdFilestatSetSize>("fd_filestat_set_size")(exec_env, fd, size)
+    })
+}
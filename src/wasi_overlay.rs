@@ -0,0 +1,186 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! preopens which instances share the content of, but write on their own.
+//! See `WasiCtxBuilder::pre_open_overlay()`
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::wasi_staged_dir::MaterializedDir;
+
+/// where the writable layer of an overlay of an instance is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlayLayer {
+    /// the temporary directory of the host, like `/tmp`
+    #[default]
+    TempDir,
+    /// the memory-backed `/dev/shm`, or the temporary directory without it
+    Memory,
+}
+
+// a read-only base directory, and where guests see it
+#[derive(Debug, Clone)]
+pub(crate) struct Overlay {
+    base: PathBuf,
+    mapped_path: String,
+    layer: OverlayLayer,
+}
+
+impl Overlay {
+    pub(crate) fn new(base: &str, mapped_path: &str, layer: OverlayLayer) -> Self {
+        Overlay {
+            base: PathBuf::from(base),
+            mapped_path: String::from(mapped_path),
+            layer,
+        }
+    }
+
+    pub(crate) fn mapped_path(&self) -> &str {
+        &self.mapped_path
+    }
+
+    // the writable layer of a new instance, with the content of the base.
+    // WAMR doesn't look up paths through layers, so the base is copied up front
+    pub(crate) fn materialize(&self) -> io::Result<MaterializedDir> {
+        let layer = MaterializedDir::create(self.layer == OverlayLayer::Memory)?;
+        copy_tree(&self.base, layer.path())?;
+        Ok(layer)
+    }
+}
+
+// directories and regular files. Symbolic links are skipped, since they may point out of
+// the base
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if file_type.is_dir() {
+            fs::create_dir(&target)?;
+            copy_tree(&entry.path(), &target)?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instance::Instance, module::Module, runtime::Runtime, wasi_context::WasiCtxBuilder,
+        wasi_staged_dir::StagedDir,
+    };
+
+    #[test]
+    fn test_overlay_layer() {
+        let base = StagedDir::new()
+            .file("assets/a.txt", "a")
+            .file("b.txt", "b")
+            .materialize()
+            .unwrap();
+        let overlay = Overlay::new(
+            base.path().to_str().unwrap(),
+            "/assets",
+            OverlayLayer::Memory,
+        );
+
+        let layer = overlay.materialize().unwrap();
+        assert_eq!(
+            fs::read_to_string(layer.path().join("assets/a.txt")).unwrap(),
+            "a"
+        );
+        fs::write(layer.path().join("b.txt"), "changed").unwrap();
+        assert_eq!(fs::read_to_string(base.path().join("b.txt")).unwrap(), "b");
+
+        let another = overlay.materialize().unwrap();
+        assert_eq!(
+            fs::read_to_string(another.path().join("b.txt")).unwrap(),
+            "b"
+        );
+    }
+
+    #[test]
+    fn test_wasi_overlay() {
+        let runtime = Runtime::new().unwrap();
+        let mut module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_read"
+                (func $fd_read (param i32 i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+              (memory (export "memory") 1)
+              ;; an iovec of 8 bytes at 64
+              (data (i32.const 0) "\40\00\00\00\08\00\00\00")
+              (data (i32.const 32) "state.txt")
+              (func $open (param $oflags i32) (param $rights i64) (result i32)
+                (drop
+                  (call $path_open (i32.const 3) (i32.const 0) (i32.const 32) (i32.const 9)
+                    (local.get $oflags) (local.get $rights) (i64.const 0) (i32.const 0)
+                    (i32.const 16)))
+                (i32.load (i32.const 16))
+              )
+              ;; overwrite state.txt with the 8 bytes at 64
+              (func (export "write") (param i64)
+                (local $fd i32)
+                (i64.store (i32.const 64) (local.get 0))
+                ;; O_TRUNC, FD_WRITE
+                (local.set $fd (call $open (i32.const 8) (i64.const 64)))
+                (drop (call $fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 20)))
+                (drop (call $fd_close (local.get $fd)))
+              )
+              ;; the first 8 bytes of state.txt
+              (func (export "read") (result i64)
+                (local $fd i32)
+                ;; FD_READ
+                (local.set $fd (call $open (i32.const 0) (i64.const 2)))
+                (drop (call $fd_read (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 20)))
+                (drop (call $fd_close (local.get $fd)))
+                (i64.load (i32.const 64))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let base = StagedDir::new()
+            .file("state.txt", 1i64.to_le_bytes())
+            .materialize()
+            .unwrap();
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .pre_open_overlay(
+                        base.path().to_str().unwrap(),
+                        "/state",
+                        OverlayLayer::TempDir,
+                    )
+                    .build(),
+            )
+            .unwrap();
+
+        let first = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let second = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        first.call("write").arg(2i64).invoke().unwrap();
+
+        let read: i64 = first.call("read").invoke_typed().unwrap();
+        assert_eq!(read, 2);
+        let read: i64 = second.call("read").invoke_typed().unwrap();
+        assert_eq!(read, 1);
+        assert_eq!(
+            fs::read(base.path().join("state.txt")).unwrap(),
+            1i64.to_le_bytes()
+        );
+    }
+}
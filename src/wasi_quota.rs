@@ -0,0 +1,344 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! limits on what an instance does to the host filesystem via WASI. set them via
+//! `WasiCtxBuilder::fs_quota()`
+
+use std::collections::HashSet;
+
+use crate::wasi_natives::{EMFILE, ENOSPC};
+
+/// how much each instance of a module may write, create and keep open via WASI. A call
+/// beyond fails with `ENOSPC` or `EMFILE` for the guest, without touching the filesystem
+///
+/// ```ignore
+/// let wasi_ctx = WasiCtxBuilder::new()
+///     .set_pre_open_path(vec!["./scratch"], vec![])
+///     .fs_quota(FsQuota::new().bytes_written(16 << 20).files(100).open_fds(16))
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsQuota {
+    bytes_written: Option<u64>,
+    files: Option<u32>,
+    open_fds: Option<u32>,
+}
+
+impl FsQuota {
+    /// no limit
+    pub fn new() -> Self {
+        FsQuota::default()
+    }
+
+    /// at most `bytes` written by `fd_write` and `fd_pwrite` during the lifetime of the
+    /// instance, overwrites included. Writes to stdin, stdout and stderr are not counted.
+    /// A write beyond fails as a whole with `ENOSPC`
+    ///
+    /// `fd_allocate` counts `len`, and `fd_filestat_set_size` the new size, as written,
+    /// whatever the file had.
+    pub fn bytes_written(mut self, bytes: u64) -> Self {
+        self.bytes_written = Some(bytes);
+        self
+    }
+
+    /// at most `files` files, directories and links created by `path_open`,
+    /// `path_create_directory`, `path_symlink` and `path_link` during the lifetime of the
+    /// instance. Removing them doesn't give the quota back. One more fails with `ENOSPC`
+    pub fn files(mut self, files: u32) -> Self {
+        self.files = Some(files);
+        self
+    }
+
+    /// at most `fds` files and directories opened by `path_open` at the same time,
+    /// preopens and stdio excluded. One more fails with `EMFILE`
+    pub fn open_fds(mut self, fds: u32) -> Self {
+        self.open_fds = Some(fds);
+        self
+    }
+
+    pub(crate) fn limits_files(&self) -> bool {
+        self.files.is_some()
+    }
+}
+
+// what an instance has done against its quota. Reservations are made before calling into
+// WASI, and settled after
+#[derive(Debug)]
+pub(crate) struct FsUsage {
+    quota: FsQuota,
+    bytes_written: u64,
+    files: u32,
+    open_fds: HashSet<u32>,
+    // `path_open` in progress
+    opening: u32,
+}
+
+impl FsUsage {
+    pub(crate) fn new(quota: FsQuota) -> Self {
+        FsUsage {
+            quota,
+            bytes_written: 0,
+            files: 0,
+            open_fds: HashSet::new(),
+            opening: 0,
+        }
+    }
+
+    pub(crate) fn quota(&self) -> &FsQuota {
+        &self.quota
+    }
+
+    pub(crate) fn reserve_bytes(&mut self, bytes: u64) -> Result<(), u16> {
+        if let Some(limit) = self.quota.bytes_written {
+            if self.bytes_written.saturating_add(bytes) > limit {
+                return Err(ENOSPC);
+            }
+        }
+        self.bytes_written += bytes;
+        Ok(())
+    }
+
+    // give back what was reserved but not written
+    pub(crate) fn release_bytes(&mut self, bytes: u64) {
+        self.bytes_written = self.bytes_written.saturating_sub(bytes);
+    }
+
+    pub(crate) fn reserve_file(&mut self) -> Result<(), u16> {
+        if let Some(limit) = self.quota.files {
+            if self.files >= limit {
+                return Err(ENOSPC);
+            }
+        }
+        self.files += 1;
+        Ok(())
+    }
+
+    pub(crate) fn release_file(&mut self) {
+        self.files = self.files.saturating_sub(1);
+    }
+
+    pub(crate) fn reserve_fd(&mut self) -> Result<(), u16> {
+        if let Some(limit) = self.quota.open_fds {
+            if self.open_fds.len() as u32 + self.opening >= limit {
+                return Err(EMFILE);
+            }
+        }
+        self.opening += 1;
+        Ok(())
+    }
+
+    // `fd` is `None` if the open failed
+    pub(crate) fn settle_fd(&mut self, fd: Option<u32>) {
+        self.opening = self.opening.saturating_sub(1);
+        if let Some(fd) = fd {
+            self.open_fds.insert(fd);
+        }
+    }
+
+    pub(crate) fn close_fd(&mut self, fd: u32) {
+        self.open_fds.remove(&fd);
+    }
+
+    // `to` is closed, and `from` takes its place
+    pub(crate) fn renumber_fd(&mut self, from: u32, to: u32) {
+        self.open_fds.remove(&to);
+        if self.open_fds.remove(&from) {
+            self.open_fds.insert(to);
+        }
+    }
+
+    // all descriptors are gone with the WASI environment, in `Instance::reset()`
+    pub(crate) fn clear_fds(&mut self) {
+        self.open_fds.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instance::Instance, module::Module, runtime::Runtime, wasi_context::WasiCtxBuilder,
+        wasi_staged_dir::StagedDir,
+    };
+
+    #[test]
+    fn test_fs_usage() {
+        let mut usage = FsUsage::new(FsQuota::new().bytes_written(10).files(1).open_fds(2));
+
+        assert_eq!(usage.reserve_bytes(8), Ok(()));
+        usage.release_bytes(2);
+        assert_eq!(usage.reserve_bytes(5), Err(ENOSPC));
+        assert_eq!(usage.reserve_bytes(4), Ok(()));
+
+        assert_eq!(usage.reserve_file(), Ok(()));
+        assert_eq!(usage.reserve_file(), Err(ENOSPC));
+        usage.release_file();
+        assert_eq!(usage.reserve_file(), Ok(()));
+
+        assert_eq!(usage.reserve_fd(), Ok(()));
+        usage.settle_fd(Some(4));
+        assert_eq!(usage.reserve_fd(), Ok(()));
+        assert_eq!(usage.reserve_fd(), Err(EMFILE));
+        usage.settle_fd(None);
+        usage.renumber_fd(4, 5);
+        assert_eq!(usage.reserve_fd(), Ok(()));
+        usage.settle_fd(Some(6));
+        assert_eq!(usage.reserve_fd(), Err(EMFILE));
+        usage.close_fd(5);
+        assert_eq!(usage.reserve_fd(), Ok(()));
+    }
+
+    #[test]
+    fn test_wasi_fs_quota() {
+        let runtime = Runtime::new().unwrap();
+        let mut module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+              (memory (export "memory") 1)
+              ;; an iovec of 8 bytes at 64
+              (data (i32.const 0) "\40\00\00\00\08\00\00\00")
+              (data (i32.const 32) "a.txt")
+              (data (i32.const 48) "b.txt")
+              ;; open a file in the first preopen. return the fd, or the negative errno
+              (func (export "open") (param $path i32) (param $oflags i32) (result i32)
+                (local $errno i32)
+                ;; FD_WRITE
+                (local.set $errno
+                  (call $path_open (i32.const 3) (i32.const 0) (local.get $path) (i32.const 5)
+                    (local.get $oflags) (i64.const 64) (i64.const 0) (i32.const 0)
+                    (i32.const 16)))
+                (if (result i32) (local.get $errno)
+                  (then (i32.sub (i32.const 0) (local.get $errno)))
+                  (else (i32.load (i32.const 16))))
+              )
+              ;; write 8 bytes. return the errno
+              (func (export "write") (param $fd i32) (result i32)
+                (call $fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 20))
+              )
+              (func (export "close") (param $fd i32) (result i32)
+                (call $fd_close (local.get $fd))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let dir = StagedDir::new().file("a.txt", "").materialize().unwrap();
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .set_pre_open_path(vec![dir.path().to_str().unwrap()], vec![])
+                    .fs_quota(FsQuota::new().bytes_written(12).files(0).open_fds(1))
+                    .build(),
+            )
+            .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let open = |path: i32, oflags: i32| -> i32 {
+            instance
+                .call("open")
+                .args((path, oflags))
+                .invoke_typed()
+                .unwrap()
+        };
+        let call =
+            |name: &str, fd: i32| -> i32 { instance.call(name).arg(fd).invoke_typed().unwrap() };
+
+        // O_CREAT of an existing file
+        let fd = open(32, 1);
+        assert!(fd > 3);
+        assert_eq!(open(32, 0), -(EMFILE as i32));
+        assert_eq!(call("write", fd), 0);
+        assert_eq!(call("write", fd), ENOSPC as i32);
+        assert_eq!(call("close", fd), 0);
+
+        // O_CREAT of a new one
+        assert_eq!(open(48, 1), -(ENOSPC as i32));
+        assert!(!dir.path().join("b.txt").exists());
+        assert!(open(32, 0) > 3);
+    }
+
+    #[test]
+    fn test_wasi_fs_quota_resize_and_link() {
+        let runtime = Runtime::new().unwrap();
+        let mut module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_allocate"
+                (func $fd_allocate (param i32 i64 i64) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_filestat_set_size"
+                (func $fd_filestat_set_size (param i32 i64) (result i32)))
+              (import "wasi_snapshot_preview1" "path_symlink"
+                (func $path_symlink (param i32 i32 i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "path_link"
+                (func $path_link (param i32 i32 i32 i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 32) "a.txt")
+              (data (i32.const 48) "b.txt")
+              ;; a.txt with FD_ALLOCATE | FD_FILESTAT_SET_SIZE, at 16
+              (func (export "open") (result i32)
+                (call $path_open (i32.const 3) (i32.const 0) (i32.const 32) (i32.const 5)
+                  (i32.const 0) (i64.const 0x400100) (i64.const 0) (i32.const 0) (i32.const 16))
+              )
+              (func (export "allocate") (param $len i64) (result i32)
+                (call $fd_allocate (i32.load (i32.const 16)) (i64.const 0) (local.get $len))
+              )
+              (func (export "set_size") (param $size i64) (result i32)
+                (call $fd_filestat_set_size (i32.load (i32.const 16)) (local.get $size))
+              )
+              ;; b.txt to a.txt
+              (func (export "symlink") (result i32)
+                (call $path_symlink (i32.const 32) (i32.const 5) (i32.const 3) (i32.const 48)
+                  (i32.const 5))
+              )
+              (func (export "link") (result i32)
+                (call $path_link (i32.const 3) (i32.const 0) (i32.const 32) (i32.const 5)
+                  (i32.const 3) (i32.const 48) (i32.const 5))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let dir = StagedDir::new().file("a.txt", "").materialize().unwrap();
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .set_pre_open_path(vec![dir.path().to_str().unwrap()], vec![])
+                    .fs_quota(FsQuota::new().bytes_written(12).files(0))
+                    .build(),
+            )
+            .unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let call = |name: &str| -> i32 { instance.call(name).invoke_typed().unwrap() };
+        let resize = |name: &str, size: i64| -> i32 {
+            instance.call(name).arg(size).invoke_typed().unwrap()
+        };
+
+        assert_eq!(call("open"), 0);
+        assert_eq!(resize("set_size", 8), 0);
+        assert_eq!(resize("set_size", 8), ENOSPC as i32);
+        // shrinking costs nothing
+        assert_eq!(resize("set_size", 0), 0);
+        assert_eq!(resize("allocate", 4), 0);
+        assert_eq!(resize("allocate", 1), ENOSPC as i32);
+        assert_eq!(
+            std::fs::metadata(dir.path().join("a.txt")).unwrap().len(),
+            4
+        );
+
+        assert_eq!(call("symlink"), ENOSPC as i32);
+        assert_eq!(call("link"), ENOSPC as i32);
+        assert!(!dir.path().join("b.txt").exists());
+    }
+}
@@ -0,0 +1,227 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! directory trees built by the host, staged into private host directories as WASI
+//! preopens. See `WasiCtxBuilder::pre_open_staged_dir()`
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+static NEXT_DIR: AtomicU32 = AtomicU32::new(0);
+
+/// files by paths relative to the directory, like `config.toml` or `assets/logo.png`
+///
+/// Guests don't access it in memory. WAMR only preopens host directories, so
+/// `WasiCtxBuilder::try_build()` writes the tree into a private temporary directory,
+/// which only guests of the module see, and which is removed with the Wasi context. It is
+/// in `/dev/shm` if there is one, or in `std::env::temp_dir()`, on disk, otherwise. Writes
+/// of guests go there as well, and are not seen here
+///
+/// ```ignore
+/// let config = StagedDir::new()
+///     .file("config.toml", "threads = 4")
+///     .file("assets/logo.png", logo);
+/// let wasi_ctx = WasiCtxBuilder::new()
+///     .pre_open_staged_dir("/etc/app", config)
+///     .build();
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StagedDir {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl StagedDir {
+    pub fn new() -> Self {
+        StagedDir::default()
+    }
+
+    /// add a file, or replace the one of the same path. Directories on the path are
+    /// created as needed
+    ///
+    /// # Panics
+    ///
+    /// If `path` is empty, absolute, or has `.` or `..`
+    pub fn file(mut self, path: &str, contents: impl Into<Vec<u8>>) -> Self {
+        let path = Path::new(path);
+        assert!(
+            path.components().next().is_some()
+                && path
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_))),
+            "invalid path {:?} in a staged directory",
+            path
+        );
+        self.files.insert(path.to_path_buf(), contents.into());
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // write the tree into a new private directory
+    pub(crate) fn materialize(&self) -> io::Result<MaterializedDir> {
+        let dir = MaterializedDir::create(true)?;
+        for (path, contents) in &self.files {
+            let path = dir.path.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+        }
+        Ok(dir)
+    }
+}
+
+// a host directory of a `StagedDir`, or a layer of an overlay, removed when it is dropped
+#[derive(Debug)]
+pub(crate) struct MaterializedDir {
+    path: PathBuf,
+}
+
+impl MaterializedDir {
+    // an empty private directory, in `/dev/shm` if `in_memory` and there is one
+    pub(crate) fn create(in_memory: bool) -> io::Result<Self> {
+        Ok(MaterializedDir {
+            path: create_private_dir(in_memory)?,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for MaterializedDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn create_private_dir(in_memory: bool) -> io::Result<PathBuf> {
+    let shm = Path::new("/dev/shm");
+    let base = match in_memory && shm.is_dir() {
+        true => shm.to_path_buf(),
+        false => std::env::temp_dir(),
+    };
+
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    loop {
+        let path = base.join(format!(
+            "wamr-staged-dir-{}-{}",
+            process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        match builder.create(&path) {
+            Ok(()) => return Ok(path),
+            // left by a previous process of the same pid
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instance::Instance, module::Module, runtime::Runtime, wasi_context::WasiCtxBuilder,
+    };
+
+    #[test]
+    fn test_materialize() {
+        let dir = StagedDir::new()
+            .file("config.toml", "threads = 4")
+            .file("assets/logo.png", vec![0x89, 0x50])
+            .file("config.toml", "threads = 8");
+        assert_eq!(dir.len(), 2);
+
+        let materialized = dir.materialize().unwrap();
+        let path = materialized.path().to_path_buf();
+        assert_eq!(
+            fs::read_to_string(path.join("config.toml")).unwrap(),
+            "threads = 8"
+        );
+        assert_eq!(
+            fs::read(path.join("assets/logo.png")).unwrap(),
+            [0x89, 0x50]
+        );
+
+        // another one for another module
+        let another = dir.materialize().unwrap();
+        assert_ne!(another.path(), path);
+
+        drop(materialized);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_wasi_staged_dir() {
+        let runtime = Runtime::new().unwrap();
+        let mut module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_read"
+                (func $fd_read (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              ;; an iovec of 64 bytes at 64
+              (data (i32.const 0) "\40\00\00\00\40\00\00\00")
+              (data (i32.const 32) "conf/app.toml")
+              ;; read the file into 64. return the size, or the negative errno
+              (func (export "read_config") (result i32)
+                (local $errno i32)
+                ;; the first preopen, with the right to read
+                (local.set $errno
+                  (call $path_open (i32.const 3) (i32.const 0) (i32.const 32) (i32.const 13)
+                    (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 16)))
+                (if (local.get $errno) (then (return (i32.sub (i32.const 0) (local.get $errno)))))
+                (local.set $errno
+                  (call $fd_read (i32.load (i32.const 16)) (i32.const 0) (i32.const 1)
+                    (i32.const 20)))
+                (if (local.get $errno) (then (return (i32.sub (i32.const 0) (local.get $errno)))))
+                (i32.load (i32.const 20))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let dir = StagedDir::new().file("conf/app.toml", "threads = 4");
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .pre_open_staged_dir("/etc", dir)
+                    .try_build()
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let read: i32 = instance.call("read_config").invoke_typed().unwrap();
+        assert_eq!(read, 11);
+        let contents = instance.memory().unwrap().read_str(64, 11).unwrap();
+        assert_eq!(contents, "threads = 4");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_escaping_path() {
+        let _ = StagedDir::new().file("../etc/passwd", "");
+    }
+}
@@ -0,0 +1,370 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! pipes of WASI stdio, instead of the file descriptors of the host. capture guest prints
+//! via `WasiCtxBuilder::stdout()` and `WasiCtxBuilder::stderr()`, and feed guests via
+//! `WasiCtxBuilder::stdin()`
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread,
+};
+
+type Callback = Box<dyn FnMut(&[u8]) + Send>;
+
+enum Sink {
+    Buffer(Vec<u8>),
+    Callback(Callback),
+}
+
+struct PipeState {
+    sink: Sink,
+    // read ends of os pipes, whose write ends are stdio of guests
+    readers: Vec<File>,
+}
+
+impl PipeState {
+    // deliver what is available from `reader` without blocking.
+    // return `false` if all write ends are closed
+    fn drain(&mut self, reader: RawFd) -> bool {
+        let Some(index) = self.readers.iter().position(|r| r.as_raw_fd() == reader) else {
+            return false;
+        };
+        let mut buf = [0u8; 4096];
+        while readable(reader, 0) {
+            // readable, so it doesn't block
+            let read = match (&self.readers[index]).read(&mut buf) {
+                Ok(0) => return false,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            };
+            match &mut self.sink {
+                Sink::Buffer(contents) => contents.extend_from_slice(&buf[..read]),
+                Sink::Callback(callback) => callback(&buf[..read]),
+            }
+        }
+        true
+    }
+}
+
+// whether a read doesn't block, because of data or closed write ends.
+// `timeout` is in milliseconds, -1 to wait forever
+fn readable(fd: RawFd, timeout: i32) -> bool {
+    let mut poll_fd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        match unsafe { libc::poll(&mut poll_fd, 1, timeout) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            1 => return poll_fd.revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0,
+            _ => return false,
+        }
+    }
+}
+
+/// where guest output goes, a buffer or a Rust closure. Clones share the same destination,
+/// so keep one to read the buffer
+///
+/// A thread per stream delivers output in the background. `flush()` delivers what has been
+/// written right away, so output of a finished call is always there
+///
+/// ```ignore
+/// let stdout = Pipe::buffer();
+/// let wasi_ctx = WasiCtxBuilder::new()
+///     .stdout(stdout.clone())
+///     .stderr(Pipe::callback(|bytes| log::warn!("{}", String::from_utf8_lossy(bytes))))
+///     .build();
+/// module.set_wasi_context(wasi_ctx)?;
+/// ...
+/// instance.call("_start").invoke()?;
+/// let printed = stdout.contents();
+/// ```
+#[derive(Clone)]
+pub struct Pipe {
+    state: Arc<Mutex<PipeState>>,
+}
+
+impl fmt::Debug for Pipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        let sink = match &state.sink {
+            Sink::Buffer(_) => "buffer",
+            Sink::Callback(_) => "callback",
+        };
+        f.debug_struct("Pipe")
+            .field("sink", &sink)
+            .field("streams", &state.readers.len())
+            .finish()
+    }
+}
+
+impl Pipe {
+    /// collect output into a byte buffer. See `contents()`
+    pub fn buffer() -> Self {
+        Pipe::new(Sink::Buffer(Vec::new()))
+    }
+
+    /// stream output to `callback`, in chunks as they are written. It runs with the pipe
+    /// locked, so it should not touch the pipe
+    pub fn callback<F>(callback: F) -> Self
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        Pipe::new(Sink::Callback(Box::new(callback)))
+    }
+
+    fn new(sink: Sink) -> Self {
+        Pipe {
+            state: Arc::new(Mutex::new(PipeState {
+                sink,
+                readers: Vec::new(),
+            })),
+        }
+    }
+
+    /// deliver output which has been written, but not delivered by the background thread yet
+    pub fn flush(&self) {
+        let mut state = self.state();
+        let readers = state
+            .readers
+            .iter()
+            .map(AsRawFd::as_raw_fd)
+            .collect::<Vec<_>>();
+        for reader in readers {
+            state.drain(reader);
+        }
+    }
+
+    /// output collected so far, after a `flush()`. empty if it is a callback
+    pub fn contents(&self) -> Vec<u8> {
+        self.flush();
+        match &self.state().sink {
+            Sink::Buffer(contents) => contents.clone(),
+            Sink::Callback(_) => Vec::new(),
+        }
+    }
+
+    /// the write end of a new os pipe, which is delivered here. It fails if an os pipe
+    /// can't be created, like running out of file descriptors
+    pub(crate) fn attach(&self) -> io::Result<OwnedFd> {
+        let (reader, writer) = io::pipe()?;
+        let reader = File::from(OwnedFd::from(reader));
+        let fd = reader.as_raw_fd();
+        self.state().readers.push(reader);
+
+        // the read end is closed here only, so `fd` is never reused before
+        let state = Arc::clone(&self.state);
+        thread::spawn(move || loop {
+            let closed = !readable(fd, -1) || {
+                let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+                !state.drain(fd)
+            };
+            if closed {
+                let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+                state.readers.retain(|reader| reader.as_raw_fd() != fd);
+                break;
+            }
+        });
+
+        Ok(OwnedFd::from(writer))
+    }
+
+    fn state(&self) -> MutexGuard<'_, PipeState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// where a stdio stream of guests goes
+#[derive(Debug, Default)]
+pub(crate) enum Stdio<T> {
+    // the one of the host
+    #[default]
+    Inherit,
+    // reads see an EOF, and writes are discarded
+    Null,
+    Redirect(T),
+}
+
+impl<T> Stdio<T> {
+    // the file descriptor for WAMR, `None` for the one of the host
+    pub(crate) fn attach(
+        self,
+        redirect: impl FnOnce(T) -> io::Result<OwnedFd>,
+    ) -> io::Result<Option<OwnedFd>> {
+        match self {
+            Stdio::Inherit => Ok(None),
+            Stdio::Null => {
+                let null = File::options().read(true).write(true).open("/dev/null")?;
+                Ok(Some(OwnedFd::from(null)))
+            }
+            Stdio::Redirect(redirect_to) => redirect(redirect_to).map(Some),
+        }
+    }
+}
+
+// guest stdin from a Rust reader
+pub(crate) struct Input(Box<dyn Read + Send>);
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Input").finish_non_exhaustive()
+    }
+}
+
+impl Input {
+    pub(crate) fn new<R: Read + Send + 'static>(reader: R) -> Self {
+        Input(Box::new(reader))
+    }
+
+    /// the read end of a new os pipe, which a background thread fills with the reader.
+    /// The write end is closed at the end of the reader, so guests see an EOF. It fails if
+    /// an os pipe can't be created
+    pub(crate) fn attach(self) -> io::Result<OwnedFd> {
+        let (reader, mut writer) = io::pipe()?;
+        let mut input = self.0;
+        // fails once the read end is closed, like the module being dropped
+        thread::spawn(move || io::copy(&mut input, &mut writer));
+        Ok(OwnedFd::from(reader))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instance::Instance, module::Module, runtime::Runtime, wasi_context::WasiCtxBuilder,
+    };
+    use std::io::Write;
+
+    #[test]
+    fn test_pipe() {
+        let pipe = Pipe::buffer();
+        let mut writer = File::from(pipe.attach().unwrap());
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"world").unwrap();
+        assert_eq!(pipe.contents(), b"hello world");
+
+        // more than the capacity of an os pipe, delivered in the background
+        let large = vec![b'x'; 256 * 1024];
+        writer.write_all(&large).unwrap();
+        drop(writer);
+        assert_eq!(pipe.contents().len(), 11 + large.len());
+
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&chunks);
+        let pipe = Pipe::callback(move |bytes| sink.lock().unwrap().extend_from_slice(bytes));
+        File::from(pipe.attach().unwrap())
+            .write_all(b"streamed")
+            .unwrap();
+        pipe.flush();
+        assert_eq!(*chunks.lock().unwrap(), b"streamed");
+        assert!(pipe.contents().is_empty());
+    }
+
+    #[test]
+    fn test_input() {
+        let mut reader = File::from(Input::new(&b"line 1\nline 2\n"[..]).attach().unwrap());
+        let mut contents = String::new();
+        // until the EOF
+        reader.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "line 1\nline 2\n");
+    }
+
+    #[test]
+    fn test_wasi_stdio() {
+        let runtime = Runtime::new().unwrap();
+        let mut module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              ;; iovecs of "hello\n" and "oops"
+              (data (i32.const 0) "\10\00\00\00\06\00\00\00\20\00\00\00\04\00\00\00")
+              (data (i32.const 16) "hello\n")
+              (data (i32.const 32) "oops")
+              (func (export "print") (result i32)
+                (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 48)))
+                (call $fd_write (i32.const 2) (i32.const 8) (i32.const 1) (i32.const 48))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let stdout = Pipe::buffer();
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&errors);
+        let stderr = Pipe::callback(move |bytes| sink.lock().unwrap().extend_from_slice(bytes));
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .stdout(stdout.clone())
+                    .stderr(stderr.clone())
+                    .build(),
+            )
+            .unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let errno: i32 = instance.call("print").invoke_typed().unwrap();
+        assert_eq!(errno, 0);
+        assert_eq!(stdout.contents(), b"hello\n");
+        stderr.flush();
+        assert_eq!(*errors.lock().unwrap(), b"oops");
+    }
+
+    #[test]
+    fn test_wasi_stdin() {
+        let runtime = Runtime::new().unwrap();
+        let mut module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "fd_read"
+                (func $fd_read (param i32 i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              ;; an iovec of 64 bytes at 16
+              (data (i32.const 0) "\10\00\00\00\40\00\00\00")
+              ;; echo a read from stdin to stdout
+              (func (export "echo") (result i32)
+                (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+                (i32.store (i32.const 4) (i32.load (i32.const 8)))
+                (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                (i32.load (i32.const 4))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let stdout = Pipe::buffer();
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .stdin_bytes("ping")
+                    .stdout(stdout.clone())
+                    .build(),
+            )
+            .unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let read: i32 = instance.call("echo").invoke_typed().unwrap();
+        assert_eq!(read, 4);
+        assert_eq!(stdout.contents(), b"ping");
+        // the EOF
+        let read: i32 = instance.call("echo").invoke_typed().unwrap();
+        assert_eq!(read, 0);
+    }
+}
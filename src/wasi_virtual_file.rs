@@ -0,0 +1,533 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! files of guests which are Rust objects. See `WasiCtxBuilder::virtual_file()`
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    wasi_context::{
+        Rights, FD_FILESTAT_GET, FD_READ, FD_SEEK, FD_SYNC, FD_TELL, FD_WRITE, PATH_CREATE_FILE,
+    },
+    wasi_natives::{
+        EACCES, EBADF, EEXIST, EINVAL, EIO, ENOTCAPABLE, ENOTDIR, ENOTSUP, FDFLAG_APPEND, O_CREAT,
+        O_DIRECTORY, O_EXCL,
+    },
+};
+
+/// the content of a virtual file, like a `Cursor<Vec<u8>>`, a database blob, or something
+/// generating content on reads
+pub trait VirtualFile: Read + Write + Seek + Send {}
+
+impl<T: Read + Write + Seek + Send> VirtualFile for T {}
+
+// file descriptors of virtual files. Far beyond those of libc-wasi
+const FIRST_FD: u32 = 1 << 30;
+
+type SharedFile = Arc<Mutex<Box<dyn VirtualFile>>>;
+
+// virtual files of a Wasi context by guest paths, and guest paths of preopens to resolve
+// paths against, with their rights
+#[derive(Default)]
+pub(crate) struct VirtualFileMap {
+    files: HashMap<PathBuf, SharedFile>,
+    // by WASI file descriptors from 3
+    pre_opens: Vec<(PathBuf, Rights)>,
+}
+
+impl VirtualFileMap {
+    pub(crate) fn insert(&mut self, path: &str, file: Box<dyn VirtualFile>) {
+        self.files
+            .insert(normalize(Path::new(path)), Arc::new(Mutex::new(file)));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // directories of the files
+    pub(crate) fn dirs(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().filter_map(|path| path.parent())
+    }
+
+    // after all preopens are known
+    pub(crate) fn set_pre_opens(&mut self, pre_opens: Vec<(PathBuf, Rights)>) {
+        self.pre_opens = pre_opens
+            .iter()
+            .map(|(path, rights)| (normalize(path), *rights))
+            .collect();
+    }
+
+    // the virtual file at `path` relative to the preopen `dirfd`, and the rights of the preopen
+    fn find(&self, dirfd: u32, path: &[u8]) -> Option<(&SharedFile, Rights)> {
+        let (dir, rights) = self.pre_opens.get(dirfd.checked_sub(3)? as usize)?;
+        let path = Path::new(std::str::from_utf8(path).ok()?);
+        if path.has_root() {
+            return None;
+        }
+
+        let mut resolved = dir.clone();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::CurDir => {}
+                // guests never leave a preopen
+                Component::ParentDir if resolved != *dir => {
+                    resolved.pop();
+                }
+                _ => return None,
+            }
+        }
+        self.files.get(&resolved).map(|file| (file, *rights))
+    }
+}
+
+impl std::fmt::Debug for VirtualFileMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VirtualFileMap")
+            .field("files", &self.files.keys())
+            .field("pre_opens", &self.pre_opens)
+            .finish()
+    }
+}
+
+// without `.`, so `/data/./a` and `/data/a` are the same
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+// a virtual file opened by an instance. Each one has a position and rights of its own
+struct OpenFile {
+    file: SharedFile,
+    position: u64,
+    append: bool,
+    // `fs_rights_base` of `path_open`
+    rights: u64,
+}
+
+// virtual files opened by an instance
+#[derive(Debug)]
+pub(crate) struct VirtualFiles {
+    map: Arc<VirtualFileMap>,
+    open: Mutex<OpenFiles>,
+}
+
+struct OpenFiles {
+    files: HashMap<u32, OpenFile>,
+    next_fd: u32,
+}
+
+impl std::fmt::Debug for OpenFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenFiles")
+            .field("fds", &self.files.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl VirtualFiles {
+    pub(crate) fn new(map: Arc<VirtualFileMap>) -> Self {
+        VirtualFiles {
+            map,
+            open: Mutex::new(OpenFiles {
+                files: HashMap::new(),
+                next_fd: FIRST_FD,
+            }),
+        }
+    }
+
+    pub(crate) fn is_virtual(fd: u32) -> bool {
+        fd >= FIRST_FD
+    }
+
+    fn open_files(&self) -> MutexGuard<'_, OpenFiles> {
+        self.open.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // `None` if `path` isn't a virtual file. Rights beyond those of the preopen fail with
+    // `ENOTCAPABLE`, as libc-wasi does
+    pub(crate) fn open(
+        &self,
+        dirfd: u32,
+        path: &[u8],
+        oflags: u32,
+        rights: u64,
+        fs_flags: u32,
+    ) -> Option<Result<u32, u16>> {
+        let (file, pre_open_rights) = self.map.find(dirfd, path)?;
+        let mut needed = rights;
+        if oflags & O_CREAT != 0 {
+            needed |= PATH_CREATE_FILE;
+        }
+        if needed & !pre_open_rights.bits() != 0 {
+            return Some(Err(ENOTCAPABLE));
+        }
+        if oflags & O_DIRECTORY != 0 {
+            return Some(Err(ENOTDIR));
+        }
+        if oflags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL {
+            return Some(Err(EEXIST));
+        }
+
+        let mut open = self.open_files();
+        let fd = open.next_fd;
+        open.next_fd += 1;
+        open.files.insert(
+            fd,
+            OpenFile {
+                file: Arc::clone(file),
+                position: 0,
+                append: fs_flags & FDFLAG_APPEND as u32 != 0,
+                rights,
+            },
+        );
+        Some(Ok(fd))
+    }
+
+    // the size of the virtual file at `path`. `None` if it isn't one
+    pub(crate) fn path_size(&self, dirfd: u32, path: &[u8]) -> Option<Result<u64, u16>> {
+        let (file, _) = self.map.find(dirfd, path)?;
+        Some(size(&mut lock(file)))
+    }
+
+    pub(crate) fn close(&self, fd: u32) -> Result<(), u16> {
+        self.open_files().files.remove(&fd).map(|_| ()).ok_or(EBADF)
+    }
+
+    // `f` on `fd`, if it has any of `rights`
+    fn with<T>(
+        &self,
+        fd: u32,
+        rights: u64,
+        f: impl FnOnce(&mut OpenFile) -> Result<T, u16>,
+    ) -> Result<T, u16> {
+        let mut open = self.open_files();
+        let file = open.files.get_mut(&fd).ok_or(EBADF)?;
+        if rights != 0 && file.rights & rights == 0 {
+            return Err(ENOTCAPABLE);
+        }
+        f(file)
+    }
+
+    // up to `len` bytes from the position, or from `offset` without moving the position
+    pub(crate) fn read(&self, fd: u32, len: usize, offset: Option<u64>) -> Result<Vec<u8>, u16> {
+        self.with(fd, FD_READ, |open| {
+            let mut file = lock(&open.file);
+            let start = offset.unwrap_or(open.position);
+            file.seek(SeekFrom::Start(start)).map_err(errno)?;
+
+            let mut buf = vec![0; len];
+            let mut read = 0;
+            while read < len {
+                match file.read(&mut buf[read..]) {
+                    Ok(0) => break,
+                    Ok(n) => read += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(errno(e)),
+                }
+            }
+            buf.truncate(read);
+            if offset.is_none() {
+                open.position = start + read as u64;
+            }
+            Ok(buf)
+        })
+    }
+
+    // at the position, the end in the append mode, or `offset` without moving the position
+    pub(crate) fn write(&self, fd: u32, buf: &[u8], offset: Option<u64>) -> Result<usize, u16> {
+        self.with(fd, FD_WRITE, |open| {
+            let mut file = lock(&open.file);
+            let start = match (offset, open.append) {
+                (Some(offset), _) => SeekFrom::Start(offset),
+                (None, true) => SeekFrom::End(0),
+                (None, false) => SeekFrom::Start(open.position),
+            };
+            let start = file.seek(start).map_err(errno)?;
+            file.write_all(buf).map_err(errno)?;
+            if offset.is_none() {
+                open.position = start + buf.len() as u64;
+            }
+            Ok(buf.len())
+        })
+    }
+
+    // `whence` of `__wasi_whence_t`
+    pub(crate) fn seek(&self, fd: u32, offset: i64, whence: u32) -> Result<u64, u16> {
+        // telling the position is enough for seeking nowhere
+        let rights = match (offset, whence) {
+            (0, 1) => FD_SEEK | FD_TELL,
+            _ => FD_SEEK,
+        };
+        self.with(fd, rights, |open| {
+            let position = match whence {
+                0 => u64::try_from(offset).ok(),
+                1 => open.position.checked_add_signed(offset),
+                2 => size(&mut lock(&open.file))?.checked_add_signed(offset),
+                _ => None,
+            };
+            open.position = position.ok_or(EINVAL)?;
+            Ok(open.position)
+        })
+    }
+
+    pub(crate) fn size(&self, fd: u32) -> Result<u64, u16> {
+        self.with(fd, FD_FILESTAT_GET, |open| size(&mut lock(&open.file)))
+    }
+
+    // whether `fd` is in the append mode, and its rights
+    pub(crate) fn fdstat(&self, fd: u32) -> Result<(bool, u64), u16> {
+        self.with(fd, 0, |open| Ok((open.append, open.rights)))
+    }
+
+    pub(crate) fn sync(&self, fd: u32) -> Result<(), u16> {
+        self.with(fd, FD_SYNC, |open| lock(&open.file).flush().map_err(errno))
+    }
+
+    // all descriptors are gone with the WASI environment, in `Instance::reset()`
+    pub(crate) fn clear(&self) {
+        let mut open = self.open_files();
+        open.files.clear();
+        open.next_fd = FIRST_FD;
+    }
+}
+
+fn lock(file: &SharedFile) -> MutexGuard<'_, Box<dyn VirtualFile>> {
+    file.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn size(file: &mut Box<dyn VirtualFile>) -> Result<u64, u16> {
+    file.seek(SeekFrom::End(0)).map_err(errno)
+}
+
+fn errno(error: io::Error) -> u16 {
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::Unsupported => ENOTSUP,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instance::Instance, module::Module, runtime::Runtime, wasi_context::WasiCtxBuilder,
+    };
+    use std::io::Cursor;
+
+    const ALL: u64 = Rights::ALL.bits();
+
+    fn virtual_files() -> VirtualFiles {
+        let mut map = VirtualFileMap::default();
+        map.insert(
+            "/data/./greeting.txt",
+            Box::new(Cursor::new(b"hello".to_vec())),
+        );
+        map.set_pre_opens(vec![
+            (PathBuf::from("/tmp"), Rights::ALL),
+            (PathBuf::from("/data"), Rights::ALL),
+            (PathBuf::from("/data"), Rights::READ),
+        ]);
+        assert_eq!(map.dirs().collect::<Vec<_>>(), [Path::new("/data")]);
+        VirtualFiles::new(Arc::new(map))
+    }
+
+    #[test]
+    fn test_virtual_file_open() {
+        let files = virtual_files();
+        assert!(files.open(4, b"greeting.txt", 0, ALL, 0).is_some());
+        assert!(files.open(4, b"./sub/../greeting.txt", 0, ALL, 0).is_some());
+        assert!(files.open(3, b"greeting.txt", 0, ALL, 0).is_none());
+        assert!(files.open(4, b"../data/greeting.txt", 0, ALL, 0).is_none());
+        assert!(files.open(4, b"/data/greeting.txt", 0, ALL, 0).is_none());
+        assert_eq!(
+            files.open(4, b"greeting.txt", O_DIRECTORY, ALL, 0),
+            Some(Err(ENOTDIR))
+        );
+        assert_eq!(
+            files.open(4, b"greeting.txt", O_CREAT | O_EXCL, ALL, 0),
+            Some(Err(EEXIST))
+        );
+        assert_eq!(files.path_size(4, b"greeting.txt"), Some(Ok(5)));
+    }
+
+    #[test]
+    fn test_virtual_file_rights() {
+        let files = virtual_files();
+        // the read-only preopen
+        assert_eq!(
+            files.open(5, b"greeting.txt", 0, FD_READ | FD_WRITE, 0),
+            Some(Err(ENOTCAPABLE))
+        );
+        assert_eq!(
+            files.open(5, b"greeting.txt", O_CREAT, FD_READ, 0),
+            Some(Err(ENOTCAPABLE))
+        );
+
+        let fd = files
+            .open(5, b"greeting.txt", 0, FD_READ, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(files.fdstat(fd), Ok((false, FD_READ)));
+        assert_eq!(files.read(fd, 5, None).unwrap(), b"hello");
+        assert_eq!(files.write(fd, b"!", None), Err(ENOTCAPABLE));
+        assert_eq!(files.seek(fd, 0, 0), Err(ENOTCAPABLE));
+    }
+
+    #[test]
+    fn test_virtual_file_io() {
+        let files = virtual_files();
+        let first = files.open(4, b"greeting.txt", 0, ALL, 0).unwrap().unwrap();
+        let second = files
+            .open(4, b"greeting.txt", 0, ALL, FDFLAG_APPEND as u32)
+            .unwrap()
+            .unwrap();
+        assert!(VirtualFiles::is_virtual(first));
+        assert_ne!(first, second);
+
+        assert_eq!(files.read(first, 2, None).unwrap(), b"he");
+        assert_eq!(files.read(first, 2, Some(3)).unwrap(), b"lo");
+        assert_eq!(files.seek(first, 0, 1), Ok(2));
+
+        assert_eq!(files.write(second, b"!", None), Ok(1));
+        assert_eq!(files.size(first), Ok(6));
+        assert_eq!(files.read(first, 10, None).unwrap(), b"llo!");
+        assert_eq!(files.write(first, b"J", Some(0)), Ok(1));
+        assert_eq!(files.seek(first, -6, 2), Ok(0));
+        assert_eq!(files.read(first, 10, None).unwrap(), b"Jello!");
+        assert_eq!(files.seek(first, -1, 0), Err(EINVAL));
+
+        assert_eq!(files.close(first), Ok(()));
+        assert_eq!(files.close(first), Err(EBADF));
+        assert_eq!(files.read(first, 1, None), Err(EBADF));
+    }
+
+    #[test]
+    fn test_wasi_virtual_file() {
+        let runtime = Runtime::new().unwrap();
+        let mut module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_read"
+                (func $fd_read (param i32 i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_seek"
+                (func $fd_seek (param i32 i64 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              ;; an iovec of 5 bytes at 64
+              (data (i32.const 0) "\40\00\00\00\05\00\00\00")
+              (data (i32.const 32) "greeting.txt")
+              (data (i32.const 64) "HELLO")
+              ;; uppercase the file, and read it back into 64. return the size read
+              (func (export "shout") (result i32)
+                (local $fd i32)
+                ;; the first preopen, with FD_READ | FD_SEEK | FD_WRITE
+                (drop
+                  (call $path_open (i32.const 3) (i32.const 0) (i32.const 32) (i32.const 12)
+                    (i32.const 0) (i64.const 70) (i64.const 0) (i32.const 0) (i32.const 16)))
+                (local.set $fd (i32.load (i32.const 16)))
+                (drop (call $fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 20)))
+                (i64.store (i32.const 64) (i64.const 0))
+                (drop (call $fd_seek (local.get $fd) (i64.const 0) (i32.const 0) (i32.const 24)))
+                (drop (call $fd_read (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 20)))
+                (i32.load (i32.const 20))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .virtual_file(
+                        "/data/greeting.txt",
+                        Box::new(Cursor::new(b"hello".to_vec())),
+                    )
+                    .build(),
+            )
+            .unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let read: i32 = instance.call("shout").invoke_typed().unwrap();
+        assert_eq!(read, 5);
+        let contents = instance.memory().unwrap().read_str(64, 5).unwrap();
+        assert_eq!(contents, "HELLO");
+    }
+
+    #[test]
+    fn test_wasi_virtual_file_quota() {
+        use crate::wasi_quota::FsQuota;
+
+        let runtime = Runtime::new().unwrap();
+        let mut module = Module::from_wat(
+            &runtime,
+            r#"
+            (module
+              (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_fdstat_get"
+                (func $fd_fdstat_get (param i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "\40\00\00\00\05\00\00\00")
+              (data (i32.const 32) "greeting.txt")
+              (data (i32.const 64) "HELLO")
+              (func (export "open") (param $rights i64) (result i32)
+                (call $path_open (i32.const 3) (i32.const 0) (i32.const 32) (i32.const 12)
+                  (i32.const 0) (local.get $rights) (i64.const 0) (i32.const 0) (i32.const 16))
+              )
+              (func (export "write") (result i32)
+                (call $fd_write (i32.load (i32.const 16)) (i32.const 0) (i32.const 1) (i32.const 20))
+              )
+              ;; `rights_base` of `__wasi_fdstat_t` at 128
+              (func (export "rights") (result i64)
+                (drop (call $fd_fdstat_get (i32.load (i32.const 16)) (i32.const 128)))
+                (i64.load (i32.const 136))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        module
+            .set_wasi_context(
+                WasiCtxBuilder::new()
+                    .virtual_file(
+                        "/data/greeting.txt",
+                        Box::new(Cursor::new(b"hello".to_vec())),
+                    )
+                    .fs_quota(FsQuota::new().bytes_written(4).open_fds(1))
+                    .build(),
+            )
+            .unwrap();
+
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+        let rights = (FD_READ | FD_WRITE) as i64;
+        let errno: i32 = instance.call("open").arg(rights).invoke_typed().unwrap();
+        assert_eq!(errno, 0);
+        let reported: i64 = instance.call("rights").invoke_typed().unwrap();
+        assert_eq!(reported, rights);
+
+        let errno: i32 = instance.call("write").invoke_typed().unwrap();
+        assert_eq!(errno, crate::wasi_natives::ENOSPC as i32);
+        let errno: i32 = instance.call("open").arg(rights).invoke_typed().unwrap();
+        assert_eq!(errno, crate::wasi_natives::EMFILE as i32);
+    }
+}
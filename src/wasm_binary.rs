@@ -0,0 +1,1032 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! parse sections of a .wasm which WAMR doesn't expose.
+//! like the `name` custom section and declarations of a module
+
+use std::collections::HashMap;
+
+use crate::types::{MemoryType, TableType, ValType};
+
+const WASM_MAGIC_AND_VERSION: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+const CUSTOM_SECTION_ID: u8 = 0;
+const TYPE_SECTION_ID: u8 = 1;
+const IMPORT_SECTION_ID: u8 = 2;
+const FUNCTION_SECTION_ID: u8 = 3;
+const TABLE_SECTION_ID: u8 = 4;
+const MEMORY_SECTION_ID: u8 = 5;
+const GLOBAL_SECTION_ID: u8 = 6;
+const EXPORT_SECTION_ID: u8 = 7;
+const START_SECTION_ID: u8 = 8;
+const CODE_SECTION_ID: u8 = 10;
+// the order of non-custom sections, by ids. The tag section goes before the global one
+const SECTION_ORDER: [u8; 13] = [1, 2, 3, 4, 5, 13, 6, 7, 8, 9, 12, 10, 11];
+const FUNCTION_NAMES_SUBSECTION_ID: u8 = 1;
+
+const IMPORT_KIND_FUNC: u8 = 0;
+const IMPORT_KIND_TABLE: u8 = 1;
+const IMPORT_KIND_MEMORY: u8 = 2;
+const IMPORT_KIND_GLOBAL: u8 = 3;
+const IMPORT_KIND_TAG: u8 = 4;
+
+const EXPORT_KIND_GLOBAL: u8 = 3;
+
+const FUNC_TYPE_FORM: u8 = 0x60;
+const VAL_TYPE_I32: u8 = 0x7f;
+const REF_TYPE_EXTERNREF: u8 = 0x6f;
+// `(ref null ht)` and `(ref ht)` of the function-references proposal
+const REF_TYPE_NULLABLE: u8 = 0x63;
+const REF_TYPE_NON_NULL: u8 = 0x64;
+// a table with an initializer expression, followed by a reserved 0x00
+const TABLE_WITH_INIT_EXPR: u8 = 0x40;
+const OPCODE_END: u8 = 0x0b;
+const LIMITS_HAS_MAX: u8 = 0x01;
+const LIMITS_SHARED: u8 = 0x02;
+const LIMITS_MEMORY64: u8 = 0x04;
+const MEMARG_HAS_MEMORY_INDEX: u32 = 0x40;
+
+/// the global `guard_memory_grow()` exports. Once the host sets it to 1, a failed
+/// `memory.grow` of the default memory traps
+pub const MEMORY_GROW_TRAP_GLOBAL: &str = "__wamr_rust_sdk_memory_grow_trap";
+
+/// the maximum of a memory without a declared maximum, in pages
+pub const DEFAULT_MAX_MEMORY_PAGES: u32 = 65536;
+/// the maximum of a table without a declared maximum
+pub const DEFAULT_MAX_TABLE_SIZE: u32 = u32::MAX;
+
+/// declarations of a .wasm
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleInfo {
+    /// imported memories first, then defined memories
+    pub memories: Vec<MemoryType>,
+    /// imported tables first, then defined tables
+    pub tables: Vec<TableType>,
+    /// names of exported tables, with their indexes in `tables`
+    pub exported_tables: Vec<(String, u32)>,
+    /// the number of functions defined in the module, excluding imported functions
+    pub function_count: u32,
+    /// the number of imported functions. they come first in the function index space
+    pub imported_function_count: u32,
+    pub has_start_function: bool,
+    /// the number of all imports, including functions, tables, memories and globals
+    pub import_count: u32,
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        let byte = self.peek_u8()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn peek_u8(&self) -> Option<u8> {
+        self.buf.get(self.pos).copied()
+    }
+
+    fn read_leb_u32(&mut self) -> Option<u32> {
+        let mut result: u32 = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.read_u8()?;
+            result |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Some(result);
+            }
+        }
+        None
+    }
+
+    // a LEB128 of any width, like a s33 block type or an u64 offset
+    fn skip_leb(&mut self) -> Option<()> {
+        for _ in 0..10 {
+            if self.read_u8()? & 0x80 == 0 {
+                return Some(());
+            }
+        }
+        None
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.buf.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+
+    fn read_name(&mut self) -> Option<String> {
+        let len = self.read_leb_u32()? as usize;
+        let bytes = self.read_bytes(len)?;
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// return (min, max, shared)
+    fn read_limits(&mut self) -> Option<(u32, Option<u32>, bool)> {
+        let flags = self.read_u8()?;
+        let min = self.read_leb_u32()?;
+        let max = match flags & LIMITS_HAS_MAX {
+            0 => None,
+            _ => Some(self.read_leb_u32()?),
+        };
+        Some((min, max, flags & LIMITS_SHARED != 0))
+    }
+
+    fn read_table_type(&mut self) -> Option<TableType> {
+        let element = match self.read_u8()? {
+            REF_TYPE_EXTERNREF => ValType::ExternRef,
+            REF_TYPE_NULLABLE | REF_TYPE_NON_NULL => match self.read_heap_type()? {
+                REF_TYPE_EXTERNREF => ValType::ExternRef,
+                _ => ValType::FuncRef,
+            },
+            _ => ValType::FuncRef,
+        };
+        let (min, max, shared) = self.read_limits()?;
+        Some(TableType {
+            element,
+            shared,
+            min,
+            max: max.unwrap_or(DEFAULT_MAX_TABLE_SIZE),
+        })
+    }
+
+    /// return the first byte. an abstract heap type, or the start of a type index
+    fn read_heap_type(&mut self) -> Option<u8> {
+        let byte = self.peek_u8()?;
+        self.skip_leb()?;
+        Some(byte)
+    }
+
+    fn skip_val_type(&mut self) -> Option<()> {
+        match self.read_u8()? {
+            REF_TYPE_NULLABLE | REF_TYPE_NON_NULL => self.read_heap_type().map(|_| ()),
+            _ => Some(()),
+        }
+    }
+
+    /// up to and including its `end`
+    fn skip_const_expr(&mut self) -> Option<()> {
+        loop {
+            match self.read_u8()? {
+                OPCODE_END => return Some(()),
+                op => skip_immediates(self, op)?,
+            }
+        }
+    }
+
+    fn read_memory_type(&mut self) -> Option<MemoryType> {
+        let (min, max, shared) = self.read_limits()?;
+        Some(MemoryType {
+            shared,
+            min,
+            max: max.unwrap_or(DEFAULT_MAX_MEMORY_PAGES),
+        })
+    }
+}
+
+/// iterate (section id, section payload) of a .wasm. stop at the first malformed section
+fn sections(binary: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut reader = match binary.starts_with(&WASM_MAGIC_AND_VERSION) {
+        true => Reader::new(&binary[WASM_MAGIC_AND_VERSION.len()..]),
+        false => Reader::new(&[]),
+    };
+
+    std::iter::from_fn(move || {
+        let id = reader.read_u8()?;
+        let size = reader.read_leb_u32()?;
+        let payload = reader.read_bytes(size as usize)?;
+        Some((id, payload))
+    })
+}
+
+fn write_leb_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        match value {
+            0 => return out.push(byte),
+            _ => out.push(byte | 0x80),
+        }
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    out.push(id);
+    write_leb_u32(out, payload.len() as u32);
+    out.extend_from_slice(payload);
+}
+
+/// make `memory.grow` of the default memory trap, right after a failed grow, once the
+/// global `MEMORY_GROW_TRAP_GLOBAL` is set to 1 by the host. So no more wasm code runs
+/// after a grow denied by `InstanceBuilder::memory_limit()`
+///
+/// A mutable i32 global is appended and exported. Each function growing the memory gets
+/// an extra i32 local, appended after others. So indexes of functions, globals and
+/// locals are kept. Code offsets, like those of DWARF, are changed.
+///
+/// Return `false` if the binary isn't a .wasm, is malformed, has a 64-bit memory or an
+/// unknown instruction. The binary is untouched then.
+pub fn guard_memory_grow(binary: &mut Vec<u8>) -> bool {
+    match guarded(binary) {
+        Some(Some(guarded)) => {
+            *binary = guarded;
+            true
+        }
+        // nothing to guard
+        Some(None) => true,
+        None => false,
+    }
+}
+
+fn guarded(binary: &[u8]) -> Option<Option<Vec<u8>>> {
+    if !binary.starts_with(&WASM_MAGIC_AND_VERSION) {
+        return None;
+    }
+
+    // all sections, which cover the whole binary
+    let mut reader = Reader::new(&binary[WASM_MAGIC_AND_VERSION.len()..]);
+    let mut all_sections = Vec::new();
+    while !reader.is_empty() {
+        let id = reader.read_u8()?;
+        let size = reader.read_leb_u32()?;
+        all_sections.push((id, reader.read_bytes(size as usize)?));
+    }
+
+    // params of types, types of functions, and count of globals
+    let mut type_params = Vec::new();
+    let mut function_types = Vec::new();
+    let mut global_count = 0;
+    for (id, payload) in all_sections.iter() {
+        let mut section = Reader::new(payload);
+        match *id {
+            TYPE_SECTION_ID => {
+                for _ in 0..section.read_leb_u32()? {
+                    if section.read_u8()? != FUNC_TYPE_FORM {
+                        return None;
+                    }
+                    let params = section.read_leb_u32()?;
+                    section.read_bytes(params as usize)?;
+                    let results = section.read_leb_u32()?;
+                    section.read_bytes(results as usize)?;
+                    type_params.push(params);
+                }
+            }
+            IMPORT_SECTION_ID => {
+                for _ in 0..section.read_leb_u32()? {
+                    section.read_name()?;
+                    section.read_name()?;
+                    match section.read_u8()? {
+                        IMPORT_KIND_FUNC => {
+                            section.read_leb_u32()?;
+                        }
+                        IMPORT_KIND_TABLE => {
+                            section.read_table_type()?;
+                        }
+                        IMPORT_KIND_MEMORY => {
+                            if section.buf.get(section.pos)? & LIMITS_MEMORY64 != 0 {
+                                return None;
+                            }
+                            section.read_memory_type()?;
+                        }
+                        IMPORT_KIND_GLOBAL => {
+                            section.read_bytes(2)?;
+                            global_count += 1;
+                        }
+                        IMPORT_KIND_TAG => {
+                            section.read_u8()?;
+                            section.read_leb_u32()?;
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            FUNCTION_SECTION_ID => {
+                for _ in 0..section.read_leb_u32()? {
+                    function_types.push(section.read_leb_u32()?);
+                }
+            }
+            MEMORY_SECTION_ID => {
+                for _ in 0..section.read_leb_u32()? {
+                    if section.buf.get(section.pos)? & LIMITS_MEMORY64 != 0 {
+                        return None;
+                    }
+                    section.read_memory_type()?;
+                }
+            }
+            GLOBAL_SECTION_ID => global_count += section.read_leb_u32()?,
+            _ => {}
+        }
+    }
+
+    let (code_index, code) = all_sections
+        .iter()
+        .enumerate()
+        .find(|(_, (id, _))| *id == CODE_SECTION_ID)
+        .map(|(i, (_, payload))| (i, *payload))?;
+    let mut section = Reader::new(code);
+    let body_count = section.read_leb_u32()?;
+    if body_count as usize != function_types.len() {
+        return None;
+    }
+
+    let mut guarded_code = Vec::with_capacity(code.len());
+    write_leb_u32(&mut guarded_code, body_count);
+    let mut changed = false;
+    for type_index in function_types {
+        let size = section.read_leb_u32()?;
+        let body = section.read_bytes(size as usize)?;
+        let params = *type_params.get(type_index as usize)?;
+        let body = match guard_body(body, params, global_count)? {
+            Some(guarded) => {
+                changed = true;
+                guarded
+            }
+            None => body.to_vec(),
+        };
+        write_leb_u32(&mut guarded_code, body.len() as u32);
+        guarded_code.extend_from_slice(&body);
+    }
+    if !changed {
+        return Some(None);
+    }
+
+    // the global, initialized to 0
+    let new_global = [VAL_TYPE_I32, 0x01, 0x41, 0x00, 0x0b];
+    let mut new_export = Vec::new();
+    write_leb_u32(&mut new_export, MEMORY_GROW_TRAP_GLOBAL.len() as u32);
+    new_export.extend_from_slice(MEMORY_GROW_TRAP_GLOBAL.as_bytes());
+    new_export.push(EXPORT_KIND_GLOBAL);
+    write_leb_u32(&mut new_export, global_count);
+
+    let appended = |payload: &[u8], entry: &[u8]| -> Option<Vec<u8>> {
+        let mut section = Reader::new(payload);
+        let count = section.read_leb_u32()?.checked_add(1)?;
+        let mut appended = Vec::with_capacity(payload.len() + entry.len() + 1);
+        write_leb_u32(&mut appended, count);
+        appended.extend_from_slice(&payload[section.pos..]);
+        appended.extend_from_slice(entry);
+        Some(appended)
+    };
+    let order = |id: u8| SECTION_ORDER.iter().position(|known| *known == id);
+
+    let mut out = Vec::with_capacity(binary.len() + guarded_code.len() - code.len() + 64);
+    out.extend_from_slice(&WASM_MAGIC_AND_VERSION);
+    let mut pending = vec![
+        (GLOBAL_SECTION_ID, new_global.as_slice()),
+        (EXPORT_SECTION_ID, new_export.as_slice()),
+    ];
+    for (i, (id, payload)) in all_sections.iter().enumerate() {
+        // a section to create goes before the first one after it
+        if *id != CUSTOM_SECTION_ID {
+            while let Some((new_id, entry)) = pending.first().copied() {
+                if order(*id) <= order(new_id) {
+                    break;
+                }
+                write_section(&mut out, new_id, &appended(&[0x00], entry)?);
+                pending.remove(0);
+            }
+        }
+
+        match pending.iter().position(|(new_id, _)| new_id == id) {
+            Some(p) => {
+                write_section(&mut out, *id, &appended(payload, pending[p].1)?);
+                pending.remove(p);
+            }
+            None if i == code_index => write_section(&mut out, *id, &guarded_code),
+            None => write_section(&mut out, *id, payload),
+        }
+    }
+    Some(Some(out))
+}
+
+// guard `memory.grow` of the default memory in a function body, with `params` parameters.
+// `None` for an unknown instruction, `Some(None)` if the body doesn't grow the memory
+fn guard_body(body: &[u8], params: u32, trap_global: u32) -> Option<Option<Vec<u8>>> {
+    let mut reader = Reader::new(body);
+    let local_groups = reader.read_leb_u32()?;
+    let groups_start = reader.pos;
+    let mut locals = params as u64;
+    for _ in 0..local_groups {
+        locals += reader.read_leb_u32()? as u64;
+        reader.read_u8()?;
+    }
+    let locals_end = reader.pos;
+    // the extra local, for the result of `memory.grow`
+    let scratch = u32::try_from(locals).ok()?;
+
+    let mut code = Vec::with_capacity(body.len() + 32);
+    let mut copied = locals_end;
+    while !reader.is_empty() {
+        let op = reader.read_u8()?;
+        if op != 0x40 {
+            skip_immediates(&mut reader, op)?;
+            continue;
+        }
+        if reader.read_leb_u32()? != 0 {
+            continue;
+        }
+
+        // (if (i32.eq (local.tee $scratch (memory.grow ..)) (i32.const -1))
+        //   (then (if (global.get $trap) (then unreachable))))
+        // (local.get $scratch)
+        code.extend_from_slice(&body[copied..reader.pos]);
+        code.push(0x22);
+        write_leb_u32(&mut code, scratch);
+        code.extend_from_slice(&[0x41, 0x7f, 0x46, 0x04, 0x40, 0x23]);
+        write_leb_u32(&mut code, trap_global);
+        code.extend_from_slice(&[0x04, 0x40, 0x00, 0x0b, 0x0b, 0x20]);
+        write_leb_u32(&mut code, scratch);
+        copied = reader.pos;
+    }
+    if code.is_empty() {
+        return Some(None);
+    }
+    code.extend_from_slice(&body[copied..]);
+
+    let mut guarded = Vec::with_capacity(code.len() + locals_end + 8);
+    write_leb_u32(&mut guarded, local_groups.checked_add(1)?);
+    guarded.extend_from_slice(&body[groups_start..locals_end]);
+    guarded.extend_from_slice(&[0x01, VAL_TYPE_I32]);
+    guarded.extend_from_slice(&code);
+    Some(Some(guarded))
+}
+
+// skip immediates of an instruction. `None` for an unknown one
+fn skip_immediates(reader: &mut Reader, op: u8) -> Option<()> {
+    let memarg = |reader: &mut Reader| -> Option<()> {
+        if reader.read_leb_u32()? & MEMARG_HAS_MEMORY_INDEX != 0 {
+            reader.read_leb_u32()?;
+        }
+        reader.skip_leb()
+    };
+
+    match op {
+        // unreachable, nop, else, throw_ref, end, return, drop, select, numeric instructions,
+        // ref.is_null, ref.eq, ref.as_non_null
+        0x00
+        | 0x01
+        | 0x05
+        | 0x0a
+        | 0x0b
+        | 0x0f
+        | 0x19
+        | 0x1a
+        | 0x1b
+        | 0x45..=0xc4
+        | 0xd1
+        | 0xd3
+        | 0xd4 => Some(()),
+        // block types, indexes, ref.null and constants of integers
+        0x02..=0x04
+        | 0x06..=0x09
+        | 0x0c
+        | 0x0d
+        | 0x10
+        | 0x12
+        | 0x14
+        | 0x15
+        | 0x18
+        | 0x20..=0x26
+        | 0x3f
+        | 0x40
+        | 0x41
+        | 0x42
+        | 0xd0
+        | 0xd2
+        | 0xd5
+        | 0xd6 => reader.skip_leb(),
+        0x0e => {
+            for _ in 0..=reader.read_leb_u32()? {
+                reader.read_leb_u32()?;
+            }
+            Some(())
+        }
+        0x11 | 0x13 => {
+            reader.read_leb_u32()?;
+            reader.read_leb_u32().map(|_| ())
+        }
+        0x1c => {
+            for _ in 0..reader.read_leb_u32()? {
+                reader.skip_leb()?;
+            }
+            Some(())
+        }
+        // try_table
+        0x1f => {
+            reader.skip_leb()?;
+            for _ in 0..reader.read_leb_u32()? {
+                if reader.read_u8()? < 2 {
+                    reader.read_leb_u32()?;
+                }
+                reader.read_leb_u32()?;
+            }
+            Some(())
+        }
+        0x28..=0x3e => memarg(reader),
+        0x43 => reader.read_bytes(4).map(|_| ()),
+        0x44 => reader.read_bytes(8).map(|_| ()),
+        0xfc => match reader.read_leb_u32()? {
+            0..=7 => Some(()),
+            9 | 11 | 13 | 15..=17 => reader.read_leb_u32().map(|_| ()),
+            8 | 10 | 12 | 14 => {
+                reader.read_leb_u32()?;
+                reader.read_leb_u32().map(|_| ())
+            }
+            _ => None,
+        },
+        0xfd => match reader.read_leb_u32()? {
+            0..=11 | 92 | 93 => memarg(reader),
+            12 | 13 => reader.read_bytes(16).map(|_| ()),
+            21..=34 => reader.read_u8().map(|_| ()),
+            84..=91 => {
+                memarg(reader)?;
+                reader.read_u8().map(|_| ())
+            }
+            _ => Some(()),
+        },
+        0xfe => match reader.read_leb_u32()? {
+            0x03 => reader.read_u8().map(|_| ()),
+            0x00..=0x02 | 0x10..=0x4e => memarg(reader),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// remove all custom sections of a .wasm in place. the malformed part, if any, is kept
+/// as it is and left to the loader
+pub fn strip_custom_sections(binary: &mut Vec<u8>) {
+    if !binary.starts_with(&WASM_MAGIC_AND_VERSION) {
+        return;
+    }
+
+    let mut read = WASM_MAGIC_AND_VERSION.len();
+    let mut write = read;
+    while read < binary.len() {
+        let mut reader = Reader::new(&binary[read..]);
+        let Some(id) = reader.read_u8() else { break };
+        let Some(size) = reader.read_leb_u32() else {
+            break;
+        };
+        let end = match (read + reader.pos).checked_add(size as usize) {
+            Some(end) if end <= binary.len() => end,
+            _ => break,
+        };
+
+        if id != CUSTOM_SECTION_ID {
+            binary.copy_within(read..end, write);
+            write += end - read;
+        }
+        read = end;
+    }
+
+    binary.copy_within(read.., write);
+    binary.truncate(write + binary.len() - read);
+    binary.shrink_to_fit();
+}
+
+/// return declarations of a .wasm.
+///
+/// Return what isn't supported if the binary isn't a .wasm, or one of the interested
+/// sections is malformed or has an unknown form.
+pub fn parse_module_info(binary: &[u8]) -> Result<ModuleInfo, String> {
+    if !binary.starts_with(&WASM_MAGIC_AND_VERSION) {
+        return Err(String::from("not a .wasm, like an .aot"));
+    }
+
+    let mut info = ModuleInfo::default();
+    for (id, payload) in sections(binary) {
+        parse_section_info(&mut info, id, &mut Reader::new(payload)).ok_or_else(|| {
+            // only these sections are parsed
+            let section = match id {
+                IMPORT_SECTION_ID => "import",
+                FUNCTION_SECTION_ID => "function",
+                TABLE_SECTION_ID => "table",
+                MEMORY_SECTION_ID => "memory",
+                _ => "export",
+            };
+            format!("malformed or unknown entries in the {} section", section)
+        })?;
+    }
+
+    Ok(info)
+}
+
+fn parse_section_info(info: &mut ModuleInfo, id: u8, section: &mut Reader) -> Option<()> {
+    match id {
+        IMPORT_SECTION_ID => {
+            info.import_count = section.read_leb_u32()?;
+            for _ in 0..info.import_count {
+                section.read_name()?;
+                section.read_name()?;
+                match section.read_u8()? {
+                    IMPORT_KIND_FUNC => {
+                        section.read_leb_u32()?;
+                        info.imported_function_count += 1;
+                    }
+                    IMPORT_KIND_TABLE => info.tables.push(section.read_table_type()?),
+                    IMPORT_KIND_MEMORY => info.memories.push(section.read_memory_type()?),
+                    IMPORT_KIND_GLOBAL => {
+                        section.skip_val_type()?;
+                        // mutability
+                        section.read_u8()?;
+                    }
+                    IMPORT_KIND_TAG => {
+                        section.read_u8()?;
+                        section.read_leb_u32()?;
+                    }
+                    _ => return None,
+                }
+            }
+        }
+        FUNCTION_SECTION_ID => info.function_count = section.read_leb_u32()?,
+        TABLE_SECTION_ID => {
+            for _ in 0..section.read_leb_u32()? {
+                if section.peek_u8()? != TABLE_WITH_INIT_EXPR {
+                    info.tables.push(section.read_table_type()?);
+                    continue;
+                }
+
+                section.read_u8()?;
+                if section.read_u8()? != 0x00 {
+                    return None;
+                }
+                info.tables.push(section.read_table_type()?);
+                section.skip_const_expr()?;
+            }
+        }
+        MEMORY_SECTION_ID => {
+            for _ in 0..section.read_leb_u32()? {
+                info.memories.push(section.read_memory_type()?);
+            }
+        }
+        EXPORT_SECTION_ID => {
+            for _ in 0..section.read_leb_u32()? {
+                let name = section.read_name()?;
+                let kind = section.read_u8()?;
+                let index = section.read_leb_u32()?;
+                if kind == IMPORT_KIND_TABLE {
+                    info.exported_tables.push((name, index));
+                }
+            }
+        }
+        START_SECTION_ID => info.has_start_function = true,
+        _ => {}
+    }
+
+    Some(())
+}
+
+/// return a function index -> function name map.
+///
+/// It is empty if the binary isn't a .wasm or there is no `name` section. A malformed
+/// `name` section is tolerated, names parsed before the broken part are kept.
+pub fn parse_function_names(binary: &[u8]) -> HashMap<u32, String> {
+    let mut names = HashMap::new();
+
+    for (id, payload) in sections(binary) {
+        if id != CUSTOM_SECTION_ID {
+            continue;
+        }
+
+        let mut section = Reader::new(payload);
+        if section.read_name().as_deref() == Some("name") {
+            parse_name_section(&mut section, &mut names);
+            break;
+        }
+    }
+
+    names
+}
+
+fn parse_name_section(section: &mut Reader, names: &mut HashMap<u32, String>) -> Option<()> {
+    while !section.is_empty() {
+        let id = section.read_u8()?;
+        let size = section.read_leb_u32()?;
+        let payload = section.read_bytes(size as usize)?;
+
+        if id != FUNCTION_NAMES_SUBSECTION_ID {
+            continue;
+        }
+
+        let mut subsection = Reader::new(payload);
+        let count = subsection.read_leb_u32()?;
+        for _ in 0..count {
+            let index = subsection.read_leb_u32()?;
+            let name = subsection.read_name()?;
+            names.insert(index, name);
+        }
+    }
+
+    Some(())
+}
+
+/// replace `$f<index>`, which is how WAMR refers to a function without a name, with the
+/// function name in `names`
+pub fn resolve_function_names(text: &str, names: &HashMap<u32, String>) -> String {
+    let mut resolved = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(pos) = rest.find("$f") {
+        resolved.push_str(&rest[..pos]);
+
+        let after = &rest[pos + 2..];
+        let digits = after.bytes().take_while(|c| c.is_ascii_digit()).count();
+        match after[..digits]
+            .parse::<u32>()
+            .ok()
+            .and_then(|i| names.get(&i))
+        {
+            Some(name) => resolved.push_str(name),
+            None => resolved.push_str(&rest[pos..pos + 2 + digits]),
+        }
+
+        rest = &after[digits..];
+    }
+
+    resolved.push_str(rest);
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_function_names() {
+        let binary = wat::parse_str(
+            r#"
+            (module
+              (func $add (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+              (func (param i32) (result i32)
+                (local.get 0)
+              )
+              (func $sub (param i32 i32) (result i32)
+                (i32.sub (local.get 0) (local.get 1))
+              )
+            )
+            "#,
+        )
+        .unwrap();
+
+        let names = parse_function_names(&binary);
+        assert_eq!(names.len(), 2);
+        assert_eq!(names.get(&0).unwrap(), "add");
+        assert_eq!(names.get(&1), None);
+        assert_eq!(names.get(&2).unwrap(), "sub");
+    }
+
+    #[test]
+    fn test_parse_function_names_without_name_section() {
+        // (module
+        //   (func (export "add") (param i32 i32) (result i32)
+        //     (local.get 0)
+        //     (local.get 1)
+        //     (i32.add)
+        //   )
+        // )
+        let binary = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+            0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x07, 0x07, 0x01, 0x03, 0x61, 0x64, 0x64,
+            0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+        ];
+        assert!(parse_function_names(&binary).is_empty());
+
+        // truncated
+        assert!(parse_function_names(&binary[..20]).is_empty());
+
+        // not a .wasm
+        assert!(parse_function_names(&[0x00, 0x61, 0x6f, 0x74]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_module_info() {
+        let binary = wat::parse_str(
+            r#"
+            (module
+              (import "env" "log" (func (param i32)))
+              (import "env" "table" (table 1 funcref))
+              (import "env" "memory" (memory 1 4))
+              (memory 2)
+              (table 8 16 externref)
+              (func $init)
+              (func (export "run"))
+              (start $init)
+            )
+            "#,
+        )
+        .unwrap();
+
+        let info = parse_module_info(&binary).unwrap();
+        assert_eq!(info.import_count, 3);
+        assert_eq!(info.function_count, 2);
+        assert_eq!(info.imported_function_count, 1);
+        assert!(info.has_start_function);
+        assert_eq!(
+            info.memories,
+            vec![
+                MemoryType {
+                    shared: false,
+                    min: 1,
+                    max: 4
+                },
+                MemoryType {
+                    shared: false,
+                    min: 2,
+                    max: DEFAULT_MAX_MEMORY_PAGES
+                }
+            ]
+        );
+        assert_eq!(
+            info.tables,
+            vec![
+                TableType {
+                    element: ValType::FuncRef,
+                    shared: false,
+                    min: 1,
+                    max: DEFAULT_MAX_TABLE_SIZE
+                },
+                TableType {
+                    element: ValType::ExternRef,
+                    shared: false,
+                    min: 8,
+                    max: 16
+                }
+            ]
+        );
+
+        assert_eq!(
+            parse_module_info(&[0x00, 0x61, 0x6f, 0x74]),
+            Err(String::from("not a .wasm, like an .aot"))
+        );
+    }
+
+    #[test]
+    fn test_parse_module_info_typed_references() {
+        let binary = wat::parse_str(
+            r#"
+            (module
+              (type $f (func))
+              (import "env" "lanes" (global v128))
+              (import "env" "callback" (global (mut (ref null $f))))
+              (import "env" "memory" (memory 1))
+              (table (export "callbacks") 2 4 (ref null $f) (ref.func $run))
+              (table 1 externref)
+              (elem declare func $run)
+              (func $run (type $f))
+            )
+            "#,
+        )
+        .unwrap();
+
+        let info = parse_module_info(&binary).unwrap();
+        assert_eq!(info.import_count, 3);
+        assert_eq!(info.function_count, 1);
+        assert_eq!(info.memories.len(), 1);
+        assert_eq!(
+            info.tables,
+            vec![
+                TableType {
+                    element: ValType::FuncRef,
+                    shared: false,
+                    min: 2,
+                    max: 4
+                },
+                TableType {
+                    element: ValType::ExternRef,
+                    shared: false,
+                    min: 1,
+                    max: DEFAULT_MAX_TABLE_SIZE
+                }
+            ]
+        );
+        assert_eq!(info.exported_tables, vec![(String::from("callbacks"), 0)]);
+
+        // an import of an unknown kind
+        let binary = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x02, 0x06, 0x01, 0x01, 0x61, 0x01,
+            0x62, 0x09,
+        ];
+        assert_eq!(
+            parse_module_info(&binary),
+            Err(String::from(
+                "malformed or unknown entries in the import section"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_strip_custom_sections() {
+        let binary = wat::parse_str(
+            r#"
+            (module
+              (func $add (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))
+              )
+              (@custom "extra" "some bytes")
+            )
+            "#,
+        )
+        .unwrap();
+        assert!(!parse_function_names(&binary).is_empty());
+
+        let mut stripped = binary.clone();
+        strip_custom_sections(&mut stripped);
+        assert!(stripped.len() < binary.len());
+        assert!(parse_function_names(&stripped).is_empty());
+        assert!(sections(&stripped).all(|(id, _)| id != CUSTOM_SECTION_ID));
+        assert_eq!(
+            sections(&stripped).count(),
+            sections(&binary)
+                .filter(|(id, _)| *id != CUSTOM_SECTION_ID)
+                .count()
+        );
+
+        // the malformed part is kept
+        let mut truncated = binary[..binary.len() - 2].to_vec();
+        let len = truncated.len();
+        strip_custom_sections(&mut truncated);
+        assert!(truncated.len() < len);
+    }
+
+    #[test]
+    fn test_guard_memory_grow() {
+        let binary = wat::parse_str(
+            r#"
+            (module
+              (import "env" "base" (global i32))
+              (memory 1)
+              (func $grow (export "grow") (param i32) (result i32)
+                (local i64)
+                (memory.grow (local.get 0))
+              )
+              (func (export "size") (result i32) (memory.size))
+            )
+            "#,
+        )
+        .unwrap();
+
+        let mut guarded = binary.clone();
+        assert!(guard_memory_grow(&mut guarded));
+        assert!(guarded.len() > binary.len());
+        assert_eq!(parse_module_info(&guarded), parse_module_info(&binary));
+        assert_eq!(
+            parse_function_names(&guarded),
+            parse_function_names(&binary)
+        );
+
+        // exported, after the imported global
+        let mut export = Vec::new();
+        write_leb_u32(&mut export, MEMORY_GROW_TRAP_GLOBAL.len() as u32);
+        export.extend_from_slice(MEMORY_GROW_TRAP_GLOBAL.as_bytes());
+        export.extend_from_slice(&[EXPORT_KIND_GLOBAL, 1]);
+        assert!(
+            sections(&guarded).any(|(id, payload)| id == EXPORT_SECTION_ID
+                && payload.windows(export.len()).any(|entry| entry == export))
+        );
+
+        // nothing to guard
+        let binary = wat::parse_str("(module (memory 1) (func (export \"run\")))").unwrap();
+        let mut unchanged = binary.clone();
+        assert!(guard_memory_grow(&mut unchanged));
+        assert_eq!(unchanged, binary);
+
+        // an unknown instruction, 0xff, in place of i32.add
+        let mut unknown = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f,
+            0x7f, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00,
+            0x20, 0x01, 0xff, 0x0b,
+        ];
+        let original = unknown.clone();
+        assert!(!guard_memory_grow(&mut unknown));
+        assert_eq!(unknown, original);
+
+        assert!(!guard_memory_grow(&mut vec![0x00, 0x61, 0x6f, 0x74]));
+    }
+
+    #[test]
+    fn test_resolve_function_names() {
+        let mut names = HashMap::new();
+        names.insert(1, String::from("gcd"));
+
+        let text = "#00: 0x0011 - $f1\n#01: 0x0022 - $f12\n#02: 0x0033 - $foo";
+        assert_eq!(
+            resolve_function_names(text, &names),
+            "#00: 0x0011 - gcd\n#01: 0x0022 - $f12\n#02: 0x0033 - $foo"
+        );
+    }
+}
@@ -0,0 +1,221 @@
+/*
+ * Copyright (C) 2019 Intel Corporation. All rights reserved.
+ * SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception
+ */
+
+//! exchange Rust values with guests through linear memories, without hand-rolled byte
+//! layouts. Values are encoded by [postcard](https://docs.rs/postcard). It requires the
+//! `codec` feature
+//!
+//! The convention with guests:
+//! - a value passed to a guest is the address and the length of its encoding, as two `i32`
+//!   parameters. The guest owns the buffer then
+//! - a value returned by a guest is an `i64`, the address of its encoding in the high 32 bits
+//!   and the length in the low 32 bits. The guest owns the buffer as well
+//!
+//! A guest in Rust decodes and encodes with `postcard` and the same types:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn alloc(len: usize) -> *mut u8 {
+//!     Vec::<u8>::with_capacity(len).leak().as_mut_ptr()
+//! }
+//!
+//! #[no_mangle]
+//! pub extern "C" fn greet(ptr: *mut u8, len: usize) -> u64 {
+//!     let request = unsafe { Vec::from_raw_parts(ptr, len, len) };
+//!     let request: Request = postcard::from_bytes(&request).unwrap();
+//!     let reply = postcard::to_allocvec(&Reply::to(&request)).unwrap().leak();
+//!     ((reply.as_ptr() as u64) << 32) | reply.len() as u64
+//! }
+//! ```
+//!
+//! And the host calls it by:
+//!
+//! ```ignore
+//! let reply: Reply = wasm_codec::call(&instance, "greet", "alloc", &request)?;
+//! ```
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{function::Function, instance::Instance, memory::Memory, RuntimeError};
+
+/// encode `value` into a buffer allocated like `Memory::alloc_str()`. return the address
+/// and the length of the encoding
+///
+/// # Error
+///
+/// Return `RuntimeError::CodecError` if failed to encode, or `RuntimeError::ExecutionError`
+/// if the allocation fails.
+pub fn write<T: Serialize + ?Sized>(
+    memory: &Memory,
+    value: &T,
+) -> Result<(u64, u64), RuntimeError> {
+    memory.alloc_bytes(&encode(value)?)
+}
+
+/// the same as `write()`, but allocate the buffer by the function exported as `allocator`.
+/// See `Memory::alloc_str_with()`
+///
+/// # Error
+///
+/// Return `RuntimeError::CodecError` if failed to encode. Others are the same as
+/// `Memory::alloc_str_with()`.
+pub fn write_with<T: Serialize + ?Sized>(
+    memory: &Memory,
+    allocator: &str,
+    value: &T,
+) -> Result<(u64, u64), RuntimeError> {
+    memory.alloc_bytes_with(allocator, &encode(value)?)
+}
+
+/// decode a value from `len` bytes at `offset`
+///
+/// # Error
+///
+/// Return `RuntimeError::MemoryAccessOutOfBounds` if the range is beyond the memory, or
+/// `RuntimeError::CodecError` if the bytes are not a `T`.
+pub fn read<T: DeserializeOwned>(
+    memory: &Memory,
+    offset: u64,
+    len: u64,
+) -> Result<T, RuntimeError> {
+    // a guest can return any length. Check it before allocating
+    match offset.checked_add(len) {
+        Some(end) if end <= memory.data_size() => {}
+        _ => return Err(RuntimeError::MemoryAccessOutOfBounds { offset, len }),
+    }
+
+    let mut bytes = vec![0u8; len as usize];
+    memory.read(offset, &mut bytes)?;
+    postcard::from_bytes(&bytes).map_err(|e| RuntimeError::CodecError(e.to_string()))
+}
+
+/// call the export function `name` by the convention. `arg` is written into the default
+/// memory in a buffer allocated by the function exported as `allocator`, and the result is
+/// decoded from the returned buffer
+///
+/// # Error
+///
+/// Return `RuntimeError::SignatureMismatch` if `name` doesn't take `(i32, i32)` and return
+/// an `i64`, `RuntimeError::CodecError` if failed to encode `arg` or decode the result.
+/// Others are the same as `write_with()` and `Function::call()`.
+pub fn call<A, R>(
+    instance: &Instance,
+    name: &str,
+    allocator: &str,
+    arg: &A,
+) -> Result<R, RuntimeError>
+where
+    A: Serialize + ?Sized,
+    R: DeserializeOwned,
+{
+    let memory = instance
+        .memory()
+        .ok_or_else(|| RuntimeError::CodecError(String::from("no default memory")))?;
+    let function = Function::find_export_func(instance, name)?;
+
+    let (offset, len) = write_with(&memory, allocator, arg)?;
+    let result: i64 = function.call_typed(instance, (offset as i32, len as i32))?;
+
+    let result = result as u64;
+    read(&memory, result >> 32, result & 0xFFFF_FFFF)
+}
+
+fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, RuntimeError> {
+    postcard::to_allocvec(value).map_err(|e| RuntimeError::CodecError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{module::Module, runtime::Runtime};
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Request {
+        name: String,
+        ids: Vec<u32>,
+        limit: Option<u32>,
+    }
+
+    // a bump allocator, and a function returning its argument as is
+    const GUEST: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (func (export "alloc") (param i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get 0)))
+          )
+          (func (export "echo") (param i32 i32) (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get 0)) (i64.const 32))
+              (i64.extend_i32_u (local.get 1))
+            )
+          )
+          (func (export "add") (param i32 i32) (result i32)
+            (i32.add (local.get 0) (local.get 1))
+          )
+        )
+        "#;
+
+    fn request() -> Request {
+        Request {
+            name: String::from("tenant"),
+            ids: vec![1, 300, 70000],
+            limit: Some(10),
+        }
+    }
+
+    #[test]
+    fn test_codec_write_read() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, GUEST).unwrap();
+        let instance = Instance::new_with_args(&runtime, &module, 1024 * 64, 1024).unwrap();
+        let memory = instance.memory().unwrap();
+
+        let (offset, len) = write(&memory, &request()).unwrap();
+        assert_eq!(read::<Request>(&memory, offset, len).unwrap(), request());
+        instance.free(offset);
+
+        let (offset, len) = write_with(&memory, "alloc", &request()).unwrap();
+        assert_eq!(offset, 1024);
+        assert_eq!(read::<Request>(&memory, offset, len).unwrap(), request());
+
+        // a small integer takes a byte
+        let (offset, len) = write_with(&memory, "alloc", &7u32).unwrap();
+        assert_eq!(len, 1);
+        assert_eq!(memory.read_u8(offset).unwrap(), 7);
+
+        assert!(matches!(
+            read::<Request>(&memory, offset, len),
+            Err(RuntimeError::CodecError(_))
+        ));
+
+        // a bogus length is rejected before allocating for it
+        assert!(matches!(
+            read::<Request>(&memory, offset, u32::MAX as u64),
+            Err(RuntimeError::MemoryAccessOutOfBounds { len, .. }) if len == u32::MAX as u64
+        ));
+    }
+
+    #[test]
+    fn test_codec_call() {
+        let runtime = Runtime::new().unwrap();
+        let module = Module::from_wat(&runtime, GUEST).unwrap();
+        let instance = Instance::new(&runtime, &module, 1024 * 64).unwrap();
+
+        let echoed: Request = call(&instance, "echo", "alloc", &request()).unwrap();
+        assert_eq!(echoed, request());
+
+        assert!(matches!(
+            call::<Request, Request>(&instance, "add", "alloc", &request()),
+            Err(RuntimeError::SignatureMismatch(_))
+        ));
+        assert!(matches!(
+            call::<Request, Request>(&instance, "echo", "malloc", &request()),
+            Err(RuntimeError::FunctionNotFound)
+        ));
+    }
+}